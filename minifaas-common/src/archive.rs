@@ -0,0 +1,190 @@
+use crate::datastore::{Revision, UserFunctionRecord};
+pub use crate::types::*;
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use uuid::Uuid;
+
+///
+/// The format of archives written by this version, newer ones can't be imported.
+///
+pub const ARCHIVE_VERSION: u32 = 1;
+
+///
+/// What's in a function's environment: its logs and other files by relative path.
+///
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct EnvironmentContents {
+    pub logs: Option<String>,
+    pub files: BTreeMap<String, Vec<u8>>,
+}
+
+impl EnvironmentContents {
+    pub fn is_empty(&self) -> bool {
+        self.logs.is_none() && self.files.is_empty()
+    }
+}
+
+///
+/// A backup of all functions in a single JSON document, see `GET /api/v1/export`.
+///
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FunctionArchive {
+    pub version: u32,
+    pub created: DateTime<Utc>,
+    pub functions: Vec<ArchivedFunction>,
+}
+
+impl FunctionArchive {
+    pub fn new(functions: Vec<ArchivedFunction>) -> Self {
+        FunctionArchive {
+            version: ARCHIVE_VERSION,
+            created: Utc::now(),
+            functions,
+        }
+    }
+}
+
+///
+/// A function with its history and, optionally, what's in its environment.
+///
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ArchivedFunction {
+    pub function: UserFunctionDeclaration,
+    pub environment_id: Uuid,
+
+    #[serde(default)]
+    pub revisions: Vec<Revision>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub logs: Option<String>,
+
+    /// The environment's files by relative path, base64-encoded
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub files: BTreeMap<String, String>,
+}
+
+impl ArchivedFunction {
+    pub fn new(
+        record: &UserFunctionRecord,
+        revisions: Vec<Revision>,
+        contents: EnvironmentContents,
+    ) -> Self {
+        ArchivedFunction {
+            function: record.declaration().clone(),
+            environment_id: record.environment_id,
+            revisions,
+            logs: contents.logs,
+            files: contents
+                .files
+                .iter()
+                .map(|(name, content)| (name.clone(), base64::encode(content)))
+                .collect(),
+        }
+    }
+
+    ///
+    /// The archived logs and (decoded) files.
+    ///
+    pub fn contents(&self) -> Result<EnvironmentContents> {
+        let mut files = BTreeMap::new();
+        for (name, content) in &self.files {
+            let content = base64::decode(content)
+                .with_context(|| format!("The file '{}' isn't valid base64", name))?;
+            files.insert(name.clone(), content);
+        }
+        Ok(EnvironmentContents {
+            logs: self.logs.clone(),
+            files,
+        })
+    }
+}
+
+///
+/// What happens to archived functions that have the same name as a stored function.
+///
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ConflictStrategy {
+    /// Keep the stored function
+    Skip,
+
+    /// Replace the stored function, keeping its environment and revisions (the archived ones are added after them)
+    Overwrite,
+
+    /// Import the function under a new name, e.g. `hello-2`
+    Rename,
+}
+
+impl Default for ConflictStrategy {
+    fn default() -> Self {
+        ConflictStrategy::Skip
+    }
+}
+
+///
+/// The first of `name-2`, `name-3`, ... that isn't `taken`.
+///
+pub fn free_name(name: &str, taken: impl Fn(&str) -> bool) -> String {
+    (2..)
+        .map(|i| format!("{}-{}", name, i))
+        .find(|candidate| !taken(candidate))
+        .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runtime::FunctionCode;
+
+    #[test]
+    fn test_archived_function_roundtrip() {
+        let record = UserFunctionRecord::from(UserFunctionDeclaration {
+            name: "hello".to_owned(),
+            code: FunctionCode::new("echo hello".to_owned(), ProgrammingLanguage::Bash),
+            ..Default::default()
+        });
+        let mut contents = EnvironmentContents {
+            logs: Some("hello\n".to_owned()),
+            ..Default::default()
+        };
+        contents
+            .files
+            .insert("data.bin".to_owned(), vec![0, 159, 146, 150]);
+        let archive = FunctionArchive::new(vec![ArchivedFunction::new(
+            &record,
+            vec![],
+            contents.clone(),
+        )]);
+
+        let json = serde_json::to_value(&archive).unwrap();
+        assert_eq!(json["version"], ARCHIVE_VERSION);
+        assert_eq!(json["functions"][0]["files"]["data.bin"], "AJ+Slg==");
+
+        let archive: FunctionArchive = serde_json::from_value(json).unwrap();
+        let function = &archive.functions[0];
+        assert_eq!(function.function.name, "hello");
+        assert_eq!(function.environment_id, record.environment_id);
+        assert_eq!(function.contents().unwrap(), contents);
+
+        let mut invalid = function.clone();
+        invalid
+            .files
+            .insert("broken".to_owned(), "not base64!".to_owned());
+        assert!(invalid.contents().is_err());
+    }
+
+    #[test]
+    fn test_free_name() {
+        assert_eq!(free_name("hello", |_| false), "hello-2");
+        assert_eq!(
+            free_name("hello", |n| n == "hello-2" || n == "hello-3"),
+            "hello-4"
+        );
+        assert_eq!(
+            serde_json::from_str::<ConflictStrategy>("\"rename\"").unwrap(),
+            ConflictStrategy::Rename
+        );
+    }
+}
@@ -1,5 +1,43 @@
 pub use crate::types::*;
 use std::path::PathBuf;
+use std::str::FromStr;
+
+///
+/// Where the functions are stored.
+///
+#[derive(Debug, Clone, PartialEq)]
+pub enum StorageBackend {
+    /// A JSON file that is replaced on every change
+    Json,
+
+    /// An embedded key-value database (a directory)
+    Embedded,
+
+    /// A Git repository with a commit per change, optionally pulling from a remote (a path or URL)
+    Git(Option<String>),
+}
+
+impl Default for StorageBackend {
+    fn default() -> Self {
+        StorageBackend::Json
+    }
+}
+
+impl FromStr for StorageBackend {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "json" => Ok(StorageBackend::Json),
+            "embedded" => Ok(StorageBackend::Embedded),
+            "git" => Ok(StorageBackend::Git(None)),
+            _ => Err(anyhow::Error::msg(format!(
+                "Unknown storage backend '{}' (use 'json', 'embedded', or 'git')",
+                s
+            ))),
+        }
+    }
+}
 
 ///
 /// DataStore's configuration options
@@ -7,6 +45,7 @@ use std::path::PathBuf;
 pub struct DataStoreConfig {
     pub path: PathBuf,
     pub serialize_on_write: bool,
+    pub backend: StorageBackend,
 }
 
 impl DataStoreConfig {
@@ -14,8 +53,24 @@ impl DataStoreConfig {
         DataStoreConfig {
             path: path.into(),
             serialize_on_write,
+            backend: StorageBackend::default(),
         }
     }
+
+    ///
+    /// Stores the functions with `backend` at `path`.
+    ///
+    pub fn with_backend(mut self, backend: StorageBackend) -> Self {
+        self.backend = backend;
+        self
+    }
+
+    ///
+    /// Stores the functions in a Git repository at `path`, optionally pulling them from `remote`.
+    ///
+    pub fn with_git(self, remote: Option<String>) -> Self {
+        self.with_backend(StorageBackend::Git(remote))
+    }
 }
 
 #[cfg(test)]
@@ -23,4 +78,17 @@ mod tests {
     use super::*;
 
     use minifaas_test::get_empty_tmp_dir;
+
+    #[test]
+    fn test_storage_backend_from_str() {
+        assert_eq!(
+            "Embedded".parse::<StorageBackend>().unwrap(),
+            StorageBackend::Embedded
+        );
+        assert_eq!(
+            "git".parse::<StorageBackend>().unwrap(),
+            StorageBackend::Git(None)
+        );
+        assert!("sqlite".parse::<StorageBackend>().is_err());
+    }
 }
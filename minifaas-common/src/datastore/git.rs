@@ -0,0 +1,350 @@
+use super::record::UserFunctionRecord;
+pub use crate::types::*;
+use anyhow::Result;
+use log::{debug, info, warn};
+use std::collections::HashMap;
+use std::path::{Component, Path, PathBuf};
+use std::process::Command;
+use std::sync::Mutex;
+use uuid::Uuid;
+
+const MANIFEST_FILE: &str = "function.json";
+const AUTHOR_NAME: &str = "MiniFaaS";
+const AUTHOR_EMAIL: &str = "minifaas@localhost";
+
+///
+/// A local Git repository with a directory per function: the code in `code.<ext>` and everything else in a
+/// `function.json` manifest. Every change is committed. Uses the `git` executable, which has to be on the `PATH`.
+///
+#[derive(Debug)]
+pub struct GitRepository {
+    path: PathBuf,
+    remote: Option<String>,
+    // git locks the index, so changes are made one at a time
+    lock: Mutex<()>,
+}
+
+impl GitRepository {
+    ///
+    /// Opens the repository at `path` or creates it. Functions can be pulled from `remote` (a path or URL).
+    ///
+    pub fn open<P: Into<PathBuf>>(path: P, remote: Option<String>) -> Result<Self> {
+        let repo = GitRepository {
+            path: path.into(),
+            remote,
+            lock: Mutex::new(()),
+        };
+        std::fs::create_dir_all(&repo.path)?;
+        if !repo.path.join(".git").exists() {
+            info!("Creating a function repository at {:?}", repo.path);
+            repo.git(&["init", "--quiet"])?;
+        }
+        // commits need an author, which the runtime's user may not have configured
+        if repo.git(&["config", "user.email"]).is_err() {
+            repo.git(&["config", "user.name", AUTHOR_NAME])?;
+            repo.git(&["config", "user.email", AUTHOR_EMAIL])?;
+        }
+        Ok(repo)
+    }
+
+    ///
+    /// Where functions are pulled from, if anywhere.
+    ///
+    pub fn remote(&self) -> Option<&str> {
+        self.remote.as_deref()
+    }
+
+    fn git(&self, args: &[&str]) -> Result<String> {
+        debug!("Running git {:?} in {:?}", args, self.path);
+        let output = Command::new("git")
+            .arg("-C")
+            .arg(&self.path)
+            .args(args)
+            .output()?;
+        if output.status.success() {
+            Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+        } else {
+            Err(anyhow::Error::msg(format!(
+                "git {} failed: {}",
+                args.join(" "),
+                String::from_utf8_lossy(&output.stderr).trim()
+            )))
+        }
+    }
+
+    ///
+    /// Reads all functions from the working tree. Functions without an environment id (e.g. written by hand) get a
+    /// new one.
+    ///
+    pub fn load(&self) -> Result<HashMap<String, UserFunctionRecord>> {
+        let mut functions = HashMap::new();
+        for entry in std::fs::read_dir(&self.path)? {
+            let entry = entry?;
+            let dir = entry.path();
+            // a symlinked directory could point anywhere
+            if !entry.file_type()?.is_dir() || !dir.join(MANIFEST_FILE).is_file() {
+                continue;
+            }
+            match read_function(&dir) {
+                Ok(record) => {
+                    functions.insert(record.name().clone(), record);
+                }
+                Err(e) => warn!("Skipping the function in {:?}: {}", dir, e),
+            }
+        }
+        Ok(functions)
+    }
+
+    ///
+    /// Writes a function's files and commits them.
+    ///
+    pub fn save(&self, record: &UserFunctionRecord) -> Result<()> {
+        let _lock = self.lock.lock().map_err(|_| poisoned())?;
+        let dir_name = dir_name(record.name());
+        let dir = self.path.join(&dir_name);
+        // the code file's name depends on the language, so nothing is kept
+        if dir.exists() {
+            std::fs::remove_dir_all(&dir)?;
+        }
+        std::fs::create_dir_all(&dir)?;
+        let code_file = format!("code.{}", extension(record.language()));
+        std::fs::write(dir.join(&code_file), &record.code().code)?;
+        let mut manifest = serde_json::to_value(record.declaration())?;
+        if let Some(manifest) = manifest.as_object_mut() {
+            manifest.remove("code");
+            manifest.insert("code_file".to_owned(), code_file.into());
+            manifest.insert(
+                "environment_id".to_owned(),
+                record.environment_id.to_string().into(),
+            );
+        }
+        std::fs::write(
+            dir.join(MANIFEST_FILE),
+            serde_json::to_vec_pretty(&manifest)?,
+        )?;
+        self.commit(&dir_name, &format!("Save function '{}'", record.name()))
+    }
+
+    ///
+    /// Removes a function's files and commits that.
+    ///
+    pub fn delete(&self, name: &str) -> Result<()> {
+        let _lock = self.lock.lock().map_err(|_| poisoned())?;
+        let dir_name = dir_name(name);
+        let dir = self.path.join(&dir_name);
+        if !dir.exists() {
+            return Ok(());
+        }
+        std::fs::remove_dir_all(&dir)?;
+        self.commit(&dir_name, &format!("Delete function '{}'", name))
+    }
+
+    fn commit(&self, dir_name: &str, message: &str) -> Result<()> {
+        self.git(&["add", "--all", "--", dir_name])?;
+        // saving a function without changes doesn't need a commit
+        if self.git(&["diff", "--cached", "--quiet"]).is_ok() {
+            return Ok(());
+        }
+        self.git(&["commit", "--quiet", "-m", message]).map(|_| ())
+    }
+
+    ///
+    /// Merges the remote's current branch and returns whether that changed anything. Merges with conflicts are
+    /// aborted, so the repository stays as it was.
+    ///
+    pub fn pull(&self) -> Result<bool> {
+        let remote = self
+            .remote
+            .as_deref()
+            .ok_or_else(|| anyhow::Error::msg("There is no remote to pull functions from"))?;
+        let _lock = self.lock.lock().map_err(|_| poisoned())?;
+        let before = self.head();
+        info!("Pulling functions from '{}'", remote);
+        let pulled = self.git(&[
+            "pull",
+            "--quiet",
+            "--no-rebase",
+            "--no-edit",
+            "--allow-unrelated-histories",
+            remote,
+        ]);
+        if let Err(e) = pulled {
+            let _ = self.git(&["merge", "--abort"]);
+            return Err(e);
+        }
+        Ok(self.head() != before)
+    }
+
+    fn head(&self) -> Option<String> {
+        self.git(&["rev-parse", "--verify", "--quiet", "HEAD"]).ok()
+    }
+}
+
+fn poisoned() -> anyhow::Error {
+    anyhow::Error::msg("A previous change to the repository failed")
+}
+
+fn read_function(dir: &Path) -> Result<UserFunctionRecord> {
+    let mut manifest: serde_json::Map<String, serde_json::Value> =
+        serde_json::from_slice(&read_regular_file(&dir.join(MANIFEST_FILE))?)?;
+    let code_file = manifest
+        .remove("code_file")
+        .and_then(|f| f.as_str().map(PathBuf::from))
+        .ok_or_else(|| anyhow::Error::msg("The manifest has no 'code_file'"))?;
+    // the code has to be next to the manifest
+    let mut components = code_file.components();
+    match (components.next(), components.next()) {
+        (Some(Component::Normal(_)), None) => {}
+        _ => {
+            return Err(anyhow::Error::msg(format!(
+                "Invalid code file {:?}",
+                code_file
+            )))
+        }
+    }
+    let environment_id = match manifest.remove("environment_id") {
+        Some(id) => serde_json::from_value(id)?,
+        None => Uuid::new_v4(),
+    };
+    let code = String::from_utf8(read_regular_file(&dir.join(&code_file))?)?;
+    manifest.insert("code".to_owned(), code.into());
+    let declaration: UserFunctionDeclaration =
+        serde_json::from_value(serde_json::Value::Object(manifest))?;
+    Ok(UserFunctionRecord::new(declaration, environment_id))
+}
+
+///
+/// Reads a file that has to be a regular file, not a symlink (e.g. to a file outside of the repository) or a device.
+///
+fn read_regular_file(path: &Path) -> Result<Vec<u8>> {
+    if !std::fs::symlink_metadata(path)?.file_type().is_file() {
+        return Err(anyhow::Error::msg(format!(
+            "{:?} isn't a regular file",
+            path
+        )));
+    }
+    std::fs::read(path).map_err(anyhow::Error::from)
+}
+
+///
+/// The directory of a function: its name with everything but letters, digits, `-`, and `_` percent-encoded.
+///
+fn dir_name(name: &str) -> String {
+    name.bytes()
+        .map(|b| {
+            if b.is_ascii_alphanumeric() || b == b'-' || b == b'_' {
+                (b as char).to_string()
+            } else {
+                format!("%{:02X}", b)
+            }
+        })
+        .collect()
+}
+
+fn extension(language: &ProgrammingLanguage) -> &'static str {
+    match language {
+        ProgrammingLanguage::JavaScript | ProgrammingLanguage::NodeJs => "js",
+        ProgrammingLanguage::Python => "py",
+        ProgrammingLanguage::Bash => "sh",
+        _ => "txt",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runtime::FunctionCode;
+    use crate::triggers::http::HttpMethod;
+    use crate::triggers::Trigger;
+
+    use minifaas_test::get_empty_tmp_dir;
+
+    fn record(name: &str, code: &str) -> UserFunctionRecord {
+        UserFunctionRecord::from(UserFunctionDeclaration {
+            name: name.to_owned(),
+            code: FunctionCode::new(code.to_owned(), ProgrammingLanguage::Python),
+            trigger: Trigger::Http(HttpMethod::GET),
+            ..Default::default()
+        })
+    }
+
+    fn commits(repo: &GitRepository) -> usize {
+        repo.git(&["rev-list", "--count", "HEAD"])
+            .unwrap()
+            .trim()
+            .parse()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_git_repository_commits_changes() {
+        let p = get_empty_tmp_dir();
+        let repo = GitRepository::open(&p, None).unwrap();
+        let hello = record("hello/world", "print('hello')");
+        repo.save(&hello).unwrap();
+        repo.save(&hello).unwrap();
+        repo.save(&record("another", "print('bye')")).unwrap();
+        assert_eq!(commits(&repo), 2);
+        assert_eq!(
+            std::fs::read_to_string(p.join("hello%2Fworld").join("code.py")).unwrap(),
+            "print('hello')"
+        );
+
+        let functions = GitRepository::open(&p, None).unwrap().load().unwrap();
+        assert_eq!(functions.len(), 2);
+        let loaded = &functions["hello/world"];
+        assert_eq!(loaded.code().code, "print('hello')");
+        assert_eq!(loaded.environment_id, hello.environment_id);
+        assert_eq!(loaded.trigger(), hello.trigger());
+
+        repo.delete("hello/world").unwrap();
+        repo.delete("doesn't exist").unwrap();
+        assert_eq!(commits(&repo), 3);
+        assert_eq!(repo.load().unwrap().len(), 1);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_git_repository_skips_symlinked_files() {
+        let p = get_empty_tmp_dir();
+        let repo = GitRepository::open(&p, None).unwrap();
+        repo.save(&record("hello", "print('hello')")).unwrap();
+        let outside = get_empty_tmp_dir().join("secret.txt");
+        std::fs::write(&outside, "not for functions").unwrap();
+
+        let code = p.join("hello").join("code.py");
+        std::fs::remove_file(&code).unwrap();
+        std::os::unix::fs::symlink(&outside, &code).unwrap();
+        assert!(repo.load().unwrap().is_empty());
+
+        // the same goes for the function's directory
+        repo.save(&record("linked", "print('linked')")).unwrap();
+        std::fs::rename(p.join("linked"), outside.with_file_name("linked")).unwrap();
+        std::os::unix::fs::symlink(outside.with_file_name("linked"), p.join("linked")).unwrap();
+        assert!(repo.load().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_git_repository_pulls_functions() {
+        let root = get_empty_tmp_dir();
+        let remote = GitRepository::open(root.join("remote"), None).unwrap();
+        remote.save(&record("hello", "print('hello')")).unwrap();
+
+        let remote_path = root.join("remote").to_string_lossy().into_owned();
+        let repo = GitRepository::open(root.join("local"), Some(remote_path)).unwrap();
+        repo.save(&record("local", "print('local')")).unwrap();
+        assert!(repo.pull().unwrap());
+        assert!(!repo.pull().unwrap());
+        assert_eq!(repo.load().unwrap().len(), 2);
+
+        // conflicting changes are left alone
+        remote.save(&record("local", "print('remote')")).unwrap();
+        assert!(repo.pull().is_err());
+        assert_eq!(repo.load().unwrap()["local"].code().code, "print('local')");
+
+        assert!(GitRepository::open(root.join("no-remote"), None)
+            .unwrap()
+            .pull()
+            .is_err());
+    }
+}
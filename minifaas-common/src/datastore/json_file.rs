@@ -1,29 +1,47 @@
 pub use crate::types::*;
 use anyhow::Result;
-use async_std::fs::File;
 use async_std::io::prelude::*;
-use async_std::sync::{Arc, RwLock};
+use async_std::sync::{Arc, Mutex, RwLock};
 use async_std::task;
-use log::{error, info};
+use log::{error, info, warn};
 use std::boxed::Box;
 use std::collections::HashMap;
 use std::fs::OpenOptions;
 use std::io::BufReader;
 use std::path::PathBuf;
 
+use super::git::GitRepository;
 use super::record::UserFunctionRecord;
+use super::revisions::RevisionStore;
+use super::{revisions_path, write_atomically, FunctionStore};
 
 pub type UserFunctionType = Arc<Box<UserFunctionRecord>>;
 type InnerStorageType = HashMap<String, UserFunctionType>;
 
 ///
-/// A key-value store for the user-defined functions. Uses an RwLock for multi-threaded reads/writes. Can serialize itself to disk.
+/// The functions a pull from the Git remote changed: saved ones with their previous version (if any) and deleted ones.
+///
+#[derive(Debug, Default)]
+pub struct PulledChanges {
+    pub saved: Vec<(Option<UserFunctionType>, UserFunctionType)>,
+    pub deleted: Vec<UserFunctionType>,
+}
+
+///
+/// A key-value store for the user-defined functions. Uses an RwLock for multi-threaded reads/writes. Can serialize itself to disk,
+/// where the JSON file is replaced atomically so a crash can't leave a partially written store behind.
+/// The functions' revisions are kept next to it (e.g. `functions.revisions.db` for `functions.db`).
+/// With a Git repository (see `from_git`), changes are committed there instead.
 ///
 #[derive(Debug)]
 pub struct JsonFaaSDataStore {
     store: RwLock<InnerStorageType>,
+    /// Held while the store is written to disk, so a write never overtakes a newer one
+    writing: Mutex<()>,
     path: PathBuf,
     serialize_on_write: bool,
+    revisions: RevisionStore,
+    git: Option<Arc<GitRepository>>,
 }
 
 impl JsonFaaSDataStore {
@@ -36,21 +54,122 @@ impl JsonFaaSDataStore {
         path: P,
         serialize_on_write: bool,
     ) -> Self {
+        let path = path.into();
+        let revisions = RevisionStore::new(revisions_path(&path), serialize_on_write);
         JsonFaaSDataStore {
             store: RwLock::new(map),
-            path: path.into(),
+            writing: Mutex::new(()),
+            path,
             serialize_on_write,
+            revisions,
+            git: None,
         }
     }
 
+    ///
+    /// Serializes the store using the provided (async_std) writer
+    ///
+    pub async fn serialize(&self, mut writer: impl Write + Unpin) -> Result<()> {
+        let db = (self.store.read().await).clone();
+        println!("{}", serde_json::to_string_pretty(&db)?);
+        let buf = task::spawn_blocking(move || {
+            serde_json::to_string(&db)
+                .expect("Couldn't serialize data store")
+                .into_bytes()
+        })
+        .await;
+        writer.write_all(&buf).await.map_err(|e| e.into())
+    }
+
+    ///
+    /// Loads a store from the provided path.
+    ///
+    pub async fn from_path<P: Into<PathBuf>>(path: P) -> Result<Self> {
+        let p = path.into();
+        info!(
+            "Reading functions from store at '{}'",
+            p.to_str().unwrap_or_default()
+        );
+
+        let _p = p.clone();
+        let store: InnerStorageType = task::spawn_blocking(move || {
+            let file = OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create(true)
+                .open(&_p)
+                .expect("Could not open/create data store");
+            let buf_reader = BufReader::new(file);
+            serde_json::from_reader(buf_reader).unwrap_or_default()
+        })
+        .await;
+        let revisions = RevisionStore::from_path(revisions_path(&p)).await?;
+        Ok(JsonFaaSDataStore {
+            revisions,
+            ..JsonFaaSDataStore::with(store, p, true)
+        })
+    }
+
+    ///
+    /// Loads the functions from a Git repository at `path` (created if necessary) and commits every change to it.
+    /// With a `remote` (a path or URL), its functions are pulled first. Revisions are kept in `<path>.revisions.db`.
+    ///
+    pub async fn from_git<P: Into<PathBuf>>(path: P, remote: Option<String>) -> Result<Self> {
+        let p = path.into();
+        info!(
+            "Reading functions from Git repository at '{}'",
+            p.to_str().unwrap_or_default()
+        );
+        let _p = p.clone();
+        let git = task::spawn_blocking(move || GitRepository::open(_p, remote)).await?;
+        let git = Arc::new(git);
+        let _git = git.clone();
+        let functions = task::spawn_blocking(move || {
+            if _git.remote().is_some() {
+                if let Err(e) = _git.pull() {
+                    warn!("Couldn't pull functions: {}", e);
+                }
+            }
+            _git.load()
+        })
+        .await?;
+        let store = functions
+            .into_iter()
+            .map(|(name, f)| (name, Arc::new(Box::new(f))))
+            .collect();
+        let revisions = RevisionStore::from_path(revisions_path(&p)).await?;
+        Ok(JsonFaaSDataStore {
+            revisions,
+            git: Some(git),
+            ..JsonFaaSDataStore::with(store, p, true)
+        })
+    }
+}
+
+#[async_trait::async_trait]
+impl FunctionStore for JsonFaaSDataStore {
+    ///
+    /// The history of the stored functions.
+    ///
+    fn revisions(&self) -> &RevisionStore {
+        &self.revisions
+    }
+
     ///
     /// Insert an entry.
     ///
-    pub async fn set(&self, key: String, value: UserFunctionRecord) {
+    async fn set(&self, key: String, value: UserFunctionRecord) {
         let val = Arc::new(Box::new(value));
-        self.store.write().await.insert(key, val);
+        self.store.write().await.insert(key, val.clone());
         if self.serialize_on_write {
-            match self.write_to_disk().await {
+            let written = match &self.git {
+                Some(git) => {
+                    let git = git.clone();
+                    task::spawn_blocking(move || git.save(&val)).await
+                }
+                None => self.write_to_disk().await,
+            };
+            match written {
                 Ok(_) => {}
                 Err(e) => error!("Couldn't serialze to disk: {}", e),
             }
@@ -60,10 +179,17 @@ impl JsonFaaSDataStore {
     ///
     /// Removes an entry without returning the result.
     ///
-    pub async fn delete(&self, key: &str) {
+    async fn delete(&self, key: &str) {
         let _old = self.store.write().await.remove(key);
         if self.serialize_on_write {
-            match self.write_to_disk().await {
+            let written = match &self.git {
+                Some(git) => {
+                    let (git, key) = (git.clone(), key.to_owned());
+                    task::spawn_blocking(move || git.delete(&key)).await
+                }
+                None => self.write_to_disk().await,
+            };
+            match written {
                 Ok(_) => {}
                 Err(e) => error!("Couldn't serialze to disk: {}", e),
             }
@@ -73,28 +199,28 @@ impl JsonFaaSDataStore {
     ///
     /// Return a record based on the key.
     ///
-    pub async fn get(&self, key: &str) -> Option<UserFunctionType> {
+    async fn get(&self, key: &str) -> Option<UserFunctionType> {
         self.store.read().await.get(key).cloned()
     }
 
     ///
     /// Creates a list of all stored records
     ///
-    pub async fn values(&self) -> Vec<UserFunctionType> {
+    async fn values(&self) -> Vec<UserFunctionType> {
         self.store.read().await.values().cloned().collect()
     }
 
     ///
     /// Creates a list of all keys.
     ///
-    pub async fn keys(&self) -> Vec<String> {
+    async fn keys(&self) -> Vec<String> {
         self.store.read().await.keys().cloned().collect()
     }
 
     ///
     /// Creates a list of tuples (key, record)
     ///
-    pub async fn items(&self) -> Vec<(String, UserFunctionType)> {
+    async fn items(&self) -> Vec<(String, UserFunctionType)> {
         self.store
             .read()
             .await
@@ -106,70 +232,92 @@ impl JsonFaaSDataStore {
     ///
     /// The number of items in the store
     ///
-    pub async fn len(&self) -> usize {
+    async fn len(&self) -> usize {
         self.store.read().await.len()
     }
 
     ///
     /// Shortcut to whether the store is empty.
     ///
-    pub async fn is_empty(&self) -> bool {
+    async fn is_empty(&self) -> bool {
         self.store.read().await.is_empty()
     }
 
     ///
     /// Shortcut to whether the store is empty.
     ///
-    pub async fn contains_key(&self, key: &str) -> bool {
+    async fn contains_key(&self, key: &str) -> bool {
         self.store.read().await.contains_key(key)
     }
 
     ///
-    /// Serializes the store using the provided (async_std) writer
+    /// Writes the store to disk at the (initially) provided location, or saves every function to the Git repository.
     ///
-    pub async fn serialize(&self, mut writer: impl Write + Unpin) -> Result<()> {
-        let db = (self.store.read().await).clone();
-        println!("{}", serde_json::to_string_pretty(&db)?);
-        let buf = task::spawn_blocking(move || {
-            serde_json::to_string(&db)
-                .expect("Couldn't serialize data store")
-                .into_bytes()
-        })
-        .await;
-        writer.write_all(&buf).await.map_err(|e| e.into())
-    }
-
-    ///
-    /// Writes the store to disk at the (initially) provided location
-    ///
-    pub async fn write_to_disk(&self) -> Result<()> {
-        let mut file = File::create(&self.path).await?;
-        self.serialize(&mut file).await
+    async fn write_to_disk(&self) -> Result<()> {
+        let _writing = self.writing.lock().await;
+        let db = self.store.read().await.clone();
+        match &self.git {
+            Some(git) => {
+                let git = git.clone();
+                task::spawn_blocking(move || db.values().try_for_each(|f| git.save(f))).await
+            }
+            None => {
+                let path = self.path.clone();
+                task::spawn_blocking(move || -> Result<()> {
+                    let buf = serde_json::to_vec(&db)?;
+                    Ok(write_atomically(&path, &buf)?)
+                })
+                .await
+            }
+        }
     }
 
     ///
-    /// Loads a store from the provided path.
+    /// Pulls the functions from the Git repository's remote. Functions that were stored before keep their environment.
     ///
-    pub async fn from_path<P: Into<PathBuf>>(path: P) -> Result<Self> {
-        let p = path.into();
-        info!(
-            "Reading functions from store at '{}'",
-            p.to_str().unwrap_or_default()
-        );
-
-        let _p = p.clone();
-        let store: InnerStorageType = task::spawn_blocking(move || {
-            let file = OpenOptions::new()
-                .read(true)
-                .write(true)
-                .create(true)
-                .open(&_p)
-                .expect("Could not open/create data store");
-            let buf_reader = BufReader::new(file);
-            serde_json::from_reader(buf_reader).unwrap_or_default()
+    async fn pull(&self) -> Result<PulledChanges> {
+        let git = self
+            .git
+            .clone()
+            .ok_or_else(|| anyhow::Error::msg("Functions aren't stored in a Git repository"))?;
+        let pulled = task::spawn_blocking(move || -> Result<_> {
+            git.pull()?;
+            git.load()
         })
-        .await;
-        Ok(JsonFaaSDataStore::with(store, p, true))
+        .await?;
+
+        let mut store = self.store.write().await;
+        let mut changes = PulledChanges::default();
+        let deleted: Vec<String> = store
+            .keys()
+            .filter(|name| !pulled.contains_key(*name))
+            .cloned()
+            .collect();
+        for name in deleted {
+            if let Some(old) = store.remove(&name) {
+                changes.deleted.push(old);
+            }
+        }
+        for (name, record) in pulled {
+            let old = store.get(&name).cloned();
+            let record = match &old {
+                Some(old) => {
+                    UserFunctionRecord::new(record.declaration().clone(), old.environment_id)
+                }
+                None => record,
+            };
+            if let Some(old) = &old {
+                if serde_json::to_value(old.declaration())?
+                    == serde_json::to_value(record.declaration())?
+                {
+                    continue;
+                }
+            }
+            let record = Arc::new(Box::new(record));
+            store.insert(name, record.clone());
+            changes.saved.push((old, record));
+        }
+        Ok(changes)
     }
 }
 
@@ -223,4 +371,49 @@ mod tests {
             .unwrap();
         assert!(JsonFaaSDataStore::from_path(p.join(f_name)).await.is_err());
     }
+
+    #[async_std::test]
+    async fn test_from_git_pulls_changes() {
+        use crate::runtime::FunctionCode;
+
+        let function = |name: &str, code: &str| {
+            UserFunctionRecord::from(UserFunctionDeclaration {
+                name: name.to_owned(),
+                code: FunctionCode::new(code.to_owned(), ProgrammingLanguage::Bash),
+                ..Default::default()
+            })
+        };
+        let p = get_empty_tmp_dir();
+        let remote = GitRepository::open(p.join("remote"), None).unwrap();
+        remote.save(&function("hello", "echo hello")).unwrap();
+
+        let remote_path = p.join("remote").to_string_lossy().into_owned();
+        let store = JsonFaaSDataStore::from_git(p.join("local"), Some(remote_path))
+            .await
+            .unwrap();
+        let hello = store.get("hello").await.unwrap();
+        assert!(store.pull().await.unwrap().saved.is_empty());
+
+        remote.save(&function("hello", "echo hi")).unwrap();
+        remote.save(&function("bye", "echo bye")).unwrap();
+        let changes = store.pull().await.unwrap();
+        assert_eq!(changes.saved.len(), 2);
+        let updated = store.get("hello").await.unwrap();
+        assert_eq!(updated.code().code, "echo hi");
+        assert_eq!(updated.environment_id, hello.environment_id);
+
+        remote.delete("bye").unwrap();
+        let changes = store.pull().await.unwrap();
+        assert_eq!(changes.deleted.len(), 1);
+        assert!(store.get("bye").await.is_none());
+
+        store
+            .set("local".to_owned(), function("local", "echo local"))
+            .await;
+        let reloaded = JsonFaaSDataStore::from_git(p.join("local"), None)
+            .await
+            .unwrap();
+        assert_eq!(reloaded.len().await, 2);
+        assert!(reloaded.pull().await.is_err());
+    }
 }
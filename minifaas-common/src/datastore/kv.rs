@@ -0,0 +1,177 @@
+pub use crate::types::*;
+use anyhow::Result;
+use async_std::sync::{Arc, RwLock};
+use async_std::task;
+use log::{error, info};
+use std::boxed::Box;
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use super::record::UserFunctionRecord;
+use super::revisions::RevisionStore;
+use super::{revisions_path, FunctionStore, UserFunctionType};
+
+const FUNCTIONS_TREE: &str = "functions";
+
+type InnerStorageType = HashMap<String, UserFunctionType>;
+
+///
+/// An embedded key-value database (sled) for the user-defined functions. Every change is a single atomic write that is
+/// flushed to disk before returning, reads are served from memory. Revisions are kept next to the database's
+/// directory (e.g. `functions.revisions.db` for `functions.kv`).
+///
+#[derive(Debug)]
+pub struct KvFaaSDataStore {
+    store: RwLock<InnerStorageType>,
+    functions: sled::Tree,
+    revisions: RevisionStore,
+}
+
+impl KvFaaSDataStore {
+    ///
+    /// Opens (or creates) the database in the directory at `path`.
+    ///
+    pub async fn from_path<P: Into<PathBuf>>(path: P) -> Result<Self> {
+        let p = path.into();
+        info!(
+            "Reading functions from embedded store at '{}'",
+            p.to_str().unwrap_or_default()
+        );
+        let _p = p.clone();
+        let (functions, store) = task::spawn_blocking(move || -> Result<_> {
+            let functions = sled::open(&_p)?.open_tree(FUNCTIONS_TREE)?;
+            let mut store = HashMap::new();
+            for entry in functions.iter() {
+                let (key, value) = entry?;
+                let record: UserFunctionRecord = serde_json::from_slice(&value)?;
+                store.insert(String::from_utf8(key.to_vec())?, Arc::new(Box::new(record)));
+            }
+            Ok((functions, store))
+        })
+        .await?;
+        Ok(KvFaaSDataStore {
+            store: RwLock::new(store),
+            functions,
+            revisions: RevisionStore::from_path(revisions_path(&p)).await?,
+        })
+    }
+
+    async fn write(&self, key: &str, value: Option<&UserFunctionRecord>) -> Result<()> {
+        match value {
+            Some(value) => {
+                self.functions.insert(key, serde_json::to_vec(value)?)?;
+            }
+            None => {
+                self.functions.remove(key)?;
+            }
+        }
+        self.functions.flush_async().await?;
+        Ok(())
+    }
+}
+
+#[async_trait::async_trait]
+impl FunctionStore for KvFaaSDataStore {
+    ///
+    /// The history of the stored functions.
+    ///
+    fn revisions(&self) -> &RevisionStore {
+        &self.revisions
+    }
+
+    ///
+    /// Insert an entry.
+    ///
+    async fn set(&self, key: String, value: UserFunctionRecord) {
+        if let Err(e) = self.write(&key, Some(&value)).await {
+            error!("Couldn't write '{}' to the embedded store: {}", key, e);
+        }
+        self.store
+            .write()
+            .await
+            .insert(key, Arc::new(Box::new(value)));
+    }
+
+    ///
+    /// Removes an entry without returning the result.
+    ///
+    async fn delete(&self, key: &str) {
+        if let Err(e) = self.write(key, None).await {
+            error!("Couldn't remove '{}' from the embedded store: {}", key, e);
+        }
+        self.store.write().await.remove(key);
+    }
+
+    ///
+    /// Return a record based on the key.
+    ///
+    async fn get(&self, key: &str) -> Option<UserFunctionType> {
+        self.store.read().await.get(key).cloned()
+    }
+
+    ///
+    /// Creates a list of tuples (key, record)
+    ///
+    async fn items(&self) -> Vec<(String, UserFunctionType)> {
+        self.store
+            .read()
+            .await
+            .iter()
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect()
+    }
+
+    ///
+    /// Replaces the database's content with the functions in memory in a single batch.
+    ///
+    async fn write_to_disk(&self) -> Result<()> {
+        let mut batch = sled::Batch::default();
+        {
+            let store = self.store.read().await;
+            for key in self.functions.iter().keys() {
+                let key = key?;
+                if !store.contains_key(String::from_utf8_lossy(&key).as_ref()) {
+                    batch.remove(key);
+                }
+            }
+            for (key, value) in store.iter() {
+                batch.insert(key.as_str(), serde_json::to_vec(value)?);
+            }
+        }
+        self.functions.apply_batch(batch)?;
+        self.functions.flush_async().await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runtime::FunctionCode;
+
+    use minifaas_test::get_empty_tmp_dir;
+
+    fn function(name: &str) -> UserFunctionRecord {
+        UserFunctionRecord::from(UserFunctionDeclaration {
+            name: name.to_owned(),
+            code: FunctionCode::new("echo hello".to_owned(), ProgrammingLanguage::Bash),
+            ..Default::default()
+        })
+    }
+
+    #[async_std::test]
+    async fn test_kv_store_persists_changes() {
+        let p = get_empty_tmp_dir().join("functions.kv");
+        {
+            let store = KvFaaSDataStore::from_path(&p).await.unwrap();
+            store.set("hello".to_owned(), function("hello")).await;
+            store.set("bye".to_owned(), function("bye")).await;
+            store.delete("bye").await;
+            assert_eq!(store.len().await, 1);
+        }
+        let store = KvFaaSDataStore::from_path(&p).await.unwrap();
+        assert_eq!(store.keys().await, vec!["hello".to_owned()]);
+        assert_eq!(store.get("hello").await.unwrap().code().code, "echo hello");
+        assert!(store.pull().await.is_err());
+    }
+}
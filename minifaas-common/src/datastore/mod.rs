@@ -1,16 +1,180 @@
 mod config;
+mod git;
 mod json_file;
+mod kv;
 mod record;
+mod revisions;
 
 pub use crate::types::*;
+use anyhow::Result;
 use async_std::sync::Arc;
 pub use record::UserFunctionRecord;
 use std::boxed::Box;
 use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use uuid::Uuid;
 pub type UserFunctionType = Arc<Box<UserFunctionRecord>>;
 
-pub use config::DataStoreConfig;
-pub use json_file::JsonFaaSDataStore as FaaSDataStore;
+pub use config::{DataStoreConfig, StorageBackend};
+pub use git::GitRepository;
+pub use json_file::{JsonFaaSDataStore, PulledChanges};
+pub use kv::KvFaaSDataStore;
+pub use revisions::{
+    changed_settings, diff_lines, DiffLine, Revision, RevisionDiff, RevisionInfo, RevisionStore,
+};
+
+///
+/// The datastore the runtime and the API work with, the backend is picked by `DataStoreConfig`.
+///
+pub type FaaSDataStore = dyn FunctionStore;
+
+///
+/// A store for the user-defined functions by name, along with their revisions.
+///
+#[async_trait::async_trait]
+pub trait FunctionStore: Send + Sync + std::fmt::Debug {
+    ///
+    /// Insert an entry.
+    ///
+    async fn set(&self, key: String, value: UserFunctionRecord);
+
+    ///
+    /// Removes an entry without returning the result.
+    ///
+    async fn delete(&self, key: &str);
+
+    ///
+    /// Return a record based on the key.
+    ///
+    async fn get(&self, key: &str) -> Option<UserFunctionType>;
+
+    ///
+    /// Creates a list of tuples (key, record)
+    ///
+    async fn items(&self) -> Vec<(String, UserFunctionType)>;
+
+    ///
+    /// Writes all functions to disk, e.g. after a migration.
+    ///
+    async fn write_to_disk(&self) -> Result<()>;
+
+    ///
+    /// The history of the stored functions.
+    ///
+    fn revisions(&self) -> &RevisionStore;
+
+    ///
+    /// Pulls the functions from a remote and returns what changed. Only supported by stores with a remote.
+    ///
+    async fn pull(&self) -> Result<PulledChanges> {
+        Err(anyhow::Error::msg(
+            "Functions aren't stored in a Git repository",
+        ))
+    }
+
+    ///
+    /// Creates a list of all stored records
+    ///
+    async fn values(&self) -> Vec<UserFunctionType> {
+        self.items().await.into_iter().map(|(_, v)| v).collect()
+    }
+
+    ///
+    /// Creates a list of all keys.
+    ///
+    async fn keys(&self) -> Vec<String> {
+        self.items().await.into_iter().map(|(k, _)| k).collect()
+    }
+
+    ///
+    /// The number of items in the store
+    ///
+    async fn len(&self) -> usize {
+        self.items().await.len()
+    }
+
+    ///
+    /// Shortcut to whether the store is empty.
+    ///
+    async fn is_empty(&self) -> bool {
+        self.len().await == 0
+    }
+
+    ///
+    /// Shortcut to whether the store contains a key.
+    ///
+    async fn contains_key(&self, key: &str) -> bool {
+        self.get(key).await.is_some()
+    }
+}
+
+///
+/// Where a store at `path` keeps its revisions, e.g. `functions.revisions.db` for `functions.db`.
+///
+pub(crate) fn revisions_path(path: &Path) -> PathBuf {
+    path.with_extension("revisions.db")
+}
+
+///
+/// Replaces the file at `path` with `buf` without ever leaving a partially written file: the content goes to a
+/// temporary file next to it, which is synced and then renamed. Also syncs the directory to persist the rename.
+/// Every call has its own temporary file, callers serialize their writes so an older snapshot can't win the rename.
+///
+pub(crate) fn write_atomically(path: &Path, buf: &[u8]) -> std::io::Result<()> {
+    write_file_atomically(path, buf, false)
+}
+
+///
+/// Like `write_atomically`, but the file is only readable by its owner from the start (Unix).
+///
+pub(crate) fn write_privately(path: &Path, buf: &[u8]) -> std::io::Result<()> {
+    write_file_atomically(path, buf, true)
+}
+
+fn write_file_atomically(path: &Path, buf: &[u8], private: bool) -> std::io::Result<()> {
+    let file_name = path
+        .file_name()
+        .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidInput, "not a file"))?;
+    let mut tmp_name = std::ffi::OsString::from(".");
+    tmp_name.push(file_name);
+    tmp_name.push(format!(".{}.tmp", Uuid::new_v4()));
+    let tmp_path = path.with_file_name(tmp_name);
+
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        if private {
+            options.mode(0o600);
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = private;
+    let written = options
+        .open(&tmp_path)
+        .and_then(|mut tmp| {
+            tmp.write_all(buf)?;
+            tmp.sync_all()
+        })
+        .and_then(|_| std::fs::rename(&tmp_path, path));
+    if let Err(e) = written {
+        let _ = std::fs::remove_file(&tmp_path);
+        return Err(e);
+    }
+    // the rename is only durable once the directory is synced
+    #[cfg(unix)]
+    {
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        File::open(dir)?.sync_all()?;
+    }
+    Ok(())
+}
 
 #[cfg(test)]
 mod tests {
@@ -39,7 +203,7 @@ mod tests {
     #[async_std::test]
     async fn test_from_path_missing_paths_create_new() {
         let p = get_empty_tmp_dir();
-        let store = FaaSDataStore::from_path(p.join("doesntexist")).await;
+        let store = JsonFaaSDataStore::from_path(p.join("doesntexist")).await;
         assert!(store.is_ok());
         assert_eq!(store.unwrap().len().await, 0);
     }
@@ -48,7 +212,7 @@ mod tests {
     #[should_panic]
     async fn test_from_path_invalid_paths_panic() {
         let p = get_empty_tmp_dir();
-        let _ = FaaSDataStore::from_path(p.join("hello").join("world")).await;
+        let _ = JsonFaaSDataStore::from_path(p.join("hello").join("world")).await;
         let _ = std::fs::remove_dir_all(p);
     }
 
@@ -60,6 +224,32 @@ mod tests {
         async_std::fs::write(p.join(f_name), b"invalidcontent")
             .await
             .unwrap();
-        assert!(FaaSDataStore::from_path(p.join(f_name)).await.is_err());
+        assert!(JsonFaaSDataStore::from_path(p.join(f_name)).await.is_err());
+    }
+
+    #[test]
+    fn test_write_atomically_replaces_files() {
+        let p = get_empty_tmp_dir().join("a-file.db");
+        write_atomically(&p, b"first").unwrap();
+        write_atomically(&p, b"second").unwrap();
+        assert_eq!(std::fs::read(&p).unwrap(), b"second");
+        assert_eq!(std::fs::read_dir(p.parent().unwrap()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn test_write_atomically_concurrently() {
+        let p = get_empty_tmp_dir().join("a-file.db");
+        let writers: Vec<_> = (0..8)
+            .map(|i| {
+                let p = p.clone();
+                std::thread::spawn(move || write_atomically(&p, format!("{}", i).as_bytes()))
+            })
+            .collect();
+        for writer in writers {
+            writer.join().unwrap().unwrap();
+        }
+        let content = String::from_utf8(std::fs::read(&p).unwrap()).unwrap();
+        assert!(content.parse::<usize>().unwrap() < 8);
+        assert_eq!(std::fs::read_dir(p.parent().unwrap()).unwrap().count(), 1);
     }
 }
@@ -1,5 +1,8 @@
 pub use crate::types::*;
-use crate::{runtime::FunctionCode, triggers::Trigger};
+use crate::{
+    limits::ResourceLimits, permissions::Permissions, runtime::FunctionCode, secrets::FunctionEnv,
+    triggers::Trigger,
+};
 use serde::{Deserialize, Serialize};
 use std::fmt;
 
@@ -21,10 +24,24 @@ impl UserFunctionRecord {
         }
     }
 
+    ///
+    /// The function as the user declared it.
+    ///
+    pub fn declaration(&self) -> &UserFunctionDeclaration {
+        &self.func
+    }
+
     pub fn language(&self) -> &ProgrammingLanguage {
         &self.func.code.language
     }
 
+    ///
+    /// The toolchain version the function is pinned to, if any.
+    ///
+    pub fn version(&self) -> Option<&str> {
+        self.func.code.version.as_deref()
+    }
+
     pub fn code(&self) -> &FunctionCode {
         &self.func.code
     }
@@ -37,6 +54,60 @@ impl UserFunctionRecord {
         &self.func.trigger
     }
 
+    ///
+    /// The route the function's HTTP trigger is reachable on.
+    ///
+    pub fn route(&self) -> String {
+        self.func
+            .route
+            .clone()
+            .unwrap_or_else(|| self.func.name.clone())
+    }
+
+    pub fn timeout_secs(&self) -> Option<u64> {
+        self.func.timeout_secs
+    }
+
+    pub fn min_instances(&self) -> Option<usize> {
+        self.func.min_instances
+    }
+
+    pub fn max_instances(&self) -> Option<usize> {
+        self.func.max_instances
+    }
+
+    pub fn permissions(&self) -> &Permissions {
+        &self.func.permissions
+    }
+
+    ///
+    /// The function's own resource limits, the runtime's limits still apply.
+    ///
+    pub fn limits(&self) -> &ResourceLimits {
+        &self.func.limits
+    }
+
+    ///
+    /// Whether the function opted out of the sandbox.
+    ///
+    pub fn trusted(&self) -> bool {
+        self.func.trusted
+    }
+
+    ///
+    /// Whether the function wants to run on a warm worker.
+    ///
+    pub fn warm(&self) -> bool {
+        self.func.warm
+    }
+
+    ///
+    /// The function's environment variables, secrets are only referenced by name.
+    ///
+    pub fn env(&self) -> &FunctionEnv {
+        &self.func.env
+    }
+
     pub fn update_function(
         &mut self,
         new_func: UserFunctionDeclaration,
@@ -69,4 +140,39 @@ mod tests {
     use super::*;
 
     use minifaas_test::get_empty_tmp_dir;
+
+    #[test]
+    fn test_record_route_defaults_to_name() {
+        let mut declaration = UserFunctionDeclaration {
+            name: "a-name".to_owned(),
+            ..Default::default()
+        };
+        assert_eq!(
+            UserFunctionRecord::from(declaration.clone()).route(),
+            "a-name"
+        );
+
+        declaration.route = Some("a-route".to_owned());
+        assert_eq!(UserFunctionRecord::from(declaration).route(), "a-route");
+    }
+
+    #[test]
+    fn test_record_version_is_optional() {
+        let json = r#"{"name":"a-name","code":"","language":{"lang":"JavaScript"},"trigger":{"type":"None"}}"#;
+        let declaration: UserFunctionDeclaration = serde_json::from_str(json).unwrap();
+        assert_eq!(
+            UserFunctionRecord::from(declaration.clone()).version(),
+            None
+        );
+        assert!(!serde_json::to_string(&declaration)
+            .unwrap()
+            .contains("version"));
+
+        let mut pinned = declaration;
+        pinned.code = pinned.code.with_version("1.9.0");
+        let json = serde_json::to_string(&pinned).unwrap();
+        assert!(json.contains(r#""version":"1.9.0""#));
+        let pinned: UserFunctionDeclaration = serde_json::from_str(&json).unwrap();
+        assert_eq!(UserFunctionRecord::from(pinned).version(), Some("1.9.0"));
+    }
 }
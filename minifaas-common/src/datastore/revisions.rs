@@ -0,0 +1,420 @@
+use super::write_atomically;
+use crate::triggers::Trigger;
+pub use crate::types::*;
+use anyhow::Result;
+use async_std::sync::{Mutex, RwLock};
+use async_std::task;
+use chrono::{DateTime, Utc};
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+use similar::{capture_diff_slices_deadline, Algorithm, ChangeTag};
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+/// How long `diff_lines` looks for the smallest diff
+const DIFF_DEADLINE: Duration = Duration::from_secs(1);
+
+///
+/// A function as it was saved, revisions are numbered from 1 per function and never change.
+///
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Revision {
+    pub number: u64,
+    pub created: DateTime<Utc>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+    pub function: UserFunctionDeclaration,
+}
+
+///
+/// A revision without the function's code, for listing them.
+///
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RevisionInfo {
+    pub number: u64,
+    pub created: DateTime<Utc>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+    pub language: ProgrammingLanguage,
+    pub trigger: Trigger,
+}
+
+impl From<&Revision> for RevisionInfo {
+    fn from(revision: &Revision) -> Self {
+        RevisionInfo {
+            number: revision.number,
+            created: revision.created,
+            message: revision.message.clone(),
+            language: revision.function.code.language.clone(),
+            trigger: revision.function.trigger.clone(),
+        }
+    }
+}
+
+///
+/// A line of a diff, e.g. `{"op": "added", "line": "console.log('hi');"}`.
+///
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "op", content = "line", rename_all = "lowercase")]
+pub enum DiffLine {
+    Same(String),
+    Added(String),
+    Removed(String),
+}
+
+///
+/// What changed between two revisions of a function.
+///
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RevisionDiff {
+    pub from: u64,
+    pub to: u64,
+
+    /// The settings other than the code that changed (e.g. `trigger` or `language`)
+    pub changed: Vec<String>,
+
+    /// The code, line by line
+    pub code: Vec<DiffLine>,
+}
+
+impl RevisionDiff {
+    pub fn new(from: &Revision, to: &Revision) -> Result<Self> {
+        Ok(RevisionDiff {
+            from: from.number,
+            to: to.number,
+            changed: changed_settings(&from.function, &to.function)?,
+            code: diff_lines(&from.function.code.code, &to.function.code.code),
+        })
+    }
+}
+
+///
+/// The settings other than the code that differ between two declarations of a function.
+///
+pub fn changed_settings(
+    old: &UserFunctionDeclaration,
+    new: &UserFunctionDeclaration,
+) -> Result<Vec<String>> {
+    let settings =
+        |function: &UserFunctionDeclaration| -> Result<BTreeMap<String, serde_json::Value>> {
+            let mut settings: BTreeMap<String, serde_json::Value> =
+                serde_json::from_value(serde_json::to_value(function)?)?;
+            settings.remove("code");
+            Ok(settings)
+        };
+    let (old, new) = (settings(old)?, settings(new)?);
+    Ok(old
+        .keys()
+        .chain(new.keys())
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .filter(|key| old.get(*key) != new.get(*key))
+        .cloned()
+        .collect())
+}
+
+///
+/// A line-by-line diff of `old` and `new` with Myers' algorithm in linear space. Texts that take longer than
+/// `DIFF_DEADLINE` to compare get a diff that isn't minimal.
+///
+pub fn diff_lines(old: &str, new: &str) -> Vec<DiffLine> {
+    let old: Vec<&str> = old.lines().collect();
+    let new: Vec<&str> = new.lines().collect();
+    let deadline = Instant::now() + DIFF_DEADLINE;
+    capture_diff_slices_deadline(Algorithm::Myers, &old, &new, Some(deadline))
+        .iter()
+        .flat_map(|op| op.iter_changes(&old, &new))
+        .map(|change| {
+            let line = change.value().to_owned();
+            match change.tag() {
+                ChangeTag::Equal => DiffLine::Same(line),
+                ChangeTag::Delete => DiffLine::Removed(line),
+                ChangeTag::Insert => DiffLine::Added(line),
+            }
+        })
+        .collect()
+}
+
+type InnerStorageType = BTreeMap<String, Vec<Revision>>;
+
+///
+/// Every saved version of every function by name. Revisions are kept when a function is deleted, so it can be
+/// restored later.
+///
+#[derive(Debug)]
+pub struct RevisionStore {
+    store: RwLock<InnerStorageType>,
+    /// Held while the revisions are written to disk, so a write never overtakes a newer one
+    writing: Mutex<()>,
+    path: PathBuf,
+    serialize_on_write: bool,
+}
+
+impl RevisionStore {
+    pub fn new<P: Into<PathBuf>>(path: P, serialize_on_write: bool) -> Self {
+        RevisionStore::with(BTreeMap::new(), path, serialize_on_write)
+    }
+
+    fn with<P: Into<PathBuf>>(map: InnerStorageType, path: P, serialize_on_write: bool) -> Self {
+        RevisionStore {
+            store: RwLock::new(map),
+            writing: Mutex::new(()),
+            path: path.into(),
+            serialize_on_write,
+        }
+    }
+
+    ///
+    /// Records a new revision of `function` with an optional message (e.g. what changed).
+    ///
+    pub async fn add(
+        &self,
+        function: &UserFunctionDeclaration,
+        message: Option<String>,
+    ) -> Revision {
+        let revision = {
+            let mut store = self.store.write().await;
+            let revisions = store.entry(function.name.clone()).or_default();
+            let revision = Revision {
+                number: revisions.last().map(|r| r.number + 1).unwrap_or(1),
+                created: Utc::now(),
+                message,
+                function: function.clone(),
+            };
+            revisions.push(revision.clone());
+            revision
+        };
+        self.persist().await;
+        revision
+    }
+
+    ///
+    /// A function's revisions, oldest first.
+    ///
+    pub async fn list(&self, name: &str) -> Vec<RevisionInfo> {
+        self.store
+            .read()
+            .await
+            .get(name)
+            .map(|revisions| revisions.iter().map(RevisionInfo::from).collect())
+            .unwrap_or_default()
+    }
+
+    pub async fn get(&self, name: &str, number: u64) -> Option<Revision> {
+        self.store
+            .read()
+            .await
+            .get(name)?
+            .iter()
+            .find(|r| r.number == number)
+            .cloned()
+    }
+
+    ///
+    /// What changed from revision `from` to revision `to`, `None` if one of them doesn't exist.
+    ///
+    pub async fn diff(&self, name: &str, from: u64, to: u64) -> Result<Option<RevisionDiff>> {
+        match (self.get(name, from).await, self.get(name, to).await) {
+            (Some(from), Some(to)) => Ok(Some(RevisionDiff::new(&from, &to)?)),
+            _ => Ok(None),
+        }
+    }
+
+    ///
+    /// A function's revisions with their code, oldest first.
+    ///
+    pub async fn all(&self, name: &str) -> Vec<Revision> {
+        self.store
+            .read()
+            .await
+            .get(name)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    ///
+    /// Adds `revisions` (e.g. from an archive) to the history of the function `name`. They keep their numbers unless
+    /// the function already has revisions, then they're numbered after those.
+    ///
+    pub async fn restore(&self, name: &str, revisions: Vec<Revision>) {
+        if revisions.is_empty() {
+            return;
+        }
+        {
+            let mut store = self.store.write().await;
+            let existing = store.entry(name.to_owned()).or_default();
+            let renumber = !existing.is_empty();
+            for mut revision in revisions {
+                if renumber {
+                    revision.number = existing.last().map(|r| r.number + 1).unwrap_or(1);
+                }
+                revision.function.name = name.to_owned();
+                existing.push(revision);
+            }
+        }
+        self.persist().await;
+    }
+
+    ///
+    /// Replaces all revisions with the ones in `other` (e.g. when migrating to another store).
+    ///
+    pub async fn copy_from(&self, other: &RevisionStore) -> Result<()> {
+        if self.path == other.path {
+            return Ok(());
+        }
+        let revisions = other.store.read().await.clone();
+        *self.store.write().await = revisions;
+        self.write_to_disk().await
+    }
+
+    async fn persist(&self) {
+        if self.serialize_on_write {
+            if let Err(e) = self.write_to_disk().await {
+                error!("Couldn't serialize revisions to disk: {}", e);
+            }
+        }
+    }
+
+    ///
+    /// Writes the revisions to disk at the (initially) provided location.
+    ///
+    pub async fn write_to_disk(&self) -> Result<()> {
+        let _writing = self.writing.lock().await;
+        let buf = serde_json::to_vec(&*self.store.read().await)?;
+        let path = self.path.clone();
+        task::spawn_blocking(move || write_atomically(&path, &buf).map_err(|e| e.into())).await
+    }
+
+    ///
+    /// Loads the revisions from the provided path, a missing file is an empty store.
+    ///
+    pub async fn from_path<P: Into<PathBuf>>(path: P) -> Result<Self> {
+        let p = path.into();
+        info!(
+            "Reading revisions from store at '{}'",
+            p.to_str().unwrap_or_default()
+        );
+        let _p = p.clone();
+        let store: InnerStorageType = task::spawn_blocking(move || -> Result<_> {
+            match std::fs::read(&_p) {
+                Ok(content) => Ok(serde_json::from_slice(&content)?),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(BTreeMap::new()),
+                Err(e) => Err(e.into()),
+            }
+        })
+        .await?;
+        Ok(RevisionStore::with(store, p, true))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runtime::FunctionCode;
+    use crate::triggers::http::HttpMethod;
+
+    use minifaas_test::get_empty_tmp_dir;
+
+    fn declaration(code: &str, trigger: Trigger) -> UserFunctionDeclaration {
+        UserFunctionDeclaration {
+            name: "a-name".to_owned(),
+            code: FunctionCode::new(code.to_owned(), ProgrammingLanguage::Bash),
+            trigger,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_diff_lines() {
+        assert_eq!(
+            diff_lines("a\nb\nc", "a\nc\nd"),
+            vec![
+                DiffLine::Same("a".to_owned()),
+                DiffLine::Removed("b".to_owned()),
+                DiffLine::Same("c".to_owned()),
+                DiffLine::Added("d".to_owned()),
+            ]
+        );
+        assert_eq!(diff_lines("", "a"), vec![DiffLine::Added("a".to_owned())]);
+
+        // far too big for a table of all pairs of lines
+        let old: Vec<String> = (0..50_000).map(|i| format!("old {}", i)).collect();
+        let new: Vec<String> = (0..50_000).map(|i| format!("new {}", i)).collect();
+        let diff = diff_lines(&old.join("\n"), &new.join("\n"));
+        assert_eq!(diff.len(), 100_000);
+        assert!(!diff.iter().any(|l| matches!(l, DiffLine::Same(_))));
+        assert_eq!(
+            serde_json::to_string(&DiffLine::Removed("b".to_owned())).unwrap(),
+            r#"{"op":"removed","line":"b"}"#
+        );
+    }
+
+    #[async_std::test]
+    async fn test_revision_store_numbers_revisions() {
+        let p = get_empty_tmp_dir().join("revisions.db");
+        let store = RevisionStore::from_path(&p).await.unwrap();
+        let first = store
+            .add(&declaration("echo 1", Trigger::Http(HttpMethod::GET)), None)
+            .await;
+        let second = store
+            .add(
+                &declaration("echo 1\necho 2", Trigger::Http(HttpMethod::POST)),
+                Some("Post instead".to_owned()),
+            )
+            .await;
+        assert_eq!((first.number, second.number), (1, 2));
+
+        let reloaded = RevisionStore::from_path(&p).await.unwrap();
+        let revisions = reloaded.list("a-name").await;
+        assert_eq!(revisions.len(), 2);
+        assert_eq!(revisions[1].message.as_deref(), Some("Post instead"));
+        assert_eq!(
+            reloaded.get("a-name", 1).await.unwrap().function.code.code,
+            "echo 1"
+        );
+        assert!(reloaded.get("a-name", 3).await.is_none());
+        assert!(reloaded.list("another-name").await.is_empty());
+
+        let diff = reloaded.diff("a-name", 1, 2).await.unwrap().unwrap();
+        assert_eq!(diff.changed, vec!["trigger".to_owned()]);
+        assert_eq!(
+            diff.code,
+            vec![
+                DiffLine::Same("echo 1".to_owned()),
+                DiffLine::Added("echo 2".to_owned())
+            ]
+        );
+        assert!(reloaded.diff("a-name", 1, 3).await.unwrap().is_none());
+    }
+
+    #[async_std::test]
+    async fn test_revision_store_restores_revisions() {
+        let store = RevisionStore::new(get_empty_tmp_dir().join("revisions.db"), false);
+        store
+            .add(&declaration("echo 1", Trigger::Http(HttpMethod::GET)), None)
+            .await;
+        store
+            .add(&declaration("echo 2", Trigger::Http(HttpMethod::GET)), None)
+            .await;
+        let archived = store.all("a-name").await;
+        assert_eq!(archived.len(), 2);
+
+        store.restore("a-copy", archived.clone()).await;
+        let copied = store.all("a-copy").await;
+        assert_eq!(copied.len(), 2);
+        assert_eq!(copied[1].number, 2);
+        assert_eq!(copied[1].function.name, "a-copy");
+
+        store.restore("a-name", archived).await;
+        let revisions = store.list("a-name").await;
+        assert_eq!(
+            revisions.iter().map(|r| r.number).collect::<Vec<_>>(),
+            vec![1, 2, 3, 4]
+        );
+        assert_eq!(
+            store.get("a-name", 4).await.unwrap().function.code.code,
+            "echo 2"
+        );
+    }
+}
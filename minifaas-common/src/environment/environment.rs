@@ -4,11 +4,12 @@ use async_std::fs::File;
 use async_std::fs::{create_dir_all, read, remove_dir_all, write};
 use async_std::task;
 use log::info;
-use std::collections::HashSet;
+use std::collections::{BTreeMap, HashSet};
 use std::fmt;
-use std::fs::read_dir;
+use std::fs::{read_dir, OpenOptions};
+use std::io::{ErrorKind, Read, Write};
 use std::iter::FromIterator;
-use std::path::PathBuf;
+use std::path::{Component, Path, PathBuf};
 use uuid::Uuid;
 
 use super::Environments;
@@ -185,6 +186,164 @@ impl Environment {
     pub async fn absolute_path<S: Into<PathBuf>>(&self, sub_path: S) -> PathBuf {
         PathBuf::from(&self.root).join(sub_path.into())
     }
+
+    ///
+    /// Reads all regular files in the environment by their relative path (e.g. `lib/util.js`), except the id file and
+    /// the ones in `exclude`. Symlinks (e.g. to a toolchain) aren't followed.
+    ///
+    pub async fn read_files(&self, exclude: &[&str]) -> Result<BTreeMap<String, Vec<u8>>> {
+        let root = PathBuf::from(&self.root);
+        let mut exclude: Vec<PathBuf> = exclude.iter().map(PathBuf::from).collect();
+        exclude.push(PathBuf::from(ID_FILE_NAME));
+        task::spawn_blocking(move || -> Result<_> {
+            let mut files = BTreeMap::new();
+            collect_files(&root, &root, &exclude, &mut files)?;
+            Ok(files)
+        })
+        .await
+    }
+
+    ///
+    /// Reads a regular file in the environment, `None` if there is none at `name`. Symlinks aren't followed, neither
+    /// for the file nor for the directories on the way to it.
+    ///
+    pub async fn read_file(&self, name: &str) -> Result<Option<Vec<u8>>> {
+        let root = PathBuf::from(&self.root);
+        let name = name.to_owned();
+        task::spawn_blocking(move || -> Result<_> {
+            let path = match inner_path(&root, &name, false)? {
+                Some(path) => path,
+                None => return Ok(None),
+            };
+            match std::fs::symlink_metadata(&path) {
+                Ok(meta) if meta.file_type().is_file() => read_nofollow(&path).map(Some),
+                Ok(_) => Ok(None),
+                Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+                Err(e) => Err(e.into()),
+            }
+        })
+        .await
+    }
+
+    ///
+    /// Writes a file to the environment, creating directories as needed. Symlinks aren't followed: directories on the
+    /// way can't be symlinks and an existing entry (e.g. a link to a toolchain) is replaced instead of written through.
+    ///
+    pub async fn write_file(&self, name: &str, content: Vec<u8>) -> Result<()> {
+        let root = PathBuf::from(&self.root);
+        let name = name.to_owned();
+        task::spawn_blocking(move || -> Result<_> {
+            let path = inner_path(&root, &name, true)?
+                .ok_or_else(|| AnyError::msg(format!("Invalid file name '{}'", name)))?;
+            match std::fs::symlink_metadata(&path) {
+                Ok(meta) if meta.file_type().is_dir() => {
+                    return Err(AnyError::msg(format!("'{}' is a directory", name)))
+                }
+                Ok(_) => std::fs::remove_file(&path)?,
+                Err(e) if e.kind() == ErrorKind::NotFound => {}
+                Err(e) => return Err(e.into()),
+            }
+            // fails instead of following a symlink that was created in the meantime
+            let mut file = OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&path)?;
+            file.write_all(&content)?;
+            Ok(())
+        })
+        .await
+    }
+
+    ///
+    /// Writes `files` to the environment by their relative path (see `write_file`). Paths that point outside of the
+    /// environment are rejected before anything is written.
+    ///
+    pub async fn write_files(&self, files: BTreeMap<String, Vec<u8>>) -> Result<()> {
+        for name in files.keys() {
+            if !is_inner_name(name) {
+                return Err(AnyError::msg(format!("Invalid file name '{}'", name)));
+            }
+        }
+        for (name, content) in files {
+            self.write_file(&name, content).await?;
+        }
+        Ok(())
+    }
+}
+
+fn is_inner_name(name: &str) -> bool {
+    let path = Path::new(name);
+    path != Path::new(ID_FILE_NAME)
+        && path.components().next().is_some()
+        && path.components().all(|c| matches!(c, Component::Normal(_)))
+}
+
+///
+/// The path of `name` in `root` if it's inside and none of the directories on the way is a symlink. Missing
+/// directories are created with `create_dirs`, otherwise there's no path.
+///
+fn inner_path(root: &Path, name: &str, create_dirs: bool) -> Result<Option<PathBuf>> {
+    if !is_inner_name(name) {
+        return Err(AnyError::msg(format!("Invalid file name '{}'", name)));
+    }
+    let mut components: Vec<_> = Path::new(name).components().collect();
+    let file = components.pop();
+    let mut path = root.to_path_buf();
+    for dir in components {
+        path.push(dir);
+        match std::fs::symlink_metadata(&path) {
+            Ok(meta) if meta.file_type().is_dir() => {}
+            Ok(_) => {
+                return Err(AnyError::msg(format!(
+                    "{:?} isn't a directory in the environment",
+                    path
+                )))
+            }
+            Err(e) if e.kind() == ErrorKind::NotFound && create_dirs => std::fs::create_dir(&path)?,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok(file.map(|f| path.join(f)))
+}
+
+#[cfg(target_family = "unix")]
+fn read_nofollow(path: &Path) -> Result<Vec<u8>> {
+    use std::os::unix::fs::OpenOptionsExt;
+    let mut file = OpenOptions::new()
+        .read(true)
+        .custom_flags(libc::O_NOFOLLOW)
+        .open(path)?;
+    let mut content = vec![];
+    file.read_to_end(&mut content)?;
+    Ok(content)
+}
+
+#[cfg(target_family = "windows")]
+fn read_nofollow(path: &Path) -> Result<Vec<u8>> {
+    Ok(std::fs::read(path)?)
+}
+
+fn collect_files(
+    root: &Path,
+    dir: &Path,
+    exclude: &[PathBuf],
+    files: &mut BTreeMap<String, Vec<u8>>,
+) -> Result<()> {
+    for entry in read_dir(dir)? {
+        let path = entry?.path();
+        let relative = path.strip_prefix(root)?;
+        let file_type = std::fs::symlink_metadata(&path)?.file_type();
+        if file_type.is_dir() {
+            collect_files(root, &path, exclude, files)?;
+        } else if file_type.is_file() && !exclude.iter().any(|e| e == relative) {
+            files.insert(
+                relative.to_string_lossy().into_owned(),
+                read_nofollow(&path)?,
+            );
+        }
+    }
+    Ok(())
 }
 
 impl fmt::Display for Environment {
@@ -330,4 +489,57 @@ mod tests {
             .unwrap();
         assert!(!e.has_dir(f_name).await);
     }
+
+    #[async_std::test]
+    async fn test_env_read_and_write_files() {
+        let root_dir = get_empty_tmp_dir();
+        let env_path = root_dir.join(Uuid::new_v4().to_string());
+        let e = Environment::create_with_id(env_path.clone(), Uuid::new_v4())
+            .await
+            .unwrap();
+        let mut files = BTreeMap::new();
+        files.insert("data.txt".to_owned(), b"hello".to_vec());
+        files.insert("lib/util.js".to_owned(), b"export {}".to_vec());
+        files.insert("output.log".to_owned(), b"a log line".to_vec());
+        e.write_files(files.clone()).await.unwrap();
+
+        files.remove("output.log");
+        assert_eq!(e.read_files(&["output.log"]).await.unwrap(), files);
+
+        let mut outside = BTreeMap::new();
+        outside.insert("../escaped.txt".to_owned(), b"bye".to_vec());
+        assert!(e.write_files(outside).await.is_err());
+        assert!(!root_dir.join("escaped.txt").exists());
+    }
+
+    #[cfg(target_family = "unix")]
+    #[async_std::test]
+    async fn test_env_files_dont_follow_symlinks() {
+        let root_dir = get_empty_tmp_dir();
+        let env_path = root_dir.join(Uuid::new_v4().to_string());
+        let e = Environment::create_with_id(env_path.clone(), Uuid::new_v4())
+            .await
+            .unwrap();
+        let outside = root_dir.join("outside.txt");
+        std::fs::write(&outside, b"outside").unwrap();
+        std::os::unix::fs::symlink(&outside, env_path.join("link.txt")).unwrap();
+        std::os::unix::fs::symlink(&root_dir, env_path.join("linked-dir")).unwrap();
+
+        assert_eq!(e.read_file("link.txt").await.unwrap(), None);
+        assert!(e.read_files(&[]).await.unwrap().is_empty());
+
+        e.write_file("link.txt", b"replaced".to_vec())
+            .await
+            .unwrap();
+        assert_eq!(std::fs::read(&outside).unwrap(), b"outside");
+        assert_eq!(
+            e.read_file("link.txt").await.unwrap(),
+            Some(b"replaced".to_vec())
+        );
+        assert!(e
+            .write_file("linked-dir/outside.txt", b"replaced".to_vec())
+            .await
+            .is_err());
+        assert_eq!(std::fs::read(&outside).unwrap(), b"outside");
+    }
 }
@@ -2,11 +2,14 @@ use crate::errors::PreparationError::EnvironmentAddFailed;
 use anyhow::Result;
 
 use super::Environment;
+use async_std::fs::{create_dir_all, rename};
 use log::debug;
 use std::collections::HashMap;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use uuid::Uuid;
 
+const ARCHIVE_DIR: &str = ".archive";
+
 #[derive(Debug, Clone, Default)]
 pub struct Environments {
     pub envs: HashMap<Uuid, Environment>,
@@ -29,6 +32,13 @@ impl Environments {
         }
     }
 
+    ///
+    /// The directory the environments are in.
+    ///
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
     ///
     /// Finds or creates an environment with the provided GUID. 
     ///
@@ -58,10 +68,66 @@ impl Environments {
         Some(())
     }
 
+    ///
+    /// Moves a file out of an environment into `<root>/.archive/<environment id>/` so it outlives the environment.
+    /// Returns the new location of the file.
+    ///
+    pub async fn archive_file<S: Into<PathBuf>>(
+        &self,
+        environment_id: &Uuid,
+        sub_path: S,
+    ) -> Result<PathBuf> {
+        let env = self.envs.get(environment_id).ok_or_else(|| {
+            anyhow::Error::msg(format!("Unknown environment '{}'", environment_id))
+        })?;
+        let sub_path = sub_path.into();
+        let archive = self.root.join(ARCHIVE_DIR).join(environment_id.to_string());
+        create_dir_all(&archive).await?;
+        let target = archive.join(&sub_path);
+        rename(env.absolute_path(sub_path).await, &target).await?;
+        debug!("Archived '{:?}' of '{}'", target, environment_id);
+        Ok(target)
+    }
+
     pub async fn count(&self) -> usize {
         self.envs.len()
     }
 }
 
 #[cfg(test)]
-mod tests {}
+mod tests {
+    use super::*;
+    use minifaas_test::get_empty_tmp_dir;
+
+    #[async_std::test]
+    async fn test_environments_archive_file_outlives_remove() {
+        let root_dir = get_empty_tmp_dir();
+        let env_id = Uuid::new_v4();
+        let mut envs = Environments::new(&root_dir, HashMap::new());
+        let env = envs.get_or_create(env_id).await.unwrap();
+        async_std::fs::write(env.absolute_path("logs").await, b"a log line")
+            .await
+            .unwrap();
+
+        let archived = envs.archive_file(&env_id, "logs").await.unwrap();
+        assert!(envs.remove(&env_id).await.is_some());
+
+        assert!(!root_dir.join(env_id.to_string()).exists());
+        assert_eq!(
+            archived,
+            root_dir
+                .join(ARCHIVE_DIR)
+                .join(env_id.to_string())
+                .join("logs")
+        );
+        assert_eq!(std::fs::read(archived).unwrap(), b"a log line".to_vec());
+        assert!(std::fs::remove_dir_all(root_dir).is_ok());
+    }
+
+    #[async_std::test]
+    async fn test_environments_archive_file_unknown_env() {
+        let root_dir = get_empty_tmp_dir();
+        let envs = Environments::new(&root_dir, HashMap::new());
+        assert!(envs.archive_file(&Uuid::new_v4(), "logs").await.is_err());
+    }
+}
@@ -1,3 +1,4 @@
+use std::time::Duration;
 use thiserror::Error;
 
 ///
@@ -22,6 +23,42 @@ pub enum ExecutionError {
     ///
     #[error("The runtime somehow died. Sorry")]
     RuntimeDeadError,
+
+    ///
+    /// The function didn't finish within its maximum runtime and was killed.
+    ///
+    #[error("Function exceeded its maximum runtime of {0:?}")]
+    Timeout(Duration),
+
+    ///
+    /// The function's process exited with a non-zero exit code. Contains stderr.
+    ///
+    #[error("Function exited with code {0}")]
+    NonZeroExit(i32, Vec<String>),
+
+    ///
+    /// The function's process was terminated by a signal. Contains stderr.
+    ///
+    #[error("Function was terminated by signal {0}")]
+    Terminated(i32, Vec<String>),
+
+    ///
+    /// The function's process hit one of its resource limits (e.g. memory or CPU time). Contains stderr.
+    ///
+    #[error("Function exceeded its {0} limit")]
+    LimitExceeded(String, Vec<String>),
+
+    ///
+    /// All of the function's executors (the number is included) are busy and its queue is full.
+    ///
+    #[error("All {0} instances of the function are busy")]
+    Saturated(usize),
+
+    ///
+    /// The function wrote more than the maximum output size (in bytes) to stdout and was killed.
+    ///
+    #[error("Function output exceeded {0} bytes")]
+    OutputTooLarge(u64),
 }
 
 ///
@@ -41,6 +78,46 @@ pub enum PreparationError {
     #[error("Can't add another environment at: {0}")]
     EnvironmentAddFailed(String),
 
+    ///
+    /// A downloaded toolchain archive doesn't match its SHA-256 checksum.
+    ///
+    #[error("Checksum mismatch for '{file}': expected SHA-256 {expected}, got {actual}")]
+    ChecksumMismatch {
+        file: String,
+        expected: String,
+        actual: String,
+    },
+
+    ///
+    /// There is no checksum to verify a toolchain archive with, but checksums are required.
+    ///
+    #[error("No checksum available for '{0}'")]
+    ChecksumMissing(String),
+
+    ///
+    /// A function asks for a toolchain version that isn't available for its language.
+    ///
+    #[error("Version '{version}' is not available for {language}")]
+    UnsupportedVersion { language: String, version: String },
+
+    ///
+    /// A function declares permissions, but its language's runtime can't restrict them.
+    ///
+    #[error("{0} functions can't be sandboxed with permissions")]
+    UnsupportedPermissions(String),
+
+    ///
+    /// A function declares resource limits, but its language's runtime can't enforce them.
+    ///
+    #[error("{0} functions can't run with resource limits")]
+    UnsupportedLimits(String),
+
+    ///
+    /// The runtime sandboxes functions, but the function's language can't run in a sandbox.
+    ///
+    #[error("{0} functions can't run in a sandbox")]
+    UnsupportedSandbox(String),
+
     ///
     /// The runtime had some issue and died.
     ///
@@ -1,15 +1,27 @@
+pub mod archive;
 mod datastore;
 mod environment;
 pub mod errors;
+pub mod limits;
+pub mod manifests;
+pub mod permissions;
 pub mod runtime;
+pub mod secrets;
 pub mod triggers;
 mod types;
 
 pub use crate::types::*;
 use anyhow::Result;
 use async_std::path::PathBuf;
-pub use datastore::{DataStoreConfig, FaaSDataStore, UserFunctionRecord, UserFunctionType};
+pub use datastore::{
+    DataStoreConfig, DiffLine, FaaSDataStore, FunctionStore, GitRepository, JsonFaaSDataStore,
+    KvFaaSDataStore, PulledChanges, Revision, RevisionDiff, RevisionInfo, RevisionStore,
+    StorageBackend, UserFunctionRecord, UserFunctionType,
+};
 pub use environment::{Environment, Environments};
+pub use limits::ResourceLimits;
+pub use permissions::Permissions;
+pub use secrets::{EnvValue, FunctionEnv, SecretStore};
 use log::info;
 use std::sync::Arc;
 pub use triggers::Trigger;
@@ -19,12 +31,38 @@ pub use runtime::{FunctionInputs, FunctionOutputs, RuntimeRequest, RuntimeRespon
 ///
 /// Creates and prepares the file system
 ///
-pub async fn create_or_load_storage(config: DataStoreConfig) -> Result<FaaSDataStore> {
-    let store = FaaSDataStore::from_path(&config.path).await?;
+pub async fn create_or_load_storage(config: DataStoreConfig) -> Result<Arc<FaaSDataStore>> {
+    let store: Arc<FaaSDataStore> = match config.backend {
+        StorageBackend::Json => Arc::new(JsonFaaSDataStore::from_path(&config.path).await?),
+        StorageBackend::Embedded => Arc::new(KvFaaSDataStore::from_path(&config.path).await?),
+        StorageBackend::Git(remote) => {
+            Arc::new(JsonFaaSDataStore::from_git(&config.path, remote).await?)
+        }
+    };
     info!("Read {} functions from store", store.len().await);
     Ok(store)
 }
 
+///
+/// Copies all functions and their revisions from one store to another (e.g. from JSON to the embedded store).
+/// Returns the number of functions copied.
+///
+pub async fn migrate_storage(from: DataStoreConfig, to: DataStoreConfig) -> Result<usize> {
+    if from.path == to.path {
+        return Err(anyhow::Error::msg("Can't migrate a store onto itself"));
+    }
+    let source = create_or_load_storage(from).await?;
+    let target = create_or_load_storage(to).await?;
+    let functions = source.items().await;
+    for (name, function) in &functions {
+        info!("Migrating function '{}'", name);
+        target.set(name.clone(), (***function).clone()).await;
+    }
+    target.write_to_disk().await?;
+    target.revisions().copy_from(source.revisions()).await?;
+    Ok(functions.len())
+}
+
 ///
 /// Sets up the enviornment directories based on the IDs contained in the datastore.
 ///
@@ -36,7 +74,7 @@ pub async fn sync_environments<P: Into<PathBuf>>(
         .items()
         .await
         .iter()
-        .map(|(_k, f)| (*f.language(), f.environment_id))
+        .map(|(_k, f)| (f.language().clone(), f.environment_id))
         .collect::<Vec<(ProgrammingLanguage, Uuid)>>();
     let expected_env_ids: Vec<_> = ids.iter().map(|i| i.1).collect();
 
@@ -61,12 +99,13 @@ mod tests {
             ),
             trigger: Trigger::Http(HttpMethod::ALL),
             name: "a-name".to_string(),
+            ..Default::default()
         };
         let record = UserFunctionRecord::from(declaration);
 
         let expected_id = record.environment_id;
 
-        let datastore = FaaSDataStore::new(root_dir.join("testing.db"), false);
+        let datastore = JsonFaaSDataStore::new(root_dir.join("testing.db"), false);
         datastore.set("a".to_string(), record).await;
 
         let envs = sync_environments(&root_dir, Arc::new(datastore))
@@ -76,4 +115,40 @@ mod tests {
         assert_eq!(envs.get(&expected_id).await.unwrap().id, expected_id);
         assert!(std::fs::remove_dir_all(root_dir).is_ok());
     }
+
+    #[async_std::test]
+    async fn test_migrate_storage_json_to_embedded() {
+        let root_dir = get_empty_tmp_dir();
+        let declaration = UserFunctionDeclaration {
+            code: FunctionCode::new("echo hi".to_string(), ProgrammingLanguage::Bash),
+            trigger: Trigger::Http(HttpMethod::GET),
+            name: "a-name".to_string(),
+            ..Default::default()
+        };
+        let json = DataStoreConfig::new(root_dir.join("functions.db"), true);
+        let source = create_or_load_storage(json).await.unwrap();
+        source
+            .set(
+                "a-name".to_string(),
+                UserFunctionRecord::from(declaration.clone()),
+            )
+            .await;
+        source.revisions().add(&declaration, None).await;
+
+        let json = DataStoreConfig::new(root_dir.join("functions.db"), true);
+        let embedded = || {
+            DataStoreConfig::new(root_dir.join("embedded").join("functions.kv"), true)
+                .with_backend(StorageBackend::Embedded)
+        };
+        assert_eq!(migrate_storage(json, embedded()).await.unwrap(), 1);
+
+        let target = create_or_load_storage(embedded()).await.unwrap();
+        let migrated = target.get("a-name").await.unwrap();
+        assert_eq!(migrated.code().code, "echo hi");
+        assert_eq!(
+            migrated.environment_id,
+            source.get("a-name").await.unwrap().environment_id
+        );
+        assert_eq!(target.revisions().list("a-name").await.len(), 1);
+    }
 }
@@ -0,0 +1,120 @@
+use serde::{Deserialize, Serialize};
+
+///
+/// Resource limits for a function's process, applied with `setrlimit` on Unix. Limits that aren't set are inherited
+/// from the runtime.
+///
+#[derive(Serialize, Deserialize, Default, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ResourceLimits {
+    /// Address space in bytes. JavaScript runtimes reserve a lot of virtual memory up front, so they need a generous limit
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub memory_bytes: Option<u64>,
+
+    /// CPU time in seconds. Warm workers count the CPU time of all the calls they handle
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cpu_secs: Option<u64>,
+
+    /// The size of files the function writes in bytes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file_size_bytes: Option<u64>,
+
+    /// The number of files the function can have open at the same time
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub open_files: Option<u64>,
+
+    /// Processes and threads of the user MiniFaaS runs as, which includes the runtime's own
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub processes: Option<u64>,
+
+    /// What the function may write to stdout in bytes. Enforced by the runtime rather than `setrlimit`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output_bytes: Option<u64>,
+}
+
+impl ResourceLimits {
+    ///
+    /// Whether no limit is set.
+    ///
+    pub fn is_empty(&self) -> bool {
+        self == &ResourceLimits::default()
+    }
+
+    ///
+    /// These limits, capped by `max` (e.g. the runtime's limits). Limits that aren't set here are taken from `max`.
+    ///
+    pub fn capped_by(&self, max: &ResourceLimits) -> ResourceLimits {
+        ResourceLimits {
+            memory_bytes: cap(self.memory_bytes, max.memory_bytes),
+            cpu_secs: cap(self.cpu_secs, max.cpu_secs),
+            file_size_bytes: cap(self.file_size_bytes, max.file_size_bytes),
+            open_files: cap(self.open_files, max.open_files),
+            processes: cap(self.processes, max.processes),
+            output_bytes: cap(self.output_bytes, max.output_bytes),
+        }
+    }
+
+    ///
+    /// The limits that are applied to the function's process, i.e. all but `output_bytes`.
+    ///
+    pub fn process_limits(&self) -> ResourceLimits {
+        ResourceLimits {
+            output_bytes: None,
+            ..*self
+        }
+    }
+}
+
+fn cap(value: Option<u64>, max: Option<u64>) -> Option<u64> {
+    match (value, max) {
+        (Some(value), Some(max)) => Some(value.min(max)),
+        (value, max) => value.or(max),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resource_limits_capped_by() {
+        let runtime = ResourceLimits {
+            memory_bytes: Some(512),
+            cpu_secs: Some(10),
+            ..Default::default()
+        };
+        let function = ResourceLimits {
+            memory_bytes: Some(1024),
+            cpu_secs: Some(5),
+            open_files: Some(64),
+            ..Default::default()
+        };
+        assert_eq!(
+            function.capped_by(&runtime),
+            ResourceLimits {
+                memory_bytes: Some(512),
+                cpu_secs: Some(5),
+                open_files: Some(64),
+                ..Default::default()
+            }
+        );
+        assert_eq!(ResourceLimits::default().capped_by(&runtime), runtime);
+        assert!(ResourceLimits::default()
+            .capped_by(&ResourceLimits::default())
+            .is_empty());
+
+        let output = ResourceLimits {
+            output_bytes: Some(100),
+            ..Default::default()
+        };
+        assert_eq!(output.capped_by(&runtime).output_bytes, Some(100));
+        assert!(output.process_limits().is_empty());
+    }
+
+    #[test]
+    fn test_resource_limits_serde_defaults() {
+        let limits: ResourceLimits = serde_json::from_str(r#"{"cpu_secs": 2}"#).unwrap();
+        assert_eq!(limits.cpu_secs, Some(2));
+        assert_eq!(limits.memory_bytes, None);
+        assert_eq!(serde_json::to_string(&limits).unwrap(), r#"{"cpu_secs":2}"#);
+    }
+}
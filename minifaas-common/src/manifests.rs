@@ -0,0 +1,335 @@
+use crate::datastore::{changed_settings, diff_lines, DiffLine, FaaSDataStore, FunctionStore};
+use crate::limits::ResourceLimits;
+use crate::permissions::Permissions;
+use crate::runtime::FunctionCode;
+use crate::secrets::FunctionEnv;
+use crate::triggers::Trigger;
+pub use crate::types::*;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Component, Path, PathBuf};
+
+const MANIFEST_EXTENSION: &str = "toml";
+
+///
+/// A function declared in a TOML file with its code in a separate file, e.g.:
+///
+/// ```toml
+/// name = "weather"
+/// language = "Python"
+/// code = "weather.py"
+/// trigger = { type = "Interval", when = "0 */5 * * * *" }
+///
+/// [env]
+/// API_KEY = { secret = "weather" }
+///
+/// [limits]
+/// memory_bytes = 268435456
+/// ```
+///
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct FunctionManifest {
+    pub name: String,
+
+    /// The language's name, e.g. `Python`, `JavaScript`, or a configured command like `Ruby`
+    pub language: String,
+
+    /// Pins the language's toolchain to a version
+    #[serde(default)]
+    pub version: Option<String>,
+
+    /// The code file, relative to the manifest
+    pub code: PathBuf,
+
+    #[serde(default)]
+    pub trigger: Trigger,
+
+    #[serde(default)]
+    pub route: Option<String>,
+
+    #[serde(default)]
+    pub timeout_secs: Option<u64>,
+
+    #[serde(default)]
+    pub min_instances: Option<usize>,
+
+    #[serde(default)]
+    pub max_instances: Option<usize>,
+
+    #[serde(default)]
+    pub permissions: Permissions,
+
+    #[serde(default)]
+    pub env: FunctionEnv,
+
+    #[serde(default)]
+    pub limits: ResourceLimits,
+
+    #[serde(default)]
+    pub trusted: bool,
+
+    #[serde(default)]
+    pub warm: bool,
+}
+
+impl FunctionManifest {
+    ///
+    /// Reads the manifest at `path` and the code file it points to.
+    ///
+    pub fn load(path: &Path) -> Result<UserFunctionDeclaration> {
+        let manifest: FunctionManifest = toml::from_str(&std::fs::read_to_string(path)?)?;
+        if manifest.name.trim().is_empty() {
+            return Err(anyhow::Error::msg("The function's name is empty"));
+        }
+        // the code has to be next to the manifest
+        let mut components = manifest.code.components();
+        match (components.next(), components.next()) {
+            (Some(Component::Normal(_)), None) => {}
+            _ => {
+                return Err(anyhow::Error::msg(format!(
+                    "Invalid code file {:?}, it has to be next to the manifest",
+                    manifest.code
+                )))
+            }
+        }
+        let code_path = path
+            .parent()
+            .unwrap_or_else(|| Path::new("."))
+            .join(&manifest.code);
+        let code = std::fs::read_to_string(&code_path)
+            .with_context(|| format!("Can't read the code at {:?}", code_path))?;
+        Ok(manifest.into_declaration(code))
+    }
+
+    fn into_declaration(self, code: String) -> UserFunctionDeclaration {
+        let mut code = FunctionCode::new(code, ProgrammingLanguage::from(self.language.as_str()));
+        code.version = self.version;
+        UserFunctionDeclaration {
+            name: self.name,
+            code,
+            trigger: self.trigger,
+            route: self.route,
+            timeout_secs: self.timeout_secs,
+            min_instances: self.min_instances,
+            max_instances: self.max_instances,
+            permissions: self.permissions,
+            env: self.env,
+            limits: self.limits,
+            trusted: self.trusted,
+            warm: self.warm,
+        }
+    }
+}
+
+///
+/// Reads all manifests (`*.toml`) in `dir`, sorted by name. Fails if any of them is invalid or two of them declare
+/// the same function.
+///
+pub fn load_manifests(dir: &Path) -> Result<Vec<UserFunctionDeclaration>> {
+    let mut paths = BTreeMap::new();
+    let mut declarations = vec![];
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if !path.is_file() || path.extension().and_then(|e| e.to_str()) != Some(MANIFEST_EXTENSION)
+        {
+            continue;
+        }
+        let function = FunctionManifest::load(&path)
+            .with_context(|| format!("Invalid manifest {:?}", path))?;
+        if let Some(other) = paths.insert(function.name.clone(), path.clone()) {
+            return Err(anyhow::Error::msg(format!(
+                "Function '{}' is declared in {:?} and {:?}",
+                function.name, other, path
+            )));
+        }
+        declarations.push(function);
+    }
+    declarations.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(declarations)
+}
+
+///
+/// A change to the stored functions that makes them match the manifests.
+///
+#[derive(Serialize, Debug, Clone)]
+#[serde(tag = "action", rename_all = "lowercase")]
+pub enum ManifestChange {
+    /// A declared function that isn't stored yet
+    Create { function: UserFunctionDeclaration },
+
+    /// A stored function that differs from its manifest, `changed` are the settings other than the code
+    Update {
+        name: String,
+        changed: Vec<String>,
+        code: Vec<DiffLine>,
+        #[serde(skip)]
+        function: UserFunctionDeclaration,
+    },
+
+    /// A stored function without a manifest
+    Delete { name: String },
+}
+
+///
+/// What it takes for the `store` to contain exactly the `declared` functions. Functions that already match their
+/// manifest are left out. Without any manifests, that would be deleting every function, which has to be `force`d
+/// since it's more likely that the manifests are missing.
+///
+pub async fn plan_reconcile(
+    store: &FaaSDataStore,
+    declared: Vec<UserFunctionDeclaration>,
+    force: bool,
+) -> Result<Vec<ManifestChange>> {
+    let mut stored: BTreeMap<String, UserFunctionDeclaration> = store
+        .items()
+        .await
+        .into_iter()
+        .map(|(name, f)| (name, f.declaration().clone()))
+        .collect();
+    if declared.is_empty() && !stored.is_empty() && !force {
+        return Err(anyhow::Error::msg(format!(
+            "There are no manifests, reconciling would delete all {} functions",
+            stored.len()
+        )));
+    }
+    let mut changes = vec![];
+    for function in declared {
+        match stored.remove(&function.name) {
+            Some(old) => {
+                let changed = changed_settings(&old, &function)?;
+                if changed.is_empty() && old.code.code == function.code.code {
+                    continue;
+                }
+                changes.push(ManifestChange::Update {
+                    name: function.name.clone(),
+                    changed,
+                    code: diff_lines(&old.code.code, &function.code.code),
+                    function,
+                });
+            }
+            None => changes.push(ManifestChange::Create { function }),
+        }
+    }
+    changes.extend(
+        stored
+            .into_iter()
+            .map(|(name, _)| ManifestChange::Delete { name }),
+    );
+    Ok(changes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::datastore::{JsonFaaSDataStore, UserFunctionRecord};
+    use crate::secrets::EnvValue;
+    use crate::triggers::http::HttpMethod;
+
+    use minifaas_test::get_empty_tmp_dir;
+
+    fn write_manifest(dir: &Path, name: &str, code: &str) {
+        std::fs::write(
+            dir.join(format!("{}.toml", name)),
+            format!(
+                "name = \"{}\"\nlanguage = \"Bash\"\ncode = \"{}.sh\"\ntrigger = {{ type = \"Http\", when = \"POST\" }}\n\n[env]\nLOG_LEVEL = \"debug\"\n",
+                name, name
+            ),
+        )
+        .unwrap();
+        std::fs::write(dir.join(format!("{}.sh", name)), code).unwrap();
+    }
+
+    #[test]
+    fn test_load_manifests() {
+        let dir = get_empty_tmp_dir();
+        write_manifest(&dir, "b-function", "echo b");
+        write_manifest(&dir, "a-function", "echo a");
+        std::fs::write(dir.join("README.md"), "not a manifest").unwrap();
+
+        let functions = load_manifests(&dir).unwrap();
+        assert_eq!(functions.len(), 2);
+        assert_eq!(functions[0].name, "a-function");
+        assert_eq!(functions[0].code.code, "echo a");
+        assert_eq!(functions[0].code.language, ProgrammingLanguage::Bash);
+        assert_eq!(functions[0].trigger, Trigger::Http(HttpMethod::POST));
+        assert_eq!(
+            functions[0].env.get("LOG_LEVEL"),
+            Some(&EnvValue::Plain("debug".to_owned()))
+        );
+
+        std::fs::write(
+            dir.join("copy.toml"),
+            "name = \"a-function\"\nlanguage = \"Bash\"\ncode = \"a-function.sh\"\n",
+        )
+        .unwrap();
+        assert!(load_manifests(&dir).is_err());
+    }
+
+    #[test]
+    fn test_load_manifest_confines_code_files() {
+        let dir = get_empty_tmp_dir();
+        let manifest = dir.join("escape.toml");
+        for code in &["../secrets.sh", "/etc/passwd", "sub/code.sh", "."] {
+            std::fs::write(
+                &manifest,
+                format!(
+                    "name = \"escape\"\nlanguage = \"Bash\"\ncode = \"{}\"\n",
+                    code
+                ),
+            )
+            .unwrap();
+            assert!(FunctionManifest::load(&manifest).is_err(), "{}", code);
+        }
+    }
+
+    #[async_std::test]
+    async fn test_plan_reconcile() {
+        let dir = get_empty_tmp_dir();
+        write_manifest(&dir, "same", "echo same");
+        write_manifest(&dir, "changed", "echo new");
+        write_manifest(&dir, "new", "echo new");
+        let declared = load_manifests(&dir).unwrap();
+
+        let store = JsonFaaSDataStore::new(dir.join("functions.db"), false);
+        for function in declared.iter().filter(|f| f.name != "new") {
+            let mut function = function.clone();
+            if function.name == "changed" {
+                function.code.code = "echo old".to_owned();
+                function.trigger = Trigger::Http(HttpMethod::GET);
+            }
+            store
+                .set(function.name.clone(), UserFunctionRecord::from(function))
+                .await;
+        }
+        store
+            .set(
+                "removed".to_owned(),
+                UserFunctionRecord::from(UserFunctionDeclaration {
+                    name: "removed".to_owned(),
+                    ..Default::default()
+                }),
+            )
+            .await;
+
+        let changes = plan_reconcile(&store, declared, false).await.unwrap();
+        let summary: Vec<_> = changes
+            .iter()
+            .map(|c| serde_json::to_value(c).unwrap())
+            .collect();
+        assert_eq!(changes.len(), 3);
+        assert_eq!(summary[0]["action"], "update");
+        assert_eq!(summary[0]["name"], "changed");
+        assert_eq!(summary[0]["changed"], serde_json::json!(["trigger"]));
+        assert_eq!(summary[1]["action"], "create");
+        assert_eq!(summary[1]["function"]["name"], "new");
+        assert_eq!(summary[2]["action"], "delete");
+        assert_eq!(summary[2]["name"], "removed");
+
+        // without manifests, everything is only deleted if that's forced
+        assert!(plan_reconcile(&store, vec![], false).await.is_err());
+        let changes = plan_reconcile(&store, vec![], true).await.unwrap();
+        assert_eq!(changes.len(), 3);
+    }
+}
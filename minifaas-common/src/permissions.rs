@@ -0,0 +1,169 @@
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+///
+/// Errors in a function's permission declaration.
+///
+#[derive(Clone, Debug, Error, PartialEq, Eq)]
+pub enum PermissionsError {
+    #[error("Invalid host '{0}', expected a host name or address with an optional port")]
+    InvalidHost(String),
+
+    #[error("Invalid environment variable name '{0}'")]
+    InvalidEnvName(String),
+
+    #[error("The environment variable '{0}' is reserved for the runtime")]
+    ReservedEnvName(String),
+}
+
+///
+/// Prefixes of the environment variables the runtime reads (`MF_*`) or sets for functions (`__MF__INPUTS`).
+///
+const RESERVED_ENV_PREFIXES: &[&str] = &["MF_", "__MF__"];
+
+///
+/// Other environment variables the runtime reads.
+///
+const RESERVED_ENV_NAMES: &[&str] = &["PATH", "RUST_LOG"];
+
+///
+/// What a function may access in a sandboxed runtime (Deno). Everything that isn't allowed is denied, which is also
+/// the default.
+///
+#[derive(Serialize, Deserialize, Default, Debug, Clone, PartialEq, Eq)]
+pub struct Permissions {
+    /// Hosts (e.g. `api.openweathermap.org` or `10.0.0.1:8080`) the function may connect to
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub allow_net: Vec<String>,
+
+    /// Read access to the function's environment directory
+    #[serde(default)]
+    pub allow_read: bool,
+
+    /// Write access to the function's environment directory
+    #[serde(default)]
+    pub allow_write: bool,
+
+    /// Environment variables the function can read besides its own (`env`), the runtime's values are never passed on
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub allow_env: Vec<String>,
+}
+
+impl Permissions {
+    ///
+    /// Whether nothing is allowed.
+    ///
+    pub fn is_empty(&self) -> bool {
+        self == &Permissions::default()
+    }
+
+    ///
+    /// Checks that hosts and variable names can be passed on to the runtime as they are.
+    ///
+    pub fn validate(&self) -> Result<(), PermissionsError> {
+        for host in self.allow_net.iter() {
+            if !is_valid_host(host) {
+                return Err(PermissionsError::InvalidHost(host.clone()));
+            }
+        }
+        for name in self.allow_env.iter() {
+            if !is_valid_env_name(name) {
+                return Err(PermissionsError::InvalidEnvName(name.clone()));
+            }
+            if is_reserved_env_name(name) {
+                return Err(PermissionsError::ReservedEnvName(name.clone()));
+            }
+        }
+        Ok(())
+    }
+}
+
+fn is_valid_host(host: &str) -> bool {
+    let (name, port) = match host.rfind(':') {
+        // IPv6 addresses have to be in brackets to carry a port
+        Some(i) if !host[..i].contains(':') || host[..i].ends_with(']') => {
+            (&host[..i], Some(&host[i + 1..]))
+        }
+        _ => (host, None),
+    };
+    let valid_name = !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-.[]:".contains(c));
+    let valid_port = port.map_or(true, |p| p.parse::<u16>().is_ok());
+    valid_name && valid_port
+}
+
+pub(crate) fn is_valid_env_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+fn is_reserved_env_name(name: &str) -> bool {
+    RESERVED_ENV_PREFIXES.iter().any(|p| name.starts_with(p)) || RESERVED_ENV_NAMES.contains(&name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_permissions_validate() {
+        let permissions = Permissions {
+            allow_net: vec![
+                "api.openweathermap.org".to_owned(),
+                "10.0.0.1:8080".to_owned(),
+                "[::1]:443".to_owned(),
+            ],
+            allow_env: vec!["API_KEY".to_owned(), "_private1".to_owned()],
+            ..Default::default()
+        };
+        assert_eq!(permissions.validate(), Ok(()));
+        assert!(!permissions.is_empty());
+        assert!(Permissions::default().is_empty());
+
+        for host in vec!["", "https://example.com", "a,b", "example.com:http", "a b"] {
+            let permissions = Permissions {
+                allow_net: vec![host.to_owned()],
+                ..Default::default()
+            };
+            assert_eq!(
+                permissions.validate(),
+                Err(PermissionsError::InvalidHost(host.to_owned()))
+            );
+        }
+        for name in vec!["", "1ABC", "A,B", "A=B"] {
+            let permissions = Permissions {
+                allow_env: vec![name.to_owned()],
+                ..Default::default()
+            };
+            assert_eq!(
+                permissions.validate(),
+                Err(PermissionsError::InvalidEnvName(name.to_owned()))
+            );
+        }
+        for name in vec!["MF_SECRETS_KEY", "__MF__INPUTS", "PATH"] {
+            let permissions = Permissions {
+                allow_env: vec![name.to_owned()],
+                ..Default::default()
+            };
+            assert_eq!(
+                permissions.validate(),
+                Err(PermissionsError::ReservedEnvName(name.to_owned()))
+            );
+        }
+    }
+
+    #[test]
+    fn test_permissions_serde_defaults() {
+        let permissions: Permissions =
+            serde_json::from_str(r#"{"allow_net": ["example.com"]}"#).unwrap();
+        assert_eq!(permissions.allow_net, vec!["example.com".to_owned()]);
+        assert!(!permissions.allow_read && !permissions.allow_write);
+        assert_eq!(
+            serde_json::to_string(&Permissions::default()).unwrap(),
+            r#"{"allow_read":false,"allow_write":false}"#
+        );
+    }
+}
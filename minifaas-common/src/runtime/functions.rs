@@ -86,6 +86,16 @@ impl From<HttpTrigger> for RawFunctionInput {
             (String::from("params"), FnInputValue::MapColl(input.params)),
             (String::from("method"), FnInputValue::Type(input.method)),
             (String::from("route"), FnInputValue::Str(input.route)),
+            (
+                String::from("path_params"),
+                FnInputValue::Map(
+                    input
+                        .path_params
+                        .into_iter()
+                        .map(|(k, v)| (k, Some(v)))
+                        .collect(),
+                ),
+            ),
         ]
         .into_iter()
         .collect();
@@ -113,6 +123,10 @@ impl From<TimerTrigger> for RawFunctionInput {
 pub struct FunctionCode {
     pub code: String,
     pub language: ProgrammingLanguage,
+
+    /// Pins the language's toolchain to a version (e.g. Deno `1.9.0`), `None` uses the runtime's default
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
 }
 
 impl FunctionCode {
@@ -121,7 +135,16 @@ impl FunctionCode {
     }
 
     pub fn existing(code: String, language: ProgrammingLanguage, uuid: Uuid) -> Self {
-        FunctionCode { code, language }
+        FunctionCode {
+            code,
+            language,
+            version: None,
+        }
+    }
+
+    pub fn with_version<S: Into<String>>(mut self, version: S) -> Self {
+        self.version = Some(version.into());
+        self
     }
 }
 
@@ -1,9 +1,11 @@
+use crate::archive::EnvironmentContents;
 use crate::triggers::http::HttpTriggerOutputs;
 use crate::UserFunctionRecord;
 use crate::{errors::ExecutionError, ProgrammingLanguage};
 use crate::{FunctionInputs, FunctionOutputs};
 use std::boxed::Box;
 use std::sync::Arc;
+use std::time::Duration;
 use uuid::Uuid;
 
 ///
@@ -22,9 +24,9 @@ pub enum RuntimeRequest {
     Disable(Arc<Box<UserFunctionRecord>>),
 
     ///
-    /// Call a function by shipping the code and a response channel.
+    /// Call a function with its inputs. HTTP calls are routed to the function by their path.
     ///
-    FunctionCall(Arc<Box<UserFunctionRecord>>, FunctionInputs),
+    FunctionCall(FunctionInputs),
 
     ///
     /// Start a new executor
@@ -32,15 +34,35 @@ pub enum RuntimeRequest {
     NewFunction(Arc<Box<UserFunctionRecord>>),
 
     ///
-    /// Deletes environment and cleans up
+    /// Stops the function, deletes its environment and cleans up. `keep_logs` archives the function's logs first.
     ///
-    DeleteFunction(Arc<Box<UserFunctionRecord>>),
+    DeleteFunction {
+        code: Arc<Box<UserFunctionRecord>>,
+        keep_logs: bool,
+    },
 
     FetchLogs {
         env_id: Uuid,
         start_line: usize,
         lines: usize,
     },
+
+    ///
+    /// Reads a function's environment, i.e. its logs and/or its other files.
+    ///
+    ExportEnvironment {
+        env_id: Uuid,
+        logs: bool,
+        files: bool,
+    },
+
+    ///
+    /// Writes logs and files into a function's environment, creating it if necessary.
+    ///
+    RestoreEnvironment {
+        env_id: Uuid,
+        contents: EnvironmentContents,
+    },
 }
 
 ///
@@ -74,13 +96,35 @@ pub enum RuntimeResponse {
         context: Vec<String>,
     },
 
+    ///
+    /// The function ran longer than allowed and was killed.
+    ///
+    FunctionTimedOut {
+        after: Duration,
+    },
+
+    ///
+    /// The function is at capacity and can't accept more calls right now.
+    ///
+    FunctionSaturated {
+        instances: usize,
+    },
+
+    ///
+    /// The function's executors are gone (e.g. during a redeployment).
+    ///
+    FunctionUnavailable,
+
     LogResponse(String),
 
+    EnvironmentContents(EnvironmentContents),
+
     Ok,
 }
 
 impl From<ExecutionError> for RuntimeResponse {
     fn from(error: ExecutionError) -> Self {
+        let message = error.to_string();
         match error {
             ExecutionError::CompilerError(message, context) => {
                 RuntimeResponse::FunctionExecutionError { message, context }
@@ -88,6 +132,21 @@ impl From<ExecutionError> for RuntimeResponse {
             ExecutionError::GeneralExecutionError(context) => {
                 RuntimeResponse::FunctionRuntimeError { context }
             }
+            ExecutionError::NonZeroExit(_, stderr)
+            | ExecutionError::Terminated(_, stderr)
+            | ExecutionError::LimitExceeded(_, stderr) => {
+                let mut context = vec![message];
+                context.extend(stderr);
+                RuntimeResponse::FunctionRuntimeError { context }
+            }
+            ExecutionError::OutputTooLarge(_) => RuntimeResponse::FunctionRuntimeError {
+                context: vec![message],
+            },
+            ExecutionError::Timeout(after) => RuntimeResponse::FunctionTimedOut { after },
+            ExecutionError::Saturated(instances) => {
+                RuntimeResponse::FunctionSaturated { instances }
+            }
+            ExecutionError::RuntimeDeadError => RuntimeResponse::FunctionUnavailable,
             _ => RuntimeResponse::FunctionRuntimeError {
                 context: vec!["The runtime threw some error. Check the server logs.".to_owned()],
             },
@@ -100,3 +159,25 @@ impl From<HttpTriggerOutputs> for RuntimeResponse {
         RuntimeResponse::FunctionResponse(FunctionOutputs::Http(fnout))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn runtime_response_from_execution_error_keeps_stderr() {
+        let response = RuntimeResponse::from(ExecutionError::NonZeroExit(
+            2,
+            vec!["ReferenceError: x is not defined".to_owned()],
+        ));
+        assert_eq!(
+            response,
+            RuntimeResponse::FunctionRuntimeError {
+                context: vec![
+                    "Function exited with code 2".to_owned(),
+                    "ReferenceError: x is not defined".to_owned()
+                ]
+            }
+        );
+    }
+}
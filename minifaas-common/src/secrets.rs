@@ -0,0 +1,608 @@
+use crate::datastore::write_privately;
+use crate::permissions::is_valid_env_name;
+use aes_gcm::aead::generic_array::GenericArray;
+use aes_gcm::aead::{Aead, NewAead, Payload};
+use aes_gcm::Aes256Gcm;
+use anyhow::Result;
+use async_std::sync::{Mutex, RwLock};
+use async_std::task;
+use chrono::{DateTime, Utc};
+use hmac::Hmac;
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::PathBuf;
+use thiserror::Error;
+
+const NONCE_LEN: usize = 12;
+const SALT_LEN: usize = 16;
+/// PBKDF2-HMAC-SHA256 iterations to derive the encryption key from the master key
+const KEY_ROUNDS: u32 = 100_000;
+const RESERVED_PREFIX: &str = "__MF__";
+const REDACTED: &str = "[REDACTED]";
+
+///
+/// Errors when managing secrets or resolving a function's environment variables.
+///
+#[derive(Clone, Debug, Error, PartialEq, Eq)]
+pub enum SecretsError {
+    #[error("Invalid secret name '{0}', use letters, digits, '-', '_', and '.'")]
+    InvalidName(String),
+
+    #[error("Invalid environment variable name '{0}'")]
+    InvalidEnvName(String),
+
+    #[error(
+        "Environment variables starting with '{}' are reserved: '{0}'",
+        RESERVED_PREFIX
+    )]
+    ReservedEnvName(String),
+
+    #[error("Secret '{0}' doesn't exist")]
+    NotFound(String),
+
+    #[error("Secrets are disabled, there is no master key")]
+    NoMasterKey,
+
+    #[error("Can't decrypt secret '{0}', was the master key changed?")]
+    Decryption(String),
+
+    #[error("Can't encrypt secret '{0}'")]
+    Encryption(String),
+
+    #[error("The secrets store has an invalid salt")]
+    InvalidSalt,
+}
+
+///
+/// The value of a function's environment variable: either the value itself or a reference to a secret, e.g.
+/// `{"LOG_LEVEL": "debug", "API_KEY": {"secret": "openweathermap"}}`.
+///
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum EnvValue {
+    Plain(String),
+    Secret { secret: String },
+}
+
+impl fmt::Display for EnvValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EnvValue::Plain(value) => write!(f, "{}", value),
+            EnvValue::Secret { secret } => write!(f, "${{secret:{}}}", secret),
+        }
+    }
+}
+
+///
+/// A function's environment variables by name.
+///
+pub type FunctionEnv = BTreeMap<String, EnvValue>;
+
+///
+/// Checks that all variables can be set and all secret names are valid. Whether the secrets exist is only known
+/// when the function runs.
+///
+pub fn validate_env(env: &FunctionEnv) -> Result<(), SecretsError> {
+    for (name, value) in env.iter() {
+        if name.starts_with(RESERVED_PREFIX) {
+            return Err(SecretsError::ReservedEnvName(name.clone()));
+        }
+        if !is_valid_env_name(name) {
+            return Err(SecretsError::InvalidEnvName(name.clone()));
+        }
+        if let EnvValue::Secret { secret } = value {
+            validate_name(secret)?;
+        }
+    }
+    Ok(())
+}
+
+fn validate_name(name: &str) -> Result<(), SecretsError> {
+    if !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_.".contains(c))
+    {
+        Ok(())
+    } else {
+        Err(SecretsError::InvalidName(name.to_owned()))
+    }
+}
+
+///
+/// A function's environment variables with the secrets filled in. Keeps the secrets' values to remove them from logs.
+///
+#[derive(Default, Clone)]
+pub struct ResolvedEnv {
+    pub vars: Vec<(String, String)>,
+    secrets: Vec<String>,
+}
+
+impl ResolvedEnv {
+    ///
+    /// Replaces all secret values in `text`.
+    ///
+    pub fn redact(&self, text: &str) -> String {
+        redact(text, &self.secrets)
+    }
+}
+
+impl fmt::Debug for ResolvedEnv {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let names: Vec<&String> = self.vars.iter().map(|(name, _)| name).collect();
+        f.debug_struct("ResolvedEnv").field("vars", &names).finish()
+    }
+}
+
+///
+/// Replaces every occurrence of the `secrets` in `text` with a placeholder.
+///
+pub fn redact(text: &str, secrets: &[String]) -> String {
+    let mut secrets: Vec<&String> = secrets.iter().filter(|s| !s.is_empty()).collect();
+    // longer secrets first, in case one contains another
+    secrets.sort_by(|a, b| b.len().cmp(&a.len()));
+    secrets.into_iter().fold(text.to_owned(), |text, secret| {
+        text.replace(secret.as_str(), REDACTED)
+    })
+}
+
+///
+/// Name and last update of a secret, all the API reveals.
+///
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct SecretInfo {
+    pub name: String,
+    pub updated: DateTime<Utc>,
+}
+
+///
+/// A secret encrypted with AES-256-GCM. The name is authenticated as well, so values can't be swapped between secrets.
+///
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct EncryptedSecret {
+    nonce: String,
+    value: String,
+    updated: DateTime<Utc>,
+}
+
+type InnerStorageType = BTreeMap<String, EncryptedSecret>;
+
+///
+/// A store on disk: the random salt the key was derived with and the encrypted secrets.
+///
+#[derive(Serialize, Deserialize, Debug)]
+struct SecretsFile {
+    salt: String,
+    secrets: InnerStorageType,
+}
+
+///
+/// A key-value store for secrets that keeps them encrypted, in memory and on disk. The encryption key is derived
+/// from a master key (e.g. a passphrase from the environment) and the store's salt with PBKDF2, without a master
+/// key no secrets can be stored or read.
+///
+pub struct SecretStore {
+    store: RwLock<InnerStorageType>,
+    /// Held while the store is written to disk, so a write never overtakes a newer one
+    writing: Mutex<()>,
+    salt: [u8; SALT_LEN],
+    cipher: Option<Aes256Gcm>,
+    path: PathBuf,
+    serialize_on_write: bool,
+}
+
+impl SecretStore {
+    pub fn new<P: Into<PathBuf>>(
+        path: P,
+        master_key: Option<&str>,
+        serialize_on_write: bool,
+    ) -> Self {
+        SecretStore::with(
+            BTreeMap::new(),
+            rand::random(),
+            path,
+            master_key,
+            serialize_on_write,
+        )
+    }
+
+    fn with<P: Into<PathBuf>>(
+        map: InnerStorageType,
+        salt: [u8; SALT_LEN],
+        path: P,
+        master_key: Option<&str>,
+        serialize_on_write: bool,
+    ) -> Self {
+        let cipher = master_key.filter(|k| !k.is_empty()).map(|key| {
+            let mut derived = [0u8; 32];
+            pbkdf2::pbkdf2::<Hmac<Sha256>>(key.as_bytes(), &salt, KEY_ROUNDS, &mut derived);
+            Aes256Gcm::new(GenericArray::from_slice(&derived))
+        });
+        SecretStore {
+            store: RwLock::new(map),
+            writing: Mutex::new(()),
+            salt,
+            cipher,
+            path: path.into(),
+            serialize_on_write,
+        }
+    }
+
+    ///
+    /// Whether there is a master key to encrypt and decrypt secrets with.
+    ///
+    pub fn is_enabled(&self) -> bool {
+        self.cipher.is_some()
+    }
+
+    ///
+    /// Encrypts and stores a secret, replacing one with the same name. Nothing changes if it can't be written to disk.
+    ///
+    pub async fn set(&self, name: &str, value: &str) -> Result<()> {
+        validate_name(name)?;
+        let cipher = self.cipher.as_ref().ok_or(SecretsError::NoMasterKey)?;
+        let nonce: [u8; NONCE_LEN] = rand::random();
+        let encrypted = cipher
+            .encrypt(
+                GenericArray::from_slice(&nonce),
+                Payload {
+                    msg: value.as_bytes(),
+                    aad: name.as_bytes(),
+                },
+            )
+            .map_err(|_| SecretsError::Encryption(name.to_owned()))?;
+        let secret = EncryptedSecret {
+            nonce: base64::encode(&nonce),
+            value: base64::encode(&encrypted),
+            updated: Utc::now(),
+        };
+        let _writing = self.writing.lock().await;
+        let previous = self.store.write().await.insert(name.to_owned(), secret);
+        if let Err(e) = self.persist().await {
+            let mut store = self.store.write().await;
+            match previous {
+                Some(previous) => store.insert(name.to_owned(), previous),
+                None => store.remove(name),
+            };
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    ///
+    /// Removes a secret, returns whether it existed. Nothing changes if the removal can't be written to disk.
+    ///
+    pub async fn delete(&self, name: &str) -> Result<bool> {
+        let _writing = self.writing.lock().await;
+        let previous = self.store.write().await.remove(name);
+        match previous {
+            Some(previous) => {
+                if let Err(e) = self.persist().await {
+                    self.store.write().await.insert(name.to_owned(), previous);
+                    return Err(e);
+                }
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    pub async fn contains(&self, name: &str) -> bool {
+        self.store.read().await.contains_key(name)
+    }
+
+    ///
+    /// All secrets by name, without their values.
+    ///
+    pub async fn list(&self) -> Vec<SecretInfo> {
+        self.store
+            .read()
+            .await
+            .iter()
+            .map(|(name, secret)| SecretInfo {
+                name: name.clone(),
+                updated: secret.updated,
+            })
+            .collect()
+    }
+
+    ///
+    /// Decrypts a secret's value.
+    ///
+    pub async fn reveal(&self, name: &str) -> Result<String> {
+        let cipher = self.cipher.as_ref().ok_or(SecretsError::NoMasterKey)?;
+        let store = self.store.read().await;
+        let secret = store
+            .get(name)
+            .ok_or_else(|| SecretsError::NotFound(name.to_owned()))?;
+        let decryption_error = || SecretsError::Decryption(name.to_owned());
+        let nonce = base64::decode(&secret.nonce).map_err(|_| decryption_error())?;
+        let value = base64::decode(&secret.value).map_err(|_| decryption_error())?;
+        if nonce.len() != NONCE_LEN {
+            return Err(decryption_error().into());
+        }
+        let decrypted = cipher
+            .decrypt(
+                GenericArray::from_slice(&nonce),
+                Payload {
+                    msg: &value,
+                    aad: name.as_bytes(),
+                },
+            )
+            .map_err(|_| decryption_error())?;
+        String::from_utf8(decrypted).map_err(|_| decryption_error().into())
+    }
+
+    ///
+    /// The variables to run a function with, secrets are decrypted.
+    ///
+    pub async fn resolve(&self, env: &FunctionEnv) -> Result<ResolvedEnv> {
+        let mut resolved = ResolvedEnv::default();
+        for (name, value) in env.iter() {
+            let value = match value {
+                EnvValue::Plain(value) => value.clone(),
+                EnvValue::Secret { secret } => {
+                    let value = self.reveal(secret).await?;
+                    resolved.secrets.push(value.clone());
+                    value
+                }
+            };
+            resolved.vars.push((name.clone(), value));
+        }
+        Ok(resolved)
+    }
+
+    ///
+    /// Writes the store to disk if it's serialized on write, the caller holds `writing`.
+    ///
+    async fn persist(&self) -> Result<()> {
+        if self.serialize_on_write {
+            self.write_file().await.map_err(|e| {
+                error!("Couldn't serialize secrets to disk: {}", e);
+                e
+            })
+        } else {
+            Ok(())
+        }
+    }
+
+    ///
+    /// Writes the (encrypted) secrets and the salt to disk at the (initially) provided location, readable only by
+    /// the owner.
+    ///
+    pub async fn write_to_disk(&self) -> Result<()> {
+        let _writing = self.writing.lock().await;
+        self.write_file().await
+    }
+
+    async fn write_file(&self) -> Result<()> {
+        let buf = serde_json::to_vec(&SecretsFile {
+            salt: base64::encode(&self.salt),
+            secrets: self.store.read().await.clone(),
+        })?;
+        let path = self.path.clone();
+        task::spawn_blocking(move || write_privately(&path, &buf).map_err(|e| e.into())).await
+    }
+
+    ///
+    /// Loads the secrets from the provided path, a missing file is an empty store.
+    ///
+    pub async fn from_path<P: Into<PathBuf>>(path: P, master_key: Option<&str>) -> Result<Self> {
+        let p = path.into();
+        info!(
+            "Reading secrets from store at '{}'",
+            p.to_str().unwrap_or_default()
+        );
+        let _p = p.clone();
+        let file: Option<SecretsFile> = task::spawn_blocking(move || -> Result<_> {
+            match std::fs::read(&_p) {
+                Ok(content) => Ok(Some(serde_json::from_slice(&content)?)),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+                Err(e) => Err(e.into()),
+            }
+        })
+        .await?;
+        match file {
+            Some(file) => {
+                let salt = base64::decode(&file.salt).map_err(|_| SecretsError::InvalidSalt)?;
+                if salt.len() != SALT_LEN {
+                    return Err(SecretsError::InvalidSalt.into());
+                }
+                let mut fixed = [0u8; SALT_LEN];
+                fixed.copy_from_slice(&salt);
+                Ok(SecretStore::with(file.secrets, fixed, p, master_key, true))
+            }
+            None => Ok(SecretStore::new(p, master_key, true)),
+        }
+    }
+}
+
+impl Default for SecretStore {
+    ///
+    /// An in-memory store without master key, i.e. without secrets.
+    ///
+    fn default() -> Self {
+        SecretStore::new(PathBuf::new(), None, false)
+    }
+}
+
+impl fmt::Debug for SecretStore {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SecretStore")
+            .field("path", &self.path)
+            .field("enabled", &self.is_enabled())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use minifaas_test::get_empty_tmp_dir;
+
+    fn secrets_error(e: anyhow::Error) -> SecretsError {
+        e.downcast::<SecretsError>().unwrap()
+    }
+
+    #[async_std::test]
+    async fn test_secret_store_roundtrip_encrypted() {
+        let p = get_empty_tmp_dir().join("secrets.db");
+        let store = SecretStore::from_path(&p, Some("a master key"))
+            .await
+            .unwrap();
+        store.set("weather", "s3cr3t-v4lue").await.unwrap();
+        assert_eq!(store.reveal("weather").await.unwrap(), "s3cr3t-v4lue");
+        assert_eq!(store.list().await[0].name, "weather");
+
+        let on_disk = std::fs::read_to_string(&p).unwrap();
+        assert!(on_disk.contains("weather"));
+        assert!(!on_disk.contains("s3cr3t-v4lue"));
+
+        #[cfg(target_family = "unix")]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(&p).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+
+        let reloaded = SecretStore::from_path(&p, Some("a master key"))
+            .await
+            .unwrap();
+        assert_eq!(reloaded.reveal("weather").await.unwrap(), "s3cr3t-v4lue");
+
+        let wrong_key = SecretStore::from_path(&p, Some("another key"))
+            .await
+            .unwrap();
+        assert_eq!(
+            secrets_error(wrong_key.reveal("weather").await.unwrap_err()),
+            SecretsError::Decryption("weather".to_owned())
+        );
+
+        assert!(reloaded.delete("weather").await.unwrap());
+        assert!(!reloaded.delete("weather").await.unwrap());
+        assert_eq!(
+            secrets_error(reloaded.reveal("weather").await.unwrap_err()),
+            SecretsError::NotFound("weather".to_owned())
+        );
+    }
+
+    #[async_std::test]
+    async fn test_secret_store_keeps_changes_that_can_be_written() {
+        let dir = get_empty_tmp_dir().join("secrets");
+        std::fs::create_dir_all(&dir).unwrap();
+        let store = SecretStore::new(dir.join("secrets.db"), Some("key"), true);
+        store.set("weather", "value").await.unwrap();
+
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(store.set("weather", "changed").await.is_err());
+        assert!(store.set("added", "value").await.is_err());
+        assert!(store.delete("weather").await.is_err());
+        assert_eq!(store.reveal("weather").await.unwrap(), "value");
+        assert!(!store.contains("added").await);
+    }
+
+    #[async_std::test]
+    async fn test_secret_store_salts_the_key() {
+        let dir = get_empty_tmp_dir();
+        let (a, b) = (dir.join("a.db"), dir.join("b.db"));
+        for p in vec![&a, &b] {
+            let store = SecretStore::from_path(p, Some("key")).await.unwrap();
+            store.set("weather", "value").await.unwrap();
+        }
+        let salt = |p: &PathBuf| -> String {
+            let file: SecretsFile = serde_json::from_slice(&std::fs::read(p).unwrap()).unwrap();
+            assert_eq!(base64::decode(&file.salt).unwrap().len(), SALT_LEN);
+            file.salt
+        };
+        assert_ne!(salt(&a), salt(&b));
+
+        // a copy takes its salt along, with another salt the key doesn't match
+        std::fs::copy(&b, &a).unwrap();
+        let store = SecretStore::from_path(&a, Some("key")).await.unwrap();
+        assert_eq!(store.reveal("weather").await.unwrap(), "value");
+        let mut file: SecretsFile = serde_json::from_slice(&std::fs::read(&a).unwrap()).unwrap();
+        file.salt = base64::encode(&[0u8; SALT_LEN]);
+        std::fs::write(&a, serde_json::to_vec(&file).unwrap()).unwrap();
+        let store = SecretStore::from_path(&a, Some("key")).await.unwrap();
+        assert_eq!(
+            secrets_error(store.reveal("weather").await.unwrap_err()),
+            SecretsError::Decryption("weather".to_owned())
+        );
+    }
+
+    #[async_std::test]
+    async fn test_secret_store_without_master_key() {
+        let store = SecretStore::default();
+        assert!(!store.is_enabled());
+        assert_eq!(
+            secrets_error(store.set("weather", "value").await.unwrap_err()),
+            SecretsError::NoMasterKey
+        );
+        assert!(store.list().await.is_empty());
+    }
+
+    #[async_std::test]
+    async fn test_secret_store_resolve_and_redact() {
+        let store = SecretStore::new(PathBuf::new(), Some("key"), false);
+        store.set("weather", "abc123").await.unwrap();
+        let env: FunctionEnv =
+            serde_json::from_str(r#"{"LOG_LEVEL": "debug", "API_KEY": {"secret": "weather"}}"#)
+                .unwrap();
+        assert_eq!(validate_env(&env), Ok(()));
+
+        let resolved = store.resolve(&env).await.unwrap();
+        assert_eq!(
+            resolved.vars,
+            vec![
+                ("API_KEY".to_owned(), "abc123".to_owned()),
+                ("LOG_LEVEL".to_owned(), "debug".to_owned())
+            ]
+        );
+        assert_eq!(
+            resolved.redact("key=abc123, level=debug"),
+            "key=[REDACTED], level=debug"
+        );
+        assert!(!format!("{:?}", resolved).contains("abc123"));
+
+        let missing: FunctionEnv =
+            serde_json::from_str(r#"{"API_KEY": {"secret": "missing"}}"#).unwrap();
+        assert_eq!(
+            secrets_error(store.resolve(&missing).await.unwrap_err()),
+            SecretsError::NotFound("missing".to_owned())
+        );
+    }
+
+    #[test]
+    fn test_validate_env() {
+        let mut env = FunctionEnv::new();
+        env.insert("__MF__INPUTS".to_owned(), EnvValue::Plain("x".to_owned()));
+        assert_eq!(
+            validate_env(&env),
+            Err(SecretsError::ReservedEnvName("__MF__INPUTS".to_owned()))
+        );
+
+        let mut env = FunctionEnv::new();
+        env.insert("A=B".to_owned(), EnvValue::Plain("x".to_owned()));
+        assert_eq!(
+            validate_env(&env),
+            Err(SecretsError::InvalidEnvName("A=B".to_owned()))
+        );
+
+        let mut env = FunctionEnv::new();
+        env.insert(
+            "API_KEY".to_owned(),
+            EnvValue::Secret {
+                secret: "../x".to_owned(),
+            },
+        );
+        assert_eq!(
+            validate_env(&env),
+            Err(SecretsError::InvalidName("../x".to_owned()))
+        );
+        assert_eq!(env["API_KEY"].to_string(), "${secret:../x}".to_owned());
+    }
+}
@@ -2,7 +2,7 @@
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 
-#[derive(Serialize, Deserialize, Debug, Copy, Clone, Eq, PartialEq)]
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, Eq, PartialEq, Hash)]
 pub enum HttpMethod {
     GET,
     HEAD,
@@ -44,6 +44,7 @@ impl HttpMethod {
 pub struct HttpTrigger {
     pub route: String,
     pub method: HttpMethod,
+    pub path_params: HashMap<String, String>,
     pub params: HashMap<String, Option<Vec<String>>>,
     pub headers: HashMap<String, Option<String>>,
     pub body: Vec<u8>,
@@ -57,6 +58,32 @@ pub struct HttpTriggerOutputs {
     pub status_code: u16,
 }
 
+impl HttpTriggerOutputs {
+    ///
+    /// An empty response with only a status code.
+    ///
+    pub fn with_status(status_code: u16) -> Self {
+        HttpTriggerOutputs {
+            status_code,
+            ..Default::default()
+        }
+    }
+
+    ///
+    /// A 405 response that lists the allowed methods in the `Allow` header.
+    ///
+    pub fn method_not_allowed(allowed: &[HttpMethod]) -> Self {
+        let allow = allowed
+            .iter()
+            .map(|m| format!("{:?}", m))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let mut outputs = HttpTriggerOutputs::with_status(405);
+        outputs.headers.insert("Allow".to_owned(), Some(allow));
+        outputs
+    }
+}
+
 impl From<HashMap<String, Vec<u8>>> for HttpTriggerOutputs {
     fn from(map: HashMap<String, Vec<u8>>) -> Self {
         let mut map = map;
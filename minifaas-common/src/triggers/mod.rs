@@ -1,5 +1,6 @@
 use serde::{Deserialize, Serialize};
 pub mod http;
+pub mod route;
 pub mod timer;
 use http::HttpMethod;
 
@@ -0,0 +1,200 @@
+use std::collections::HashMap;
+use thiserror::Error;
+
+const PARAM_PREFIX: char = ':';
+const WILDCARD_PREFIX: char = '*';
+
+///
+/// Errors in a route pattern declaration.
+///
+#[derive(Clone, Debug, Error, PartialEq, Eq)]
+pub enum RoutePatternError {
+    #[error("Route parameters need a name: '{0}'")]
+    UnnamedParameter(String),
+
+    #[error("Route parameter '{0}' is declared more than once")]
+    DuplicateParameter(String),
+
+    #[error("Wildcards ('{0}') are only allowed as the last segment")]
+    WildcardNotLast(String),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Segment {
+    Literal(String),
+    Param(String),
+    Wildcard(String),
+}
+
+impl Segment {
+    ///
+    /// How specific a segment is. Literals beat parameters, which beat wildcards.
+    ///
+    fn rank(&self) -> u8 {
+        match self {
+            Segment::Literal(_) => 2,
+            Segment::Param(_) => 1,
+            Segment::Wildcard(_) => 0,
+        }
+    }
+}
+
+///
+/// A route an HTTP function listens on, e.g. `weather/:city/*rest`.
+/// `:name` matches exactly one path segment, `*name` matches all remaining segments (including none).
+///
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RoutePattern {
+    pattern: String,
+    segments: Vec<Segment>,
+}
+
+fn split_path(path: &str) -> Vec<&str> {
+    path.split('/').filter(|s| !s.is_empty()).collect()
+}
+
+impl RoutePattern {
+    pub fn parse(pattern: &str) -> Result<Self, RoutePatternError> {
+        let parts = split_path(pattern);
+        let mut segments = Vec::with_capacity(parts.len());
+        let mut names = vec![];
+        for (i, part) in parts.iter().enumerate() {
+            let segment = if let Some(name) = part.strip_prefix(PARAM_PREFIX) {
+                Segment::Param(name.to_owned())
+            } else if let Some(name) = part.strip_prefix(WILDCARD_PREFIX) {
+                if i != parts.len() - 1 {
+                    return Err(RoutePatternError::WildcardNotLast(part.to_string()));
+                }
+                Segment::Wildcard(name.to_owned())
+            } else {
+                Segment::Literal(part.to_string())
+            };
+
+            if let Segment::Param(name) | Segment::Wildcard(name) = &segment {
+                if name.is_empty() {
+                    return Err(RoutePatternError::UnnamedParameter(pattern.to_owned()));
+                }
+                if names.contains(name) {
+                    return Err(RoutePatternError::DuplicateParameter(name.clone()));
+                }
+                names.push(name.clone());
+            }
+            segments.push(segment);
+        }
+        Ok(RoutePattern {
+            pattern: parts.join("/"),
+            segments,
+        })
+    }
+
+    ///
+    /// The normalized pattern without leading or trailing slashes.
+    ///
+    pub fn as_str(&self) -> &str {
+        &self.pattern
+    }
+
+    ///
+    /// Matches a request path and returns the extracted path parameters.
+    ///
+    pub fn matches(&self, path: &str) -> Option<HashMap<String, String>> {
+        let parts = split_path(path);
+        let mut params = HashMap::new();
+        for (i, segment) in self.segments.iter().enumerate() {
+            match segment {
+                Segment::Literal(literal) => {
+                    if parts.get(i) != Some(&literal.as_str()) {
+                        return None;
+                    }
+                }
+                Segment::Param(name) => {
+                    params.insert(name.clone(), parts.get(i)?.to_string());
+                }
+                Segment::Wildcard(name) => {
+                    params.insert(name.clone(), parts[i..].join("/"));
+                    return Some(params);
+                }
+            }
+        }
+        if parts.len() == self.segments.len() {
+            Some(params)
+        } else {
+            None
+        }
+    }
+
+    ///
+    /// Ranks of all segments, compare them to find the most specific pattern.
+    ///
+    pub fn specificity(&self) -> Vec<u8> {
+        self.segments.iter().map(Segment::rank).collect()
+    }
+}
+
+impl std::fmt::Display for RoutePattern {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.pattern)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn params(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_route_pattern_literals() {
+        let p = RoutePattern::parse("/hello/world/").unwrap();
+        assert_eq!(p.as_str(), "hello/world");
+        assert_eq!(p.matches("hello/world"), Some(HashMap::new()));
+        assert_eq!(p.matches("/hello/world/"), Some(HashMap::new()));
+        assert_eq!(p.matches("hello"), None);
+        assert_eq!(p.matches("hello/world/again"), None);
+    }
+
+    #[test]
+    fn test_route_pattern_params_and_wildcards() {
+        let p = RoutePattern::parse("weather/:city/*rest").unwrap();
+        assert_eq!(
+            p.matches("weather/vienna/today/hourly"),
+            Some(params(&[("city", "vienna"), ("rest", "today/hourly")]))
+        );
+        assert_eq!(
+            p.matches("weather/vienna"),
+            Some(params(&[("city", "vienna"), ("rest", "")]))
+        );
+        assert_eq!(p.matches("weather"), None);
+        assert_eq!(p.matches("climate/vienna"), None);
+    }
+
+    #[test]
+    fn test_route_pattern_invalid() {
+        assert_eq!(
+            RoutePattern::parse("a/*rest/b"),
+            Err(RoutePatternError::WildcardNotLast("*rest".to_owned()))
+        );
+        assert_eq!(
+            RoutePattern::parse("a/:/b"),
+            Err(RoutePatternError::UnnamedParameter("a/:/b".to_owned()))
+        );
+        assert_eq!(
+            RoutePattern::parse("a/:x/:x"),
+            Err(RoutePatternError::DuplicateParameter("x".to_owned()))
+        );
+    }
+
+    #[test]
+    fn test_route_pattern_specificity() {
+        let literal = RoutePattern::parse("weather/vienna").unwrap();
+        let param = RoutePattern::parse("weather/:city").unwrap();
+        let wildcard = RoutePattern::parse("weather/*rest").unwrap();
+        assert!(literal.specificity() > param.specificity());
+        assert!(param.specificity() > wildcard.specificity());
+    }
+}
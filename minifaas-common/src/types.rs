@@ -1,29 +1,81 @@
+use crate::limits::ResourceLimits;
+use crate::permissions::Permissions;
 use crate::runtime::FunctionCode;
+use crate::secrets::FunctionEnv;
 use crate::triggers::Trigger;
 use serde::{Deserialize, Serialize};
 
 ///
 /// The programming language the FaaS function is created with. There should be a runtime available for each of the variants except `Unknown`.
 ///
-#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq, Hash)]
-#[serde(tag = "lang")]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+#[serde(from = "LanguageTag", into = "LanguageTag")]
 pub enum ProgrammingLanguage {
     /// Vanilla JavaScript
     JavaScript,
 
+    /// JavaScript running on Node.js
+    NodeJs,
+
+    /// Python 3 scripts
+    Python,
+
     /// Good old bash scripts
     Bash,
 
+    /// An interpreter that was configured by the admin (e.g. Ruby or Lua), identified by its name
+    Command(String),
+
     /// No known Programming language
     Unknown,
 }
 
+///
+/// The serialized form of a `ProgrammingLanguage`: `{"lang": "<name>"}`. Names that aren't built in refer to configured commands.
+///
+#[derive(Serialize, Deserialize)]
+struct LanguageTag {
+    lang: String,
+}
+
+impl From<LanguageTag> for ProgrammingLanguage {
+    fn from(tag: LanguageTag) -> Self {
+        match tag.lang.as_str() {
+            "JavaScript" => ProgrammingLanguage::JavaScript,
+            "NodeJs" => ProgrammingLanguage::NodeJs,
+            "Python" => ProgrammingLanguage::Python,
+            "Bash" => ProgrammingLanguage::Bash,
+            "Unknown" | "" => ProgrammingLanguage::Unknown,
+            _ => ProgrammingLanguage::Command(tag.lang),
+        }
+    }
+}
+
+impl From<&str> for ProgrammingLanguage {
+    fn from(lang: &str) -> Self {
+        ProgrammingLanguage::from(LanguageTag {
+            lang: lang.to_owned(),
+        })
+    }
+}
+
+impl From<ProgrammingLanguage> for LanguageTag {
+    fn from(lang: ProgrammingLanguage) -> Self {
+        LanguageTag {
+            lang: lang.to_string(),
+        }
+    }
+}
+
 impl std::fmt::Display for ProgrammingLanguage {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let text = match &self {
-            ProgrammingLanguage::JavaScript => "JavaScript".to_owned(),
-            ProgrammingLanguage::Bash => "Bash".to_owned(),
-            ProgrammingLanguage::Unknown => "Unknown".to_owned(),
+            ProgrammingLanguage::JavaScript => "JavaScript",
+            ProgrammingLanguage::NodeJs => "NodeJs",
+            ProgrammingLanguage::Python => "Python",
+            ProgrammingLanguage::Bash => "Bash",
+            ProgrammingLanguage::Command(name) => name,
+            ProgrammingLanguage::Unknown => "Unknown",
         };
         write!(f, "{}", text)
     }
@@ -36,8 +88,16 @@ impl Default for ProgrammingLanguage {
 }
 
 impl ProgrammingLanguage {
+    ///
+    /// The built-in languages.
+    ///
     pub fn available() -> Vec<Self> {
-        vec![ProgrammingLanguage::JavaScript, ProgrammingLanguage::Bash]
+        vec![
+            ProgrammingLanguage::JavaScript,
+            ProgrammingLanguage::NodeJs,
+            ProgrammingLanguage::Python,
+            ProgrammingLanguage::Bash,
+        ]
     }
 }
 
@@ -50,4 +110,68 @@ pub struct UserFunctionDeclaration {
     #[serde(flatten)]
     pub code: FunctionCode,
     pub trigger: Trigger,
+
+    /// The route pattern an HTTP trigger listens on (e.g. `weather/:city/*rest`), defaults to the function's name
+    #[serde(default)]
+    pub route: Option<String>,
+
+    /// Overrides the runtime's maximum execution time (in seconds) for this function
+    #[serde(default)]
+    pub timeout_secs: Option<u64>,
+
+    /// The number of executors that are kept running for this function, defaults to one
+    #[serde(default)]
+    pub min_instances: Option<usize>,
+
+    /// The maximum number of concurrent executors for this function, defaults to the runtime's limit
+    #[serde(default)]
+    pub max_instances: Option<usize>,
+
+    /// What the function may access (network, files, environment), only supported by JavaScript (Deno)
+    #[serde(default, skip_serializing_if = "Permissions::is_empty")]
+    pub permissions: Permissions,
+
+    /// Environment variables the function runs with, values can reference secrets
+    #[serde(default, skip_serializing_if = "FunctionEnv::is_empty")]
+    pub env: FunctionEnv,
+
+    /// Resource limits (memory, CPU time, ...) for the function's process, capped by the runtime's limits
+    #[serde(default, skip_serializing_if = "ResourceLimits::is_empty")]
+    pub limits: ResourceLimits,
+
+    /// Runs the function outside the runtime's sandbox, with the runtime's privileges and network access
+    #[serde(default)]
+    pub trusted: bool,
+
+    /// Keeps a resident worker process for the function between calls (JavaScript only, if the runtime allows it)
+    #[serde(default)]
+    pub warm: bool,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn programming_language_serde_roundtrip() {
+        for lang in ProgrammingLanguage::available().into_iter().chain(vec![
+            ProgrammingLanguage::Command("Ruby".to_owned()),
+            ProgrammingLanguage::Unknown,
+        ]) {
+            let json = serde_json::to_string(&lang).unwrap();
+            assert_eq!(json, format!("{{\"lang\":\"{}\"}}", lang));
+            assert_eq!(
+                serde_json::from_str::<ProgrammingLanguage>(&json).unwrap(),
+                lang
+            );
+        }
+    }
+
+    #[test]
+    fn programming_language_unknown_names_are_commands() {
+        assert_eq!(
+            serde_json::from_str::<ProgrammingLanguage>(r#"{"lang": "Lua"}"#).unwrap(),
+            ProgrammingLanguage::Command("Lua".to_owned())
+        );
+    }
 }
@@ -1,4 +1,5 @@
-use crate::ext::toolchain::ActiveToolchain;
+use crate::ext::process::{StdoutStream, DEFAULT_MAX_OUTPUT_BYTES};
+use crate::ext::toolchain::Toolchain;
 use crate::logs::collectors::FileLogCollector;
 use crate::logs::collectors::LogCollector;
 use crate::output_parser::Parser;
@@ -7,41 +8,115 @@ use crate::output_parser::STDOUT_PREFIX;
 use crate::OpsMsg;
 use anyhow::Result;
 use async_std::sync::Arc;
+use futures::channel::mpsc::Receiver;
+use futures::StreamExt;
 use log::{debug, info};
 use minifaas_common::runtime::{RawFunctionInput, RawFunctionOutputWrapper};
-use minifaas_common::Environment;
+use minifaas_common::secrets::ResolvedEnv;
 use minifaas_common::UserFunctionRecord;
-use std::io::Cursor;
+use minifaas_common::{Environment, SecretStore};
+use std::collections::HashMap;
+use std::time::Duration;
 use uuid::Uuid;
 use xactor::*;
 
 use super::EnvironmentIdMsg;
 
+///
+/// How many lines of stdout go to the log at once, if they arrive faster than they can be written.
+///
+const LOG_BATCH_LINES: usize = 64;
+
 pub struct FunctionExecutor {
     environment: Environment,
     code: Arc<Box<UserFunctionRecord>>,
-    toolchain: Arc<ActiveToolchain>,
+    toolchain: Arc<dyn Toolchain>,
     log_collector: Arc<FileLogCollector>,
+    timeout: Duration,
+    secrets: Arc<SecretStore>,
+    max_output_bytes: u64,
 }
 
 impl FunctionExecutor {
     pub fn new(
         environment: Environment,
         code: Arc<Box<UserFunctionRecord>>,
-        toolchain: Arc<ActiveToolchain>,
+        toolchain: Arc<dyn Toolchain>,
         log_collector: Arc<FileLogCollector>,
+        timeout: Duration,
     ) -> Self {
         info!(
-            "Function executor for {} started. Toolchain {:?}",
+            "Function executor for {} started. Toolchain {:?}, timeout {:?}",
             code.name(),
-            toolchain
+            toolchain,
+            timeout
         );
         FunctionExecutor {
             code,
             environment,
             toolchain,
             log_collector,
+            timeout,
+            secrets: Arc::new(SecretStore::default()),
+            max_output_bytes: DEFAULT_MAX_OUTPUT_BYTES,
+        }
+    }
+
+    ///
+    /// Looks up the secrets the function's environment variables refer to in `secrets` (on every call).
+    ///
+    pub fn with_secrets(mut self, secrets: Arc<SecretStore>) -> Self {
+        self.secrets = secrets;
+        self
+    }
+
+    ///
+    /// Kills functions that write more than `max_output_bytes` to stdout.
+    ///
+    pub fn with_max_output_bytes(mut self, max_output_bytes: u64) -> Self {
+        self.max_output_bytes = max_output_bytes;
+        self
+    }
+
+    ///
+    /// Parses the outputs in `lines` of stdout into `outputs` and writes the lines to the function's log.
+    ///
+    async fn collect_lines<L: AsRef<[u8]>>(
+        &self,
+        lines: &[L],
+        parser: &Parser,
+        env: &ResolvedEnv,
+        outputs: &mut HashMap<String, Vec<u8>>,
+    ) -> Result<()> {
+        let mut logs = String::new();
+        for line in lines.iter().map(|l| l.as_ref()) {
+            if let Some((key, value)) = parser.parse_line(line) {
+                outputs.insert(key, value);
+            }
+            logs.push_str(&String::from_utf8_lossy(line));
         }
+        // secrets neither end up in the logs nor in errors
+        self.log_collector
+            .collect(&env.redact(&logs), &self.environment)
+            .await
+    }
+
+    ///
+    /// Collects stdout while the function is running.
+    ///
+    async fn collect_stdout(
+        &self,
+        lines: Receiver<Vec<u8>>,
+        parser: &Parser,
+        env: &ResolvedEnv,
+        outputs: &mut HashMap<String, Vec<u8>>,
+    ) -> Result<()> {
+        // lines that arrived while the last batch was written are written together
+        let mut batches = lines.ready_chunks(LOG_BATCH_LINES);
+        while let Some(batch) = batches.next().await {
+            self.collect_lines(&batch, parser, env, outputs).await?;
+        }
+        Ok(())
     }
 }
 
@@ -72,16 +147,42 @@ impl Handler<RawFunctionInput> for FunctionExecutor {
         );
         let bytes = self.toolchain.build(&self.code.code().code).await?;
         debug!("Built!");
-        let stdout = self
-            .toolchain
-            .execute(bytes, Arc::new(msg), &self.environment)
-            .await?;
-        self.log_collector
-            .collect(&stdout, &self.environment)
-            .await?;
-        let output = p.parse_to_map(Cursor::new(stdout))?;
-        debug!("Function output: {:?}", output);
-        Ok(RawFunctionOutputWrapper::from(output))
+        let env = self.secrets.resolve(self.code.env()).await?;
+        let (stdout, lines) = StdoutStream::new(self.max_output_bytes);
+        let mut outputs = HashMap::new();
+        let execution = self.toolchain.execute(
+            bytes,
+            Arc::new(msg),
+            env.vars.clone(),
+            &self.environment,
+            self.timeout,
+            stdout,
+        );
+        let collection = self.collect_stdout(lines, &p, &env, &mut outputs);
+        let (output, collected) = futures::join!(execution, collection);
+        collected?;
+        let mut output = output?;
+        // toolchains that don't stream return stdout in one piece
+        if !output.stdout.is_empty() {
+            let stdout = std::mem::take(&mut output.stdout);
+            let lines: Vec<&[u8]> = stdout.split_inclusive(|b| *b == b'\n').collect();
+            self.collect_lines(&lines, &p, &env, &mut outputs).await?;
+        }
+        if !output.stderr.is_empty() {
+            output.stderr = env
+                .redact(&String::from_utf8_lossy(&output.stderr))
+                .into_bytes();
+            self.log_collector
+                .collect(&String::from_utf8_lossy(&output.stderr), &self.environment)
+                .await?;
+        }
+        if !output.success() {
+            let error = self.toolchain.failure(&output);
+            info!("Function '{}' failed: {}", self.code.name(), error);
+            return Err(error.into());
+        }
+        debug!("Function output: {:?}", outputs);
+        Ok(RawFunctionOutputWrapper::from(outputs))
     }
 }
 
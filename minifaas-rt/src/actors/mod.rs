@@ -1,6 +1,7 @@
+use minifaas_common::archive::EnvironmentContents;
 use minifaas_common::ProgrammingLanguage;
 
-use minifaas_common::triggers::http::HttpMethod;
+use minifaas_common::triggers::http::{HttpMethod, HttpTrigger};
 use minifaas_common::UserFunctionRecord;
 use uuid::Uuid;
 use xactor::*;
@@ -8,24 +9,36 @@ use xactor::*;
 use async_std::sync::Arc;
 
 mod function_executor;
+mod pool;
 mod runtime_controller;
 mod triggered;
 use cron::Schedule;
 pub use function_executor::FunctionExecutor;
+pub use pool::{ExecutorPool, PoolLimits};
 pub use runtime_controller::RuntimeController;
-pub use triggered::{HttpTriggered, TimerTriggered};
+pub use triggered::{HttpRoute, HttpTriggered, TimerTriggered};
 
 #[message(result = "anyhow::Result<()>")]
 pub struct SetupMsg {
     pub env_id: Uuid,
     pub toolchain: ProgrammingLanguage,
+    /// The toolchain version to install, `None` for the default
+    pub version: Option<String>,
 }
 
 #[message(result = "anyhow::Result<()>")]
 pub struct DestroyMsg {
     pub env_id: Uuid,
+    /// Archive the logs instead of deleting them with the environment
+    pub keep_logs: bool,
 }
 
+///
+/// Removes the shared toolchain versions no environment uses and returns them as `(toolchain, version)`.
+///
+#[message(result = "anyhow::Result<Vec<(String, String)>>")]
+pub struct CollectToolchainsMsg;
+
 #[message(result = "anyhow::Result<String>")]
 pub struct LogsMsg {
     pub env_id: Uuid,
@@ -33,6 +46,25 @@ pub struct LogsMsg {
     pub lines: usize,
 }
 
+///
+/// Reads an environment's logs and/or its other files, an unknown environment is empty.
+///
+#[message(result = "anyhow::Result<EnvironmentContents>")]
+pub struct ExportEnvMsg {
+    pub env_id: Uuid,
+    pub logs: bool,
+    pub files: bool,
+}
+
+///
+/// Writes logs and files into an environment, replacing existing logs.
+///
+#[message(result = "anyhow::Result<()>")]
+pub struct RestoreEnvMsg {
+    pub env_id: Uuid,
+    pub contents: EnvironmentContents,
+}
+
 #[message(result = "anyhow::Result<()>")]
 pub struct StartExecutorMsg {
     pub code: Arc<Box<UserFunctionRecord>>,
@@ -43,28 +75,39 @@ pub struct StopExecutorMsg {
     pub code: Arc<Box<UserFunctionRecord>>,
 }
 
-#[message]
+///
+/// (Un-)subscribes a function's executors to HTTP calls. A route and method belong to one function (environment id),
+/// subscribing another one to them fails.
+///
+#[message(result = "anyhow::Result<()>")]
 #[derive(Clone)]
 pub enum HttpTriggerMsg {
     Subscribe {
+        env_id: Uuid,
         route: String,
-        addr: Addr<FunctionExecutor>,
+        pool: ExecutorPool,
         method: HttpMethod,
     },
     Unsubscribe {
-        route: String,
+        env_id: Uuid,
     },
 }
 
+///
+/// Looks up the executors for an HTTP call without running it, so calls don't wait for each other in `HttpTriggered`.
+///
+#[message(result = "HttpRoute")]
+pub struct ResolveHttpMsg(pub HttpTrigger);
+
 #[message]
 pub enum IntervalTriggerMsg {
     Subscribe {
+        env_id: Uuid,
         schedule: Schedule,
-        addr: Addr<FunctionExecutor>,
+        pool: ExecutorPool,
     },
     Unsubscribe {
-        schedule: Schedule,
-        addr: Addr<FunctionExecutor>,
+        env_id: Uuid,
     },
 }
 
@@ -0,0 +1,413 @@
+use crate::{FunctionExecutor, OpsMsg};
+use anyhow::Result;
+use async_std::sync::{Arc, Mutex, Weak};
+use async_std::task;
+use log::{debug, error, info};
+use minifaas_common::errors::ExecutionError;
+use minifaas_common::runtime::{RawFunctionInput, RawFunctionOutputWrapper};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+use xactor::*;
+
+type ExecutorFactory = Arc<dyn Fn() -> FunctionExecutor + Send + Sync>;
+
+///
+/// How long an executor may go without calls before it's stopped (unless the pool is at `min_instances`).
+///
+pub const DEFAULT_IDLE_TIMEOUT: Duration = Duration::from_secs(300);
+
+///
+/// How long a call waits before it looks for an executor again when all of them are still starting.
+///
+const STARTING_POLL_MS: u64 = 10;
+
+///
+/// How many executors a function may have and how many calls may wait for them.
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PoolLimits {
+    pub min_instances: usize,
+    pub max_instances: usize,
+    pub max_queue_length: usize,
+    pub idle_timeout: Duration,
+}
+
+impl PoolLimits {
+    pub fn new(min_instances: usize, max_instances: usize, max_queue_length: usize) -> Self {
+        // there has to be at least one executor to call
+        let max_instances = max_instances.max(1);
+        PoolLimits {
+            min_instances: min_instances.min(max_instances),
+            max_instances,
+            max_queue_length,
+            idle_timeout: DEFAULT_IDLE_TIMEOUT,
+        }
+    }
+
+    ///
+    /// Stops executors beyond `min_instances` once they had no calls for `idle_timeout`.
+    ///
+    pub fn with_idle_timeout(mut self, idle_timeout: Duration) -> Self {
+        self.idle_timeout = idle_timeout;
+        self
+    }
+}
+
+///
+/// How busy an executor is and since when it's idle.
+///
+struct Usage {
+    in_flight: AtomicUsize,
+    last_used: std::sync::Mutex<Instant>,
+}
+
+struct Instance {
+    addr: Addr<FunctionExecutor>,
+    usage: Arc<Usage>,
+}
+
+impl Instance {
+    fn load(&self) -> usize {
+        self.usage.in_flight.load(Ordering::SeqCst)
+    }
+
+    fn is_idle_for(&self, timeout: Duration) -> bool {
+        self.load() == 0 && self.usage.last_used.lock().unwrap().elapsed() >= timeout
+    }
+}
+
+#[derive(Default)]
+struct PoolState {
+    instances: Vec<Instance>,
+    /// Executors that are being started for a call
+    starting: usize,
+    closed: bool,
+}
+
+impl PoolState {
+    ///
+    /// Removes the executors that have been idle for too long, as long as there are more than `min_instances`.
+    ///
+    fn take_idle(&mut self, limits: &PoolLimits) -> Vec<Instance> {
+        let mut idle = vec![];
+        while self.instances.len() > limits.min_instances {
+            match self
+                .instances
+                .iter()
+                .position(|i| i.is_idle_for(limits.idle_timeout))
+            {
+                Some(idx) => idle.push(self.instances.remove(idx)),
+                None => break,
+            }
+        }
+        idle
+    }
+}
+
+///
+/// Marks a call as in flight on an executor for as long as it's alive.
+///
+struct InFlight(Arc<Usage>);
+
+impl InFlight {
+    fn new(usage: Arc<Usage>) -> Self {
+        usage.in_flight.fetch_add(1, Ordering::SeqCst);
+        InFlight(usage)
+    }
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        *self.0.last_used.lock().unwrap() = Instant::now();
+        self.0.in_flight.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+async fn shutdown_all(instances: Vec<Instance>) {
+    for instance in instances {
+        // ignore result since it may be an error due to the executor already being shut down
+        let _ = instance.addr.call(OpsMsg::Shutdown).await.map_err(|e| {
+            error!("While shutting down an executor: {:?}. Ignoring.", e);
+        });
+    }
+}
+
+///
+/// Stops idle executors of `pool` every now and then, until the pool is shut down or dropped.
+///
+fn evict_idle_instances(pool: Weak<Mutex<PoolState>>, limits: PoolLimits) {
+    let interval = (limits.idle_timeout / 2).max(Duration::from_millis(STARTING_POLL_MS));
+    task::spawn(async move {
+        loop {
+            task::sleep(interval).await;
+            let state = match pool.upgrade() {
+                Some(state) => state,
+                None => break,
+            };
+            let idle = {
+                let mut state = state.lock().await;
+                if state.closed {
+                    break;
+                }
+                state.take_idle(&limits)
+            };
+            if !idle.is_empty() {
+                debug!("Stopping {} idle executors", idle.len());
+                shutdown_all(idle).await;
+            }
+        }
+    });
+}
+
+///
+/// The executors of a single function. Calls go to the least busy executor and new executors are started on demand
+/// until `max_instances` is reached. Once all executors are busy, up to `max_queue_length` calls wait in their
+/// mailboxes, any calls beyond that are rejected with `ExecutionError::Saturated`. Executors beyond `min_instances` are
+/// stopped again once they were idle for `idle_timeout`.
+///
+#[derive(Clone)]
+pub struct ExecutorPool {
+    state: Arc<Mutex<PoolState>>,
+    factory: ExecutorFactory,
+    limits: PoolLimits,
+}
+
+impl ExecutorPool {
+    ///
+    /// Creates a pool and starts `min_instances` executors using `factory`.
+    ///
+    pub async fn start<F>(limits: PoolLimits, factory: F) -> Result<Self>
+    where
+        F: Fn() -> FunctionExecutor + Send + Sync + 'static,
+    {
+        let pool = ExecutorPool {
+            state: Arc::new(Mutex::new(PoolState::default())),
+            factory: Arc::new(factory),
+            limits,
+        };
+        {
+            let mut state = pool.state.lock().await;
+            for _ in 0..limits.min_instances {
+                let instance = pool.start_instance().await?;
+                state.instances.push(instance);
+            }
+        }
+        evict_idle_instances(Arc::downgrade(&pool.state), limits);
+        Ok(pool)
+    }
+
+    async fn start_instance(&self) -> Result<Instance> {
+        let addr = (self.factory)().start().await?;
+        Ok(Instance {
+            addr,
+            usage: Arc::new(Usage {
+                in_flight: AtomicUsize::new(0),
+                last_used: std::sync::Mutex::new(Instant::now()),
+            }),
+        })
+    }
+
+    ///
+    /// Picks the executor for the next call, starting a new one if all existing executors are busy.
+    ///
+    async fn acquire(&self) -> Result<(Addr<FunctionExecutor>, InFlight)> {
+        loop {
+            let mut state = self.state.lock().await;
+            if state.closed {
+                return Err(ExecutionError::RuntimeDeadError.into());
+            }
+            let least_busy = state
+                .instances
+                .iter()
+                .enumerate()
+                .min_by_key(|(_, i)| i.load())
+                .map(|(idx, i)| (idx, i.load()));
+
+            let idx = match least_busy {
+                Some((idx, 0)) => idx,
+                _ if state.instances.len() + state.starting < self.limits.max_instances => {
+                    // the lock isn't held while the executor starts, so calls to the others aren't held up
+                    state.starting += 1;
+                    drop(state);
+                    return self.scale_up().await;
+                }
+                Some((idx, _)) => {
+                    // every executor is running a call, everything beyond that is waiting
+                    let in_flight: usize = state.instances.iter().map(Instance::load).sum();
+                    if in_flight - state.instances.len() >= self.limits.max_queue_length {
+                        return Err(ExecutionError::Saturated(state.instances.len()).into());
+                    }
+                    idx
+                }
+                None => {
+                    // all executors are still starting
+                    drop(state);
+                    task::sleep(Duration::from_millis(STARTING_POLL_MS)).await;
+                    continue;
+                }
+            };
+            let instance = &state.instances[idx];
+            return Ok((instance.addr.clone(), InFlight::new(instance.usage.clone())));
+        }
+    }
+
+    ///
+    /// Starts an executor for a call that reserved it in `PoolState::starting`.
+    ///
+    async fn scale_up(&self) -> Result<(Addr<FunctionExecutor>, InFlight)> {
+        let started = self.start_instance().await;
+        let mut state = self.state.lock().await;
+        state.starting -= 1;
+        let instance = started?;
+        if state.closed {
+            drop(state);
+            shutdown_all(vec![instance]).await;
+            return Err(ExecutionError::RuntimeDeadError.into());
+        }
+        let acquired = (instance.addr.clone(), InFlight::new(instance.usage.clone()));
+        state.instances.push(instance);
+        debug!("Scaled up to {} executors", state.instances.len());
+        Ok(acquired)
+    }
+
+    ///
+    /// Calls the function on one of the pool's executors.
+    ///
+    pub async fn call(&self, input: RawFunctionInput) -> Result<RawFunctionOutputWrapper> {
+        let (addr, _in_flight) = self.acquire().await?;
+        addr.call(input)
+            .await
+            .map_err(|_| anyhow::Error::from(ExecutionError::RuntimeDeadError))?
+    }
+
+    ///
+    /// The number of running executors.
+    ///
+    pub async fn size(&self) -> usize {
+        self.state.lock().await.instances.len()
+    }
+
+    ///
+    /// Stops all executors. Calls that are already queued are processed first, new calls are rejected.
+    ///
+    pub async fn shutdown(&self) {
+        let mut state = self.state.lock().await;
+        state.closed = true;
+        info!("Shutting down {} executors", state.instances.len());
+        shutdown_all(state.instances.drain(..).collect()).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ext::bash::{Bash, BashSetup};
+    use crate::ext::toolchain::{LifecycleToolchain, Toolchain};
+    use crate::logs::collectors::FileLogCollector;
+    use futures::future::join_all;
+    use minifaas_common::runtime::FunctionCode;
+    use minifaas_common::triggers::http::HttpTrigger;
+    use minifaas_common::{
+        Environment, ProgrammingLanguage, UserFunctionDeclaration, UserFunctionRecord,
+    };
+    use minifaas_test::get_empty_tmp_dir;
+    use std::time::{Duration, Instant};
+    use uuid::Uuid;
+
+    async fn sleeping_pool(limits: PoolLimits) -> ExecutorPool {
+        let root_dir = get_empty_tmp_dir();
+        let env = Environment::create_with_id(root_dir.join("pool"), Uuid::new_v4())
+            .await
+            .unwrap();
+        let code = Arc::new(Box::new(UserFunctionRecord::from(
+            UserFunctionDeclaration {
+                name: "sleepy".to_owned(),
+                code: FunctionCode::new(
+                    "sleep 1; echo \"__MF__body:done\"".to_owned(),
+                    ProgrammingLanguage::Bash,
+                ),
+                ..Default::default()
+            },
+        )));
+        let toolchain: Arc<dyn Toolchain> = Arc::new(LifecycleToolchain::new(
+            BashSetup::default(),
+            Bash::default(),
+        ));
+        let log_collector = Arc::new(FileLogCollector::new("logs"));
+        ExecutorPool::start(limits, move || {
+            FunctionExecutor::new(
+                env.clone(),
+                code.clone(),
+                toolchain.clone(),
+                log_collector.clone(),
+                Duration::from_secs(5),
+            )
+        })
+        .await
+        .unwrap()
+    }
+
+    fn input() -> RawFunctionInput {
+        HttpTrigger::default().into()
+    }
+
+    #[async_std::test]
+    async fn executor_pool_runs_calls_concurrently() {
+        let pool = sleeping_pool(PoolLimits::new(1, 4, 0)).await;
+        assert_eq!(pool.size().await, 1);
+
+        let started = Instant::now();
+        let results = join_all((0..4).map(|_| pool.call(input()))).await;
+        assert!(results.iter().all(Result::is_ok));
+        // four calls of one second each finish in about one second
+        assert!(started.elapsed() < Duration::from_secs(3));
+        assert_eq!(pool.size().await, 4);
+    }
+
+    #[async_std::test]
+    async fn executor_pool_stops_idle_executors() {
+        let limits = PoolLimits::new(1, 3, 0).with_idle_timeout(Duration::from_millis(500));
+        let pool = sleeping_pool(limits).await;
+
+        let results = join_all((0..3).map(|_| pool.call(input()))).await;
+        assert!(results.iter().all(Result::is_ok));
+        assert_eq!(pool.size().await, 3);
+
+        task::sleep(Duration::from_secs(2)).await;
+        // min_instances are kept
+        assert_eq!(pool.size().await, 1);
+        assert!(pool.call(input()).await.is_ok());
+    }
+
+    #[async_std::test]
+    async fn executor_pool_rejects_calls_when_saturated() {
+        let pool = sleeping_pool(PoolLimits::new(1, 1, 1)).await;
+
+        let results = join_all((0..3).map(|_| pool.call(input()))).await;
+        let saturated = results
+            .iter()
+            .filter(|r| match r {
+                Err(e) => matches!(
+                    e.downcast_ref::<ExecutionError>(),
+                    Some(ExecutionError::Saturated(1))
+                ),
+                _ => false,
+            })
+            .count();
+        // one call is running, one is queued
+        assert_eq!(saturated, 1);
+        assert_eq!(results.iter().filter(|r| r.is_ok()).count(), 2);
+    }
+
+    #[async_std::test]
+    async fn executor_pool_rejects_calls_after_shutdown() {
+        let pool = sleeping_pool(PoolLimits::new(2, 2, 0)).await;
+        pool.shutdown().await;
+        assert_eq!(pool.size().await, 0);
+        let result = pool.call(input()).await;
+        assert!(matches!(
+            result.unwrap_err().downcast::<ExecutionError>(),
+            Ok(ExecutionError::RuntimeDeadError)
+        ));
+    }
+}
@@ -1,17 +1,21 @@
-use crate::ext::toolchain::BuildToolchain;
-use crate::ext::toolchain::ToolchainSetup;
+use crate::ext::process::DEFAULT_MAX_OUTPUT_BYTES;
 use crate::logs::collectors::{FileLogCollector, LogCollector};
 use crate::{
-    DestroyMsg, FunctionExecutor, HttpTriggerMsg, HttpTriggered, LogsMsg, OpsMsg, SetupMsg,
-    StartExecutorMsg, StopExecutorMsg, TimerTriggered, ToolchainMap, Trigger,
+    CollectToolchainsMsg, DestroyMsg, ExecutorPool, ExportEnvMsg, FunctionExecutor, HttpTriggerMsg,
+    HttpTriggered, LogsMsg, OpsMsg, PoolLimits, RestoreEnvMsg, SetupMsg, StartExecutorMsg,
+    StopExecutorMsg, TimerTriggered, ToolchainCache, ToolchainRegistry, Trigger,
 };
 use anyhow::Result;
 use async_std::prelude::*;
+use async_std::task;
 use cron::Schedule;
-use log::{debug, error, info};
-use minifaas_common::Environments;
-use std::collections::HashMap;
+use log::{debug, error, info, warn};
+use minifaas_common::archive::EnvironmentContents;
+use minifaas_common::errors::PreparationError;
+use minifaas_common::{Environments, ResourceLimits, SecretStore, UserFunctionRecord};
+use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
+use std::time::Duration;
 use uuid::Uuid;
 use xactor::*;
 
@@ -19,45 +23,143 @@ use super::IntervalTriggerMsg;
 
 pub struct RuntimeController {
     environments: Environments,
-    setup_map: ToolchainMap<BuildToolchain>,
-    executors: HashMap<Uuid, Addr<FunctionExecutor>>,
+    toolchains: ToolchainRegistry,
+    executors: HashMap<Uuid, (ExecutorPool, Arc<Box<UserFunctionRecord>>)>,
     http_trigger: Addr<HttpTriggered>,
     timer_trigger: Addr<TimerTriggered>,
     log_collector: Arc<FileLogCollector>,
+    default_timeout: Duration,
+    default_limits: PoolLimits,
+    toolchain_cache: Option<ToolchainCache>,
+    secrets: Arc<SecretStore>,
+    resource_limits: ResourceLimits,
+    sandbox: bool,
+    trusted_functions: Arc<HashSet<String>>,
+    max_output_bytes: u64,
 }
 
 impl RuntimeController {
     pub fn new(
         existing_environments: Environments,
-        toolchains: ToolchainMap<BuildToolchain>,
+        toolchains: ToolchainRegistry,
         http_trigger: Addr<HttpTriggered>,
         timer_trigger: Addr<TimerTriggered>,
         log_collector: Arc<FileLogCollector>,
+        default_timeout: Duration,
+        default_limits: PoolLimits,
     ) -> Self {
         RuntimeController {
             environments: existing_environments,
-            setup_map: toolchains,
+            toolchains,
             executors: HashMap::default(),
             timer_trigger,
             http_trigger,
             log_collector,
+            default_timeout,
+            default_limits,
+            toolchain_cache: None,
+            secrets: Arc::new(SecretStore::default()),
+            resource_limits: ResourceLimits::default(),
+            sandbox: false,
+            trusted_functions: Arc::new(HashSet::new()),
+            max_output_bytes: DEFAULT_MAX_OUTPUT_BYTES,
         }
     }
 
+    ///
+    /// Removes versions from the shared toolchain installations once no environment uses them.
+    ///
+    pub fn with_toolchain_cache(mut self, cache: ToolchainCache) -> Self {
+        self.toolchain_cache = Some(cache);
+        self
+    }
+
+    ///
+    /// Fills in the secrets functions refer to in their environment variables from `secrets`.
+    ///
+    pub fn with_secrets(mut self, secrets: Arc<SecretStore>) -> Self {
+        self.secrets = secrets;
+        self
+    }
+
+    ///
+    /// Caps the resource limits of all functions at `limits`, which also apply to functions that don't declare any.
+    ///
+    pub fn with_resource_limits(mut self, limits: ResourceLimits) -> Self {
+        self.resource_limits = limits;
+        self
+    }
+
+    ///
+    /// Runs all functions that aren't trusted in a sandbox, with access to only their environment and no network.
+    ///
+    pub fn with_sandbox(mut self, sandbox: bool) -> Self {
+        self.sandbox = sandbox;
+        self
+    }
+
+    ///
+    /// The functions that may run outside of the sandbox if they are declared as trusted.
+    ///
+    pub fn with_trusted_functions(mut self, names: Arc<HashSet<String>>) -> Self {
+        self.trusted_functions = names;
+        self
+    }
+
+    ///
+    /// Kills functions once they wrote more than `max_output_bytes` to stdout.
+    ///
+    pub fn with_max_output_bytes(mut self, max_output_bytes: u64) -> Self {
+        self.max_output_bytes = max_output_bytes;
+        self
+    }
+
+    async fn collect_toolchains(&self) -> Result<Vec<(String, String)>> {
+        match &self.toolchain_cache {
+            Some(cache) => {
+                let mut roots = vec![];
+                for env in self.environments.envs.values() {
+                    roots.push(env.absolute_path("").await);
+                }
+                let cache = cache.clone();
+                task::spawn_blocking(move || cache.collect_garbage(&roots)).await
+            }
+            None => Ok(vec![]),
+        }
+    }
+
+    ///
+    /// The function's own limits, capped by the runtime's.
+    ///
+    fn pool_limits(&self, code: &UserFunctionRecord) -> PoolLimits {
+        let max_instances = code
+            .max_instances()
+            .unwrap_or(self.default_limits.max_instances)
+            .min(self.default_limits.max_instances);
+        PoolLimits::new(
+            code.min_instances()
+                .unwrap_or(self.default_limits.min_instances),
+            max_instances,
+            self.default_limits.max_queue_length,
+        )
+        .with_idle_timeout(self.default_limits.idle_timeout)
+    }
+
     async fn subscribe_to_triggers(
         &self,
         msg: &StartExecutorMsg,
-        addr: Addr<FunctionExecutor>,
+        pool: ExecutorPool,
         trigger: Trigger,
     ) -> Result<()> {
         match trigger {
             Trigger::Http(method) => {
                 let sub = HttpTriggerMsg::Subscribe {
-                    route: msg.code.name().clone(),
-                    addr: addr,
+                    env_id: msg.code.environment_id,
+                    route: msg.code.route(),
+                    pool,
                     method,
                 };
-                self.http_trigger.call(sub).await?;
+                self.http_trigger.call(sub).await??;
                 Ok(())
             }
             Trigger::Interval(cron_str) => {
@@ -65,8 +167,9 @@ impl RuntimeController {
                     .parse::<Schedule>()
                     .map_err(|e| anyhow::Error::msg(e.to_string()))?;
                 let sub = IntervalTriggerMsg::Subscribe {
+                    env_id: msg.code.environment_id,
                     schedule,
-                    addr: addr,
+                    pool,
                 };
                 self.timer_trigger.call(sub).await?;
                 Ok(())
@@ -75,21 +178,45 @@ impl RuntimeController {
         }
     }
 
-    async fn unsubscribe_from_triggers(
-        &self,
-        name: &String,
-        _addr: Addr<FunctionExecutor>,
-        trigger: Trigger,
-    ) -> Result<()> {
-        match trigger {
+    ///
+    /// Removes the subscriptions of a deployed function. `code` has to be the deployed version of the function
+    /// since an update may change the trigger.
+    ///
+    async fn unsubscribe_from_triggers(&self, code: &UserFunctionRecord) -> Result<()> {
+        match code.trigger() {
             Trigger::Http(_) => {
                 let sub = HttpTriggerMsg::Unsubscribe {
-                    route: name.clone(),
+                    env_id: code.environment_id,
+                };
+                self.http_trigger.call(sub).await??;
+                Ok(())
+            }
+            Trigger::Interval(_) => {
+                let sub = IntervalTriggerMsg::Unsubscribe {
+                    env_id: code.environment_id,
                 };
-                self.http_trigger.call(sub).await?;
+                self.timer_trigger.call(sub).await?;
                 Ok(())
             }
-            _ => Ok(()),
+            Trigger::None => Ok(()),
+        }
+    }
+
+    ///
+    /// Shuts down the executors of an environment (if there are any) and removes their subscriptions.
+    ///
+    async fn stop_executor(&mut self, env_id: &Uuid) -> Result<()> {
+        if let Some((existing, deployed)) = self.executors.remove(env_id) {
+            // unsubscribe first so no new calls arrive while the executors finish up
+            let unsubscribed = self.unsubscribe_from_triggers(&deployed).await;
+            existing.shutdown().await;
+            if let Some(toolchain) = self.toolchains.select(deployed.language()) {
+                toolchain.retire(env_id);
+            }
+            debug!("Stopped the executors for '{}'", env_id);
+            unsubscribed
+        } else {
+            Ok(())
         }
     }
 }
@@ -125,25 +252,68 @@ impl Handler<LogsMsg> for RuntimeController {
     }
 }
 
+#[async_trait::async_trait]
+impl Handler<ExportEnvMsg> for RuntimeController {
+    async fn handle(
+        &mut self,
+        _ctx: &mut Context<Self>,
+        msg: ExportEnvMsg,
+    ) -> Result<EnvironmentContents> {
+        let env = match self.environments.get(&msg.env_id).await {
+            Some(env) => env,
+            None => return Ok(EnvironmentContents::default()),
+        };
+        info!("Exporting environment '{}'", msg.env_id);
+        let log_file = &self.log_collector.file_name;
+        // a function can replace its log file with a symlink, so it's read without following them
+        let logs = if msg.logs {
+            env.read_file(log_file)
+                .await?
+                .map(|logs| String::from_utf8_lossy(&logs).into_owned())
+        } else {
+            None
+        };
+        let files = if msg.files {
+            env.read_files(&[log_file.as_str()]).await?
+        } else {
+            Default::default()
+        };
+        Ok(EnvironmentContents { logs, files })
+    }
+}
+
+#[async_trait::async_trait]
+impl Handler<RestoreEnvMsg> for RuntimeController {
+    async fn handle(&mut self, _ctx: &mut Context<Self>, msg: RestoreEnvMsg) -> Result<()> {
+        let env = self.environments.get_or_create(msg.env_id).await?;
+        info!("Restoring environment '{}'", msg.env_id);
+        env.write_files(msg.contents.files).await?;
+        if let Some(logs) = msg.contents.logs {
+            env.write_file(&self.log_collector.file_name, logs.into_bytes())
+                .await?;
+        }
+        Ok(())
+    }
+}
+
 #[async_trait::async_trait]
 impl Handler<SetupMsg> for RuntimeController {
     async fn handle(&mut self, _ctx: &mut Context<Self>, msg: SetupMsg) -> Result<()> {
         match self.environments.get_or_create(msg.env_id).await {
             Ok(env) => {
                 info!("Found an environment for '{}'", msg.env_id);
-                match self.setup_map.select_for_mut(&msg.toolchain) {
-                    Some(toolchain) => {
-                        toolchain.pre_setup(env).await?;
+                match self
+                    .toolchains
+                    .resolve(&msg.toolchain, msg.version.as_deref())
+                {
+                    Ok(toolchain) => {
                         toolchain.setup(env).await?;
-                        toolchain.post_setup(env).await?;
                         info!("Setup complete for '{}'", msg.env_id);
                         Ok(())
                     }
-                    _ => {
-                        let msg =
-                            format!("Setup failed: no toolchain found for '{}'", msg.toolchain);
-                        error!("Couldn't run setup: {}", &msg);
-                        Err(anyhow::Error::msg(msg))
+                    Err(e) => {
+                        error!("Couldn't run setup for '{}': {}", msg.env_id, e);
+                        Err(e)
                     }
                 }
             }
@@ -155,9 +325,30 @@ impl Handler<SetupMsg> for RuntimeController {
 #[async_trait::async_trait]
 impl Handler<DestroyMsg> for RuntimeController {
     async fn handle(&mut self, _ctx: &mut Context<Self>, msg: DestroyMsg) -> Result<()> {
+        let env = match self.environments.get(&msg.env_id).await {
+            Some(env) => env,
+            None => {
+                // e.g. the function was never deployed, deleting it shouldn't fail because of that
+                debug!(
+                    "No environment found for '{}', it's already deleted",
+                    msg.env_id
+                );
+                return Ok(());
+            }
+        };
+        if msg.keep_logs && env.has_file(&self.log_collector.file_name).await {
+            let archived = self
+                .environments
+                .archive_file(&msg.env_id, &self.log_collector.file_name)
+                .await?;
+            info!("Archived logs of '{}' to {:?}", msg.env_id, archived);
+        }
         match self.environments.remove(&msg.env_id).await {
             Some(_) => {
                 debug!("Environment '{}' successfully deleted.", msg.env_id);
+                if let Err(e) = self.collect_toolchains().await {
+                    error!("Couldn't remove unused toolchains: {:?}", e);
+                }
                 Ok(())
             }
             None => {
@@ -171,6 +362,17 @@ impl Handler<DestroyMsg> for RuntimeController {
     }
 }
 
+#[async_trait::async_trait]
+impl Handler<CollectToolchainsMsg> for RuntimeController {
+    async fn handle(
+        &mut self,
+        _ctx: &mut Context<Self>,
+        _msg: CollectToolchainsMsg,
+    ) -> Result<Vec<(String, String)>> {
+        self.collect_toolchains().await
+    }
+}
+
 #[async_trait::async_trait]
 impl Handler<StartExecutorMsg> for RuntimeController {
     async fn handle(&mut self, _ctx: &mut Context<Self>, msg: StartExecutorMsg) -> Result<()> {
@@ -178,39 +380,107 @@ impl Handler<StartExecutorMsg> for RuntimeController {
         debug!("Starting/replacing executors for env '{}'", env_id);
         match self.environments.get(&env_id).await {
             Some(env) => {
-                if let Some(toolchain) = self.setup_map.select_executor(&msg.code.language()) {
-                    let a = FunctionExecutor::new(
+                let mut toolchain = self
+                    .toolchains
+                    .resolve(msg.code.language(), msg.code.version())?;
+                let permissions = msg.code.permissions();
+                if !permissions.is_empty() {
+                    toolchain = toolchain
+                        .with_permissions(permissions)
+                        .map(Arc::from)
+                        .ok_or_else(|| {
+                            PreparationError::UnsupportedPermissions(
+                                msg.code.language().to_string(),
+                            )
+                        })?;
+                }
+                let resource_limits = msg.code.limits().capped_by(&self.resource_limits);
+                let max_output_bytes = resource_limits
+                    .output_bytes
+                    .map_or(self.max_output_bytes, |max| max.min(self.max_output_bytes));
+                let resource_limits = resource_limits.process_limits();
+                if !resource_limits.is_empty() {
+                    match toolchain.with_limits(&resource_limits) {
+                        Some(limited) => toolchain = Arc::from(limited),
+                        None if !msg.code.limits().process_limits().is_empty() => {
+                            return Err(PreparationError::UnsupportedLimits(
+                                msg.code.language().to_string(),
+                            )
+                            .into())
+                        }
+                        None => warn!(
+                            "Can't limit the resources of {} functions, running '{}' without limits",
+                            msg.code.language(),
+                            env_id
+                        ),
+                    }
+                }
+                let trusted =
+                    msg.code.trusted() && self.trusted_functions.contains(msg.code.name());
+                if msg.code.trusted() && !trusted {
+                    warn!(
+                        "'{}' isn't allowed to be trusted, running it like any other function",
+                        msg.code.name()
+                    );
+                }
+                if self.sandbox && !trusted {
+                    toolchain = toolchain.sandboxed().map(Arc::from).ok_or_else(|| {
+                        PreparationError::UnsupportedSandbox(msg.code.language().to_string())
+                    })?;
+                }
+                if msg.code.warm() {
+                    match toolchain.warm() {
+                        Some(warm) => toolchain = Arc::from(warm),
+                        None => warn!(
+                            "There are no warm workers for {} functions, running '{}' in a new process per call",
+                            msg.code.language(),
+                            env_id
+                        ),
+                    }
+                }
+                let timeout = msg
+                    .code
+                    .timeout_secs()
+                    .map(Duration::from_secs)
+                    .unwrap_or(self.default_timeout);
+                let limits = self.pool_limits(&msg.code);
+                let (env, code, log_collector, secrets, max_output_bytes) = (
+                    env.clone(),
+                    msg.code.clone(),
+                    self.log_collector.clone(),
+                    self.secrets.clone(),
+                    max_output_bytes,
+                );
+                let pool = ExecutorPool::start(limits, move || {
+                    FunctionExecutor::new(
                         env.clone(),
-                        msg.code.clone(),
+                        code.clone(),
                         toolchain.clone(),
-                        self.log_collector.clone(),
+                        log_collector.clone(),
+                        timeout,
                     )
-                    .start()
-                    .await?;
-                    if let Some(existing) = self.executors.get(&env_id) {
-                        // ignore result since it may be an error due to the executor already being shut down
-                        let _ = existing.call(OpsMsg::Shutdown).await.map_err(|e| {
-                            error!(
-                                "While shutting down the executor for '{}': {:?}. Ignoring.",
-                                env_id, e
-                            );
-                        });
-                        self.unsubscribe_from_triggers(
-                            &msg.code.name(),
-                            existing.clone(),
-                            msg.code.trigger().clone(),
-                        )
-                        .await?;
+                    .with_secrets(secrets.clone())
+                    .with_max_output_bytes(max_output_bytes)
+                })
+                .await?;
+                info!("Started executors for '{}' with {:?}", env_id, limits);
+                // the previous executors keep running until the new ones are ready
+                if let Err(e) = self.stop_executor(&env_id).await {
+                    pool.shutdown().await;
+                    return Err(e);
+                }
+                self.executors
+                    .insert(env_id, (pool.clone(), msg.code.clone()));
+                let subscribed = self
+                    .subscribe_to_triggers(&msg, pool, msg.code.trigger().clone())
+                    .await;
+                if subscribed.is_err() {
+                    // e.g. another function has the route, the executors wouldn't be reachable
+                    if let Err(e) = self.stop_executor(&env_id).await {
+                        warn!("Couldn't stop the executors for '{}': {:?}", env_id, e);
                     }
-                    self.executors.insert(env_id, a.clone());
-                    self.subscribe_to_triggers(&msg, a.clone(), msg.code.trigger().clone())
-                        .await
-                } else {
-                    Err(anyhow::Error::msg(format!(
-                        "Execute failed: no toolchain found for '{}'",
-                        msg.code.language()
-                    )))
                 }
+                subscribed
             }
             _ => Err(anyhow::Error::msg(format!(
                 "Execute failed: no environment found for '{}'",
@@ -224,25 +494,14 @@ impl Handler<StartExecutorMsg> for RuntimeController {
 impl Handler<StopExecutorMsg> for RuntimeController {
     async fn handle(&mut self, _ctx: &mut Context<Self>, msg: StopExecutorMsg) -> Result<()> {
         let env_id = msg.code.environment_id;
-        match self.environments.get(&env_id).await {
-            Some(_env) => {
-                if let Some(existing) = self.executors.get(&env_id) {
-                    existing.call(OpsMsg::Shutdown).await?;
-                    self.unsubscribe_from_triggers(
-                        &msg.code.name(),
-                        existing.clone(),
-                        msg.code.trigger().clone(),
-                    )
-                    .await
-                } else {
-                    Ok(())
-                }
-            }
-            _ => Err(anyhow::Error::msg(format!(
-                "Stop failed: no environment found for '{}'",
+        if self.environments.get(&env_id).await.is_none() {
+            debug!(
+                "No environment found for '{}', it's already stopped",
                 env_id
-            ))),
+            );
         }
+        // stops whatever is still running, there's nothing to do if the function wasn't deployed
+        self.stop_executor(&env_id).await
     }
 }
 
@@ -254,3 +513,542 @@ impl Handler<OpsMsg> for RuntimeController {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ext::bash::{Bash, BashSetup};
+    use crate::ext::process::{ProcessOutput, StdoutStream};
+    use crate::ext::toolchain::{LifecycleToolchain, Toolchain};
+    use chrono::Utc;
+    use minifaas_common::runtime::FunctionCode;
+    use minifaas_common::runtime::RawFunctionInput;
+    use minifaas_common::triggers::http::{HttpMethod, HttpTrigger};
+    use minifaas_common::triggers::timer::TimerTrigger;
+    use minifaas_common::{
+        Environment, Permissions, ProgrammingLanguage, ResourceLimits, UserFunctionDeclaration,
+        UserFunctionRecord,
+    };
+    use minifaas_test::get_empty_tmp_dir;
+    use std::path::Path;
+
+    fn count_lines(path: &Path) -> usize {
+        std::fs::read_to_string(path)
+            .map(|s| s.lines().count())
+            .unwrap_or(0)
+    }
+
+    // timer calls run in the background, so the tests wait for their output
+    async fn wait_for_lines(path: &Path, lines: usize) -> usize {
+        for _ in 0..50 {
+            if count_lines(path) >= lines {
+                break;
+            }
+            task::sleep(Duration::from_millis(100)).await;
+        }
+        count_lines(path)
+    }
+
+    ///
+    /// Stands in for an embedder's toolchain: answers with the code as body.
+    ///
+    #[derive(Debug)]
+    struct EchoToolchain;
+
+    #[async_trait::async_trait]
+    impl Toolchain for EchoToolchain {
+        async fn setup(&self, env: &Environment) -> Result<()> {
+            env.add_file("echo-installed").map(|_| ())
+        }
+
+        async fn build(&self, code: &str) -> Result<Vec<u8>> {
+            Ok(code.as_bytes().to_vec())
+        }
+
+        async fn execute(
+            &self,
+            code: Vec<u8>,
+            _input: Arc<RawFunctionInput>,
+            _vars: Vec<(String, String)>,
+            _env: &Environment,
+            _timeout: Duration,
+            _stdout: StdoutStream,
+        ) -> Result<ProcessOutput> {
+            Ok(ProcessOutput {
+                stdout: [b"__MF__body:".to_vec(), code].concat(),
+                exit_code: Some(0),
+                ..Default::default()
+            })
+        }
+    }
+
+    async fn start_controller(
+        root: &Path,
+    ) -> (
+        Addr<RuntimeController>,
+        Addr<HttpTriggered>,
+        Addr<TimerTriggered>,
+    ) {
+        start_controller_with_cache(root, ToolchainCache::new(root.join(".toolchains"))).await
+    }
+
+    async fn start_controller_with_cache(
+        root: &Path,
+        cache: ToolchainCache,
+    ) -> (
+        Addr<RuntimeController>,
+        Addr<HttpTriggered>,
+        Addr<TimerTriggered>,
+    ) {
+        let mut toolchains = ToolchainRegistry::new();
+        toolchains.register(
+            ProgrammingLanguage::Bash,
+            Box::new(LifecycleToolchain::new(
+                BashSetup::default(),
+                Bash::default(),
+            )),
+        );
+        toolchains.register(
+            ProgrammingLanguage::Command("Echo".to_owned()),
+            Box::new(EchoToolchain),
+        );
+        let http = HttpTriggered::new().start().await.unwrap();
+        // the tests tick the timer themselves
+        let timer = TimerTriggered::new(Duration::from_secs(3600))
+            .start()
+            .await
+            .unwrap();
+        let secrets = Arc::new(SecretStore::new(
+            root.join("secrets.db"),
+            Some("key"),
+            false,
+        ));
+        secrets.set("token", "s3cr3t").await.unwrap();
+        let controller = RuntimeController::new(
+            Environments::new(root, HashMap::new()),
+            toolchains,
+            http.clone(),
+            timer.clone(),
+            Arc::new(FileLogCollector::new("logs")),
+            Duration::from_secs(5),
+            PoolLimits::new(1, 2, 10),
+        )
+        .with_toolchain_cache(cache)
+        .with_secrets(secrets)
+        .start()
+        .await
+        .unwrap();
+        (controller, http, timer)
+    }
+
+    fn http_function(name: &str) -> Arc<Box<UserFunctionRecord>> {
+        let declaration = UserFunctionDeclaration {
+            name: name.to_owned(),
+            code: FunctionCode::new(
+                "echo \"__MF__body:hello\"".to_owned(),
+                ProgrammingLanguage::Bash,
+            ),
+            trigger: Trigger::Http(HttpMethod::GET),
+            ..Default::default()
+        };
+        Arc::new(Box::new(UserFunctionRecord::from(declaration)))
+    }
+
+    async fn call(http: &Addr<HttpTriggered>, route: &str) -> u16 {
+        http.call(HttpTrigger {
+            route: route.to_owned(),
+            method: HttpMethod::GET,
+            ..Default::default()
+        })
+        .await
+        .unwrap()
+        .unwrap()
+        .status_code
+    }
+
+    async fn deploy(controller: &Addr<RuntimeController>, code: Arc<Box<UserFunctionRecord>>) {
+        controller
+            .call(SetupMsg {
+                env_id: code.environment_id,
+                toolchain: code.language().clone(),
+                version: code.version().map(str::to_owned),
+            })
+            .await
+            .unwrap()
+            .unwrap();
+        controller
+            .call(StartExecutorMsg { code })
+            .await
+            .unwrap()
+            .unwrap();
+    }
+
+    #[async_std::test]
+    async fn runtime_controller_stop_and_destroy_removes_function() {
+        let root_dir = get_empty_tmp_dir();
+        let (controller, http, _) = start_controller(&root_dir).await;
+        let code = http_function("to-delete");
+        let env_id = code.environment_id;
+        deploy(&controller, code.clone()).await;
+        assert_eq!(call(&http, "to-delete").await, 200);
+
+        controller
+            .call(StopExecutorMsg { code })
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(call(&http, "to-delete").await, 404);
+
+        controller
+            .call(DestroyMsg {
+                env_id,
+                keep_logs: false,
+            })
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(!root_dir.join(env_id.to_string()).exists());
+        assert!(!root_dir.join(".archive").exists());
+
+        // stopping it again is fine
+        controller
+            .call(StopExecutorMsg {
+                code: http_function("to-delete"),
+            })
+            .await
+            .unwrap()
+            .unwrap();
+    }
+
+    #[async_std::test]
+    async fn runtime_controller_deletes_function_without_environment() {
+        let root_dir = get_empty_tmp_dir();
+        let (controller, _, _) = start_controller(&root_dir).await;
+        let code = http_function("never-deployed");
+        let env_id = code.environment_id;
+
+        // what DeleteFunction does
+        controller
+            .call(StopExecutorMsg { code })
+            .await
+            .unwrap()
+            .unwrap();
+        controller
+            .call(DestroyMsg {
+                env_id,
+                keep_logs: true,
+            })
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(!root_dir.join(env_id.to_string()).exists());
+        assert!(!root_dir.join(".archive").exists());
+    }
+
+    #[async_std::test]
+    async fn runtime_controller_destroy_keeps_logs() {
+        let root_dir = get_empty_tmp_dir();
+        let (controller, http, _) = start_controller(&root_dir).await;
+        let code = http_function("keep-logs");
+        let env_id = code.environment_id;
+        deploy(&controller, code.clone()).await;
+        assert_eq!(call(&http, "keep-logs").await, 200);
+
+        controller
+            .call(StopExecutorMsg { code })
+            .await
+            .unwrap()
+            .unwrap();
+        controller
+            .call(DestroyMsg {
+                env_id,
+                keep_logs: true,
+            })
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(!root_dir.join(env_id.to_string()).exists());
+        let archived = root_dir
+            .join(".archive")
+            .join(env_id.to_string())
+            .join("logs");
+        assert!(std::fs::read_to_string(archived).unwrap().contains("hello"));
+    }
+
+    #[async_std::test]
+    async fn runtime_controller_replaced_cron_function_fires_once_per_tick() {
+        let root_dir = get_empty_tmp_dir();
+        let runs = root_dir.join("runs");
+        let (controller, _, timer) = start_controller(&root_dir).await;
+        let declaration = UserFunctionDeclaration {
+            name: "cron".to_owned(),
+            code: FunctionCode::new(format!("echo run >> {:?}", runs), ProgrammingLanguage::Bash),
+            trigger: Trigger::Interval("* * * * * *".to_owned()),
+            ..Default::default()
+        };
+        let code = Arc::new(Box::new(UserFunctionRecord::from(declaration.clone())));
+        let env_id = code.environment_id;
+        deploy(&controller, code).await;
+        // an update keeps the environment
+        let updated = Arc::new(Box::new(UserFunctionRecord::new(declaration, env_id)));
+        deploy(&controller, updated.clone()).await;
+
+        timer
+            .call(TimerTrigger {
+                when: Utc::now() + chrono::Duration::seconds(2),
+            })
+            .await
+            .unwrap();
+        assert_eq!(wait_for_lines(&runs, 1).await, 1);
+        // a second subscription would run shortly after the first
+        task::sleep(Duration::from_secs(1)).await;
+        assert_eq!(count_lines(&runs), 1);
+
+        controller
+            .call(StopExecutorMsg { code: updated })
+            .await
+            .unwrap()
+            .unwrap();
+        timer
+            .call(TimerTrigger {
+                when: Utc::now() + chrono::Duration::seconds(4),
+            })
+            .await
+            .unwrap();
+        // nothing is subscribed anymore, give a stray call the time to show up
+        task::sleep(Duration::from_secs(1)).await;
+        assert_eq!(count_lines(&runs), 1);
+    }
+
+    #[async_std::test]
+    async fn runtime_controller_runs_registered_toolchain() {
+        let root_dir = get_empty_tmp_dir();
+        let (controller, http, _) = start_controller(&root_dir).await;
+        let declaration = UserFunctionDeclaration {
+            name: "echo".to_owned(),
+            code: FunctionCode::new(
+                "from a custom toolchain".to_owned(),
+                ProgrammingLanguage::Command("Echo".to_owned()),
+            ),
+            trigger: Trigger::Http(HttpMethod::GET),
+            ..Default::default()
+        };
+        let code = Arc::new(Box::new(UserFunctionRecord::from(declaration)));
+        deploy(&controller, code.clone()).await;
+        assert!(root_dir
+            .join(code.environment_id.to_string())
+            .join("echo-installed")
+            .exists());
+
+        let outputs = http
+            .call(HttpTrigger {
+                route: "echo".to_owned(),
+                method: HttpMethod::GET,
+                ..Default::default()
+            })
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(outputs.body, b"from a custom toolchain".to_vec());
+    }
+
+    #[async_std::test]
+    #[cfg(unix)]
+    async fn runtime_controller_destroy_removes_unused_toolchains() {
+        use crate::ext::install::link_executable;
+        let root_dir = get_empty_tmp_dir();
+        let cache = ToolchainCache::new(root_dir.join(".toolchains"));
+        let (controller, _, _) = start_controller_with_cache(&root_dir, cache.clone()).await;
+
+        let mut env_ids = vec![];
+        for (name, version) in vec![("old", "1.0.0"), ("new", "2.0.0")] {
+            let code = http_function(name);
+            deploy(&controller, code.clone()).await;
+            let binary = cache.path("tool", version, "tool");
+            std::fs::create_dir_all(binary.parent().unwrap()).unwrap();
+            std::fs::write(&binary, b"").unwrap();
+            cache.mark_installed("tool", version).unwrap();
+            let env_root = root_dir.join(code.environment_id.to_string());
+            link_executable(&binary, &env_root.join("tool")).unwrap();
+            env_ids.push(code.environment_id);
+        }
+        assert!(controller
+            .call(CollectToolchainsMsg)
+            .await
+            .unwrap()
+            .unwrap()
+            .is_empty());
+
+        controller
+            .call(DestroyMsg {
+                env_id: env_ids[0],
+                keep_logs: false,
+            })
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            cache.versions().unwrap(),
+            vec![("tool".to_owned(), "2.0.0".to_owned())]
+        );
+    }
+
+    #[async_std::test]
+    #[cfg(unix)]
+    async fn runtime_controller_injects_and_redacts_secrets() {
+        let root_dir = get_empty_tmp_dir();
+        let (controller, http, _) = start_controller(&root_dir).await;
+        let declaration = UserFunctionDeclaration {
+            name: "secret".to_owned(),
+            code: FunctionCode::new(
+                "echo \"__MF__body:$TOKEN\"; echo \"token: $TOKEN, level: $LEVEL\" >&2".to_owned(),
+                ProgrammingLanguage::Bash,
+            ),
+            trigger: Trigger::Http(HttpMethod::GET),
+            env: serde_json::from_str(r#"{"TOKEN": {"secret": "token"}, "LEVEL": "debug"}"#)
+                .unwrap(),
+            ..Default::default()
+        };
+        let code = Arc::new(Box::new(UserFunctionRecord::from(declaration)));
+        deploy(&controller, code.clone()).await;
+
+        let outputs = http
+            .call(HttpTrigger {
+                route: "secret".to_owned(),
+                method: HttpMethod::GET,
+                ..Default::default()
+            })
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(outputs.body, b"s3cr3t".to_vec());
+
+        let logs =
+            std::fs::read_to_string(root_dir.join(code.environment_id.to_string()).join("logs"))
+                .unwrap();
+        assert!(logs.contains("token: [REDACTED], level: debug"));
+        assert!(!logs.contains("s3cr3t"));
+    }
+
+    #[async_std::test]
+    #[cfg(unix)]
+    async fn runtime_controller_applies_output_limits() {
+        let root_dir = get_empty_tmp_dir();
+        let (controller, http, _) = start_controller(&root_dir).await;
+        let declaration = UserFunctionDeclaration {
+            name: "chatty".to_owned(),
+            code: FunctionCode::new(
+                "for i in $(seq 100); do echo \"line $i\"; done".to_owned(),
+                ProgrammingLanguage::Bash,
+            ),
+            trigger: Trigger::Http(HttpMethod::GET),
+            limits: ResourceLimits {
+                output_bytes: Some(50),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        deploy(
+            &controller,
+            Arc::new(Box::new(UserFunctionRecord::from(declaration))),
+        )
+        .await;
+
+        let result = http
+            .call(HttpTrigger {
+                route: "chatty".to_owned(),
+                method: HttpMethod::GET,
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        assert!(result
+            .unwrap_err()
+            .to_string()
+            .contains("exceeded 50 bytes"));
+    }
+
+    #[async_std::test]
+    async fn runtime_controller_rejects_unsupported_versions() {
+        let root_dir = get_empty_tmp_dir();
+        let (controller, _, _) = start_controller(&root_dir).await;
+        let code = http_function("pinned");
+        let result = controller
+            .call(SetupMsg {
+                env_id: code.environment_id,
+                toolchain: code.language().clone(),
+                version: Some("5.1".to_owned()),
+            })
+            .await
+            .unwrap();
+        assert!(matches!(
+            result.unwrap_err().downcast_ref::<PreparationError>(),
+            Some(PreparationError::UnsupportedVersion { .. })
+        ));
+    }
+
+    #[async_std::test]
+    async fn runtime_controller_rejects_permissions_without_sandbox() {
+        let root_dir = get_empty_tmp_dir();
+        let (controller, _, _) = start_controller(&root_dir).await;
+        let declaration = UserFunctionDeclaration {
+            name: "sandboxed".to_owned(),
+            code: FunctionCode::new("echo hi".to_owned(), ProgrammingLanguage::Bash),
+            trigger: Trigger::Http(HttpMethod::GET),
+            permissions: Permissions {
+                allow_read: true,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let code = Arc::new(Box::new(UserFunctionRecord::from(declaration)));
+        controller
+            .call(SetupMsg {
+                env_id: code.environment_id,
+                toolchain: code.language().clone(),
+                version: None,
+            })
+            .await
+            .unwrap()
+            .unwrap();
+        let result = controller.call(StartExecutorMsg { code }).await.unwrap();
+        assert!(matches!(
+            result.unwrap_err().downcast_ref::<PreparationError>(),
+            Some(PreparationError::UnsupportedPermissions(_))
+        ));
+    }
+
+    #[async_std::test]
+    async fn runtime_controller_rejects_limits_without_support() {
+        let root_dir = get_empty_tmp_dir();
+        let (controller, _, _) = start_controller(&root_dir).await;
+        let declaration = UserFunctionDeclaration {
+            name: "limited".to_owned(),
+            code: FunctionCode::new(
+                "hi".to_owned(),
+                ProgrammingLanguage::Command("Echo".to_owned()),
+            ),
+            trigger: Trigger::Http(HttpMethod::GET),
+            limits: ResourceLimits {
+                cpu_secs: Some(1),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let code = Arc::new(Box::new(UserFunctionRecord::from(declaration)));
+        controller
+            .call(SetupMsg {
+                env_id: code.environment_id,
+                toolchain: code.language().clone(),
+                version: None,
+            })
+            .await
+            .unwrap()
+            .unwrap();
+        let result = controller.call(StartExecutorMsg { code }).await.unwrap();
+        assert!(matches!(
+            result.unwrap_err().downcast_ref::<PreparationError>(),
+            Some(PreparationError::UnsupportedLimits(_))
+        ));
+    }
+}
@@ -1,23 +1,54 @@
 use crate::runtime::RawFunctionInput;
-use crate::{FunctionExecutor, HttpTriggerMsg, OpsMsg};
+use crate::{ExecutorPool, HttpTriggerMsg, OpsMsg, ResolveHttpMsg};
 use anyhow::Result;
+use async_std::task;
 use chrono::{DateTime, Utc};
 use cron::Schedule;
-use futures::future::join_all;
 use log::{debug, info, warn};
+use minifaas_common::triggers::http::HttpMethod;
 use minifaas_common::triggers::http::HttpTrigger;
 use minifaas_common::triggers::http::HttpTriggerOutputs;
+use minifaas_common::triggers::route::RoutePattern;
 use minifaas_common::triggers::timer::TimerTrigger;
 use std::collections::{BTreeMap, HashMap};
 use std::ops::Bound::Included;
 use std::time::Duration;
+use uuid::Uuid;
 use xactor::*;
 
 use super::IntervalTriggerMsg;
 
+///
+/// The executors for each method of a route, together with the environment id of their function.
+///
+type MethodTable = HashMap<HttpMethod, (Uuid, ExecutorPool)>;
+
+///
+/// Where an HTTP call goes: either to a function's executors or straight back with a response.
+///
+pub enum HttpRoute {
+    Found(ExecutorPool, HttpTrigger),
+    Respond(HttpTriggerOutputs),
+}
+
+impl HttpRoute {
+    ///
+    /// Runs the call (if there is a function for it).
+    ///
+    pub async fn dispatch(self) -> Result<HttpTriggerOutputs> {
+        match self {
+            HttpRoute::Found(pool, msg) => {
+                let inputs: RawFunctionInput = msg.into();
+                pool.call(inputs).await.map(|output| output.into())
+            }
+            HttpRoute::Respond(outputs) => Ok(outputs),
+        }
+    }
+}
+
 #[derive(Default)]
 pub struct HttpTriggered {
-    route_table: HashMap<String, Addr<FunctionExecutor>>,
+    route_table: HashMap<String, (RoutePattern, MethodTable)>,
 }
 
 impl HttpTriggered {
@@ -26,6 +57,53 @@ impl HttpTriggered {
             route_table: HashMap::default(),
         }
     }
+
+    ///
+    /// Finds the executors for an HTTP call, or the response if there are none.
+    ///
+    fn resolve(&self, msg: HttpTrigger) -> HttpRoute {
+        debug!("Resolving route: {} {:?}", msg.route, msg.method);
+        match self.find_route(&msg.route) {
+            Some((methods, path_params)) => match select_executor(methods, &msg.method) {
+                Some(pool) => {
+                    debug!("Found matching executors for '{}'", msg.route);
+                    HttpRoute::Found(pool.clone(), HttpTrigger { path_params, ..msg })
+                }
+                None => {
+                    debug!("'{}' doesn't allow {:?}", msg.route, msg.method);
+                    let mut allowed: Vec<HttpMethod> = methods.keys().cloned().collect();
+                    allowed.sort_by_key(|m| format!("{:?}", m));
+                    HttpRoute::Respond(HttpTriggerOutputs::method_not_allowed(&allowed))
+                }
+            },
+            None => HttpRoute::Respond(HttpTriggerOutputs::with_status(404)),
+        }
+    }
+
+    ///
+    /// Finds the most specific route pattern that matches the path, together with the extracted path parameters.
+    ///
+    fn find_route(&self, path: &str) -> Option<(&MethodTable, HashMap<String, String>)> {
+        self.route_table
+            .values()
+            .filter_map(|(pattern, methods)| {
+                pattern
+                    .matches(path)
+                    .map(|params| (pattern.specificity(), methods, params))
+            })
+            .max_by(|a, b| a.0.cmp(&b.0))
+            .map(|(_, methods, params)| (methods, params))
+    }
+}
+
+///
+/// Picks the executor for a method, falling back to the one subscribed for `HttpMethod::ALL`.
+///
+fn select_executor<'a>(methods: &'a MethodTable, method: &HttpMethod) -> Option<&'a ExecutorPool> {
+    methods
+        .get(method)
+        .or_else(|| methods.get(&HttpMethod::ALL))
+        .map(|(_, pool)| pool)
 }
 
 #[async_trait::async_trait]
@@ -40,36 +118,52 @@ impl Handler<HttpTrigger> for HttpTriggered {
         _ctx: &mut Context<Self>,
         msg: HttpTrigger,
     ) -> Result<HttpTriggerOutputs> {
-        debug!("Triggering route: {}", msg.route);
-        let result = match self.route_table.get(&msg.route) {
-            Some(addr) => {
-                debug!("Found matching executor for '{}'", msg.route);
-                let inputs: RawFunctionInput = msg.into();
-                match addr.call(inputs).await? {
-                    Ok(output) => Ok(output.into()),
-                    _ => Err(Error::msg("Nothing found")),
-                }
-            }
-            None => Ok(HttpTriggerOutputs::default()),
-        };
-        result
+        self.resolve(msg).dispatch().await
+    }
+}
+
+#[async_trait::async_trait]
+impl Handler<ResolveHttpMsg> for HttpTriggered {
+    async fn handle(&mut self, _ctx: &mut Context<Self>, msg: ResolveHttpMsg) -> HttpRoute {
+        self.resolve(msg.0)
     }
 }
 
 #[async_trait::async_trait]
 impl Handler<HttpTriggerMsg> for HttpTriggered {
-    async fn handle(&mut self, _ctx: &mut Context<Self>, msg: HttpTriggerMsg) {
+    async fn handle(&mut self, _ctx: &mut Context<Self>, msg: HttpTriggerMsg) -> Result<()> {
         match msg {
             HttpTriggerMsg::Subscribe {
+                env_id,
                 route,
-                addr,
-                method: _,
+                pool,
+                method,
             } => {
-                debug!("New route subscribed: {}", route);
-                self.route_table.insert(route, addr)
+                let pattern = RoutePattern::parse(&route)?;
+                let (_, methods) = self
+                    .route_table
+                    .entry(pattern.as_str().to_owned())
+                    .or_insert_with(|| (pattern.clone(), MethodTable::default()));
+                // only the function itself may replace its subscription (e.g. with an update)
+                if let Some((existing, _)) = methods.get(&method).filter(|(id, _)| *id != env_id) {
+                    return Err(anyhow::Error::msg(format!(
+                        "{:?} {} is already taken by the function in '{}'",
+                        method, pattern, existing
+                    )));
+                }
+                debug!("New route subscribed: {} {:?}", pattern, method);
+                methods.insert(method, (env_id, pool));
+            }
+            HttpTriggerMsg::Unsubscribe { env_id } => {
+                debug!("Routes of '{}' unsubscribed", env_id);
+                for (_, methods) in self.route_table.values_mut() {
+                    methods.retain(|_, (id, _)| *id != env_id);
+                }
+                self.route_table
+                    .retain(|_, (_, methods)| !methods.is_empty());
             }
-            HttpTriggerMsg::Unsubscribe { route } => self.route_table.remove(&route),
         };
+        Ok(())
     }
 }
 
@@ -83,24 +177,24 @@ impl Handler<OpsMsg> for HttpTriggered {
 }
 
 // ---------------------------------
-struct ScheduleAddr {
-    pub addr: Addr<FunctionExecutor>,
+struct ScheduledPool {
+    pub pool: ExecutorPool,
     pub schedule: Schedule,
 }
 
-impl ScheduleAddr {
+impl ScheduledPool {
     pub fn next(&self) -> DateTime<Utc> {
         self.schedule.upcoming(Utc).next().unwrap()
     }
-
-    pub fn id(&self) -> u64 {
-        self.addr.actor_id()
-    }
 }
 
+///
+/// Calls subscribed functions on their (cron) schedule. Subscriptions are keyed by the function's environment id,
+/// so there is at most one schedule per function.
+///
 pub struct TimerTriggered {
-    schedules: HashMap<u64, ScheduleAddr>,
-    next: BTreeMap<DateTime<Utc>, Vec<Addr<FunctionExecutor>>>,
+    schedules: HashMap<Uuid, ScheduledPool>,
+    next: BTreeMap<DateTime<Utc>, Vec<Uuid>>,
     resolution: Duration,
     since: DateTime<Utc>,
 }
@@ -114,6 +208,23 @@ impl TimerTriggered {
             since: Utc::now(),
         }
     }
+
+    fn schedule_next(&mut self, env_id: Uuid) {
+        if let Some(sp) = self.schedules.get(&env_id) {
+            self.next.entry(sp.next()).or_default().push(env_id);
+        }
+    }
+
+    ///
+    /// Removes a subscription including every pending run.
+    ///
+    fn remove(&mut self, env_id: &Uuid) -> Option<ScheduledPool> {
+        self.next.retain(|_, ids| {
+            ids.retain(|id| id != env_id);
+            !ids.is_empty()
+        });
+        self.schedules.remove(env_id)
+    }
 }
 
 impl Default for TimerTriggered {
@@ -138,34 +249,32 @@ impl Handler<TimerTrigger> for TimerTriggered {
         let triggered: Vec<DateTime<Utc>> = self
             .next
             .range((Included(&self.since), Included(&msg.when)))
-            .map(|(k, _)| k.clone())
+            .map(|(k, _)| *k)
             .collect();
 
-        let addrs: Vec<_> = triggered
+        let mut env_ids: Vec<Uuid> = triggered
             .iter()
             .filter_map(|t| self.next.remove(t))
             .flatten()
             .collect();
+        // a function is only called once per tick, even if it has fallen behind
+        env_ids.sort();
+        env_ids.dedup();
 
+        // the calls run on their own, so a slow function doesn't hold up the timer or anything sent to it
         let input: RawFunctionInput = msg.into();
-        let tasks: Vec<_> = addrs.iter().map(|a| a.call(input.clone())).collect();
-
-        let _ = join_all(tasks).await.into_iter().map(|r| match r {
-            Ok(_) => info!("Timer trigger went through ok."),
-            Err(e) => warn!("One of the timer triggers failed: {:?}", e),
-        });
-        let schedules = &self.schedules;
-        let new_next: Vec<_> = addrs
-            .iter()
-            .filter_map(|a| schedules.get(&a.actor_id()))
-            .collect();
+        for sp in env_ids.iter().filter_map(|id| self.schedules.get(id)) {
+            let (pool, input) = (sp.pool.clone(), input.clone());
+            task::spawn(async move {
+                match pool.call(input).await {
+                    Ok(_) => info!("Timer trigger went through ok."),
+                    Err(e) => warn!("One of the timer triggers failed: {:?}", e),
+                }
+            });
+        }
 
-        for sa in new_next {
-            let next = sa.next();
-            self.next
-                .entry(next)
-                .and_modify(|e| e.push(sa.addr.clone()))
-                .or_insert(vec![sa.addr.clone()]);
+        for env_id in env_ids {
+            self.schedule_next(env_id);
         }
     }
 }
@@ -174,34 +283,22 @@ impl Handler<TimerTrigger> for TimerTriggered {
 impl Handler<IntervalTriggerMsg> for TimerTriggered {
     async fn handle(&mut self, _ctx: &mut Context<Self>, msg: IntervalTriggerMsg) {
         match msg {
-            IntervalTriggerMsg::Subscribe { addr, schedule } => {
-                let sa = ScheduleAddr {
-                    addr: addr.clone(),
-                    schedule,
-                };
-
-                let next = sa.next();
-                self.schedules.entry(sa.id()).or_insert(sa);
-                self.next
-                    .entry(next)
-                    .and_modify(|e| e.push(addr.clone()))
-                    .or_insert(vec![addr]);
+            IntervalTriggerMsg::Subscribe {
+                env_id,
+                pool,
+                schedule,
+            } => {
+                // replaces any previous schedule of this function
+                if self.remove(&env_id).is_some() {
+                    debug!("Replacing the schedule of '{}'", env_id);
+                }
+                self.schedules
+                    .insert(env_id, ScheduledPool { pool, schedule });
+                self.schedule_next(env_id);
             }
-            IntervalTriggerMsg::Unsubscribe { schedule, addr } => {
-                let sa = ScheduleAddr {
-                    addr: addr.clone(),
-                    schedule,
-                };
-                let next = sa.next();
-                match self.schedules.remove(&sa.id()) {
-                    Some(_) => {
-                        self.next.entry(next).and_modify(|e| {
-                            if let Some(p) = e.iter().position(|a| a == &addr) {
-                                e.remove(p);
-                            }
-                        });
-                    }
-                    _ => {}
+            IntervalTriggerMsg::Unsubscribe { env_id } => {
+                if self.remove(&env_id).is_none() {
+                    debug!("No schedule to remove for '{}'", env_id);
                 }
             }
         };
@@ -216,3 +313,342 @@ impl Handler<OpsMsg> for TimerTriggered {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ext::bash::{Bash, BashSetup};
+    use crate::ext::toolchain::LifecycleToolchain;
+    use crate::logs::collectors::FileLogCollector;
+    use crate::{FunctionExecutor, PoolLimits};
+    use minifaas_common::runtime::FunctionCode;
+    use minifaas_common::{
+        Environment, ProgrammingLanguage, UserFunctionDeclaration, UserFunctionRecord,
+    };
+    use minifaas_test::get_empty_tmp_dir;
+    use std::sync::Arc;
+    use uuid::Uuid;
+
+    async fn start_bash_executor(name: &str, body: &str) -> ExecutorPool {
+        start_bash_script(name, &format!("echo \"__MF__body:{}\"", body)).await
+    }
+
+    async fn start_bash_script(name: &str, script: &str) -> ExecutorPool {
+        let root_dir = get_empty_tmp_dir();
+        let env = Environment::create_with_id(root_dir.join(name), Uuid::new_v4())
+            .await
+            .unwrap();
+        let declaration = UserFunctionDeclaration {
+            name: name.to_owned(),
+            code: FunctionCode::new(script.to_owned(), ProgrammingLanguage::Bash),
+            ..Default::default()
+        };
+        let code = Arc::new(Box::new(UserFunctionRecord::from(declaration)));
+        ExecutorPool::start(PoolLimits::new(1, 1, 10), move || {
+            FunctionExecutor::new(
+                env.clone(),
+                code.clone(),
+                Arc::new(LifecycleToolchain::new(
+                    BashSetup::default(),
+                    Bash::default(),
+                )),
+                Arc::new(FileLogCollector::new("logs")),
+                Duration::from_secs(5),
+            )
+        })
+        .await
+        .unwrap()
+    }
+
+    fn http_call(route: &str, method: HttpMethod) -> HttpTrigger {
+        HttpTrigger {
+            route: route.to_owned(),
+            method,
+            ..Default::default()
+        }
+    }
+
+    #[async_std::test]
+    async fn http_triggered_dispatches_by_method() {
+        let http = HttpTriggered::new().start().await.unwrap();
+        for (method, body) in vec![(HttpMethod::GET, "got"), (HttpMethod::POST, "posted")] {
+            let pool = start_bash_executor(body, body).await;
+            http.call(HttpTriggerMsg::Subscribe {
+                env_id: Uuid::new_v4(),
+                route: "shared".to_owned(),
+                pool,
+                method,
+            })
+            .await
+            .unwrap()
+            .unwrap();
+        }
+
+        let got = http
+            .call(http_call("shared", HttpMethod::GET))
+            .await
+            .unwrap();
+        assert_eq!(got.unwrap().body, b"got".to_vec());
+        let posted = http
+            .call(http_call("shared", HttpMethod::POST))
+            .await
+            .unwrap();
+        assert_eq!(posted.unwrap().body, b"posted".to_vec());
+
+        let not_allowed = http
+            .call(http_call("shared", HttpMethod::DELETE))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(not_allowed.status_code, 405);
+        assert_eq!(
+            not_allowed.headers.get("Allow"),
+            Some(&Some("GET, POST".to_owned()))
+        );
+
+        let not_found = http
+            .call(http_call("elsewhere", HttpMethod::GET))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(not_found.status_code, 404);
+    }
+
+    #[async_std::test]
+    async fn http_triggered_all_is_a_wildcard() {
+        let http = HttpTriggered::new().start().await.unwrap();
+        let any = start_bash_executor("any", "any").await;
+        let get = start_bash_executor("get", "get").await;
+        let any_id = Uuid::new_v4();
+        for (env_id, method, pool) in vec![
+            (any_id, HttpMethod::ALL, any),
+            (Uuid::new_v4(), HttpMethod::GET, get),
+        ] {
+            http.call(HttpTriggerMsg::Subscribe {
+                env_id,
+                route: "wild".to_owned(),
+                pool,
+                method,
+            })
+            .await
+            .unwrap()
+            .unwrap();
+        }
+
+        let get = http.call(http_call("wild", HttpMethod::GET)).await.unwrap();
+        assert_eq!(get.unwrap().body, b"get".to_vec());
+        let delete = http
+            .call(http_call("wild", HttpMethod::DELETE))
+            .await
+            .unwrap();
+        assert_eq!(delete.unwrap().body, b"any".to_vec());
+
+        http.call(HttpTriggerMsg::Unsubscribe { env_id: any_id })
+            .await
+            .unwrap()
+            .unwrap();
+        let delete = http
+            .call(http_call("wild", HttpMethod::DELETE))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(delete.status_code, 405);
+    }
+
+    #[async_std::test]
+    async fn http_triggered_matches_route_patterns() {
+        let http = HttpTriggered::new().start().await.unwrap();
+        let city = start_bash_executor("city", "city").await;
+        let vienna = start_bash_executor("vienna", "vienna").await;
+        let rest = start_bash_executor("rest", "rest").await;
+        for (route, pool) in vec![
+            ("weather/:city", city),
+            ("/weather/vienna/", vienna),
+            ("weather/:city/*rest", rest),
+        ] {
+            http.call(HttpTriggerMsg::Subscribe {
+                env_id: Uuid::new_v4(),
+                route: route.to_owned(),
+                pool,
+                method: HttpMethod::ALL,
+            })
+            .await
+            .unwrap()
+            .unwrap();
+        }
+
+        for (path, expected) in vec![
+            ("weather/vienna", "vienna"),
+            ("weather/berlin", "city"),
+            ("weather/berlin/today", "rest"),
+        ] {
+            let outputs = http
+                .call(http_call(path, HttpMethod::GET))
+                .await
+                .unwrap()
+                .unwrap();
+            assert_eq!(outputs.body, expected.as_bytes().to_vec());
+        }
+    }
+
+    #[async_std::test]
+    async fn http_triggered_rejects_invalid_patterns() {
+        let http = HttpTriggered::new().start().await.unwrap();
+        let pool = start_bash_executor("invalid", "invalid").await;
+        let result = http
+            .call(HttpTriggerMsg::Subscribe {
+                env_id: Uuid::new_v4(),
+                route: "a/*rest/b".to_owned(),
+                pool,
+                method: HttpMethod::ALL,
+            })
+            .await
+            .unwrap();
+        assert!(result.is_err());
+    }
+
+    #[async_std::test]
+    async fn http_triggered_rejects_taken_routes() {
+        let http = HttpTriggered::new().start().await.unwrap();
+        let first_id = Uuid::new_v4();
+        for (env_id, body) in vec![(first_id, "first"), (first_id, "updated")] {
+            let pool = start_bash_executor(body, body).await;
+            http.call(HttpTriggerMsg::Subscribe {
+                env_id,
+                route: "taken".to_owned(),
+                pool,
+                method: HttpMethod::GET,
+            })
+            .await
+            .unwrap()
+            .unwrap();
+        }
+        let pool = start_bash_executor("second", "second").await;
+        let result = http
+            .call(HttpTriggerMsg::Subscribe {
+                env_id: Uuid::new_v4(),
+                route: "/taken/".to_owned(),
+                pool,
+                method: HttpMethod::GET,
+            })
+            .await
+            .unwrap();
+        assert!(result.is_err());
+
+        let got = http
+            .call(http_call("taken", HttpMethod::GET))
+            .await
+            .unwrap();
+        assert_eq!(got.unwrap().body, b"updated".to_vec());
+    }
+
+    // a timer that doesn't tick on its own, the tests send TimerTrigger messages instead
+    async fn start_manual_timer() -> Addr<TimerTriggered> {
+        TimerTriggered::new(Duration::from_secs(3600))
+            .start()
+            .await
+            .unwrap()
+    }
+
+    fn every_second() -> Schedule {
+        "* * * * * *".parse().unwrap()
+    }
+
+    fn count_lines(path: &std::path::Path) -> usize {
+        std::fs::read_to_string(path)
+            .map(|s| s.lines().count())
+            .unwrap_or(0)
+    }
+
+    // timer calls run in the background, so the tests wait for their output
+    async fn wait_for_lines(path: &std::path::Path, lines: usize) -> usize {
+        for _ in 0..50 {
+            if count_lines(path) >= lines {
+                break;
+            }
+            task::sleep(Duration::from_millis(100)).await;
+        }
+        count_lines(path)
+    }
+
+    async fn tick(timer: &Addr<TimerTriggered>, secs_from_now: i64) {
+        timer
+            .call(TimerTrigger {
+                when: Utc::now() + chrono::Duration::seconds(secs_from_now),
+            })
+            .await
+            .unwrap();
+    }
+
+    #[async_std::test]
+    async fn timer_triggered_replaced_schedule_fires_once_per_tick() {
+        let out_dir = get_empty_tmp_dir();
+        let (old_runs, new_runs) = (out_dir.join("old"), out_dir.join("new"));
+        let old = start_bash_script("old", &format!("echo run >> {:?}", old_runs)).await;
+        let new = start_bash_script("new", &format!("echo run >> {:?}", new_runs)).await;
+        let env_id = Uuid::new_v4();
+        let timer = start_manual_timer().await;
+        for pool in vec![old, new] {
+            timer
+                .call(IntervalTriggerMsg::Subscribe {
+                    env_id,
+                    schedule: every_second(),
+                    pool,
+                })
+                .await
+                .unwrap();
+        }
+
+        tick(&timer, 2).await;
+        assert_eq!(wait_for_lines(&new_runs, 1).await, 1);
+        tick(&timer, 4).await;
+        assert_eq!(wait_for_lines(&new_runs, 2).await, 2);
+        assert_eq!(count_lines(&old_runs), 0);
+    }
+
+    #[async_std::test]
+    async fn timer_triggered_doesnt_wait_for_calls() {
+        let out_dir = get_empty_tmp_dir();
+        let runs = out_dir.join("runs");
+        let pool = start_bash_script("slow", &format!("sleep 2; echo run >> {:?}", runs)).await;
+        let timer = start_manual_timer().await;
+        timer
+            .call(IntervalTriggerMsg::Subscribe {
+                env_id: Uuid::new_v4(),
+                schedule: every_second(),
+                pool,
+            })
+            .await
+            .unwrap();
+
+        let started = std::time::Instant::now();
+        tick(&timer, 2).await;
+        assert!(started.elapsed() < Duration::from_secs(1));
+        assert_eq!(wait_for_lines(&runs, 1).await, 1);
+    }
+
+    #[async_std::test]
+    async fn timer_triggered_unsubscribe_removes_pending_runs() {
+        let out_dir = get_empty_tmp_dir();
+        let runs = out_dir.join("runs");
+        let pool = start_bash_script("unsubscribed", &format!("echo run >> {:?}", runs)).await;
+        let env_id = Uuid::new_v4();
+        let timer = start_manual_timer().await;
+        timer
+            .call(IntervalTriggerMsg::Subscribe {
+                env_id,
+                schedule: every_second(),
+                pool,
+            })
+            .await
+            .unwrap();
+        timer
+            .call(IntervalTriggerMsg::Unsubscribe { env_id })
+            .await
+            .unwrap();
+
+        tick(&timer, 2).await;
+        task::sleep(Duration::from_secs(1)).await;
+        assert_eq!(count_lines(&runs), 0);
+    }
+}
@@ -1,13 +1,17 @@
+use crate::ext::process::{limit_resources, run_streaming, ProcessOutput, StdoutStream};
+use crate::ext::sandbox::Sandbox;
 use crate::ext::toolchain::ToolchainLifecycle;
 use crate::ext::toolchain::ToolchainSetup;
 use crate::Environment;
 use anyhow::Result;
 use async_std::task;
 use log::{error, debug};
+use minifaas_common::errors::ExecutionError;
 use minifaas_common::runtime::RawFunctionInput;
-use std::io::Write;
-use std::process::{Command, Stdio};
+use minifaas_common::ResourceLimits;
+use std::process::Command;
 use std::sync::Arc;
+use std::time::Duration;
 
 pub const DEFAULT_VERSION: &str = "3.2.57";
 const DEFAULT_BASH_EXE_NAME: &str = "bash";
@@ -16,6 +20,8 @@ const DEFAULT_BASH_EXE_NAME: &str = "bash";
 pub struct Bash {
     local_path: String,
     default_args: Vec<String>,
+    limits: ResourceLimits,
+    sandboxed: bool,
 }
 
 #[derive(Clone, Debug)]
@@ -52,6 +58,8 @@ impl Bash {
         Bash {
             local_path: DEFAULT_BASH_EXE_NAME.to_string(),
             default_args,
+            limits: ResourceLimits::default(),
+            sandboxed: false,
         }
     }
 }
@@ -61,6 +69,8 @@ impl Default for Bash {
         Bash {
             local_path: DEFAULT_BASH_EXE_NAME.into(),
             default_args: vec![],
+            limits: ResourceLimits::default(),
+            sandboxed: false,
         }
     }
 }
@@ -87,9 +97,17 @@ impl ToolchainLifecycle for Bash {
         &self,
         code: Vec<u8>,
         input: Arc<RawFunctionInput>,
-        _env: &Environment,
-    ) -> Result<String> {
+        vars: Vec<(String, String)>,
+        env: &Environment,
+        timeout: Duration,
+        stdout: StdoutStream,
+    ) -> Result<ProcessOutput> {
         let exe = self.local_path.clone(); // bash should be in everyone's path on Linux
+        let sandbox = if self.sandboxed {
+            Some(Sandbox::new(env.absolute_path("").await).with_binary(&exe)?)
+        } else {
+            None
+        };
 
         let code = code.clone();
         debug!(
@@ -98,27 +116,19 @@ impl ToolchainLifecycle for Bash {
             code.len()
         );
         let default_args = self.default_args.clone();
+        let limits = self.limits;
         debug!("Starting execution with {}", exe);
         task::spawn_blocking(move || {
-            let mut child = Command::new(&*exe)
-                .args(default_args)
+            let mut cmd = Command::new(&*exe);
+            cmd.args(default_args)
                 .env_clear()
-                .env("__MF__INPUTS", serde_json::to_string(&input)?)
-                .stdin(Stdio::piped())
-                .stdout(Stdio::piped())
-                .spawn()
-                .expect("failed to execute child");
-
-            let stdin = child.stdin.as_mut().expect("Failed to open stdin");
-            stdin.write_all(&code).expect("Failed to write to stdin");
-            let output = child.wait_with_output().expect("failed to wait on child");
-            if output.status.success() {
-                std::str::from_utf8(&output.stdout)
-                    .map(|s| s.to_owned())
-                    .map_err(|e| e.into())
-            } else {
-                Err(std::io::Error::from(std::io::ErrorKind::NotFound).into())
+                .envs(vars)
+                .env("__MF__INPUTS", serde_json::to_string(&input)?);
+            if let Some(sandbox) = sandbox {
+                sandbox.apply(&mut cmd)?;
             }
+            limit_resources(&mut cmd, &limits);
+            run_streaming(cmd, &code, timeout, stdout)
         })
         .await
     }
@@ -126,6 +136,28 @@ impl ToolchainLifecycle for Bash {
     async fn post_execute(&self) -> Result<()> {
         Ok(())
     }
+
+    fn failure(&self, output: &ProcessOutput) -> ExecutionError {
+        let stderr = output.stderr_lines();
+        match stderr.iter().find(|l| l.contains("syntax error")) {
+            Some(line) => ExecutionError::CompilerError(line.clone(), stderr.clone()),
+            None => output.exit_error(&self.limits),
+        }
+    }
+
+    fn with_limits(&self, limits: &ResourceLimits) -> Option<Self> {
+        Some(Bash {
+            limits: *limits,
+            ..self.clone()
+        })
+    }
+
+    fn sandboxed(&self) -> Option<Self> {
+        Some(Bash {
+            sandboxed: true,
+            ..self.clone()
+        })
+    }
 }
 
 #[async_trait::async_trait]
@@ -133,7 +165,7 @@ impl ToolchainSetup for BashSetup {
     async fn pre_setup(&mut self, _env: &Environment) -> Result<()> {
         self.installed = Command::new(&*self.local_path)
             .arg("--version")
-            .spawn()
+            .output()
             .is_ok();
         debug!("Is Bash available? {}", self.installed);
         Ok(())
@@ -187,4 +219,58 @@ mod tests {
         bash_setup.pre_setup(&e).await.unwrap();
         assert!(bash_setup.installed);
     }
+
+    #[async_std::test]
+    #[cfg(unix)]
+    async fn bash_failure_syntax_error() {
+        let root_dir = get_empty_tmp_dir();
+        let e = Environment::create_with_id(root_dir.join("env"), Uuid::new_v4())
+            .await
+            .unwrap();
+        let bash = Bash::default();
+        let output = bash
+            ._execute(
+                b"echo (".to_vec(),
+                Arc::new(RawFunctionInput::default()),
+                vec![],
+                &e,
+                Duration::from_secs(5),
+                StdoutStream::default(),
+            )
+            .await
+            .unwrap();
+        assert!(!output.success());
+        match bash.failure(&output) {
+            ExecutionError::CompilerError(message, context) => {
+                assert!(message.contains("syntax error"));
+                assert!(!context.is_empty());
+            }
+            other => panic!("Unexpected error: {:?}", other),
+        }
+    }
+
+    #[async_std::test]
+    #[cfg(unix)]
+    async fn bash_failure_exit_code() {
+        let root_dir = get_empty_tmp_dir();
+        let e = Environment::create_with_id(root_dir.join("env"), Uuid::new_v4())
+            .await
+            .unwrap();
+        let bash = Bash::default();
+        let output = bash
+            ._execute(
+                b"echo 'went wrong' >&2; exit 4".to_vec(),
+                Arc::new(RawFunctionInput::default()),
+                vec![],
+                &e,
+                Duration::from_secs(5),
+                StdoutStream::default(),
+            )
+            .await
+            .unwrap();
+        match bash.failure(&output) {
+            ExecutionError::NonZeroExit(4, context) => assert_eq!(context, vec!["went wrong"]),
+            other => panic!("Unexpected error: {:?}", other),
+        }
+    }
 }
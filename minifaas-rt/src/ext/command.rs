@@ -0,0 +1,388 @@
+use crate::ext::process::{
+    find_on_path, limit_resources, run_streaming, ProcessOutput, StdoutStream,
+};
+use crate::ext::sandbox::Sandbox;
+use crate::ext::toolchain::ToolchainLifecycle;
+use crate::ext::toolchain::ToolchainSetup;
+use crate::ext::worker::write_code_file;
+use crate::Environment;
+use anyhow::Result;
+use async_std::task;
+use log::{debug, error};
+use minifaas_common::errors::ExecutionError;
+use minifaas_common::runtime::RawFunctionInput;
+use minifaas_common::{ProgrammingLanguage, ResourceLimits};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::path::Path;
+use std::process::Command;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Replaced with the path of the code file in a command's arguments
+pub const FILE_PLACEHOLDER: &str = "{file}";
+
+///
+/// How the function's code is passed to the interpreter.
+///
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum CodeInput {
+    /// The code is written to the interpreter's stdin
+    Stdin,
+
+    /// The code is written to a file in the environment that's passed as argument
+    File,
+}
+
+impl Default for CodeInput {
+    fn default() -> Self {
+        CodeInput::Stdin
+    }
+}
+
+///
+/// An interpreter declared by the admin, e.g.:
+///
+/// ```json
+/// { "name": "Ruby", "interpreter": "/usr/bin/ruby", "args": ["{file}"], "extension": "rb", "code": "file" }
+/// ```
+///
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct CommandToolchainConfig {
+    /// The language name functions select
+    pub name: String,
+
+    /// An absolute path or the name of an executable on the `PATH`
+    pub interpreter: String,
+
+    /// Arguments for the interpreter, `{file}` is replaced with the code file (if `code` is `file`)
+    #[serde(default)]
+    pub args: Vec<String>,
+
+    /// The extension for code files (without the dot)
+    #[serde(default)]
+    pub extension: String,
+
+    #[serde(default, rename = "code")]
+    pub code_input: CodeInput,
+}
+
+impl CommandToolchainConfig {
+    pub fn language(&self) -> ProgrammingLanguage {
+        ProgrammingLanguage::Command(self.name.clone())
+    }
+
+    ///
+    /// The interpreter's arguments with `file` filled in. The file is appended if there is no placeholder.
+    ///
+    fn args_for(&self, file: Option<&str>) -> Vec<String> {
+        match file {
+            Some(file) => {
+                let mut args: Vec<String> = self
+                    .args
+                    .iter()
+                    .map(|a| a.replace(FILE_PLACEHOLDER, file))
+                    .collect();
+                if !self.args.iter().any(|a| a.contains(FILE_PLACEHOLDER)) {
+                    args.push(file.to_owned());
+                }
+                args
+            }
+            None => self.args.clone(),
+        }
+    }
+}
+
+///
+/// Reads the command toolchains from a JSON file containing a list of `CommandToolchainConfig`s.
+/// Names have to be unique and must not shadow one of the built-in languages.
+///
+pub fn load_command_toolchains<P: AsRef<Path>>(path: P) -> Result<Vec<CommandToolchainConfig>> {
+    let configs: Vec<CommandToolchainConfig> =
+        serde_json::from_slice(&std::fs::read(path.as_ref())?)?;
+    let mut names = HashSet::new();
+    for config in configs.iter() {
+        match config.language() {
+            ProgrammingLanguage::Command(_) if names.insert(config.name.clone()) => {}
+            _ => {
+                return Err(anyhow::Error::msg(format!(
+                    "Invalid toolchain name '{}' in {:?}",
+                    config.name,
+                    path.as_ref()
+                )))
+            }
+        }
+    }
+    Ok(configs)
+}
+
+#[derive(Clone, Debug)]
+pub struct CommandToolchain {
+    config: CommandToolchainConfig,
+    limits: ResourceLimits,
+    sandboxed: bool,
+}
+
+impl CommandToolchain {
+    pub fn new(config: CommandToolchainConfig) -> Self {
+        CommandToolchain {
+            config,
+            limits: ResourceLimits::default(),
+            sandboxed: false,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct CommandSetup {
+    config: CommandToolchainConfig,
+    pub installed: bool,
+}
+
+impl CommandSetup {
+    pub fn new(config: CommandToolchainConfig) -> Self {
+        CommandSetup {
+            config,
+            installed: false,
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl ToolchainLifecycle for CommandToolchain {
+    async fn _build(&self, code: &str) -> Result<Vec<u8>> {
+        Ok(code.to_owned().into_bytes())
+    }
+
+    async fn _execute(
+        &self,
+        code: Vec<u8>,
+        input: Arc<RawFunctionInput>,
+        vars: Vec<(String, String)>,
+        env: &Environment,
+        timeout: Duration,
+        stdout: StdoutStream,
+    ) -> Result<ProcessOutput> {
+        let config = self.config.clone();
+        let limits = self.limits;
+        let env_root = env.absolute_path("").await;
+        let sandbox = if self.sandboxed {
+            Some(Sandbox::new(&env_root).with_binary(&config.interpreter)?)
+        } else {
+            None
+        };
+        debug!(
+            "Executing on '{}' ({} bytes)",
+            config.interpreter,
+            code.len()
+        );
+        task::spawn_blocking(move || {
+            let (code_file, stdin) = match config.code_input {
+                CodeInput::File => (
+                    Some(write_code_file(
+                        &env_root,
+                        ".mf-function-",
+                        &code,
+                        &config.extension,
+                    )?),
+                    vec![],
+                ),
+                CodeInput::Stdin => (None, code),
+            };
+            let file = code_file.as_ref().and_then(|p| p.to_str());
+            let mut cmd = Command::new(&config.interpreter);
+            cmd.args(config.args_for(file))
+                .env_clear()
+                .envs(vars)
+                .env("__MF__INPUTS", serde_json::to_string(&input)?);
+            if let Some(sandbox) = &sandbox {
+                sandbox.apply(&mut cmd)?;
+            }
+            limit_resources(&mut cmd, &limits);
+            run_streaming(cmd, &stdin, timeout, stdout)
+        })
+        .await
+    }
+
+    fn failure(&self, output: &ProcessOutput) -> ExecutionError {
+        output.exit_error(&self.limits)
+    }
+
+    fn with_limits(&self, limits: &ResourceLimits) -> Option<Self> {
+        Some(CommandToolchain {
+            limits: *limits,
+            ..self.clone()
+        })
+    }
+
+    fn sandboxed(&self) -> Option<Self> {
+        Some(CommandToolchain {
+            sandboxed: true,
+            ..self.clone()
+        })
+    }
+}
+
+#[async_trait::async_trait]
+impl ToolchainSetup for CommandSetup {
+    async fn pre_setup(&mut self, _env: &Environment) -> Result<()> {
+        let interpreter = &self.config.interpreter;
+        self.installed = if interpreter.contains(std::path::MAIN_SEPARATOR) {
+            Path::new(interpreter).is_file()
+        } else {
+            find_on_path(interpreter).is_some()
+        };
+        debug!("Is '{}' available? {}", interpreter, self.installed);
+        Ok(())
+    }
+
+    async fn setup(&self, env: &Environment) -> Result<()> {
+        if self.installed {
+            Ok(())
+        } else {
+            error!(
+                "Could not find '{}' for {}",
+                self.config.interpreter, self.config.name
+            );
+            Err(anyhow::Error::msg(format!(
+                "No '{}' executable available for {} in {}",
+                self.config.interpreter, self.config.name, env
+            )))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    use minifaas_common::Environment;
+    use minifaas_test::get_empty_tmp_dir;
+    use uuid::Uuid;
+
+    async fn create_temp_env() -> Environment {
+        let root_dir = get_empty_tmp_dir();
+        Environment::create_with_id(root_dir.join(Uuid::new_v4().to_string()), Uuid::new_v4())
+            .await
+            .unwrap()
+    }
+
+    fn config(args: Vec<&str>, code_input: CodeInput) -> CommandToolchainConfig {
+        CommandToolchainConfig {
+            name: "Shell".to_owned(),
+            interpreter: "sh".to_owned(),
+            args: args.into_iter().map(|a| a.to_owned()).collect(),
+            extension: "sh".to_owned(),
+            code_input,
+        }
+    }
+
+    async fn run(config: CommandToolchainConfig, code: &str, e: &Environment) -> ProcessOutput {
+        CommandToolchain::new(config)
+            ._execute(
+                code.as_bytes().to_vec(),
+                Arc::new(RawFunctionInput::default()),
+                vec![],
+                e,
+                Duration::from_secs(5),
+                StdoutStream::default(),
+            )
+            .await
+            .unwrap()
+    }
+
+    #[test]
+    fn load_command_toolchains_from_file() {
+        let path = get_empty_tmp_dir().join("toolchains.json");
+        std::fs::write(
+            &path,
+            r#"[
+                { "name": "Ruby", "interpreter": "/usr/bin/ruby", "args": ["{file}"], "extension": "rb", "code": "file" },
+                { "name": "Lua", "interpreter": "lua", "args": ["-"] }
+            ]"#,
+        )
+        .unwrap();
+        let configs = load_command_toolchains(&path).unwrap();
+        assert_eq!(configs.len(), 2);
+        assert_eq!(configs[0].code_input, CodeInput::File);
+        assert_eq!(
+            configs[1].language(),
+            ProgrammingLanguage::Command("Lua".to_owned())
+        );
+        assert_eq!(configs[1].code_input, CodeInput::Stdin);
+    }
+
+    #[test]
+    fn load_command_toolchains_rejects_builtin_and_duplicate_names() {
+        let path = get_empty_tmp_dir().join("toolchains.json");
+        std::fs::write(&path, r#"[{ "name": "Python", "interpreter": "python2" }]"#).unwrap();
+        assert!(load_command_toolchains(&path).is_err());
+
+        std::fs::write(
+            &path,
+            r#"[{ "name": "Lua", "interpreter": "lua" }, { "name": "Lua", "interpreter": "luajit" }]"#,
+        )
+        .unwrap();
+        assert!(load_command_toolchains(&path).is_err());
+    }
+
+    #[async_std::test]
+    #[cfg(unix)]
+    async fn command_execute_via_stdin() {
+        let e = create_temp_env().await;
+        let output = run(
+            config(vec!["-s"], CodeInput::Stdin),
+            "echo \"__MF__body:$__MF__INPUTS\"",
+            &e,
+        )
+        .await;
+        assert!(output.success());
+        assert_eq!(output.stdout, b"__MF__body:{}\n".to_vec());
+    }
+
+    #[async_std::test]
+    #[cfg(unix)]
+    async fn command_execute_via_file() {
+        let e = create_temp_env().await;
+        let output = run(
+            config(vec!["-e", "{file}"], CodeInput::File),
+            "echo \"__MF__body:$0\"",
+            &e,
+        )
+        .await;
+        assert!(output.success());
+        let stdout = String::from_utf8(output.stdout).unwrap();
+        assert!(stdout.starts_with("__MF__body:"));
+        assert!(stdout.trim_end().ends_with(".sh"));
+
+        // without a placeholder the file is the last argument
+        let output = run(config(vec![], CodeInput::File), "exit 3", &e).await;
+        assert_eq!(output.exit_code, Some(3));
+
+        // only the file of the latest code is kept
+        let code_files: Vec<_> = std::fs::read_dir(e.absolute_path("").await)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .filter(|name| name.starts_with(".mf-function-"))
+            .collect();
+        assert_eq!(code_files.len(), 1);
+    }
+
+    #[async_std::test]
+    async fn commandsetup_requires_interpreter() {
+        let e = create_temp_env().await;
+        let mut setup = CommandSetup::new(CommandToolchainConfig {
+            interpreter: "surely-not-an-interpreter".to_owned(),
+            ..config(vec![], CodeInput::Stdin)
+        });
+        setup.pre_setup(&e).await.unwrap();
+        assert!(!setup.installed);
+        assert!(setup.setup(&e).await.is_err());
+
+        let mut setup = CommandSetup::new(config(vec![], CodeInput::Stdin));
+        setup.pre_setup(&e).await.unwrap();
+        assert!(setup.installed);
+    }
+}
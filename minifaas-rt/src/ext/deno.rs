@@ -1,14 +1,24 @@
+use crate::ext::install::{
+    is_linked, link_executable, ToolchainCache, ToolchainInstaller, ToolchainSource,
+};
+use crate::ext::process::{limit_resources, run_streaming, ProcessOutput, StdoutStream};
+use crate::ext::sandbox::Sandbox;
 use crate::ext::toolchain::ToolchainLifecycle;
 use crate::ext::toolchain::ToolchainSetup;
+use crate::ext::worker::{worker_key, write_js_worker, WarmWorker, WarmWorkers};
 use crate::Environment;
 use anyhow::Result;
 use async_std::task;
 use log::{debug, error, info, warn};
+use minifaas_common::errors::ExecutionError;
 use minifaas_common::runtime::RawFunctionInput;
-use std::io::Read;
-use std::io::Write;
-use std::process::{Command, Stdio};
+use minifaas_common::Permissions;
+use minifaas_common::ResourceLimits;
+use std::path::Path;
+use std::process::Command;
+use std::time::Duration;
 use std::{io, sync::Arc};
+use uuid::Uuid;
 
 /*
 -A, --allow-all Allow all permissions. This disables all security.
@@ -22,12 +32,18 @@ use std::{io, sync::Arc};
 */
 
 pub const DEFAULT_VERSION: &str = "1.7.4";
+pub const DEFAULT_SOURCE: &str = "https://github.com/denoland/deno/releases/download";
 const DEFAULT_DENO_EXE_NAME: &str = "deno";
 
 #[derive(Clone, Debug)]
 pub struct Deno {
     local_path: String,
     default_args: Vec<String>,
+    workers: Option<WarmWorkers>,
+    warm: bool,
+    permissions: Permissions,
+    limits: ResourceLimits,
+    sandboxed: bool,
 }
 
 #[derive(Clone, Debug)]
@@ -35,6 +51,7 @@ pub struct DenoSetup {
     local_path: String,
     system: os_info::Info,
     version: String,
+    installer: ToolchainInstaller,
     pub installed: bool,
 }
 
@@ -44,6 +61,10 @@ impl DenoSetup {
             local_path: deno_name.into(),
             system,
             version: version.into(),
+            installer: ToolchainInstaller::new(
+                ToolchainSource::Remote(DEFAULT_SOURCE.to_owned()),
+                ToolchainCache::default_root(),
+            ),
             installed: false,
         }
     }
@@ -51,6 +72,21 @@ impl DenoSetup {
     pub fn with_version<S: Into<String>>(version: S) -> Self {
         DenoSetup::new(DEFAULT_DENO_EXE_NAME, os_info::get(), &version.into())
     }
+
+    ///
+    /// Fetches and caches the Deno binary with `installer` (e.g. from a local mirror) instead of GitHub.
+    ///
+    pub fn with_installer(mut self, installer: ToolchainInstaller) -> Self {
+        self.installer = installer;
+        self
+    }
+
+    ///
+    /// The release archive's path relative to the source, e.g. `v1.7.4/deno-x86_64-unknown-linux-gnu.zip`.
+    ///
+    fn archive(&self) -> String {
+        format!("v{}/deno-{}.zip", self.version, os_arch_tuple(&self.system))
+    }
 }
 
 impl Default for DenoSetup {
@@ -64,8 +100,72 @@ impl Deno {
         Deno {
             local_path: DEFAULT_DENO_EXE_NAME.to_string(),
             default_args,
+            workers: None,
+            warm: false,
+            permissions: Permissions::default(),
+            limits: ResourceLimits::default(),
+            sandboxed: false,
         }
     }
+
+    ///
+    /// Lets functions that opt in (see `ToolchainLifecycle::warm`) keep a resident worker process, which is replaced
+    /// after `max_calls` invocations.
+    ///
+    pub fn with_warm_workers(mut self, max_calls: usize) -> Self {
+        self.workers = Some(WarmWorkers::new(max_calls));
+        self
+    }
+
+    ///
+    /// The arguments for `deno`, with the permission flags before the script.
+    ///
+    fn run_args(&self, env_root: &Path, vars: &[(String, String)]) -> Vec<String> {
+        let mut args = self.default_args.clone();
+        let at = args
+            .iter()
+            .position(|a| a == "run")
+            .map(|i| i + 1)
+            .unwrap_or(0);
+        args.splice(
+            at..at,
+            permission_args(&self.permissions_for(vars), env_root),
+        );
+        args
+    }
+
+    ///
+    /// The function's permissions, which always include reading its own environment variables.
+    ///
+    fn permissions_for(&self, vars: &[(String, String)]) -> Permissions {
+        let mut permissions = self.permissions.clone();
+        for (name, _) in vars.iter() {
+            if !permissions.allow_env.contains(name) {
+                permissions.allow_env.push(name.clone());
+            }
+        }
+        permissions
+    }
+}
+
+///
+/// Deno's `--allow-*` flags for a function's permissions. File access is limited to the environment's directory.
+///
+fn permission_args(permissions: &Permissions, env_root: &Path) -> Vec<String> {
+    let mut args = vec![];
+    if !permissions.allow_net.is_empty() {
+        args.push(format!("--allow-net={}", permissions.allow_net.join(",")));
+    }
+    if permissions.allow_read {
+        args.push(format!("--allow-read={}", env_root.display()));
+    }
+    if permissions.allow_write {
+        args.push(format!("--allow-write={}", env_root.display()));
+    }
+    if !permissions.allow_env.is_empty() {
+        args.push(format!("--allow-env={}", permissions.allow_env.join(",")));
+    }
+    args
 }
 
 impl Default for Deno {
@@ -73,6 +173,11 @@ impl Default for Deno {
         Deno {
             local_path: DEFAULT_DENO_EXE_NAME.into(),
             default_args: vec!["run".to_owned(), "-".to_owned()],
+            workers: None,
+            warm: false,
+            permissions: Permissions::default(),
+            limits: ResourceLimits::default(),
+            sandboxed: false,
         }
     }
 }
@@ -99,8 +204,11 @@ impl ToolchainLifecycle for Deno {
         &self,
         code: Vec<u8>,
         input: Arc<RawFunctionInput>,
+        vars: Vec<(String, String)>,
         env: &Environment,
-    ) -> Result<String> {
+        timeout: Duration,
+        stdout: StdoutStream,
+    ) -> Result<ProcessOutput> {
         let exe = env
             .absolute_path(&self.local_path)
             .await
@@ -114,28 +222,58 @@ impl ToolchainLifecycle for Deno {
             std::str::from_utf8(&code)?,
             code.len()
         );
-        let default_args = self.default_args.clone();
-        debug!("Starting execution with {}", exe);
+        let env_root = env.absolute_path("").await;
+        let limits = self.limits;
+        let sandbox = if self.sandboxed {
+            Some(Sandbox::new(&env_root).with_binary(&exe)?)
+        } else {
+            None
+        };
+        if let Some(workers) = self.workers.as_ref().filter(|_| self.warm) {
+            let workers = workers.clone();
+            let env_id = env.id;
+            let mut permissions = self.permissions_for(&vars);
+            // the worker passes the inputs to the code through the environment
+            permissions.allow_env.push("__MF__INPUTS".to_owned());
+            let args = permission_args(&permissions, &env_root);
+            let key = worker_key(&code, &vars);
+            debug!("Starting warm execution with {}", exe);
+            return task::spawn_blocking(move || {
+                // the files are written for every call, an earlier version of the code may have removed them
+                let (script_path, module_path) = write_js_worker(&env_root, &code)?;
+                workers.execute(env_id, &key, &input, timeout, stdout, || {
+                    let mut cmd = Command::new(&*exe);
+                    cmd.arg("run").args(&args);
+                    if !permissions.allow_read {
+                        // importing the module is a read like any other
+                        cmd.arg(format!("--allow-read={}", module_path.display()));
+                    }
+                    cmd.arg(&script_path)
+                        .arg(&module_path)
+                        .env_clear()
+                        .envs(vars);
+                    if let Some(sandbox) = &sandbox {
+                        sandbox.apply(&mut cmd)?;
+                    }
+                    limit_resources(&mut cmd, &limits);
+                    WarmWorker::spawn(cmd, &key)
+                })
+            })
+            .await;
+        }
+        let args = self.run_args(&env_root, &vars);
+        debug!("Starting execution with {} {:?}", exe, args);
         task::spawn_blocking(move || {
-            let mut child = Command::new(&*exe)
-                .args(default_args)
+            let mut cmd = Command::new(&*exe);
+            cmd.args(args)
                 .env_clear()
-                .env("__MF__INPUTS", serde_json::to_string(&input)?)
-                .stdin(Stdio::piped())
-                .stdout(Stdio::piped())
-                .spawn()
-                .expect("failed to execute child");
-
-            let stdin = child.stdin.as_mut().expect("Failed to open stdin");
-            stdin.write_all(&code).expect("Failed to write to stdin");
-            let output = child.wait_with_output().expect("failed to wait on child");
-            if output.status.success() {
-                std::str::from_utf8(&output.stdout)
-                    .map(|s| s.to_owned())
-                    .map_err(|e| e.into())
-            } else {
-                Err(std::io::Error::from(std::io::ErrorKind::NotFound).into())
+                .envs(vars)
+                .env("__MF__INPUTS", serde_json::to_string(&input)?);
+            if let Some(sandbox) = &sandbox {
+                sandbox.apply(&mut cmd)?;
             }
+            limit_resources(&mut cmd, &limits);
+            run_streaming(cmd, &code, timeout, stdout)
         })
         .await
     }
@@ -143,13 +281,69 @@ impl ToolchainLifecycle for Deno {
     async fn post_execute(&self) -> Result<()> {
         Ok(())
     }
+
+    fn retire(&self, env_id: &Uuid) {
+        if let Some(workers) = &self.workers {
+            workers.retire(env_id);
+        }
+    }
+
+    fn warm(&self) -> Option<Self> {
+        self.workers.as_ref().map(|_| Deno {
+            warm: true,
+            ..self.clone()
+        })
+    }
+
+    fn with_permissions(&self, permissions: &Permissions) -> Option<Self> {
+        Some(Deno {
+            permissions: permissions.clone(),
+            ..self.clone()
+        })
+    }
+
+    fn with_limits(&self, limits: &ResourceLimits) -> Option<Self> {
+        Some(Deno {
+            limits: *limits,
+            ..self.clone()
+        })
+    }
+
+    fn sandboxed(&self) -> Option<Self> {
+        Some(Deno {
+            sandboxed: true,
+            ..self.clone()
+        })
+    }
+
+    fn failure(&self, output: &ProcessOutput) -> ExecutionError {
+        // Deno prefixes all errors with "error:", but only uncaught exceptions happen at runtime
+        let stderr = output.stderr_lines();
+        match stderr
+            .iter()
+            .find(|l| l.starts_with("error:") && !l.starts_with("error: Uncaught"))
+        {
+            Some(line) => ExecutionError::CompilerError(line.clone(), stderr.clone()),
+            None => output.exit_error(&self.limits),
+        }
+    }
 }
 
 #[async_trait::async_trait]
 impl ToolchainSetup for DenoSetup {
     async fn pre_setup(&mut self, env: &Environment) -> Result<()> {
-        self.installed = env.has_file(&self.local_path).await;
-        debug!("Is Deno installed in {}? {}", env, self.installed);
+        // environments link to the shared installation, anything else (e.g. a copy or another version) is replaced
+        let binary = self
+            .installer
+            .cached_path("deno", &self.version, DEFAULT_DENO_EXE_NAME);
+        self.installed = is_linked(&env.absolute_path(&self.local_path).await, &binary)
+            && self
+                .installer
+                .is_installed("deno", &self.version, DEFAULT_DENO_EXE_NAME);
+        debug!(
+            "Is Deno {} installed in {}? {}",
+            self.version, env, self.installed
+        );
         Ok(())
     }
 
@@ -158,31 +352,33 @@ impl ToolchainSetup for DenoSetup {
             info!("Found Deno in {}, skipping setup", env);
             Ok(())
         } else {
-            warn!("Could not find Deno in Env: {}, downloading", env);
-            let origin = format!(
-                "https://github.com/denoland/deno/releases/download/v{}/deno-{}.zip",
-                self.version,
-                os_arch_tuple(&self.system)
-            );
-            debug!("Downloading from '{}'", origin);
-            let mut file = env.add_file(&self.local_path)?;
-
-            let download_task = task::spawn_blocking(move || -> anyhow::Result<()>{
-                let resp = ureq::get(&origin).call()?;
-                let mut r = resp.into_reader();
-                let mut zipped = vec![];
-                r.read_to_end(&mut zipped)?;
-                let mut extracted = zip::ZipArchive::new(std::io::Cursor::new(zipped))?;
-                let mut deno_exe = extracted.by_index(0)?;
-                if io::copy(&mut deno_exe, &mut file)? == deno_exe.size() {
-                    Ok(())
-                } else {
-                    Err(io::Error::from(io::ErrorKind::NotFound).into())
-                }
+            warn!("Could not find Deno in Env: {}, installing", env);
+            let installer = self.installer.clone();
+            let version = self.version.clone();
+            let archive = self.archive();
+            let target = env.absolute_path(&self.local_path).await;
+
+            let install_task = task::spawn_blocking(move || -> anyhow::Result<()> {
+                let binary = installer.install(
+                    "deno",
+                    &version,
+                    DEFAULT_DENO_EXE_NAME,
+                    &archive,
+                    |zipped, file| {
+                        let mut extracted = zip::ZipArchive::new(std::io::Cursor::new(zipped))?;
+                        let mut deno_exe = extracted.by_index(0)?;
+                        if io::copy(&mut deno_exe, file)? == deno_exe.size() {
+                            Ok(())
+                        } else {
+                            Err(io::Error::from(io::ErrorKind::NotFound).into())
+                        }
+                    },
+                )?;
+                link_executable(&binary, &target)
             })
             .await;
-            debug!("Result from downloading: {:?}", download_task);
-            download_task
+            debug!("Result from installing: {:?}", install_task);
+            install_task
         }
     }
 
@@ -191,6 +387,14 @@ impl ToolchainSetup for DenoSetup {
         Ok(())
     }
 
+    fn with_version(&self, version: &str) -> Option<Self> {
+        Some(DenoSetup {
+            version: version.to_owned(),
+            installed: false,
+            ..self.clone()
+        })
+    }
+
     #[cfg(target_family = "unix")]
     async fn post_setup(&self, env: &Environment) -> Result<()> {
         use std::os::unix::fs::PermissionsExt;
@@ -238,6 +442,45 @@ mod tests {
             .unwrap()
     }
 
+    #[test]
+    fn deno_run_args_with_permissions() {
+        let root = Path::new("/envs/abc");
+        assert_eq!(Deno::default().run_args(root, &[]), vec!["run", "-"]);
+
+        let deno = Deno::default()
+            .with_permissions(&Permissions {
+                allow_net: vec!["example.com".to_owned(), "10.0.0.1:80".to_owned()],
+                allow_read: true,
+                allow_write: false,
+                allow_env: vec!["API_KEY".to_owned()],
+            })
+            .unwrap();
+        assert_eq!(
+            deno.run_args(root, &[]),
+            vec![
+                "run",
+                "--allow-net=example.com,10.0.0.1:80",
+                "--allow-read=/envs/abc",
+                "--allow-env=API_KEY",
+                "-"
+            ]
+        );
+
+        // functions can always read their own variables
+        let vars = vec![
+            ("API_KEY".to_owned(), "secret".to_owned()),
+            ("LOG_LEVEL".to_owned(), "debug".to_owned()),
+        ];
+        assert_eq!(
+            Deno::default().run_args(root, &vars),
+            vec!["run", "--allow-env=API_KEY,LOG_LEVEL", "-"]
+        );
+        assert_eq!(
+            deno.run_args(root, &vars)[3],
+            "--allow-env=API_KEY,LOG_LEVEL"
+        );
+    }
+
     #[async_std::test]
     #[ignore] // download takes some time
     async fn denosetup_setup_download() {
@@ -248,11 +491,77 @@ mod tests {
     }
 
     #[async_std::test]
+    async fn denosetup_setup_from_mirror() {
+        use crate::ext::install::sha256_hex;
+        use std::io::Write;
+
+        let e = create_temp_env(None).await;
+        let mirror = get_empty_tmp_dir().join("mirror");
+        let deno_setup = DenoSetup::default();
+        let archive = mirror.join(deno_setup.archive());
+        std::fs::create_dir_all(archive.parent().unwrap()).unwrap();
+
+        let mut zipped = zip::ZipWriter::new(std::io::Cursor::new(vec![]));
+        zipped
+            .start_file("deno", zip::write::FileOptions::default())
+            .unwrap();
+        zipped.write_all(b"not really deno").unwrap();
+        let zipped = zipped.finish().unwrap().into_inner();
+        std::fs::write(&archive, &zipped).unwrap();
+
+        let mut checksums = std::collections::HashMap::new();
+        checksums.insert(
+            archive.file_name().unwrap().to_str().unwrap().to_owned(),
+            sha256_hex(&zipped),
+        );
+        let cache = get_empty_tmp_dir().join("cache");
+        let installer = ToolchainInstaller::new(ToolchainSource::Mirror(mirror), &cache)
+            .with_checksums(checksums)
+            .require_checksums(true);
+        deno_setup
+            .with_installer(installer)
+            .setup(&e)
+            .await
+            .unwrap();
+
+        assert!(e.has_file("deno").await);
+        assert_eq!(
+            std::fs::read(e.absolute_path("deno").await).unwrap(),
+            b"not really deno".to_vec()
+        );
+        assert!(cache
+            .join("deno")
+            .join(DEFAULT_VERSION)
+            .join("deno")
+            .is_file());
+    }
+
+    #[async_std::test]
+    #[cfg(unix)]
     async fn denosetup_presetup_download() {
+        use crate::ext::install::record_checksum;
         let e = create_temp_env(None).await;
-        let mut deno_setup = DenoSetup::default();
+        let cache = get_empty_tmp_dir();
+        let mut deno_setup = DenoSetup::default().with_installer(ToolchainInstaller::new(
+            ToolchainSource::Remote(DEFAULT_SOURCE.to_owned()),
+            &cache,
+        ));
+
+        // a copy of its own isn't shared
         e.add_file("deno").unwrap();
         deno_setup.pre_setup(&e).await.unwrap();
+        assert!(!deno_setup.installed);
+
+        let binary = cache.join("deno").join(DEFAULT_VERSION).join("deno");
+        std::fs::create_dir_all(binary.parent().unwrap()).unwrap();
+        std::fs::write(&binary, b"").unwrap();
+        link_executable(&binary, &e.absolute_path("deno").await).unwrap();
+        // without a recorded checksum, it's not known where the binary came from
+        deno_setup.pre_setup(&e).await.unwrap();
+        assert!(!deno_setup.installed);
+
+        record_checksum(&binary).unwrap();
+        deno_setup.pre_setup(&e).await.unwrap();
         assert!(deno_setup.installed);
     }
 
@@ -0,0 +1,790 @@
+use anyhow::Result;
+use log::{debug, info, warn};
+use minifaas_common::errors::PreparationError;
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use uuid::Uuid;
+
+/// The checksum of an installed executable is recorded next to it, named like the executable plus this suffix
+const RECORD_SUFFIX: &str = ".sha256";
+
+/// The owner of the cache that installed a version is recorded in this file in the version's directory
+const OWNER_FILE: &str = ".installed-by";
+
+/// The SHA-256 checksums of the default toolchain releases (see `parse_checksums`)
+const PINNED_CHECKSUMS: &str = include_str!("toolchain-checksums.sha256");
+
+///
+/// Where toolchain archives come from: a web server (e.g. GitHub releases) or a local directory with the same layout.
+///
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ToolchainSource {
+    /// A base URL, archives are fetched from `<url>/<archive>`
+    Remote(String),
+
+    /// A local directory (or mount), archives are read from `<dir>/<archive>`
+    Mirror(PathBuf),
+}
+
+impl ToolchainSource {
+    ///
+    /// Parses `http(s)://` URLs as remote sources, `file://` URLs and plain paths as local mirrors.
+    ///
+    pub fn parse(source: &str) -> Result<Self> {
+        if let Some(path) = source.strip_prefix("file://") {
+            Ok(ToolchainSource::Mirror(PathBuf::from(path)))
+        } else if source.starts_with("http://") || source.starts_with("https://") {
+            Ok(ToolchainSource::Remote(
+                source.trim_end_matches('/').to_owned(),
+            ))
+        } else if source.contains("://") {
+            Err(anyhow::Error::msg(format!(
+                "Unsupported toolchain source '{}'",
+                source
+            )))
+        } else {
+            Ok(ToolchainSource::Mirror(PathBuf::from(source)))
+        }
+    }
+
+    ///
+    /// Reads `archive` from the source, `None` if the source doesn't have it.
+    ///
+    fn fetch(&self, archive: &str) -> Result<Option<Vec<u8>>> {
+        match self {
+            ToolchainSource::Remote(base) => {
+                let url = format!("{}/{}", base, archive);
+                debug!("Downloading from '{}'", url);
+                match ureq::get(&url).call() {
+                    Ok(resp) => {
+                        let mut bytes = vec![];
+                        resp.into_reader().read_to_end(&mut bytes)?;
+                        Ok(Some(bytes))
+                    }
+                    Err(ureq::Error::Status(404, _)) => Ok(None),
+                    Err(e) => Err(e.into()),
+                }
+            }
+            ToolchainSource::Mirror(dir) => {
+                let path = dir.join(archive);
+                debug!("Reading from {:?}", path);
+                if path.is_file() {
+                    Ok(Some(std::fs::read(path)?))
+                } else {
+                    Ok(None)
+                }
+            }
+        }
+    }
+}
+
+impl fmt::Display for ToolchainSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ToolchainSource::Remote(url) => write!(f, "{}", url),
+            ToolchainSource::Mirror(dir) => write!(f, "{}", dir.display()),
+        }
+    }
+}
+
+///
+/// The toolchain installations shared by all environments, laid out as `<root>/<toolchain>/<version>/<executable>`.
+/// Environments link to the executables, so a version is in use as long as some environment links into it. Each
+/// version records the owner of the cache that installed it, clones share the owner, see `collect_garbage`.
+///
+#[derive(Clone, Debug)]
+pub struct ToolchainCache {
+    root: PathBuf,
+    owner: Arc<RwLock<String>>,
+}
+
+impl ToolchainCache {
+    ///
+    /// The cache of this user if none is configured, in the system's temp directory.
+    ///
+    pub fn default_root() -> PathBuf {
+        std::env::temp_dir().join(format!("minifaas-toolchains-{}", current_user()))
+    }
+
+    pub fn new<P: Into<PathBuf>>(root: P) -> Self {
+        let root = root.into();
+        // environments link into the cache, which only works with absolute paths
+        let root = match std::env::current_dir() {
+            Ok(cwd) if root.is_relative() => cwd.join(root),
+            _ => root,
+        };
+        ToolchainCache {
+            root,
+            owner: Arc::new(RwLock::new(String::new())),
+        }
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    ///
+    /// Identifies the runtime using this cache (and its clones) across restarts, e.g. by its environments' directory.
+    ///
+    pub fn set_owner(&self, owner: &str) {
+        if let Ok(mut current) = self.owner.write() {
+            *current = owner.to_owned();
+        }
+    }
+
+    fn owner(&self) -> Result<String> {
+        self.owner
+            .read()
+            .map(|owner| owner.clone())
+            .map_err(|_| anyhow::Error::msg("The toolchain cache is poisoned"))
+    }
+
+    ///
+    /// Creates the cache directory if necessary and makes sure only this user can change it (mode 0700). Fails if it
+    /// belongs to another user or is a symlink, since anyone who can write to the cache can run code as functions.
+    ///
+    #[cfg(target_family = "unix")]
+    pub fn ensure_private(&self) -> Result<()> {
+        use std::os::unix::fs::{DirBuilderExt, MetadataExt, PermissionsExt};
+        if let Err(e) = std::fs::DirBuilder::new()
+            .recursive(true)
+            .mode(0o700)
+            .create(&self.root)
+        {
+            if e.kind() != std::io::ErrorKind::AlreadyExists {
+                return Err(e.into());
+            }
+        }
+        let meta = std::fs::symlink_metadata(&self.root)?;
+        if !meta.file_type().is_dir() || meta.uid() != current_user() {
+            return Err(anyhow::Error::msg(format!(
+                "The toolchain cache {:?} has to be a directory owned by the runtime's user",
+                self.root
+            )));
+        }
+        if meta.mode() & 0o077 != 0 {
+            warn!(
+                "Restricting the toolchain cache {:?} to its owner",
+                self.root
+            );
+            std::fs::set_permissions(&self.root, std::fs::Permissions::from_mode(0o700))?;
+        }
+        Ok(())
+    }
+
+    #[cfg(target_family = "windows")]
+    pub fn ensure_private(&self) -> Result<()> {
+        std::fs::create_dir_all(&self.root).map_err(anyhow::Error::from)
+    }
+
+    pub fn path(&self, toolchain: &str, version: &str, executable: &str) -> PathBuf {
+        self.root.join(toolchain).join(version).join(executable)
+    }
+
+    ///
+    /// All installed `(toolchain, version)` pairs, sorted.
+    ///
+    pub fn versions(&self) -> Result<Vec<(String, String)>> {
+        let mut versions = vec![];
+        for toolchain in sub_dirs(&self.root)? {
+            for version in sub_dirs(&self.root.join(&toolchain))? {
+                versions.push((toolchain.clone(), version));
+            }
+        }
+        versions.sort();
+        Ok(versions)
+    }
+
+    ///
+    /// Records in the version's directory that this cache's owner installed `version` of `toolchain`.
+    ///
+    pub fn mark_installed(&self, toolchain: &str, version: &str) -> Result<()> {
+        let marker = self.root.join(toolchain).join(version).join(OWNER_FILE);
+        std::fs::write(marker, self.owner()?).map_err(anyhow::Error::from)
+    }
+
+    fn is_owned(&self, toolchain: &str, version: &str, owner: &str) -> bool {
+        let marker = self.root.join(toolchain).join(version).join(OWNER_FILE);
+        std::fs::read_to_string(marker)
+            .map(|installed_by| installed_by == owner)
+            .unwrap_or(false)
+    }
+
+    ///
+    /// Removes the versions that this cache's owner installed (also before a restart) and that none of the
+    /// `environments` (their root directories) link to, and returns them. Versions installed by others are kept since
+    /// their environments aren't known, e.g. those of another runtime sharing the directory.
+    ///
+    pub fn collect_garbage(&self, environments: &[PathBuf]) -> Result<Vec<(String, String)>> {
+        self.ensure_private()?;
+        let mut used = HashSet::new();
+        for env in environments {
+            let entries = match std::fs::read_dir(env) {
+                Ok(entries) => entries,
+                Err(_) => continue,
+            };
+            for entry in entries.filter_map(|e| e.ok()) {
+                if let Ok(target) = std::fs::read_link(entry.path()) {
+                    if let Ok(rel) = target.strip_prefix(&self.root) {
+                        used.insert(rel.iter().take(2).collect::<PathBuf>());
+                    }
+                }
+            }
+        }
+        let owner = self.owner()?;
+        let mut removed = vec![];
+        for (toolchain, version) in self.versions()? {
+            if self.is_owned(&toolchain, &version, &owner)
+                && !used.contains(&Path::new(&toolchain).join(&version))
+            {
+                std::fs::remove_dir_all(self.root.join(&toolchain).join(&version))?;
+                info!("Removed unused {} {}", toolchain, version);
+                removed.push((toolchain, version));
+            }
+        }
+        Ok(removed)
+    }
+}
+
+///
+/// The names of the (non-hidden) directories in `dir`, none if it doesn't exist.
+///
+fn sub_dirs(dir: &Path) -> Result<Vec<String>> {
+    if !dir.is_dir() {
+        return Ok(vec![]);
+    }
+    let mut names = vec![];
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() {
+            if let Some(name) = entry.file_name().to_str() {
+                if !name.starts_with('.') {
+                    names.push(name.to_owned());
+                }
+            }
+        }
+    }
+    Ok(names)
+}
+
+///
+/// Fetches toolchain archives, verifies them, and keeps the extracted executables in a cache that's shared by all
+/// environments. Checksums come from the configured list (by archive file name) or the ones pinned for the default
+/// releases, never from the source itself. Cached executables are only used while they match the checksum that was
+/// recorded when they were installed.
+///
+#[derive(Clone, Debug)]
+pub struct ToolchainInstaller {
+    source: ToolchainSource,
+    cache: ToolchainCache,
+    checksums: Arc<HashMap<String, String>>,
+    require_checksums: bool,
+}
+
+impl ToolchainInstaller {
+    pub fn new<P: Into<PathBuf>>(source: ToolchainSource, cache: P) -> Self {
+        ToolchainInstaller {
+            source,
+            cache: ToolchainCache::new(cache),
+            checksums: Arc::new(parse_checksums(PINNED_CHECKSUMS)),
+            require_checksums: true,
+        }
+    }
+
+    ///
+    /// Known SHA-256 checksums by archive file name (see `load_checksums`), in addition to the pinned ones.
+    ///
+    pub fn with_checksums(mut self, checksums: HashMap<String, String>) -> Self {
+        let mut all = parse_checksums(PINNED_CHECKSUMS);
+        all.extend(checksums);
+        self.checksums = Arc::new(all);
+        self
+    }
+
+    ///
+    /// Refuses archives without a checksum (the default) instead of installing them with a warning.
+    ///
+    pub fn require_checksums(mut self, required: bool) -> Self {
+        self.require_checksums = required;
+        self
+    }
+
+    pub fn with_source(mut self, source: ToolchainSource) -> Self {
+        self.source = source;
+        self
+    }
+
+    pub fn with_cache<P: Into<PathBuf>>(mut self, cache: P) -> Self {
+        self.cache = ToolchainCache::new(cache);
+        self
+    }
+
+    ///
+    /// Installs into `cache`, so its clones know which versions were installed here.
+    ///
+    pub fn with_shared_cache(mut self, cache: ToolchainCache) -> Self {
+        self.cache = cache;
+        self
+    }
+
+    pub fn cache(&self) -> &ToolchainCache {
+        &self.cache
+    }
+
+    ///
+    /// Where `executable` of a toolchain's version is (or will be) cached.
+    ///
+    pub fn cached_path(&self, toolchain: &str, version: &str, executable: &str) -> PathBuf {
+        self.cache.path(toolchain, version, executable)
+    }
+
+    ///
+    /// Whether `executable` is cached and still what was installed.
+    ///
+    pub fn is_installed(&self, toolchain: &str, version: &str, executable: &str) -> bool {
+        let path = self.cached_path(toolchain, version, executable);
+        self.cache.ensure_private().is_ok() && path.is_file() && matches_record(&path)
+    }
+
+    ///
+    /// Returns the cached `executable` and installs it first if necessary: `archive` (relative to the source) is
+    /// fetched, verified against its checksum, and handed to `extract` to write the executable.
+    ///
+    pub fn install<F>(
+        &self,
+        toolchain: &str,
+        version: &str,
+        executable: &str,
+        archive: &str,
+        extract: F,
+    ) -> Result<PathBuf>
+    where
+        F: FnOnce(&[u8], &mut File) -> Result<()>,
+    {
+        self.cache.ensure_private()?;
+        let target = self.cached_path(toolchain, version, executable);
+        if target.is_file() {
+            if matches_record(&target) {
+                debug!("Using cached {} {} at {:?}", toolchain, version, target);
+                return Ok(target);
+            }
+            warn!(
+                "The cached {} {} at {:?} isn't what was installed, reinstalling it",
+                toolchain, version, target
+            );
+        }
+        let bytes = self.source.fetch(archive)?.ok_or_else(|| {
+            anyhow::Error::msg(format!("'{}' not found in {}", archive, self.source))
+        })?;
+        self.verify(archive, &bytes)?;
+
+        let dir = target
+            .parent()
+            .ok_or_else(|| anyhow::Error::msg("Invalid toolchain cache"))?;
+        std::fs::create_dir_all(dir)?;
+        // extract next to the target and rename it, so concurrent setups never see a partial file
+        let tmp = dir.join(format!(".{}-{}", executable, Uuid::new_v4()));
+        let extracted = File::create(&tmp)
+            .map_err(anyhow::Error::from)
+            .and_then(|mut file| extract(&bytes, &mut file))
+            .and_then(|_| set_executable(&tmp))
+            .and_then(|_| std::fs::rename(&tmp, &target).map_err(anyhow::Error::from))
+            .and_then(|_| record_checksum(&target));
+        if let Err(e) = extracted {
+            let _ = std::fs::remove_file(&tmp);
+            return Err(e);
+        }
+        self.cache.mark_installed(toolchain, version)?;
+        info!("Installed {} {} to {:?}", toolchain, version, target);
+        Ok(target)
+    }
+
+    fn expected_checksum(&self, archive: &str) -> Option<String> {
+        let name = Path::new(archive)
+            .file_name()
+            .and_then(|n| n.to_str())
+            .unwrap_or(archive);
+        // a checksum from the same source as the archive wouldn't prove anything
+        self.checksums.get(name).map(|c| c.to_lowercase())
+    }
+
+    fn verify(&self, archive: &str, bytes: &[u8]) -> Result<()> {
+        let actual = sha256_hex(bytes);
+        match self.expected_checksum(archive) {
+            Some(expected) if expected == actual => {
+                debug!("Verified '{}' (SHA-256 {})", archive, actual);
+                Ok(())
+            }
+            Some(expected) => Err(PreparationError::ChecksumMismatch {
+                file: archive.to_owned(),
+                expected,
+                actual,
+            }
+            .into()),
+            None if self.require_checksums => {
+                Err(PreparationError::ChecksumMissing(archive.to_owned()).into())
+            }
+            None => {
+                warn!(
+                    "No checksum for '{}' (SHA-256 {}), installing it unverified",
+                    archive, actual
+                );
+                Ok(())
+            }
+        }
+    }
+}
+
+///
+/// The hex encoded SHA-256 digest of `bytes`.
+///
+pub fn sha256_hex(bytes: &[u8]) -> String {
+    hex::encode(Sha256::digest(bytes))
+}
+
+///
+/// The hex encoded SHA-256 digest of the file at `path`.
+///
+fn sha256_file(path: &Path) -> Result<String> {
+    let mut hasher = Sha256::new();
+    std::io::copy(&mut File::open(path)?, &mut hasher)?;
+    Ok(hex::encode(hasher.finalize()))
+}
+
+///
+/// Records the checksum of an installed executable next to it.
+///
+pub(crate) fn record_checksum(executable: &Path) -> Result<()> {
+    std::fs::write(record_path(executable), sha256_file(executable)?).map_err(anyhow::Error::from)
+}
+
+///
+/// Whether `executable` matches the checksum recorded when it was installed.
+///
+fn matches_record(executable: &Path) -> bool {
+    match (
+        std::fs::read_to_string(record_path(executable)),
+        sha256_file(executable),
+    ) {
+        (Ok(recorded), Ok(actual)) => recorded.trim() == actual,
+        _ => false,
+    }
+}
+
+fn record_path(executable: &Path) -> PathBuf {
+    let mut name = executable.file_name().unwrap_or_default().to_os_string();
+    name.push(RECORD_SUFFIX);
+    executable.with_file_name(name)
+}
+
+#[cfg(target_family = "unix")]
+fn current_user() -> u32 {
+    unsafe { libc::geteuid() }
+}
+
+#[cfg(target_family = "windows")]
+fn current_user() -> u32 {
+    0
+}
+
+///
+/// Parses checksums in the format of `sha256sum` (`<checksum>  <file name>` per line, `#` starts a comment) into a
+/// map by file name.
+///
+pub fn parse_checksums(text: &str) -> HashMap<String, String> {
+    text.lines()
+        .filter(|line| !line.trim_start().starts_with('#'))
+        .filter_map(|line| {
+            let mut parts = line.split_whitespace();
+            let checksum = parts.next()?;
+            // binary mode marks the file name with a '*'
+            let name = parts.next()?.trim_start_matches('*');
+            let name = Path::new(name).file_name()?.to_str()?;
+            Some((name.to_owned(), checksum.to_lowercase()))
+        })
+        .collect()
+}
+
+///
+/// Reads a checksum file (see `parse_checksums`).
+///
+pub fn load_checksums<P: AsRef<Path>>(path: P) -> Result<HashMap<String, String>> {
+    Ok(parse_checksums(&std::fs::read_to_string(path)?))
+}
+
+#[cfg(target_family = "unix")]
+fn set_executable(path: &Path) -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    // permissions for this should be -rwxr-xr-x, or 755
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o755))
+        .map_err(anyhow::Error::from)
+}
+
+#[cfg(target_family = "windows")]
+fn set_executable(_path: &Path) -> Result<()> {
+    Ok(())
+}
+
+///
+/// Makes `binary` available at `link`, replacing whatever is there (e.g. a link to a removed version).
+///
+#[cfg(target_family = "unix")]
+pub(crate) fn link_executable(binary: &Path, link: &Path) -> Result<()> {
+    if link.symlink_metadata().is_ok() {
+        std::fs::remove_file(link)?;
+    }
+    std::os::unix::fs::symlink(binary, link).map_err(anyhow::Error::from)
+}
+
+///
+/// Whether `link` points to `binary`, and `binary` is still there.
+///
+#[cfg(target_family = "unix")]
+pub(crate) fn is_linked(link: &Path, binary: &Path) -> bool {
+    std::fs::read_link(link)
+        .map(|target| target == binary)
+        .unwrap_or(false)
+        && binary.is_file()
+}
+
+#[cfg(target_family = "windows")]
+pub(crate) fn is_linked(link: &Path, _binary: &Path) -> bool {
+    link.is_file()
+}
+
+#[cfg(target_family = "windows")]
+pub(crate) fn link_executable(binary: &Path, link: &Path) -> Result<()> {
+    std::fs::copy(binary, link)
+        .map(|_| ())
+        .map_err(anyhow::Error::from)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use minifaas_test::get_empty_tmp_dir;
+    use std::io::Write;
+
+    const ARCHIVE: &str = "v1.0.0/tool-linux.bin";
+    const CONTENT: &[u8] = b"#!/bin/sh\necho tool\n";
+
+    fn mirror() -> PathBuf {
+        let dir = get_empty_tmp_dir().join("mirror");
+        std::fs::create_dir_all(dir.join("v1.0.0")).unwrap();
+        std::fs::write(dir.join(ARCHIVE), CONTENT).unwrap();
+        dir
+    }
+
+    fn copy(bytes: &[u8], file: &mut File) -> Result<()> {
+        file.write_all(bytes).map_err(anyhow::Error::from)
+    }
+
+    #[test]
+    fn toolchain_source_parse() {
+        assert_eq!(
+            ToolchainSource::parse("https://example.com/releases/").unwrap(),
+            ToolchainSource::Remote("https://example.com/releases".to_owned())
+        );
+        assert_eq!(
+            ToolchainSource::parse("file:///srv/mirror").unwrap(),
+            ToolchainSource::Mirror(PathBuf::from("/srv/mirror"))
+        );
+        assert_eq!(
+            ToolchainSource::parse("/srv/mirror").unwrap(),
+            ToolchainSource::Mirror(PathBuf::from("/srv/mirror"))
+        );
+        assert!(ToolchainSource::parse("ftp://example.com").is_err());
+    }
+
+    #[test]
+    fn parse_checksums_sha256sum_format() {
+        let checksums =
+            parse_checksums("# pinned\nABCD  deno-x86_64.zip\nef01 *v1/node.tar.gz\n\ninvalid\n");
+        assert_eq!(checksums.len(), 2);
+        assert_eq!(checksums["deno-x86_64.zip"], "abcd");
+        assert_eq!(checksums["node.tar.gz"], "ef01");
+    }
+
+    #[test]
+    fn toolchain_installer_verifies_and_caches() {
+        let mirror = mirror();
+        let cache = get_empty_tmp_dir().join("cache");
+        let mut checksums = HashMap::new();
+        checksums.insert("tool-linux.bin".to_owned(), sha256_hex(CONTENT));
+        let installer = ToolchainInstaller::new(ToolchainSource::Mirror(mirror.clone()), &cache)
+            .with_checksums(checksums)
+            .require_checksums(true);
+
+        let path = installer
+            .install("tool", "1.0.0", "tool", ARCHIVE, copy)
+            .unwrap();
+        assert_eq!(path, cache.join("tool").join("1.0.0").join("tool"));
+        assert_eq!(std::fs::read(&path).unwrap(), CONTENT.to_vec());
+
+        assert!(installer.is_installed("tool", "1.0.0", "tool"));
+
+        // the second install doesn't need the source anymore
+        std::fs::remove_dir_all(&mirror).unwrap();
+        assert_eq!(
+            installer
+                .install("tool", "1.0.0", "tool", ARCHIVE, copy)
+                .unwrap(),
+            path
+        );
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn toolchain_installer_reinstalls_changed_executables() {
+        use std::os::unix::fs::PermissionsExt;
+        let cache = get_empty_tmp_dir().join("cache");
+        // e.g. planted before the first install
+        let planted = cache.join("tool").join("1.0.0").join("tool");
+        std::fs::create_dir_all(planted.parent().unwrap()).unwrap();
+        std::fs::write(&planted, b"#!/bin/sh\necho planted\n").unwrap();
+        std::fs::set_permissions(&cache, std::fs::Permissions::from_mode(0o777)).unwrap();
+
+        let mut checksums = HashMap::new();
+        checksums.insert("tool-linux.bin".to_owned(), sha256_hex(CONTENT));
+        let installer = ToolchainInstaller::new(ToolchainSource::Mirror(mirror()), &cache)
+            .with_checksums(checksums);
+        assert!(!installer.is_installed("tool", "1.0.0", "tool"));
+        let path = installer
+            .install("tool", "1.0.0", "tool", ARCHIVE, copy)
+            .unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), CONTENT.to_vec());
+        let mode = std::fs::metadata(&cache).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o700);
+
+        std::fs::write(&path, b"#!/bin/sh\necho changed\n").unwrap();
+        assert!(!installer.is_installed("tool", "1.0.0", "tool"));
+        installer
+            .install("tool", "1.0.0", "tool", ARCHIVE, copy)
+            .unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), CONTENT.to_vec());
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn toolchain_cache_collect_garbage() {
+        let cache = ToolchainCache::new(get_empty_tmp_dir().join("cache"));
+        for version in vec!["0.1.0", "1.0.0", "2.0.0"] {
+            let path = cache.path("tool", version, "tool");
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(&path, CONTENT).unwrap();
+        }
+        // 0.1.0 was installed by someone else
+        cache.clone().mark_installed("tool", "1.0.0").unwrap();
+        cache.mark_installed("tool", "2.0.0").unwrap();
+        assert_eq!(
+            cache.versions().unwrap(),
+            vec![
+                ("tool".to_owned(), "0.1.0".to_owned()),
+                ("tool".to_owned(), "1.0.0".to_owned()),
+                ("tool".to_owned(), "2.0.0".to_owned())
+            ]
+        );
+
+        let env = get_empty_tmp_dir();
+        let binary = cache.path("tool", "2.0.0", "tool");
+        link_executable(&binary, &env.join("tool")).unwrap();
+        assert!(is_linked(&env.join("tool"), &binary));
+
+        assert_eq!(
+            cache.collect_garbage(&[env.clone()]).unwrap(),
+            vec![("tool".to_owned(), "1.0.0".to_owned())]
+        );
+        assert_eq!(
+            cache.versions().unwrap(),
+            vec![
+                ("tool".to_owned(), "0.1.0".to_owned()),
+                ("tool".to_owned(), "2.0.0".to_owned())
+            ]
+        );
+
+        // without the environment, nothing uses the cache anymore
+        std::fs::remove_dir_all(&env).unwrap();
+        assert_eq!(cache.collect_garbage(&[env]).unwrap().len(), 1);
+        assert_eq!(
+            cache.versions().unwrap(),
+            vec![("tool".to_owned(), "0.1.0".to_owned())]
+        );
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn toolchain_cache_collect_garbage_after_restart() {
+        let root = get_empty_tmp_dir().join("cache");
+        let before = ToolchainCache::new(&root);
+        before.set_owner("runtime");
+        for version in vec!["1.0.0", "2.0.0"] {
+            let path = before.path("tool", version, "tool");
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(&path, CONTENT).unwrap();
+        }
+        before.mark_installed("tool", "1.0.0").unwrap();
+        // another runtime shares the directory
+        let other = ToolchainCache::new(&root);
+        other.set_owner("other");
+        other.mark_installed("tool", "2.0.0").unwrap();
+
+        let cache = ToolchainCache::new(&root);
+        cache.set_owner("runtime");
+        assert_eq!(
+            cache.collect_garbage(&[]).unwrap(),
+            vec![("tool".to_owned(), "1.0.0".to_owned())]
+        );
+        assert_eq!(
+            cache.versions().unwrap(),
+            vec![("tool".to_owned(), "2.0.0".to_owned())]
+        );
+    }
+
+    #[test]
+    fn toolchain_installer_rejects_checksum_mismatch() {
+        let cache = get_empty_tmp_dir().join("cache");
+        let mut checksums = HashMap::new();
+        checksums.insert("tool-linux.bin".to_owned(), sha256_hex(b"something else"));
+        let installer = ToolchainInstaller::new(ToolchainSource::Mirror(mirror()), &cache)
+            .with_checksums(checksums);
+
+        let err = installer
+            .install("tool", "1.0.0", "tool", ARCHIVE, copy)
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<PreparationError>(),
+            Some(PreparationError::ChecksumMismatch { .. })
+        ));
+        assert!(!installer.cached_path("tool", "1.0.0", "tool").exists());
+    }
+
+    #[test]
+    fn toolchain_installer_ignores_checksum_files() {
+        let mirror = mirror();
+        let cache = get_empty_tmp_dir().join("cache");
+        std::fs::write(
+            mirror.join("v1.0.0/tool-linux.bin.sha256sum"),
+            format!("{}  tool-linux.bin\n", sha256_hex(CONTENT)),
+        )
+        .unwrap();
+        let installer = ToolchainInstaller::new(ToolchainSource::Mirror(mirror.clone()), &cache);
+        let err = installer
+            .install("tool", "1.0.0", "tool", ARCHIVE, copy)
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<PreparationError>(),
+            Some(PreparationError::ChecksumMissing(_))
+        ));
+
+        // unless unverified installs are allowed explicitly
+        assert!(installer
+            .require_checksums(false)
+            .install("tool", "1.0.0", "tool", ARCHIVE, copy)
+            .is_ok());
+    }
+}
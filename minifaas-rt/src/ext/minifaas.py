@@ -0,0 +1,68 @@
+"""Helpers for miniFaaS functions written in Python.
+
+The function's inputs are in the `__MF__INPUTS` environment variable, outputs are lines on stdout that start with
+`__MF__<name>:`. Values are hex encoded so they may contain anything (including line breaks).
+
+    import minifaas
+
+    name = minifaas.params().get("name", ["world"])[0]
+    minifaas.respond("Hello " + name, headers={"Content-Type": "text/plain"})
+"""
+import json
+import os
+import sys
+
+PREFIX = "__MF__"
+
+_inputs = None
+
+
+def inputs():
+    """All inputs of the current call as dictionary."""
+    global _inputs
+    if _inputs is None:
+        _inputs = json.loads(os.environ.get("__MF__INPUTS") or "{}")
+    return _inputs
+
+
+def body():
+    """The raw request body as bytes."""
+    return bytes(inputs().get("body") or [])
+
+
+def json_body():
+    """The request body parsed as JSON."""
+    return json.loads(body().decode("utf-8"))
+
+
+def params():
+    """The query parameters, each with a list of values."""
+    return inputs().get("params") or {}
+
+
+def path_params():
+    """The parameters of the route pattern (e.g. `city` in `weather/:city`)."""
+    return inputs().get("path_params") or {}
+
+
+def headers():
+    """The request headers."""
+    return inputs().get("headers") or {}
+
+
+def output(name, value):
+    """Sets a single output value. Strings are UTF-8 encoded, everything that isn't bytes is serialized as JSON."""
+    if isinstance(value, str):
+        value = value.encode("utf-8")
+    elif not isinstance(value, (bytes, bytearray)):
+        value = json.dumps(value).encode("utf-8")
+    sys.stdout.write("{}{}:{}\n".format(PREFIX, name, bytes(value).hex()))
+    sys.stdout.flush()
+
+
+def respond(body=b"", status_code=200, headers=None):
+    """Sets the HTTP response."""
+    output("status_code", str(status_code))
+    if headers:
+        output("headers", headers)
+    output("body", body)
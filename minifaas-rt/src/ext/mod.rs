@@ -1,3 +1,10 @@
 pub mod bash;
+pub mod command;
 pub mod deno;
+pub mod install;
+pub mod nodejs;
+pub mod process;
+pub mod python;
+pub mod sandbox;
 pub mod toolchain;
+pub mod worker;
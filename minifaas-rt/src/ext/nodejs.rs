@@ -1,113 +1,156 @@
+use crate::ext::install::{
+    is_linked, link_executable, ToolchainCache, ToolchainInstaller, ToolchainSource,
+};
+use crate::ext::process::{
+    find_on_path, limit_resources, run_streaming, ProcessOutput, StdoutStream,
+};
+use crate::ext::sandbox::Sandbox;
 use crate::ext::toolchain::ToolchainLifecycle;
 use crate::ext::toolchain::ToolchainSetup;
+use crate::ext::worker::{worker_key, write_js_worker, WarmWorker, WarmWorkers};
 use crate::Environment;
 use anyhow::Result;
 use async_std::task;
-use log::{debug, error, info, warn};
+use flate2::read::GzDecoder;
+use log::{debug, info, warn};
+use minifaas_common::errors::ExecutionError;
 use minifaas_common::runtime::RawFunctionInput;
-use std::io::Read;
-use std::io::Write;
-use std::process::{Command, Stdio};
-use std::{io, sync::Arc};
-
-/*
--A, --allow-all Allow all permissions. This disables all security.
---allow-env Allow environment access for things like getting and setting of environment variables.
---allow-hrtime Allow high-resolution time measurement. High-resolution time can be used in timing attacks and fingerprinting.
---allow-net=<allow-net> Allow network access. You can specify an optional, comma-separated list of domains to provide an allow-list of allowed domains.
---allow-plugin Allow loading plugins. Please note that --allow-plugin is an unstable feature.
---allow-read=<allow-read> Allow file system read access. You can specify an optional, comma-separated list of directories or files to provide a allow-list of allowed file system access.
---allow-run Allow running subprocesses. Be aware that subprocesses are not run in a sandbox and therefore do not have the same security restrictions as the deno process. Therefore, use with caution.
---allow-write=<allow-write> Allow file system write access. You can specify an optional, comma-separated list of directories or files to provide a allow-list of allowed file system access.
-*/
-
-pub const DEFAULT_VERSION: &str = "16.0.0";
+use minifaas_common::ResourceLimits;
+use std::fs::File;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::Arc;
+use std::time::Duration;
+use uuid::Uuid;
+
 const DEFAULT_EXE_NAME: &str = "node";
 
-#[cfg(target_arch = "x86_64")]
-const DOWNLOAD_URL: &str = "x86_64";
-#[cfg(target_arch = "aarch64")]
-const DOWNLOAD_URL: &str = "aarch64";
-#[cfg(target_arch = "arm")]
-const DOWNLOAD_URL: &str = "https://unofficial-builds.nodejs.org/download/release/v16.0.0/node-v16.0.0-linux-x64-musl.tar.gz";
+///
+/// Where the Node.js binary for an environment comes from.
+///
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum NodeJsSource {
+    /// A release archive (`.tar.gz`) as distributed on nodejs.org, containing `<name>/bin/node`
+    Tarball(PathBuf),
+
+    /// An existing `node` binary on the `PATH`
+    SystemPath,
+}
 
 #[derive(Clone, Debug)]
 pub struct NodeJs {
     local_path: String,
     default_args: Vec<String>,
+    workers: Option<WarmWorkers>,
+    warm: bool,
+    limits: ResourceLimits,
+    sandboxed: bool,
 }
 
 #[derive(Clone, Debug)]
 pub struct NodeJsSetup {
     local_path: String,
-    system: os_info::Info,
-    version: String,
+    source: NodeJsSource,
+    installer: ToolchainInstaller,
     pub installed: bool,
 }
 
 impl NodeJsSetup {
-    pub fn new<S: Into<String>>(nodejs_name: S, system: os_info::Info, version: S) -> Self {
+    pub fn new<S: Into<String>>(nodejs_name: S, source: NodeJsSource) -> Self {
         NodeJsSetup {
             local_path: nodejs_name.into(),
-            system,
-            version: version.into(),
+            source,
+            installer: ToolchainInstaller::new(
+                ToolchainSource::Mirror(PathBuf::new()),
+                ToolchainCache::default_root(),
+            ),
             installed: false,
         }
     }
 
-    pub fn with_version<S: Into<String>>(version: S) -> Self {
-        NodeJsSetup::new(DEFAULT_EXE_NAME, os_info::get(), &version.into())
+    pub fn with_source(source: NodeJsSource) -> Self {
+        NodeJsSetup::new(DEFAULT_EXE_NAME, source)
+    }
+
+    ///
+    /// Extracts tarballs into the installer's cache (verified with its checksums) and links environments to it.
+    ///
+    pub fn with_installer(mut self, installer: ToolchainInstaller) -> Self {
+        self.installer = installer;
+        self
+    }
+
+    ///
+    /// The installer for the tarball's directory and its version, e.g. `node-v16.0.0-linux-x64`.
+    ///
+    fn tarball_installer(&self, archive: &Path) -> (ToolchainInstaller, String, String) {
+        let name = archive
+            .file_name()
+            .and_then(|n| n.to_str())
+            .unwrap_or_default()
+            .to_owned();
+        let version = name.trim_end_matches(".tar.gz").to_owned();
+        let dir = archive.parent().map(Path::to_path_buf).unwrap_or_default();
+        (
+            self.installer
+                .clone()
+                .with_source(ToolchainSource::Mirror(dir)),
+            name,
+            version,
+        )
     }
 }
 
 impl Default for NodeJsSetup {
     fn default() -> Self {
-        NodeJsSetup::with_version(DEFAULT_VERSION)
+        NodeJsSetup::with_source(NodeJsSource::SystemPath)
     }
 }
 
-impl Deno {
+impl NodeJs {
     pub fn new(default_args: Vec<String>) -> Self {
-        Deno {
+        NodeJs {
             local_path: DEFAULT_EXE_NAME.to_string(),
             default_args,
+            workers: None,
+            warm: false,
+            limits: ResourceLimits::default(),
+            sandboxed: false,
         }
     }
+
+    ///
+    /// Lets functions that opt in (see `ToolchainLifecycle::warm`) keep a resident worker process, which is replaced
+    /// after `max_calls` invocations.
+    ///
+    pub fn with_warm_workers(mut self, max_calls: usize) -> Self {
+        self.workers = Some(WarmWorkers::new(max_calls));
+        self
+    }
 }
 
-impl Default for Deno {
+impl Default for NodeJs {
     fn default() -> Self {
-        Deno {
-            local_path: DEFAULT_EXE_NAME.into(),
-            default_args: vec!["run".to_owned(), "-".to_owned()],
-        }
+        NodeJs::new(vec!["-".to_owned()])
     }
 }
 
 #[async_trait::async_trait]
-impl ToolchainLifecycle for Deno {
-    async fn pre_build(&self) -> Result<()> {
-        Ok(())
-    }
-
+impl ToolchainLifecycle for NodeJs {
     async fn _build(&self, code: &str) -> Result<Vec<u8>> {
         Ok(code.to_owned().into_bytes())
     }
 
-    async fn post_build(&self) -> Result<()> {
-        Ok(())
-    }
-
-    async fn pre_execute(&self, _input: Arc<RawFunctionInput>) -> Result<()> {
-        Ok(())
-    }
-
     async fn _execute(
         &self,
         code: Vec<u8>,
         input: Arc<RawFunctionInput>,
+        vars: Vec<(String, String)>,
         env: &Environment,
-    ) -> Result<String> {
+        timeout: Duration,
+        stdout: StdoutStream,
+    ) -> Result<ProcessOutput> {
         let exe = env
             .absolute_path(&self.local_path)
             .await
@@ -115,78 +158,161 @@ impl ToolchainLifecycle for Deno {
             .into_string()
             .expect("Invalid chars in path");
 
-        let code = code.clone();
-        debug!(
-            "Executing on Deno: {} (length: {} chars)",
-            std::str::from_utf8(&code)?,
-            code.len()
-        );
+        debug!("Executing on Node.js ({} bytes)", code.len());
+        let limits = self.limits;
+        let sandbox = if self.sandboxed {
+            Some(Sandbox::new(env.absolute_path("").await).with_binary(&exe)?)
+        } else {
+            None
+        };
+        if let Some(workers) = self.workers.as_ref().filter(|_| self.warm) {
+            let workers = workers.clone();
+            let env_id = env.id;
+            let env_root = env.absolute_path("").await;
+            let key = worker_key(&code, &vars);
+            debug!("Starting warm execution with {}", exe);
+            return task::spawn_blocking(move || {
+                // the files are written for every call, an earlier version of the code may have removed them
+                let (script_path, module_path) = write_js_worker(&env_root, &code)?;
+                workers.execute(env_id, &key, &input, timeout, stdout, || {
+                    let mut cmd = Command::new(&*exe);
+                    cmd.arg(&script_path)
+                        .arg(&module_path)
+                        .env_clear()
+                        .envs(vars);
+                    if let Some(sandbox) = &sandbox {
+                        sandbox.apply(&mut cmd)?;
+                    }
+                    limit_resources(&mut cmd, &limits);
+                    WarmWorker::spawn(cmd, &key)
+                })
+            })
+            .await;
+        }
         let default_args = self.default_args.clone();
         debug!("Starting execution with {}", exe);
         task::spawn_blocking(move || {
-            let mut child = Command::new(&*exe)
-                .args(default_args)
+            let mut cmd = Command::new(&*exe);
+            cmd.args(default_args)
                 .env_clear()
-                .env("__MF__INPUTS", serde_json::to_string(&input)?)
-                .stdin(Stdio::piped())
-                .stdout(Stdio::piped())
-                .spawn()
-                .expect("failed to execute child");
-
-            let stdin = child.stdin.as_mut().expect("Failed to open stdin");
-            stdin.write_all(&code).expect("Failed to write to stdin");
-            let output = child.wait_with_output().expect("failed to wait on child");
-            if output.status.success() {
-                std::str::from_utf8(&output.stdout)
-                    .map(|s| s.to_owned())
-                    .map_err(|e| e.into())
-            } else {
-                Err(std::io::Error::from(std::io::ErrorKind::NotFound).into())
+                .envs(vars)
+                .env("__MF__INPUTS", serde_json::to_string(&input)?);
+            if let Some(sandbox) = &sandbox {
+                sandbox.apply(&mut cmd)?;
             }
+            limit_resources(&mut cmd, &limits);
+            run_streaming(cmd, &code, timeout, stdout)
         })
         .await
     }
 
-    async fn post_execute(&self) -> Result<()> {
-        Ok(())
+    fn retire(&self, env_id: &Uuid) {
+        if let Some(workers) = &self.workers {
+            workers.retire(env_id);
+        }
+    }
+
+    fn warm(&self) -> Option<Self> {
+        self.workers.as_ref().map(|_| NodeJs {
+            warm: true,
+            ..self.clone()
+        })
+    }
+
+    fn failure(&self, output: &ProcessOutput) -> ExecutionError {
+        // warm workers prefix compile errors with "error: "
+        let stderr = output.stderr_lines();
+        match stderr
+            .iter()
+            .find(|l| l.trim_start_matches("error: ").starts_with("SyntaxError:"))
+        {
+            Some(line) => ExecutionError::CompilerError(line.clone(), stderr.clone()),
+            None => output.exit_error(&self.limits),
+        }
+    }
+
+    fn with_limits(&self, limits: &ResourceLimits) -> Option<Self> {
+        Some(NodeJs {
+            limits: *limits,
+            ..self.clone()
+        })
+    }
+
+    fn sandboxed(&self) -> Option<Self> {
+        Some(NodeJs {
+            sandboxed: true,
+            ..self.clone()
+        })
+    }
+}
+
+///
+/// Copies `bin/node` out of a Node.js release archive.
+///
+fn extract_node(archive: &[u8], file: &mut File) -> Result<()> {
+    let mut tarball = tar::Archive::new(GzDecoder::new(archive));
+    for entry in tarball.entries()? {
+        let mut entry = entry?;
+        let path = entry.path()?.into_owned();
+        if path.ends_with("bin/node") {
+            io::copy(&mut entry, file)?;
+            return Ok(());
+        }
     }
+    Err(anyhow::Error::msg("No 'bin/node' found in the archive"))
 }
+
 #[async_trait::async_trait]
 impl ToolchainSetup for NodeJsSetup {
     async fn pre_setup(&mut self, env: &Environment) -> Result<()> {
-        self.installed = env.has_file(&self.local_path).await;
-        debug!("Is Deno installed in {}? {}", env, self.installed);
+        let local = env.absolute_path(&self.local_path).await;
+        self.installed = match &self.source {
+            // environments link to the shared installation of the tarball
+            NodeJsSource::Tarball(archive) => {
+                let (installer, _, version) = self.tarball_installer(archive);
+                is_linked(
+                    &local,
+                    &installer.cached_path("node", &version, DEFAULT_EXE_NAME),
+                ) && installer.is_installed("node", &version, DEFAULT_EXE_NAME)
+            }
+            NodeJsSource::SystemPath => env.has_file(&self.local_path).await,
+        };
+        debug!("Is Node.js installed in {}? {}", env, self.installed);
         Ok(())
     }
 
     async fn setup(&self, env: &Environment) -> Result<()> {
         if self.installed {
-            info!("Found Deno in {}, skipping setup", env);
-            Ok(())
-        } else {
-            warn!("Could not find Deno in Env: {}, downloading", env);
-            let origin = format!(
-                "https://github.com/denoland/deno/releases/download/v{}/deno-{}.zip",
-                self.version,
-                os_arch_tuple(&self.system)
-            );
-            debug!("Downloading from '{}'", origin);
-            let mut file = env.add_file(&self.local_path)?;
-
-            task::spawn_blocking(move || {
-                let resp = ureq::get(&origin).call()?;
-                let mut r = resp.into_reader();
-                let mut zipped = vec![];
-                r.read_to_end(&mut zipped)?;
-                let mut extracted = zip::ZipArchive::new(std::io::Cursor::new(zipped)).unwrap();
-                let mut deno_exe = extracted.by_index(0).unwrap();
-                if io::copy(&mut deno_exe, &mut file)? == deno_exe.size() {
-                    Ok(())
-                } else {
-                    Err(io::Error::from(io::ErrorKind::NotFound).into())
-                }
-            })
-            .await
+            info!("Found Node.js in {}, skipping setup", env);
+            return Ok(());
+        }
+        let target = env.absolute_path(&self.local_path).await;
+        match &self.source {
+            NodeJsSource::Tarball(archive) => {
+                warn!(
+                    "Could not find Node.js in {}, extracting {:?}",
+                    env, archive
+                );
+                let (installer, name, version) = self.tarball_installer(archive);
+                task::spawn_blocking(move || {
+                    let binary = installer.install(
+                        "node",
+                        &version,
+                        DEFAULT_EXE_NAME,
+                        &name,
+                        extract_node,
+                    )?;
+                    link_executable(&binary, &target)
+                })
+                .await
+            }
+            NodeJsSource::SystemPath => {
+                let binary = find_on_path(DEFAULT_EXE_NAME).ok_or_else(|| {
+                    anyhow::Error::msg("Node.js setup failed: no 'node' on the PATH")
+                })?;
+                warn!("Could not find Node.js in {}, linking {:?}", env, binary);
+                link_executable(&binary, &target)
+            }
         }
     }
 
@@ -198,80 +324,172 @@ impl ToolchainSetup for NodeJsSetup {
     #[cfg(target_family = "unix")]
     async fn post_setup(&self, env: &Environment) -> Result<()> {
         use std::os::unix::fs::PermissionsExt;
-        let path = env.absolute_path(&self.local_path).await;
-        // permissions for this should be -rwxr-xr-x, or 755
-        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+        if let NodeJsSource::Tarball(_) = self.source {
+            let path = env.absolute_path(&self.local_path).await;
+            // permissions for this should be -rwxr-xr-x, or 755
+            std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755))?;
+        }
         Ok(())
     }
 }
 
-#[cfg(target_arch = "x86_64")]
-const SYSTEM_ARCH: &str = "x86_64";
-#[cfg(target_arch = "aarch64")]
-const SYSTEM_ARCH: &str = "aarch64";
-#[cfg(target_arch = "arm")]
-const SYSTEM_ARCH: &str = "arm";
-
-
-///
-/// Guesses the OS string from the current operating system.
-///
-fn os_arch_tuple(info: &os_info::Info) -> String {
-    let os = String::from(match info.os_type() {
-        os_info::Type::Windows => "pc-windows-msvc",
-        os_info::Type::Macos => "apple-darwin",
-        _ => "unknown-linux-gnu",
-    });
-    format!("{}-{}", SYSTEM_ARCH, os)
-}
-
 #[cfg(test)]
 mod tests {
 
     use super::*;
+    use crate::ext::install::sha256_hex;
 
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use minifaas_common::triggers::http::HttpTrigger;
     use minifaas_common::Environment;
     use minifaas_test::get_empty_tmp_dir;
-    use uuid::Uuid;
 
-    async fn create_temp_env(expected_id: Option<Uuid>) -> Environment {
+    // stands in for Node.js: ignores the code and prints a fixed output
+    const FAKE_NODE: &[u8] = b"#!/bin/sh\ncat > /dev/null\necho \"__MF__body:from fake node\"\n";
+
+    async fn create_temp_env() -> Environment {
         let root_dir = get_empty_tmp_dir();
-        let expected_id = expected_id.unwrap_or(Uuid::new_v4());
         let env_path = root_dir.join(Uuid::new_v4().to_string());
-        Environment::create_with_id(env_path.clone(), expected_id)
+        Environment::create_with_id(env_path.clone(), Uuid::new_v4())
             .await
             .unwrap()
     }
 
-    #[async_std::test]
-    #[ignore] // download takes some time
-    async fn NodeJsSetup_setup_download() {
-        let e = create_temp_env(None).await;
-        let deno_setup = NodeJsSetup::default();
-        deno_setup.setup(&e).await.unwrap();
-        assert!(e.has_file("deno").await);
+    fn fake_archive() -> PathBuf {
+        let path = get_empty_tmp_dir().join("node-v16.0.0-linux-x64.tar.gz");
+        let mut builder = tar::Builder::new(GzEncoder::new(
+            File::create(&path).unwrap(),
+            Compression::default(),
+        ));
+        for (name, content) in vec![
+            ("node-v16.0.0-linux-x64/README.md", b"# Node.js".as_ref()),
+            ("node-v16.0.0-linux-x64/bin/node", FAKE_NODE),
+        ] {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(content.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder.append_data(&mut header, name, content).unwrap();
+        }
+        builder.into_inner().unwrap().finish().unwrap();
+        path
+    }
+
+    fn tarball_setup(archive: PathBuf) -> NodeJsSetup {
+        let mut checksums = std::collections::HashMap::new();
+        checksums.insert(
+            archive.file_name().unwrap().to_str().unwrap().to_owned(),
+            sha256_hex(&std::fs::read(&archive).unwrap()),
+        );
+        NodeJsSetup::with_source(NodeJsSource::Tarball(archive)).with_installer(
+            ToolchainInstaller::new(ToolchainSource::Mirror(PathBuf::new()), get_empty_tmp_dir())
+                .with_checksums(checksums),
+        )
+    }
+
+    async fn install(setup: NodeJsSetup, e: &Environment) {
+        let mut setup = setup;
+        setup.pre_setup(e).await.unwrap();
+        setup.setup(e).await.unwrap();
+        setup.post_setup(e).await.unwrap();
     }
 
     #[async_std::test]
-    async fn NodeJsSetup_presetup_download() {
-        let e = create_temp_env(None).await;
-        let mut deno_setup = NodeJsSetup::default();
-        e.add_file("deno").unwrap();
-        deno_setup.pre_setup(&e).await.unwrap();
-        assert!(deno_setup.installed);
+    async fn nodejssetup_presetup_installed() {
+        let e = create_temp_env().await;
+        let mut setup = NodeJsSetup::default();
+        e.add_file("node").unwrap();
+        setup.pre_setup(&e).await.unwrap();
+        assert!(setup.installed);
     }
 
     #[async_std::test]
     #[cfg(unix)]
-    async fn NodeJsSetup_postsetup_set_execute_flag() {
+    async fn nodejssetup_setup_extracts_tarball() {
         use std::os::unix::fs::PermissionsExt;
-        let filename = "deno";
-        let e = create_temp_env(None).await;
+        let e = create_temp_env().await;
+        install(tarball_setup(fake_archive()), &e).await;
 
-        e.add_file(filename).unwrap();
-        NodeJsSetup::default().post_setup(&e).await.unwrap();
-        let meta = std::fs::metadata(e.absolute_path(filename).await).unwrap();
-        let perm = meta.permissions();
+        let path = e.absolute_path("node").await;
+        assert_eq!(std::fs::read(&path).unwrap(), FAKE_NODE.to_vec());
+        let perm = std::fs::metadata(&path).unwrap().permissions();
         assert_eq!(0o100755, perm.mode());
     }
+
+    #[async_std::test]
+    #[cfg(unix)]
+    async fn nodejssetup_setup_shares_tarball_installation() {
+        let (e1, e2) = (create_temp_env().await, create_temp_env().await);
+        let setup = tarball_setup(fake_archive());
+        install(setup.clone(), &e1).await;
+        install(setup.clone(), &e2).await;
+
+        let target1 = std::fs::read_link(e1.absolute_path("node").await).unwrap();
+        let target2 = std::fs::read_link(e2.absolute_path("node").await).unwrap();
+        assert_eq!(target1, target2);
+        assert!(target1.ends_with("node/node-v16.0.0-linux-x64/node"));
+
+        let mut setup = setup;
+        setup.pre_setup(&e2).await.unwrap();
+        assert!(setup.installed);
+    }
+
+    #[async_std::test]
+    async fn nodejssetup_setup_fails_without_node_in_tarball() {
+        let e = create_temp_env().await;
+        let archive = get_empty_tmp_dir().join("empty.tar.gz");
+        let builder = tar::Builder::new(GzEncoder::new(
+            File::create(&archive).unwrap(),
+            Compression::default(),
+        ));
+        builder.into_inner().unwrap().finish().unwrap();
+
+        let setup = tarball_setup(archive);
+        assert!(setup.setup(&e).await.is_err());
+    }
+
+    #[async_std::test]
+    #[cfg(unix)]
+    async fn nodejs_execute_runs_installed_binary() {
+        let e = create_temp_env().await;
+        install(tarball_setup(fake_archive()), &e).await;
+
+        let output = NodeJs::default()
+            ._execute(
+                b"console.log('ignored')".to_vec(),
+                Arc::new(HttpTrigger::default().into()),
+                vec![],
+                &e,
+                Duration::from_secs(5),
+                StdoutStream::default(),
+            )
+            .await
+            .unwrap();
+        assert!(output.success());
+        assert_eq!(output.stdout, b"__MF__body:from fake node\n".to_vec());
+    }
+
+    #[test]
+    fn nodejs_warm_needs_warm_workers() {
+        assert!(NodeJs::default().warm().is_none());
+        let warm = NodeJs::default().with_warm_workers(10).warm().unwrap();
+        assert!(warm.warm);
+    }
+
+    #[test]
+    fn nodejs_failure_detects_syntax_errors() {
+        let output = ProcessOutput {
+            stderr: b"[stdin]:1\nthis is not js(\n     ^^\n\nSyntaxError: Unexpected identifier\n"
+                .to_vec(),
+            exit_code: Some(1),
+            ..Default::default()
+        };
+        match NodeJs::default().failure(&output) {
+            ExecutionError::CompilerError(line, _) => {
+                assert_eq!(line, "SyntaxError: Unexpected identifier")
+            }
+            other => panic!("Unexpected error: {:?}", other),
+        }
+    }
 }
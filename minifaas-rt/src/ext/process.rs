@@ -0,0 +1,712 @@
+use anyhow::Result;
+use futures::channel::mpsc::{channel, Receiver, Sender};
+use futures::executor::block_on;
+use futures::SinkExt;
+use log::{debug, warn};
+use minifaas_common::errors::ExecutionError;
+use minifaas_common::ResourceLimits;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::PathBuf;
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+const POLL_INTERVAL_MS: u64 = 10;
+
+///
+/// How long the pipes of a process may stay open after it exited, e.g. because it left something running in the
+/// background.
+///
+const PIPE_GRACE_MS: u64 = 500;
+
+///
+/// How much a function may write to stdout unless configured otherwise: 10 MiB.
+///
+pub const DEFAULT_MAX_OUTPUT_BYTES: u64 = 10 * 1024 * 1024;
+
+///
+/// How many lines a `StdoutStream` holds before the process has to wait for the receiver.
+///
+const STREAM_BUFFER_LINES: usize = 64;
+
+///
+/// What processes print when they run into a resource limit that doesn't come with a signal, and the limit.
+///
+const LIMIT_MESSAGES: &[(&str, &str)] = &[
+    ("Cannot allocate memory", "memory"),
+    ("out of memory", "memory"),
+    ("MemoryError", "memory"),
+    ("Too many open files", "open files"),
+    ("Resource temporarily unavailable", "processes"),
+    ("File size limit exceeded", "file size"),
+];
+
+///
+/// What a function's process left behind after it exited.
+///
+#[derive(Debug, Default, Clone)]
+pub struct ProcessOutput {
+    pub stdout: Vec<u8>,
+    pub stderr: Vec<u8>,
+
+    /// The exit code, `None` if the process was terminated by a signal
+    pub exit_code: Option<i32>,
+
+    /// The signal that terminated the process (Unix only)
+    pub signal: Option<i32>,
+}
+
+impl ProcessOutput {
+    pub fn new(stdout: Vec<u8>, stderr: Vec<u8>, status: ExitStatus) -> Self {
+        ProcessOutput {
+            stdout,
+            stderr,
+            exit_code: status.code(),
+            signal: exit_signal(&status),
+        }
+    }
+
+    pub fn success(&self) -> bool {
+        self.exit_code == Some(0)
+    }
+
+    ///
+    /// stderr split into lines. Invalid UTF-8 is replaced.
+    ///
+    pub fn stderr_lines(&self) -> Vec<String> {
+        String::from_utf8_lossy(&self.stderr)
+            .lines()
+            .map(|l| l.to_owned())
+            .collect()
+    }
+
+    ///
+    /// The one of `limits` the process ran into. Limits that are enforced with a signal (CPU time, file size) have to
+    /// match how the process was terminated, shells report that as an exit code above 128. For the others, the process
+    /// has to have printed the matching error.
+    ///
+    pub fn exceeded_limit(&self, limits: &ResourceLimits) -> Option<&'static str> {
+        let signal = self.signal.or_else(|| {
+            self.exit_code
+                .filter(|code| *code > 128)
+                .map(|code| code - 128)
+        });
+        if let Some(limit) = signal.and_then(signal_limit) {
+            return Some(limit).filter(|limit| is_limited(limits, limit));
+        }
+        let stderr = String::from_utf8_lossy(&self.stderr);
+        LIMIT_MESSAGES
+            .iter()
+            .filter(|(_, limit)| is_limited(limits, limit))
+            .find(|(message, _)| stderr.contains(message))
+            .map(|(_, limit)| *limit)
+    }
+
+    ///
+    /// Maps an unsuccessful exit of a process that ran with `limits` to an `ExecutionError` using the stderr lines as
+    /// context.
+    ///
+    pub fn exit_error(&self, limits: &ResourceLimits) -> ExecutionError {
+        if let Some(limit) = self.exceeded_limit(limits) {
+            return ExecutionError::LimitExceeded(limit.to_owned(), self.stderr_lines());
+        }
+        match (self.exit_code, self.signal) {
+            (Some(code), _) => ExecutionError::NonZeroExit(code, self.stderr_lines()),
+            (None, Some(signal)) => ExecutionError::Terminated(signal, self.stderr_lines()),
+            (None, None) => ExecutionError::GeneralExecutionError(self.stderr_lines()),
+        }
+    }
+}
+
+///
+/// Where a function's stdout goes while the function runs: line by line to a receiver, or into `ProcessOutput::stdout`
+/// if it's buffered. Either way, the process is killed once it wrote more than `max_bytes`.
+///
+#[derive(Debug)]
+pub struct StdoutStream {
+    lines: Option<Sender<Vec<u8>>>,
+    max_bytes: u64,
+    written: u64,
+}
+
+impl StdoutStream {
+    ///
+    /// A stream that sends every line (including its line break) to the returned receiver as soon as it was written.
+    ///
+    pub fn new(max_bytes: u64) -> (Self, Receiver<Vec<u8>>) {
+        let (tx, rx) = channel(STREAM_BUFFER_LINES);
+        let stream = StdoutStream {
+            lines: Some(tx),
+            max_bytes,
+            written: 0,
+        };
+        (stream, rx)
+    }
+
+    ///
+    /// A stream that keeps stdout in `ProcessOutput::stdout`.
+    ///
+    pub fn buffered(max_bytes: u64) -> Self {
+        StdoutStream {
+            lines: None,
+            max_bytes,
+            written: 0,
+        }
+    }
+
+    pub fn max_bytes(&self) -> u64 {
+        self.max_bytes
+    }
+
+    ///
+    /// How many more bytes may be written.
+    ///
+    pub fn remaining(&self) -> u64 {
+        self.max_bytes.saturating_sub(self.written)
+    }
+
+    ///
+    /// Passes on a piece of output, or appends it to `kept` if the stream is buffered. Fails with
+    /// `ExecutionError::OutputTooLarge` once there was too much output.
+    ///
+    pub fn forward(&mut self, line: Vec<u8>, kept: &mut Vec<u8>) -> Result<()> {
+        self.written += line.len() as u64;
+        if self.written > self.max_bytes {
+            return Err(ExecutionError::OutputTooLarge(self.max_bytes).into());
+        }
+        match self.lines.as_mut() {
+            // waits for the receiver if it's behind, so the process can't outpace it
+            Some(lines) => block_on(lines.send(line))
+                .map_err(|_| anyhow::Error::msg("Nobody receives the function's output")),
+            None => {
+                kept.extend_from_slice(&line);
+                Ok(())
+            }
+        }
+    }
+
+    ///
+    /// Forwards everything `pipe` produces line by line and returns what was kept.
+    ///
+    pub fn forward_all(&mut self, pipe: impl Read) -> Result<Vec<u8>> {
+        let mut reader = BufReader::new(pipe);
+        let mut kept = vec![];
+        loop {
+            // lines are never read past the limit, so a single endless line doesn't end up in memory
+            let mut line = vec![];
+            let limit = self.remaining().saturating_add(1);
+            if (&mut reader).take(limit).read_until(b'\n', &mut line)? == 0 {
+                return Ok(kept);
+            }
+            self.forward(line, &mut kept)?;
+        }
+    }
+}
+
+impl Default for StdoutStream {
+    fn default() -> Self {
+        StdoutStream::buffered(DEFAULT_MAX_OUTPUT_BYTES)
+    }
+}
+
+///
+/// Spawns `cmd` in its own process group, writes `stdin` to it and waits for it to exit while collecting stdout and stderr.
+/// If the process is still running after `timeout`, the entire process group is killed and `ExecutionError::Timeout` is returned.
+///
+pub fn run_with_timeout(cmd: Command, stdin: &[u8], timeout: Duration) -> Result<ProcessOutput> {
+    run_streaming(cmd, stdin, timeout, StdoutStream::default())
+}
+
+///
+/// Like `run_with_timeout`, but stdout goes to `stdout` while the process runs. The process group is killed as soon
+/// as the stream fails, e.g. because the process wrote too much.
+///
+pub fn run_streaming(
+    mut cmd: Command,
+    stdin: &[u8],
+    timeout: Duration,
+    mut stdout: StdoutStream,
+) -> Result<ProcessOutput> {
+    own_process_group(&mut cmd);
+    let mut child = cmd
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+
+    // stdin, stdout, and stderr are handled on separate threads so a process that doesn't read
+    // its input (or fills up a pipe) can't block us past the deadline
+    let mut child_stdin = child
+        .stdin
+        .take()
+        .ok_or_else(|| anyhow::Error::msg("Failed to open stdin"))?;
+    let input = stdin.to_vec();
+    // the writer isn't waited for: it only blocks if something holds on to stdin without reading it
+    thread::spawn(move || {
+        // the pipe is closed when child_stdin goes out of scope
+        let _ = child_stdin.write_all(&input);
+    });
+
+    let child_stdout = child
+        .stdout
+        .take()
+        .ok_or_else(|| anyhow::Error::msg("Failed to open stdout"))?;
+    let stdout_failed = Arc::new(AtomicBool::new(false));
+    let failed = stdout_failed.clone();
+    let stdout_reader = spawn_reader(move || {
+        let result = stdout.forward_all(child_stdout);
+        failed.store(result.is_err(), Ordering::SeqCst);
+        result
+    });
+
+    let child_stderr = child
+        .stderr
+        .take()
+        .ok_or_else(|| anyhow::Error::msg("Failed to open stderr"))?;
+    let stderr_reader = spawn_reader(move || Ok(read_all(child_stderr)?));
+
+    let started = Instant::now();
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if stdout_failed.load(Ordering::SeqCst) {
+            // the reader's error is returned below
+            warn!(
+                "Process {} failed to write its output, killing it",
+                child.id()
+            );
+            kill_process_group(&mut child);
+            break child.wait()?;
+        }
+        if started.elapsed() >= timeout {
+            warn!(
+                "Process {} exceeded its runtime of {:?}, killing it",
+                child.id(),
+                timeout
+            );
+            kill_process_group(&mut child);
+            let _ = child.wait();
+            return Err(ExecutionError::Timeout(timeout).into());
+        }
+        thread::sleep(Duration::from_millis(POLL_INTERVAL_MS));
+    };
+    debug!(
+        "Process exited with {} after {:?}",
+        status,
+        started.elapsed()
+    );
+
+    let stdout = receive_output(&stdout_reader, &mut child, "stdout")?;
+    let stderr = receive_output(&stderr_reader, &mut child, "stderr")?;
+    Ok(ProcessOutput::new(stdout, stderr, status))
+}
+
+///
+/// Finds an executable on the `PATH`.
+///
+pub(crate) fn find_on_path(name: &str) -> Option<PathBuf> {
+    std::env::var_os("PATH").and_then(|paths| {
+        std::env::split_paths(&paths)
+            .map(|dir| dir.join(name))
+            .find(|candidate| candidate.is_file())
+    })
+}
+
+///
+/// Runs `read` on a separate thread and returns a receiver for its result, so it can be waited for with a deadline.
+///
+fn spawn_reader<T: Send + 'static>(
+    read: impl FnOnce() -> Result<T> + Send + 'static,
+) -> mpsc::Receiver<Result<T>> {
+    let (tx, rx) = mpsc::sync_channel(1);
+    thread::spawn(move || {
+        let _ = tx.send(read());
+    });
+    rx
+}
+
+///
+/// Waits for what was read from the pipe called `name` after the process exited. Processes it left running in the
+/// background may still hold the pipe open, so they get `PIPE_GRACE_MS` to finish before what's left of the process
+/// group is killed. If the pipe still isn't closed after that, the reader is abandoned.
+///
+fn receive_output<T>(
+    reader: &mpsc::Receiver<Result<T>>,
+    child: &mut Child,
+    name: &str,
+) -> Result<T> {
+    let grace = Duration::from_millis(PIPE_GRACE_MS);
+    let received = match reader.recv_timeout(grace) {
+        Err(RecvTimeoutError::Timeout) => {
+            warn!(
+                "The {} of process {} is still open, killing its process group",
+                name,
+                child.id()
+            );
+            kill_process_group(child);
+            reader.recv_timeout(grace)
+        }
+        received => received,
+    };
+    match received {
+        Ok(result) => result,
+        Err(RecvTimeoutError::Timeout) => Err(anyhow::Error::msg(format!(
+            "The function's {} wasn't closed after it exited",
+            name
+        ))),
+        Err(RecvTimeoutError::Disconnected) => {
+            Err(anyhow::Error::msg(format!("Reading {} failed", name)))
+        }
+    }
+}
+
+fn read_all(mut pipe: impl Read) -> std::io::Result<Vec<u8>> {
+    let mut buf = vec![];
+    pipe.read_to_end(&mut buf)?;
+    Ok(buf)
+}
+
+#[cfg(unix)]
+fn exit_signal(status: &ExitStatus) -> Option<i32> {
+    use std::os::unix::process::ExitStatusExt;
+    status.signal()
+}
+
+#[cfg(not(unix))]
+fn exit_signal(_status: &ExitStatus) -> Option<i32> {
+    None
+}
+
+///
+/// The limit a signal reports a violation of. The kernel sends SIGKILL once the CPU time's hard limit is reached.
+///
+#[cfg(unix)]
+fn signal_limit(signal: i32) -> Option<&'static str> {
+    match signal {
+        libc::SIGXCPU | libc::SIGKILL => Some("CPU time"),
+        libc::SIGXFSZ => Some("file size"),
+        _ => None,
+    }
+}
+
+#[cfg(not(unix))]
+fn signal_limit(_signal: i32) -> Option<&'static str> {
+    None
+}
+
+///
+/// Whether `limits` set the `limit` (as named by `signal_limit` and `LIMIT_MESSAGES`).
+///
+fn is_limited(limits: &ResourceLimits, limit: &str) -> bool {
+    match limit {
+        "memory" => limits.memory_bytes.is_some(),
+        "CPU time" => limits.cpu_secs.is_some(),
+        "file size" => limits.file_size_bytes.is_some(),
+        "open files" => limits.open_files.is_some(),
+        "processes" => limits.processes.is_some(),
+        _ => false,
+    }
+}
+
+///
+/// Applies `limits` to the process `cmd` starts, with `setrlimit` right before the program is executed. Limits above
+/// the runtime's own hard limits are lowered to them.
+///
+#[cfg(unix)]
+pub fn limit_resources(cmd: &mut Command, limits: &ResourceLimits) {
+    use std::os::unix::process::CommandExt;
+    if limits.is_empty() {
+        return;
+    }
+    let limits = *limits;
+    // runs in the forked child, so it may only make async-signal-safe calls (no allocations)
+    unsafe {
+        cmd.pre_exec(move || set_resource_limits(&limits));
+    }
+}
+
+#[cfg(not(unix))]
+pub fn limit_resources(_cmd: &mut Command, _limits: &ResourceLimits) {}
+
+#[cfg(unix)]
+fn set_resource_limits(limits: &ResourceLimits) -> std::io::Result<()> {
+    // a CPU time hard limit above the soft limit sends SIGXCPU before SIGKILL, so the violation can be told apart
+    let resources = [
+        (libc::RLIMIT_AS, limits.memory_bytes, 0),
+        (libc::RLIMIT_CPU, limits.cpu_secs, 1),
+        (libc::RLIMIT_FSIZE, limits.file_size_bytes, 0),
+        (libc::RLIMIT_NOFILE, limits.open_files, 0),
+        (libc::RLIMIT_NPROC, limits.processes, 0),
+    ];
+    for (resource, limit, grace) in resources.iter() {
+        if let Some(limit) = limit {
+            let mut current = libc::rlimit {
+                rlim_cur: 0,
+                rlim_max: 0,
+            };
+            if unsafe { libc::getrlimit(*resource, &mut current) } != 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+            let hard = (current.rlim_max as u64).min(limit.saturating_add(*grace));
+            let new = libc::rlimit {
+                rlim_cur: (*limit).min(hard) as libc::rlim_t,
+                rlim_max: hard as libc::rlim_t,
+            };
+            if unsafe { libc::setrlimit(*resource, &new) } != 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+pub(crate) fn own_process_group(cmd: &mut Command) {
+    use std::os::unix::process::CommandExt;
+    cmd.process_group(0);
+}
+
+#[cfg(not(unix))]
+pub(crate) fn own_process_group(_cmd: &mut Command) {}
+
+#[cfg(unix)]
+pub(crate) fn kill_process_group(child: &mut Child) {
+    // the child leads its own process group, so its pid is also the group's id
+    unsafe {
+        libc::kill(-(child.id() as libc::pid_t), libc::SIGKILL);
+    }
+}
+
+#[cfg(not(unix))]
+pub(crate) fn kill_process_group(child: &mut Child) {
+    let _ = child.kill();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[cfg(unix)]
+    fn run_with_timeout_collects_stdout() {
+        let mut cmd = Command::new("bash");
+        cmd.env_clear();
+        let output = run_with_timeout(cmd, b"echo hello", Duration::from_secs(5)).unwrap();
+        assert!(output.success());
+        assert_eq!(output.stdout, b"hello\n".to_vec());
+        assert!(output.stderr.is_empty());
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn run_with_timeout_collects_stderr_and_exit_code() {
+        let cmd = Command::new("bash");
+        let output = run_with_timeout(
+            cmd,
+            b"echo out; echo oh no >&2; exit 3",
+            Duration::from_secs(5),
+        )
+        .unwrap();
+        assert!(!output.success());
+        assert_eq!(output.exit_code, Some(3));
+        assert_eq!(output.stdout, b"out\n".to_vec());
+        assert_eq!(output.stderr_lines(), vec!["oh no".to_owned()]);
+        match output.exit_error(&ResourceLimits::default()) {
+            ExecutionError::NonZeroExit(3, context) => assert_eq!(context, vec!["oh no"]),
+            other => panic!("Unexpected error: {:?}", other),
+        }
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn run_with_timeout_records_signals() {
+        let cmd = Command::new("bash");
+        let output = run_with_timeout(cmd, b"kill -9 $$", Duration::from_secs(5)).unwrap();
+        assert_eq!(output.exit_code, None);
+        assert_eq!(output.signal, Some(9));
+        assert!(matches!(
+            output.exit_error(&ResourceLimits::default()),
+            ExecutionError::Terminated(9, _)
+        ));
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn run_with_timeout_keeps_binary_output() {
+        let cmd = Command::new("bash");
+        let output =
+            run_with_timeout(cmd, br"printf 'a\xff\x00b\n'", Duration::from_secs(5)).unwrap();
+        assert!(output.success());
+        assert_eq!(output.stdout, b"a\xff\x00b\n".to_vec());
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn run_streaming_sends_lines() {
+        let (stdout, lines) = StdoutStream::new(100);
+        let cmd = Command::new("bash");
+        let output = run_streaming(
+            cmd,
+            b"echo one; echo two; printf three",
+            Duration::from_secs(5),
+            stdout,
+        )
+        .unwrap();
+        assert!(output.success());
+        assert!(output.stdout.is_empty());
+        let lines: Vec<Vec<u8>> = block_on(futures::StreamExt::collect(lines));
+        assert_eq!(
+            lines,
+            vec![b"one\n".to_vec(), b"two\n".to_vec(), b"three".to_vec()]
+        );
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn run_streaming_kills_chatty_processes() {
+        let cmd = Command::new("bash");
+        let started = Instant::now();
+        let result = run_streaming(
+            cmd,
+            b"while true; do echo spam; done",
+            Duration::from_secs(10),
+            StdoutStream::buffered(1000),
+        );
+        assert!(started.elapsed() < Duration::from_secs(5));
+        match result.unwrap_err().downcast::<ExecutionError>() {
+            Ok(ExecutionError::OutputTooLarge(max)) => assert_eq!(max, 1000),
+            other => panic!("Unexpected result: {:?}", other),
+        }
+
+        // a single line counts, too
+        let cmd = Command::new("bash");
+        let result = run_streaming(
+            cmd,
+            b"yes | tr -d '\\n'",
+            Duration::from_secs(10),
+            StdoutStream::buffered(1000),
+        );
+        assert!(matches!(
+            result.unwrap_err().downcast::<ExecutionError>(),
+            Ok(ExecutionError::OutputTooLarge(1000))
+        ));
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn limit_resources_stops_cpu_hogs() {
+        let limits = ResourceLimits {
+            cpu_secs: Some(1),
+            ..Default::default()
+        };
+        let mut cmd = Command::new("bash");
+        limit_resources(&mut cmd, &limits);
+        let output =
+            run_with_timeout(cmd, b"while true; do :; done", Duration::from_secs(10)).unwrap();
+        assert_eq!(output.signal, Some(libc::SIGXCPU));
+        match output.exit_error(&limits) {
+            ExecutionError::LimitExceeded(limit, _) => assert_eq!(limit, "CPU time"),
+            other => panic!("Unexpected error: {:?}", other),
+        }
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn limit_resources_limits_file_sizes() {
+        let file = minifaas_test::get_empty_tmp_dir().join("big");
+        let limits = ResourceLimits {
+            file_size_bytes: Some(10),
+            ..Default::default()
+        };
+        let mut cmd = Command::new("bash");
+        limit_resources(&mut cmd, &limits);
+        let script = format!("printf '%0100d' 0 > {}", file.display());
+        let output = run_with_timeout(cmd, script.as_bytes(), Duration::from_secs(5)).unwrap();
+        assert!(!output.success());
+        assert!(matches!(
+            output.exit_error(&limits),
+            ExecutionError::LimitExceeded(limit, _) if limit == "file size"
+        ));
+        assert!(std::fs::metadata(&file).unwrap().len() <= 10);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn exit_error_only_reports_configured_limits() {
+        let cmd = Command::new("bash");
+        let output = run_with_timeout(
+            cmd,
+            b"echo 'Cannot allocate memory' >&2; kill -XCPU $$",
+            Duration::from_secs(5),
+        )
+        .unwrap();
+        assert!(matches!(
+            output.exit_error(&ResourceLimits::default()),
+            ExecutionError::Terminated(signal, _) if signal == libc::SIGXCPU
+        ));
+
+        let memory = ResourceLimits {
+            memory_bytes: Some(1 << 30),
+            ..Default::default()
+        };
+        // the signal doesn't match the memory limit
+        assert_eq!(output.exceeded_limit(&memory), None);
+        let cmd = Command::new("bash");
+        let output = run_with_timeout(
+            cmd,
+            b"echo 'Cannot allocate memory' >&2; exit 1",
+            Duration::from_secs(5),
+        )
+        .unwrap();
+        assert_eq!(output.exceeded_limit(&memory), Some("memory"));
+        assert!(matches!(
+            output.exit_error(&ResourceLimits::default()),
+            ExecutionError::NonZeroExit(1, _)
+        ));
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn run_with_timeout_kills_infinite_loops() {
+        let cmd = Command::new("bash");
+        let started = Instant::now();
+        let result = run_with_timeout(cmd, b"while true; do :; done", Duration::from_secs(1));
+        assert!(started.elapsed() < Duration::from_secs(5));
+        match result.unwrap_err().downcast::<ExecutionError>() {
+            Ok(ExecutionError::Timeout(after)) => assert_eq!(after, Duration::from_secs(1)),
+            other => panic!("Unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn run_with_timeout_reports_sub_second_timeouts() {
+        let cmd = Command::new("bash");
+        let result = run_with_timeout(cmd, b"sleep 5", Duration::from_millis(200));
+        match result.unwrap_err().downcast::<ExecutionError>() {
+            Ok(e @ ExecutionError::Timeout(_)) => {
+                assert_eq!(
+                    e.to_string(),
+                    "Function exceeded its maximum runtime of 200ms"
+                )
+            }
+            other => panic!("Unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn run_with_timeout_doesnt_wait_for_background_processes() {
+        let cmd = Command::new("bash");
+        let started = Instant::now();
+        let output =
+            run_with_timeout(cmd, b"sleep 30 & echo started", Duration::from_secs(10)).unwrap();
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert!(output.success());
+        assert_eq!(output.stdout, b"started\n".to_vec());
+    }
+}
@@ -0,0 +1,312 @@
+use crate::ext::process::{limit_resources, run_streaming, ProcessOutput, StdoutStream};
+use crate::ext::sandbox::Sandbox;
+use crate::ext::toolchain::ToolchainLifecycle;
+use crate::ext::toolchain::ToolchainSetup;
+use crate::Environment;
+use anyhow::Result;
+use async_std::task;
+use log::{debug, info};
+use minifaas_common::errors::ExecutionError;
+use minifaas_common::runtime::RawFunctionInput;
+use minifaas_common::ResourceLimits;
+use std::process::Command;
+use std::sync::Arc;
+use std::time::Duration;
+
+const DEFAULT_PYTHON_EXE_NAME: &str = "python3";
+const VENV_DIR: &str = ".venv";
+const VENV_PYTHON: &str = ".venv/bin/python";
+
+/// The helper module functions can `import minifaas` from
+const HELPER_MODULE: &str = include_str!("minifaas.py");
+const HELPER_MODULE_FILE: &str = "minifaas.py";
+
+#[derive(Clone, Debug)]
+pub struct Python {
+    interpreter: String,
+    default_args: Vec<String>,
+    limits: ResourceLimits,
+    sandboxed: bool,
+}
+
+#[derive(Clone, Debug)]
+pub struct PythonSetup {
+    interpreter: String,
+    virtualenv: bool,
+    pub installed: bool,
+}
+
+impl PythonSetup {
+    pub fn new<S: Into<String>>(interpreter: S, virtualenv: bool) -> Self {
+        PythonSetup {
+            interpreter: interpreter.into(),
+            virtualenv,
+            installed: false,
+        }
+    }
+
+    ///
+    /// Creates a virtualenv in each environment, so functions can install their own packages.
+    ///
+    pub fn with_virtualenv() -> Self {
+        PythonSetup::new(DEFAULT_PYTHON_EXE_NAME, true)
+    }
+}
+
+impl Default for PythonSetup {
+    fn default() -> Self {
+        PythonSetup::new(DEFAULT_PYTHON_EXE_NAME, false)
+    }
+}
+
+impl Python {
+    pub fn new<S: Into<String>>(interpreter: S, default_args: Vec<String>) -> Self {
+        Python {
+            interpreter: interpreter.into(),
+            default_args,
+            limits: ResourceLimits::default(),
+            sandboxed: false,
+        }
+    }
+}
+
+impl Default for Python {
+    fn default() -> Self {
+        Python::new(DEFAULT_PYTHON_EXE_NAME, vec!["-".to_owned()])
+    }
+}
+
+#[async_trait::async_trait]
+impl ToolchainLifecycle for Python {
+    async fn _build(&self, code: &str) -> Result<Vec<u8>> {
+        Ok(code.to_owned().into_bytes())
+    }
+
+    async fn _execute(
+        &self,
+        code: Vec<u8>,
+        input: Arc<RawFunctionInput>,
+        vars: Vec<(String, String)>,
+        env: &Environment,
+        timeout: Duration,
+        stdout: StdoutStream,
+    ) -> Result<ProcessOutput> {
+        // prefer the environment's virtualenv over the system interpreter
+        let exe = if env.has_file(VENV_PYTHON).await {
+            env.absolute_path(VENV_PYTHON).await.into_os_string()
+        } else {
+            self.interpreter.clone().into()
+        };
+        let env_root = env.absolute_path("").await;
+        let sandbox = if self.sandboxed {
+            Some(Sandbox::new(&env_root).with_binary(&exe)?)
+        } else {
+            None
+        };
+
+        debug!("Executing on Python ({} bytes)", code.len());
+        let default_args = self.default_args.clone();
+        let limits = self.limits;
+        task::spawn_blocking(move || {
+            let mut cmd = Command::new(&exe);
+            cmd.args(default_args)
+                .env_clear()
+                .envs(vars)
+                .env("PYTHONPATH", &env_root)
+                .env("PYTHONDONTWRITEBYTECODE", "1")
+                .env("__MF__INPUTS", serde_json::to_string(&input)?);
+            if let Some(sandbox) = sandbox {
+                sandbox.apply(&mut cmd)?;
+            }
+            limit_resources(&mut cmd, &limits);
+            run_streaming(cmd, &code, timeout, stdout)
+        })
+        .await
+    }
+
+    fn failure(&self, output: &ProcessOutput) -> ExecutionError {
+        let stderr = output.stderr_lines();
+        match stderr
+            .iter()
+            .find(|l| l.starts_with("SyntaxError:") || l.starts_with("IndentationError:"))
+        {
+            Some(line) => ExecutionError::CompilerError(line.clone(), stderr.clone()),
+            None => output.exit_error(&self.limits),
+        }
+    }
+
+    fn with_limits(&self, limits: &ResourceLimits) -> Option<Self> {
+        Some(Python {
+            limits: *limits,
+            ..self.clone()
+        })
+    }
+
+    fn sandboxed(&self) -> Option<Self> {
+        Some(Python {
+            sandboxed: true,
+            ..self.clone()
+        })
+    }
+}
+
+#[async_trait::async_trait]
+impl ToolchainSetup for PythonSetup {
+    async fn pre_setup(&mut self, env: &Environment) -> Result<()> {
+        self.installed = env.has_file(HELPER_MODULE_FILE).await
+            && (!self.virtualenv || env.has_file(VENV_PYTHON).await);
+        debug!("Is Python set up in {}? {}", env, self.installed);
+        Ok(())
+    }
+
+    async fn setup(&self, env: &Environment) -> Result<()> {
+        if self.installed {
+            info!("Found Python in {}, skipping setup", env);
+            return Ok(());
+        }
+        if self.virtualenv && !env.has_file(VENV_PYTHON).await {
+            info!("Creating virtualenv in {}", env);
+            let venv = env.absolute_path(VENV_DIR).await;
+            let interpreter = self.interpreter.clone();
+            let status = task::spawn_blocking(move || {
+                Command::new(&*interpreter)
+                    .args(&["-m", "venv"])
+                    .arg(&venv)
+                    .status()
+            })
+            .await?;
+            if !status.success() {
+                return Err(anyhow::Error::msg(format!(
+                    "Creating a virtualenv failed with {}",
+                    status
+                )));
+            }
+        }
+        std::fs::write(env.absolute_path(HELPER_MODULE_FILE).await, HELPER_MODULE)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    use minifaas_common::triggers::http::HttpTrigger;
+    use minifaas_common::Environment;
+    use minifaas_test::get_empty_tmp_dir;
+    use std::collections::HashMap;
+    use uuid::Uuid;
+
+    async fn create_temp_env() -> Environment {
+        let root_dir = get_empty_tmp_dir();
+        Environment::create_with_id(root_dir.join(Uuid::new_v4().to_string()), Uuid::new_v4())
+            .await
+            .unwrap()
+    }
+
+    async fn install(setup: PythonSetup, e: &Environment) {
+        let mut setup = setup;
+        setup.pre_setup(e).await.unwrap();
+        setup.setup(e).await.unwrap();
+        setup.post_setup(e).await.unwrap();
+    }
+
+    #[async_std::test]
+    async fn pythonsetup_setup_writes_helper_module() {
+        let e = create_temp_env().await;
+        install(PythonSetup::default(), &e).await;
+        assert!(e.has_file("minifaas.py").await);
+
+        let mut setup = PythonSetup::default();
+        setup.pre_setup(&e).await.unwrap();
+        assert!(setup.installed);
+        // a virtualenv is still missing
+        let mut setup = PythonSetup::with_virtualenv();
+        setup.pre_setup(&e).await.unwrap();
+        assert!(!setup.installed);
+    }
+
+    #[async_std::test]
+    #[cfg(unix)]
+    async fn pythonsetup_setup_creates_virtualenv() {
+        let e = create_temp_env().await;
+        install(PythonSetup::with_virtualenv(), &e).await;
+        assert!(e.has_file(".venv/bin/python").await);
+
+        let output = Python::default()
+            ._execute(
+                b"import sys\nprint('__MF__body:' + sys.prefix)".to_vec(),
+                Arc::new(RawFunctionInput::default()),
+                vec![],
+                &e,
+                Duration::from_secs(5),
+                StdoutStream::default(),
+            )
+            .await
+            .unwrap();
+        assert!(output.success());
+        let stdout = String::from_utf8(output.stdout).unwrap();
+        assert!(stdout.trim().ends_with(".venv"));
+    }
+
+    #[async_std::test]
+    async fn python_execute_with_helper_module() {
+        let e = create_temp_env().await;
+        install(PythonSetup::default(), &e).await;
+
+        let mut params = HashMap::new();
+        params.insert("name".to_owned(), Some(vec!["world".to_owned()]));
+        let input = HttpTrigger {
+            params,
+            ..Default::default()
+        };
+        let code = "import minifaas\n\
+                    name = minifaas.params()['name'][0]\n\
+                    minifaas.respond('Hello ' + name, status_code=201)\n";
+        let output = Python::default()
+            ._execute(
+                code.as_bytes().to_vec(),
+                Arc::new(input.into()),
+                vec![],
+                &e,
+                Duration::from_secs(5),
+                StdoutStream::default(),
+            )
+            .await
+            .unwrap();
+        assert!(output.success());
+        assert_eq!(
+            String::from_utf8(output.stdout).unwrap(),
+            format!(
+                "__MF__status_code:{}\n__MF__body:{}\n",
+                hex::encode("201"),
+                hex::encode("Hello world")
+            )
+        );
+    }
+
+    #[async_std::test]
+    async fn python_failure_syntax_error() {
+        let e = create_temp_env().await;
+        let python = Python::default();
+        let output = python
+            ._execute(
+                b"print('unclosed'".to_vec(),
+                Arc::new(RawFunctionInput::default()),
+                vec![],
+                &e,
+                Duration::from_secs(5),
+                StdoutStream::default(),
+            )
+            .await
+            .unwrap();
+        assert!(!output.success());
+        match python.failure(&output) {
+            ExecutionError::CompilerError(message, _) => {
+                assert!(message.starts_with("SyntaxError:"))
+            }
+            other => panic!("Unexpected error: {:?}", other),
+        }
+    }
+}
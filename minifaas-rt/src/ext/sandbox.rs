@@ -0,0 +1,696 @@
+use crate::ext::process::find_on_path;
+use anyhow::Result;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// The system's programs and libraries, which every sandbox can read
+const SYSTEM_PATHS: &[&str] = &["/bin", "/lib", "/lib64", "/usr"];
+
+/// The devices a sandbox can use
+const DEVICES: &[&str] = &["/dev/null", "/dev/zero", "/dev/random", "/dev/urandom"];
+
+/// The (empty) directory in the system's temp directory that sandboxes mount their root on, each in its own namespace
+const ROOT_DIR: &str = "minifaas-sandbox";
+
+///
+/// Runs a process in new user, mount, PID, and network namespaces (Linux only). The process can only see its working
+/// directory, the binaries added with `with_binary`, the system's programs and libraries (read-only), and an empty
+/// `/tmp`. It has no network access and can't see or signal processes outside of the sandbox.
+///
+#[derive(Clone, Debug)]
+pub struct Sandbox {
+    workdir: PathBuf,
+    binaries: Vec<PathBuf>,
+}
+
+impl Sandbox {
+    ///
+    /// A sandbox that runs in `workdir`, which is mounted read-write at the same path.
+    ///
+    pub fn new<P: Into<PathBuf>>(workdir: P) -> Self {
+        Sandbox {
+            workdir: workdir.into(),
+            binaries: vec![],
+        }
+    }
+
+    ///
+    /// Mounts `binary` (e.g. an interpreter) read-only, either a path or a name that is looked up on the `PATH`.
+    ///
+    pub fn with_binary<P: AsRef<Path>>(mut self, binary: P) -> Result<Self> {
+        let binary = binary.as_ref();
+        let path = if binary.components().count() > 1 {
+            binary.to_path_buf()
+        } else {
+            find_on_path(&binary.to_string_lossy()).ok_or_else(|| {
+                anyhow::Error::msg(format!("'{}' not found on the PATH", binary.display()))
+            })?
+        };
+        // links (e.g. from an environment to the shared toolchains) have to point to something in the sandbox
+        self.binaries.push(path.canonicalize()?);
+        Ok(self)
+    }
+
+    ///
+    /// Whether sandboxes are supported on this OS.
+    ///
+    pub fn is_supported() -> bool {
+        cfg!(target_os = "linux")
+    }
+
+    ///
+    /// Makes `cmd` start its process in the sandbox. Hooks that are added to `cmd` later (e.g. `limit_resources`)
+    /// only apply to the sandboxed process.
+    ///
+    #[cfg(target_os = "linux")]
+    pub fn apply(&self, cmd: &mut Command) -> Result<()> {
+        use std::os::unix::process::CommandExt;
+        let plan = linux::Plan::new(self)?;
+        unsafe {
+            cmd.pre_exec(move || plan.enter());
+        }
+        Ok(())
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub fn apply(&self, _cmd: &mut Command) -> Result<()> {
+        Err(anyhow::Error::msg("Sandboxes are only supported on Linux"))
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use super::{Sandbox, DEVICES, ROOT_DIR, SYSTEM_PATHS};
+    use anyhow::Result;
+    use std::ffi::CString;
+    use std::io;
+    use std::mem;
+    use std::os::unix::ffi::OsStrExt;
+    use std::path::{Path, PathBuf};
+    use std::ptr;
+
+    const NAMESPACES: libc::c_int =
+        libc::CLONE_NEWUSER | libc::CLONE_NEWNS | libc::CLONE_NEWPID | libc::CLONE_NEWNET;
+
+    /// Not available in all versions of `libc`, but the same on all architectures
+    const SYS_CLOSE_RANGE: libc::c_long = 436;
+
+    ///
+    /// Sets up part of the sandbox's file system.
+    ///
+    #[derive(Debug)]
+    enum Step {
+        Dir(CString),
+        File(CString),
+        Bind {
+            source: CString,
+            target: CString,
+            writable: bool,
+        },
+        Symlink {
+            target: CString,
+            link: CString,
+        },
+        Tmpfs {
+            target: CString,
+            options: CString,
+        },
+        Proc(CString),
+    }
+
+    ///
+    /// Everything it takes to enter a sandbox, prepared before the fork since the child can't allocate.
+    ///
+    #[derive(Debug)]
+    pub(super) struct Plan {
+        root: CString,
+        steps: Vec<Step>,
+        workdir: CString,
+        uid_map: CString,
+        gid_map: CString,
+    }
+
+    impl Plan {
+        pub(super) fn new(sandbox: &Sandbox) -> Result<Self> {
+            let root = std::env::temp_dir().join(ROOT_DIR);
+            std::fs::create_dir_all(&root)?;
+            let inside = |path: &Path| cstr(&root.join(path.strip_prefix("/").unwrap_or(path)));
+
+            let mut steps = vec![Step::Tmpfs {
+                target: cstr(&root)?,
+                options: CString::new("mode=0755")?,
+            }];
+            let mut system_dirs = vec![];
+            for path in SYSTEM_PATHS.iter().map(Path::new) {
+                match path.symlink_metadata() {
+                    Ok(meta) if meta.file_type().is_symlink() => steps.push(Step::Symlink {
+                        target: cstr(&std::fs::read_link(path)?)?,
+                        link: inside(path)?,
+                    }),
+                    Ok(meta) if meta.is_dir() => {
+                        steps.push(Step::Dir(inside(path)?));
+                        steps.push(Step::Bind {
+                            source: cstr(path)?,
+                            target: inside(path)?,
+                            writable: false,
+                        });
+                        system_dirs.push(path);
+                    }
+                    _ => {}
+                }
+            }
+            steps.push(Step::Dir(inside(Path::new("/dev"))?));
+            for device in DEVICES.iter().map(Path::new).filter(|d| d.exists()) {
+                steps.push(Step::File(inside(device)?));
+                steps.push(Step::Bind {
+                    source: cstr(device)?,
+                    target: inside(device)?,
+                    writable: true,
+                });
+            }
+            steps.push(Step::Dir(inside(Path::new("/proc"))?));
+            steps.push(Step::Proc(inside(Path::new("/proc"))?));
+            steps.push(Step::Dir(inside(Path::new("/tmp"))?));
+            steps.push(Step::Tmpfs {
+                target: inside(Path::new("/tmp"))?,
+                options: CString::new("mode=1777")?,
+            });
+
+            let workdir = std::env::current_dir()?.join(&sandbox.workdir);
+            let canonical_workdir = workdir.canonicalize()?;
+            for binary in sandbox.binaries.iter() {
+                if binary.starts_with(&canonical_workdir)
+                    || system_dirs.iter().any(|dir| binary.starts_with(dir))
+                {
+                    continue;
+                }
+                for dir in parents(binary) {
+                    steps.push(Step::Dir(inside(&dir)?));
+                }
+                steps.push(Step::File(inside(binary)?));
+                steps.push(Step::Bind {
+                    source: cstr(binary)?,
+                    target: inside(binary)?,
+                    writable: false,
+                });
+            }
+            for dir in parents(&workdir) {
+                steps.push(Step::Dir(inside(&dir)?));
+            }
+            steps.push(Step::Dir(inside(&workdir)?));
+            steps.push(Step::Bind {
+                source: cstr(&workdir)?,
+                target: inside(&workdir)?,
+                writable: true,
+            });
+
+            // the sandboxed process keeps its (unprivileged) user and group
+            let (uid, gid) = unsafe { (libc::getuid(), libc::getgid()) };
+            Ok(Plan {
+                root: cstr(&root)?,
+                steps,
+                workdir: cstr(&workdir)?,
+                uid_map: CString::new(format!("{0} {0} 1", uid))?,
+                gid_map: CString::new(format!("{0} {0} 1", gid))?,
+            })
+        }
+
+        ///
+        /// Runs in the forked child, so it may only make async-signal-safe calls (no allocations). Only returns in the
+        /// sandboxed process, the processes in between wait for it and exit the same way.
+        ///
+        pub(super) fn enter(&self) -> io::Result<()> {
+            let mut status = [0; 2];
+            check(unsafe { libc::pipe2(status.as_mut_ptr(), libc::O_CLOEXEC) })?;
+            check(unsafe { libc::unshare(NAMESPACES) })?;
+            write_file(b"/proc/self/setgroups\0", b"deny")?;
+            write_file(b"/proc/self/uid_map\0", self.uid_map.as_bytes())?;
+            write_file(b"/proc/self/gid_map\0", self.gid_map.as_bytes())?;
+            // the first process in the new PID namespace is its init
+            match check(unsafe { libc::fork() })? {
+                0 => {
+                    unsafe {
+                        libc::close(status[0]);
+                        libc::prctl(libc::PR_SET_PDEATHSIG, libc::SIGKILL);
+                    }
+                    self.mount()?;
+                    init(status[1])
+                }
+                pid => {
+                    unsafe { libc::close(status[1]) };
+                    relay(pid, status[0])
+                }
+            }
+        }
+
+        fn mount(&self) -> io::Result<()> {
+            unsafe {
+                // keep the mounts from propagating back to the runtime's namespace
+                check(libc::mount(
+                    ptr::null(),
+                    c_str(b"/\0"),
+                    ptr::null(),
+                    libc::MS_REC | libc::MS_PRIVATE,
+                    ptr::null(),
+                ))?;
+            }
+            for step in self.steps.iter() {
+                step.run()?;
+            }
+            unsafe {
+                check(libc::chdir(self.root.as_ptr()))?;
+                // stacks the old root below the new one, so it can be detached
+                check(
+                    libc::syscall(libc::SYS_pivot_root, c_str(b".\0"), c_str(b".\0"))
+                        as libc::c_int,
+                )?;
+                check(libc::umount2(c_str(b".\0"), libc::MNT_DETACH))?;
+                check(libc::chdir(self.workdir.as_ptr()))?;
+            }
+            Ok(())
+        }
+    }
+
+    impl Step {
+        fn run(&self) -> io::Result<()> {
+            unsafe {
+                match self {
+                    Step::Dir(path) => {
+                        if libc::mkdir(path.as_ptr(), 0o755) != 0 {
+                            let err = io::Error::last_os_error();
+                            if err.raw_os_error() != Some(libc::EEXIST) {
+                                return Err(err);
+                            }
+                        }
+                    }
+                    Step::File(path) => {
+                        let fd = check(libc::open(
+                            path.as_ptr(),
+                            libc::O_CREAT | libc::O_WRONLY | libc::O_CLOEXEC,
+                            0o644 as libc::c_uint,
+                        ))?;
+                        libc::close(fd);
+                    }
+                    Step::Bind {
+                        source,
+                        target,
+                        writable,
+                    } => {
+                        check(libc::mount(
+                            source.as_ptr(),
+                            target.as_ptr(),
+                            ptr::null(),
+                            libc::MS_BIND | libc::MS_REC,
+                            ptr::null(),
+                        ))?;
+                        if !writable {
+                            remount_read_only(target)?;
+                        }
+                    }
+                    Step::Symlink { target, link } => {
+                        check(libc::symlink(target.as_ptr(), link.as_ptr()))?;
+                    }
+                    Step::Tmpfs { target, options } => {
+                        check(libc::mount(
+                            c_str(b"tmpfs\0"),
+                            target.as_ptr(),
+                            c_str(b"tmpfs\0"),
+                            libc::MS_NOSUID | libc::MS_NODEV,
+                            options.as_ptr().cast(),
+                        ))?;
+                    }
+                    Step::Proc(target) => {
+                        check(libc::mount(
+                            c_str(b"proc\0"),
+                            target.as_ptr(),
+                            c_str(b"proc\0"),
+                            libc::MS_NOSUID | libc::MS_NODEV | libc::MS_NOEXEC,
+                            ptr::null(),
+                        ))?;
+                    }
+                }
+            }
+            Ok(())
+        }
+    }
+
+    fn remount_read_only(target: &CString) -> io::Result<()> {
+        let mut stat: libc::statvfs = unsafe { mem::zeroed() };
+        check(unsafe { libc::statvfs(target.as_ptr(), &mut stat) })?;
+        // a user namespace can't clear the flags a mount already has
+        let mut flags = libc::MS_BIND | libc::MS_REMOUNT | libc::MS_RDONLY;
+        let locked = [
+            (libc::ST_NOSUID, libc::MS_NOSUID),
+            (libc::ST_NODEV, libc::MS_NODEV),
+            (libc::ST_NOEXEC, libc::MS_NOEXEC),
+            (libc::ST_NOATIME, libc::MS_NOATIME),
+            (libc::ST_NODIRATIME, libc::MS_NODIRATIME),
+            (libc::ST_RELATIME, libc::MS_RELATIME),
+        ];
+        for (st, ms) in locked.iter() {
+            if stat.f_flag & st != 0 {
+                flags |= ms;
+            }
+        }
+        check(unsafe {
+            libc::mount(
+                ptr::null(),
+                target.as_ptr(),
+                ptr::null(),
+                flags,
+                ptr::null(),
+            )
+        })?;
+        Ok(())
+    }
+
+    ///
+    /// The init process of the sandbox: starts the sandboxed process, reaps orphans, and reports the sandboxed
+    /// process' wait status through `status`.
+    ///
+    fn init(status: libc::c_int) -> io::Result<()> {
+        let sandboxed = check(unsafe { libc::fork() })?;
+        if sandboxed == 0 {
+            unsafe { libc::prctl(libc::PR_SET_PDEATHSIG, libc::SIGKILL) };
+            return Ok(());
+        }
+        close_fds(status);
+        loop {
+            let mut wait_status = 0;
+            let pid = unsafe { libc::waitpid(-1, &mut wait_status, 0) };
+            if pid == sandboxed {
+                unsafe {
+                    libc::write(
+                        status,
+                        (&wait_status as *const libc::c_int).cast(),
+                        mem::size_of::<libc::c_int>(),
+                    );
+                    libc::_exit(0);
+                }
+            } else if pid < 0 && io::Error::last_os_error().raw_os_error() != Some(libc::EINTR) {
+                unsafe { libc::_exit(1) };
+            }
+        }
+    }
+
+    ///
+    /// Waits for the sandbox and exits like the sandboxed process (or the sandbox's init, if it failed).
+    ///
+    fn relay(init: libc::pid_t, status: libc::c_int) -> ! {
+        close_fds(status);
+        let mut wait_status: libc::c_int = 0;
+        let read = loop {
+            let read = unsafe {
+                libc::read(
+                    status,
+                    (&mut wait_status as *mut libc::c_int).cast(),
+                    mem::size_of::<libc::c_int>(),
+                )
+            };
+            if read >= 0 || io::Error::last_os_error().raw_os_error() != Some(libc::EINTR) {
+                break read;
+            }
+        };
+        let mut init_status = 0;
+        while unsafe { libc::waitpid(init, &mut init_status, 0) } < 0
+            && io::Error::last_os_error().raw_os_error() == Some(libc::EINTR)
+        {}
+        if read != mem::size_of::<libc::c_int>() as isize {
+            wait_status = init_status;
+        }
+        unsafe {
+            if libc::WIFSIGNALED(wait_status) {
+                let signal = libc::WTERMSIG(wait_status);
+                // die the same way, but don't leave a core dump behind
+                let no_core = libc::rlimit {
+                    rlim_cur: 0,
+                    rlim_max: 0,
+                };
+                libc::setrlimit(libc::RLIMIT_CORE, &no_core);
+                libc::signal(signal, libc::SIG_DFL);
+                libc::kill(libc::getpid(), signal);
+                libc::_exit(128 + signal);
+            }
+            libc::_exit(libc::WEXITSTATUS(wait_status))
+        }
+    }
+
+    ///
+    /// Closes all file descriptors but `keep`. Otherwise the processes in between would hold on to pipes (e.g. the
+    /// one `Command::spawn` waits on until the program was executed).
+    ///
+    fn close_fds(keep: libc::c_int) {
+        unsafe {
+            for fd in 0..3 {
+                libc::close(fd);
+            }
+            if keep > 3 {
+                libc::syscall(SYS_CLOSE_RANGE, 3, keep - 1, 0);
+            }
+            if libc::syscall(SYS_CLOSE_RANGE, keep + 1, libc::c_uint::MAX, 0) != 0 {
+                // kernels before 5.9
+                let max = libc::sysconf(libc::_SC_OPEN_MAX).min(65536) as libc::c_int;
+                for fd in (3..max).filter(|fd| *fd != keep) {
+                    libc::close(fd);
+                }
+            }
+        }
+    }
+
+    fn write_file(path: &'static [u8], content: &[u8]) -> io::Result<()> {
+        unsafe {
+            let fd = check(libc::open(c_str(path), libc::O_WRONLY | libc::O_CLOEXEC))?;
+            let written = libc::write(fd, content.as_ptr().cast(), content.len());
+            libc::close(fd);
+            if written < 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        Ok(())
+    }
+
+    fn check(result: libc::c_int) -> io::Result<libc::c_int> {
+        if result < 0 {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(result)
+        }
+    }
+
+    fn c_str(bytes: &'static [u8]) -> *const libc::c_char {
+        bytes.as_ptr().cast()
+    }
+
+    fn cstr(path: &Path) -> Result<CString> {
+        Ok(CString::new(path.as_os_str().as_bytes())?)
+    }
+
+    ///
+    /// The directories `path` is in, from the top.
+    ///
+    fn parents(path: &Path) -> Vec<PathBuf> {
+        let mut parents: Vec<PathBuf> = path
+            .ancestors()
+            .skip(1)
+            .filter(|p| p.parent().is_some())
+            .map(Path::to_path_buf)
+            .collect();
+        parents.reverse();
+        parents
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use minifaas_test::get_empty_tmp_dir;
+        use std::ffi::OsStr;
+
+        fn path(c: &CString) -> PathBuf {
+            PathBuf::from(OsStr::from_bytes(c.as_bytes()))
+        }
+
+        /// The bind mounts as `(source, target, writable)`
+        fn binds(plan: &Plan) -> Vec<(PathBuf, PathBuf, bool)> {
+            plan.steps
+                .iter()
+                .filter_map(|step| match step {
+                    Step::Bind {
+                        source,
+                        target,
+                        writable,
+                    } => Some((path(source), path(target), *writable)),
+                    _ => None,
+                })
+                .collect()
+        }
+
+        #[test]
+        fn plan_mounts_the_workdir_and_binaries() {
+            let root_dir = get_empty_tmp_dir();
+            let workdir = root_dir.join("sandboxed");
+            std::fs::create_dir_all(&workdir).unwrap();
+            let binary = root_dir.join("toolchain").join("deno");
+            std::fs::create_dir_all(binary.parent().unwrap()).unwrap();
+            std::fs::write(&binary, "").unwrap();
+            let binary = binary.canonicalize().unwrap();
+            let in_workdir = workdir.join("deno");
+            std::fs::write(&in_workdir, "").unwrap();
+
+            let sandbox = Sandbox::new(&workdir)
+                .with_binary(&binary)
+                .unwrap()
+                .with_binary(&in_workdir)
+                .unwrap();
+            let plan = Plan::new(&sandbox).unwrap();
+            let root = std::env::temp_dir().join(ROOT_DIR);
+            let inside = |p: &Path| root.join(p.strip_prefix("/").unwrap());
+            match &plan.steps[0] {
+                Step::Tmpfs { target, .. } => assert_eq!(path(target), root),
+                step => panic!("The root isn't a tmpfs: {:?}", step),
+            }
+            assert_eq!(path(&plan.workdir), workdir);
+
+            let binds = binds(&plan);
+            assert!(binds.contains(&(workdir.clone(), inside(&workdir), true)));
+            assert!(binds.contains(&(binary.clone(), inside(&binary), false)));
+            // the workdir is mounted already
+            let in_workdir = in_workdir.canonicalize().unwrap();
+            assert!(binds.iter().all(|(source, _, _)| source != &in_workdir));
+            // besides the workdir, only devices are writable
+            assert!(binds
+                .iter()
+                .filter(|(_, _, writable)| *writable)
+                .all(|(source, _, _)| source == &workdir || source.starts_with("/dev")));
+            for dir in SYSTEM_PATHS.iter().map(Path::new).filter(|d| d.is_dir()) {
+                if !dir.symlink_metadata().unwrap().file_type().is_symlink() {
+                    assert!(binds.contains(&(dir.to_path_buf(), inside(dir), false)));
+                }
+            }
+            let uid = unsafe { libc::getuid() };
+            assert_eq!(plan.uid_map.to_str().unwrap(), format!("{0} {0} 1", uid));
+        }
+
+        #[test]
+        fn parents_from_the_top() {
+            assert_eq!(
+                parents(Path::new("/a/b/c")),
+                vec![PathBuf::from("/a"), PathBuf::from("/a/b")]
+            );
+            assert!(parents(Path::new("/a")).is_empty());
+        }
+    }
+}
+
+#[cfg(test)]
+#[cfg(target_os = "linux")]
+mod tests {
+    use super::*;
+    use crate::ext::process::{run_with_timeout, ProcessOutput};
+    use minifaas_common::ResourceLimits;
+    use minifaas_test::get_empty_tmp_dir;
+    use std::time::Duration;
+
+    ///
+    /// Whether this system allows unprivileged user namespaces, the sandbox can't run without them.
+    ///
+    fn user_namespaces_supported() -> bool {
+        use std::os::unix::process::CommandExt;
+        let mut cmd = Command::new("true");
+        // in a forked child, unsharing in the (multi-threaded) test process would fail
+        unsafe {
+            cmd.pre_exec(|| {
+                if libc::unshare(libc::CLONE_NEWUSER) == 0 {
+                    Ok(())
+                } else {
+                    Err(std::io::Error::last_os_error())
+                }
+            });
+        }
+        let supported = cmd.status().map(|s| s.success()).unwrap_or(false);
+        if !supported {
+            eprintln!("Skipping: unprivileged user namespaces aren't available");
+        }
+        supported
+    }
+
+    #[test]
+    fn sandbox_resolves_binaries() {
+        let sandbox = Sandbox::new("sandboxed").with_binary("bash").unwrap();
+        assert_eq!(sandbox.binaries.len(), 1);
+        assert!(sandbox.binaries[0].is_absolute());
+        assert!(sandbox.binaries[0].is_file());
+        assert!(Sandbox::new("sandboxed")
+            .with_binary("minifaas-doesnt-exist")
+            .is_err());
+    }
+
+    fn run_sandboxed(workdir: &Path, script: &str) -> ProcessOutput {
+        let mut cmd = Command::new("bash");
+        cmd.env_clear();
+        Sandbox::new(workdir)
+            .with_binary("bash")
+            .unwrap()
+            .apply(&mut cmd)
+            .unwrap();
+        run_with_timeout(cmd, script.as_bytes(), Duration::from_secs(5)).unwrap()
+    }
+
+    #[test]
+    fn sandbox_only_shows_the_workdir() {
+        if !user_namespaces_supported() {
+            return;
+        }
+        let root_dir = get_empty_tmp_dir();
+        let workdir = root_dir.join("sandboxed");
+        std::fs::create_dir_all(&workdir).unwrap();
+        std::fs::write(root_dir.join("functions.db"), "secret").unwrap();
+
+        let output = run_sandboxed(
+            &workdir,
+            &format!(
+                "pwd; echo hi > out.txt; cat {}",
+                root_dir.join("functions.db").display()
+            ),
+        );
+        assert!(!output.success());
+        assert_eq!(
+            String::from_utf8_lossy(&output.stdout).trim(),
+            workdir.display().to_string()
+        );
+        assert_eq!(
+            std::fs::read_to_string(workdir.join("out.txt")).unwrap(),
+            "hi\n"
+        );
+    }
+
+    #[test]
+    fn sandbox_isolates_processes_and_network() {
+        if !user_namespaces_supported() {
+            return;
+        }
+        let root_dir = get_empty_tmp_dir();
+        let output = run_sandboxed(&root_dir, "echo $$; tail -n +3 /proc/net/dev | cut -d: -f1");
+        assert!(output.success());
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let lines: Vec<&str> = stdout.lines().map(str::trim).collect();
+        // bash comes right after the sandbox's init, and there is only a loopback device
+        assert_eq!(lines, vec!["2", "lo"]);
+    }
+
+    #[test]
+    fn sandbox_passes_on_the_exit_status() {
+        if !user_namespaces_supported() {
+            return;
+        }
+        let root_dir = get_empty_tmp_dir();
+        let output = run_sandboxed(&root_dir, "exit 3");
+        assert_eq!(output.exit_code, Some(3));
+
+        let output = run_sandboxed(&root_dir, "kill -XFSZ $$");
+        let limits = ResourceLimits {
+            file_size_bytes: Some(10),
+            ..Default::default()
+        };
+        assert_eq!(output.exceeded_limit(&limits), Some("file size"));
+    }
+}
@@ -1,90 +1,183 @@
+use std::fmt;
 use std::sync::Arc;
+use std::time::Duration;
 
-use crate::ext::bash::Bash;
-use crate::ext::bash::BashSetup;
-use crate::ext::deno::Deno;
-use crate::DenoSetup;
+use crate::ext::process::{ProcessOutput, StdoutStream};
 use anyhow::Result;
+use minifaas_common::errors::ExecutionError;
 use minifaas_common::runtime::RawFunctionInput;
-use minifaas_common::Environment;
+use minifaas_common::{Environment, Permissions, ResourceLimits};
+use uuid::Uuid;
 
-#[derive(Debug, Clone)]
-pub enum ActiveToolchain {
-    Deno(Deno),
-    Bash(Bash),
-    Noop,
-}
-
-impl ActiveToolchain {
-    pub async fn build(&self, code: &str) -> Result<Vec<u8>> {
-        match self {
-            ActiveToolchain::Deno(deno) => deno._build(code).await,
-            ActiveToolchain::Bash(bash) => bash._build(code).await,
+///
+/// A language runtime that prepares environments and runs functions in them. The runtime looks up toolchains by
+/// `ProgrammingLanguage` in a `ToolchainRegistry`, embedders can add their own using `RuntimeBuilder::with_toolchain`.
+///
+#[async_trait::async_trait]
+pub trait Toolchain: Send + Sync + fmt::Debug {
+    ///
+    /// Prepares an environment for running functions (e.g. installs an interpreter). Runs whenever a function is deployed.
+    ///
+    async fn setup(&self, env: &Environment) -> Result<()>;
 
-            _ => Ok(vec![]),
-        }
-    }
+    ///
+    /// Turns the function's source code into what `execute` runs.
+    ///
+    async fn build(&self, code: &str) -> Result<Vec<u8>>;
 
-    pub async fn execute(
+    ///
+    /// Runs the built code and returns what the process wrote and how it exited. The process only gets the function's
+    /// `vars` (and `__MF__INPUTS`) as environment variables. Implementations have to kill the process once `timeout` has passed.
+    /// Its stdout goes to `stdout` while it runs, toolchains that can't stream it return it in `ProcessOutput::stdout`.
+    ///
+    async fn execute(
         &self,
         code: Vec<u8>,
         input: Arc<RawFunctionInput>,
+        vars: Vec<(String, String)>,
         env: &Environment,
-    ) -> Result<String> {
-        match self {
-            ActiveToolchain::Deno(deno) => {
-                deno.pre_execute(input.clone()).await?;
-                deno._execute(code, input, env).await
-            }
-            ActiveToolchain::Bash(bash) => {
-                bash.pre_execute(input.clone()).await?;
-                bash._execute(code, input, env).await
-            }
-            _ => Ok(String::new()),
-        }
+        timeout: Duration,
+        stdout: StdoutStream,
+    ) -> Result<ProcessOutput>;
+
+    ///
+    /// Maps an unsuccessful run to the matching `ExecutionError`. Toolchains that support resource limits override
+    /// this to report the ones that were exceeded.
+    ///
+    fn failure(&self, output: &ProcessOutput) -> ExecutionError {
+        output.exit_error(&ResourceLimits::default())
+    }
+
+    ///
+    /// Releases everything the toolchain keeps around for an environment (e.g. warm workers).
+    ///
+    fn retire(&self, _env_id: &Uuid) {}
+
+    ///
+    /// The same toolchain for another version of the language (e.g. Deno 1.9.0), `None` if it can't switch versions.
+    ///
+    fn with_version(&self, _version: &str) -> Option<Box<dyn Toolchain>> {
+        None
     }
-}
 
-impl Default for ActiveToolchain {
-    fn default() -> Self {
-        ActiveToolchain::Noop
+    ///
+    /// The same toolchain restricted to a function's `permissions`, `None` if it can't sandbox functions.
+    ///
+    fn with_permissions(&self, _permissions: &Permissions) -> Option<Box<dyn Toolchain>> {
+        None
+    }
+
+    ///
+    /// The same toolchain with its processes restricted to `limits`, `None` if it can't limit them.
+    ///
+    fn with_limits(&self, _limits: &ResourceLimits) -> Option<Box<dyn Toolchain>> {
+        None
+    }
+
+    ///
+    /// The same toolchain running its processes in a `Sandbox`, `None` if it can't.
+    ///
+    fn sandboxed(&self) -> Option<Box<dyn Toolchain>> {
+        None
+    }
+
+    ///
+    /// The same toolchain running functions on resident worker processes, `None` if it can't (or warm workers are
+    /// turned off).
+    ///
+    fn warm(&self) -> Option<Box<dyn Toolchain>> {
+        None
     }
 }
 
+///
+/// A `Toolchain` made of a `ToolchainSetup` and a `ToolchainLifecycle`, which is how the built-in toolchains work.
+///
 #[derive(Debug, Clone)]
-pub enum BuildToolchain {
-    Deno(DenoSetup),
-    Bash(BashSetup),
-    Noop,
+pub struct LifecycleToolchain<S, L> {
+    setup: S,
+    lifecycle: L,
 }
 
-#[async_trait::async_trait]
-impl ToolchainSetup for BuildToolchain {
-    async fn pre_setup(&mut self, env: &Environment) -> Result<()> {
-        match self {
-            BuildToolchain::Deno(d) => d.pre_setup(env).await,
-            _ => Ok(()),
-        }
+impl<S, L> LifecycleToolchain<S, L> {
+    pub fn new(setup: S, lifecycle: L) -> Self {
+        LifecycleToolchain { setup, lifecycle }
     }
+}
 
+#[async_trait::async_trait]
+impl<S, L> Toolchain for LifecycleToolchain<S, L>
+where
+    S: ToolchainSetup + Clone + Send + Sync + fmt::Debug + 'static,
+    L: ToolchainLifecycle + Clone + Send + Sync + fmt::Debug + 'static,
+{
     async fn setup(&self, env: &Environment) -> Result<()> {
-        match self {
-            BuildToolchain::Deno(d) => d.setup(env).await,
-            _ => Ok(()),
-        }
+        // setups keep what they found in `pre_setup`, so every environment gets a fresh one
+        let mut setup = self.setup.clone();
+        setup.pre_setup(env).await?;
+        setup.setup(env).await?;
+        setup.post_setup(env).await
     }
 
-    async fn post_setup(&self, env: &Environment) -> Result<()> {
-        match self {
-            BuildToolchain::Deno(d) => d.post_setup(env).await,
-            _ => Ok(()),
-        }
+    async fn build(&self, code: &str) -> Result<Vec<u8>> {
+        self.lifecycle._build(code).await
     }
-}
 
-impl Default for BuildToolchain {
-    fn default() -> Self {
-        BuildToolchain::Noop
+    async fn execute(
+        &self,
+        code: Vec<u8>,
+        input: Arc<RawFunctionInput>,
+        vars: Vec<(String, String)>,
+        env: &Environment,
+        timeout: Duration,
+        stdout: StdoutStream,
+    ) -> Result<ProcessOutput> {
+        self.lifecycle.pre_execute(input.clone()).await?;
+        self.lifecycle
+            ._execute(code, input, vars, env, timeout, stdout)
+            .await
+    }
+
+    fn failure(&self, output: &ProcessOutput) -> ExecutionError {
+        self.lifecycle.failure(output)
+    }
+
+    fn retire(&self, env_id: &Uuid) {
+        self.lifecycle.retire(env_id)
+    }
+
+    fn with_version(&self, version: &str) -> Option<Box<dyn Toolchain>> {
+        // environments run whatever their setup installed, so only the setup depends on the version
+        self.setup.with_version(version).map(|setup| {
+            Box::new(LifecycleToolchain::new(setup, self.lifecycle.clone())) as Box<dyn Toolchain>
+        })
+    }
+
+    fn with_permissions(&self, permissions: &Permissions) -> Option<Box<dyn Toolchain>> {
+        self.lifecycle
+            .with_permissions(permissions)
+            .map(|lifecycle| {
+                Box::new(LifecycleToolchain::new(self.setup.clone(), lifecycle))
+                    as Box<dyn Toolchain>
+            })
+    }
+
+    fn with_limits(&self, limits: &ResourceLimits) -> Option<Box<dyn Toolchain>> {
+        self.lifecycle.with_limits(limits).map(|lifecycle| {
+            Box::new(LifecycleToolchain::new(self.setup.clone(), lifecycle)) as Box<dyn Toolchain>
+        })
+    }
+
+    fn sandboxed(&self) -> Option<Box<dyn Toolchain>> {
+        self.lifecycle.sandboxed().map(|lifecycle| {
+            Box::new(LifecycleToolchain::new(self.setup.clone(), lifecycle)) as Box<dyn Toolchain>
+        })
+    }
+
+    fn warm(&self) -> Option<Box<dyn Toolchain>> {
+        self.lifecycle.warm().map(|lifecycle| {
+            Box::new(LifecycleToolchain::new(self.setup.clone(), lifecycle)) as Box<dyn Toolchain>
+        })
     }
 }
 
@@ -108,6 +201,16 @@ pub trait ToolchainSetup {
     async fn post_setup(&self, _env: &Environment) -> Result<()> {
         Ok(())
     }
+
+    ///
+    /// A setup that installs `version` instead, `None` if the toolchain has only one version.
+    ///
+    fn with_version(&self, _version: &str) -> Option<Self>
+    where
+        Self: Sized,
+    {
+        None
+    }
 }
 
 #[async_trait::async_trait]
@@ -128,14 +231,75 @@ pub trait ToolchainLifecycle {
         Ok(())
     }
 
+    ///
+    /// Runs the code with the function's environment variables (`vars`) and returns what the process wrote and how it
+    /// exited. Implementations have to kill the process once `timeout` has passed and pass its stdout on to `stdout`
+    /// (see `run_streaming`).
+    ///
     async fn _execute(
         &self,
         code: Vec<u8>,
         _input: Arc<RawFunctionInput>,
+        vars: Vec<(String, String)>,
         env: &Environment,
-    ) -> Result<String>;
+        timeout: Duration,
+        stdout: StdoutStream,
+    ) -> Result<ProcessOutput>;
 
     async fn post_execute(&self) -> Result<()> {
         Ok(())
     }
+
+    ///
+    /// Classifies a failed run. Toolchains can override this to recognize e.g. syntax errors in stderr, or exceeded
+    /// resource limits (see `with_limits`).
+    ///
+    fn failure(&self, output: &ProcessOutput) -> ExecutionError {
+        output.exit_error(&ResourceLimits::default())
+    }
+
+    ///
+    /// Releases everything the toolchain keeps around for an environment (e.g. warm workers).
+    ///
+    fn retire(&self, _env_id: &Uuid) {}
+
+    ///
+    /// A lifecycle that runs functions with only the `permissions` they declared, `None` if it can't.
+    ///
+    fn with_permissions(&self, _permissions: &Permissions) -> Option<Self>
+    where
+        Self: Sized,
+    {
+        None
+    }
+
+    ///
+    /// A lifecycle that starts processes with `limits` (see `limit_resources`), `None` if it can't.
+    ///
+    fn with_limits(&self, _limits: &ResourceLimits) -> Option<Self>
+    where
+        Self: Sized,
+    {
+        None
+    }
+
+    ///
+    /// A lifecycle that runs functions in a `Sandbox` with access to only their environment, `None` if it can't.
+    ///
+    fn sandboxed(&self) -> Option<Self>
+    where
+        Self: Sized,
+    {
+        None
+    }
+
+    ///
+    /// A lifecycle that runs functions on warm workers (see `WarmWorkers`), `None` if it can't.
+    ///
+    fn warm(&self) -> Option<Self>
+    where
+        Self: Sized,
+    {
+        None
+    }
 }
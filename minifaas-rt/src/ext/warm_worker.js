@@ -0,0 +1,104 @@
+// Resident worker for warm JavaScript functions (Deno and Node.js).
+// The function's code is an ES module (the first argument) that is imported once per invocation, every line on stdin
+// is a JSON request ({"inputs": ...}) for one. Modules the code imports are only loaded once. What the function logs
+// is written to stdout right away, the response follows as a single line: the frame marker followed by
+// {"stderr", "ok", "compile_error"}.
+const FRAME = "__MF_FRAME__";
+
+const isDeno = typeof Deno !== "undefined";
+const MODULE_PATH = isDeno ? Deno.args[0] : process.argv[2];
+const MODULE_URL = "file://" + MODULE_PATH.split("/").map(encodeURIComponent).join("/");
+const encoder = new TextEncoder();
+
+const format = (args) =>
+  args.map((a) => (typeof a === "string" ? a : JSON.stringify(a))).join(" ");
+
+const write = (text) => {
+  const bytes = encoder.encode(text);
+  if (isDeno) {
+    let written = 0;
+    while (written < bytes.length) {
+      written += Deno.stdout.writeSync(bytes.subarray(written));
+    }
+  } else {
+    process.stdout.write(bytes);
+  }
+};
+
+const respond = (response) => write(FRAME + JSON.stringify(response) + "\n");
+
+const setInputs = (inputs) => {
+  if (isDeno) {
+    Deno.env.set("__MF__INPUTS", inputs);
+  } else {
+    process.env.__MF__INPUTS = inputs;
+  }
+};
+
+let stderr = [];
+console.log = (...args) => write(format(args) + "\n");
+console.info = console.log;
+console.error = (...args) => stderr.push(format(args));
+console.warn = console.error;
+
+let calls = 0;
+
+async function handle(line) {
+  if (!line.trim()) {
+    return;
+  }
+  stderr = [];
+  calls += 1;
+  // the module's first statement sets this, everything that fails before didn't compile (or resolve)
+  globalThis.__MF_STARTED__ = false;
+  let ok = true;
+  try {
+    setInputs(JSON.stringify(JSON.parse(line).inputs));
+    // a new URL gets a new instance of the module, which runs its code again
+    await import(MODULE_URL + "?call=" + calls);
+  } catch (e) {
+    if (!globalThis.__MF_STARTED__) {
+      respond({ stderr: String(e), ok: false, compile_error: true });
+      return;
+    }
+    ok = false;
+    stderr.push(String((e && e.stack) || e));
+  }
+  respond({ stderr: stderr.join("\n"), ok, compile_error: false });
+}
+
+async function* chunks() {
+  if (isDeno) {
+    const buf = new Uint8Array(64 * 1024);
+    while (true) {
+      const n = await Deno.stdin.read(buf);
+      if (n === null) {
+        return;
+      }
+      yield buf.slice(0, n);
+    }
+  } else {
+    for await (const chunk of process.stdin) {
+      yield chunk;
+    }
+  }
+}
+
+(async () => {
+  if (!isDeno) {
+    // functions run with `node -` otherwise, where require() is available
+    const { createRequire } = await import("module");
+    globalThis.require = createRequire(MODULE_PATH);
+  }
+  const decoder = new TextDecoder();
+  let pending = "";
+  for await (const chunk of chunks()) {
+    pending += decoder.decode(chunk, { stream: true });
+    let newline;
+    while ((newline = pending.indexOf("\n")) >= 0) {
+      const line = pending.slice(0, newline);
+      pending = pending.slice(newline + 1);
+      await handle(line);
+    }
+  }
+})();
@@ -0,0 +1,666 @@
+use crate::ext::process::{kill_process_group, own_process_group, ProcessOutput, StdoutStream};
+use anyhow::Result;
+use log::{debug, info, warn};
+use minifaas_common::errors::ExecutionError;
+use minifaas_common::runtime::RawFunctionInput;
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{sync_channel, Receiver, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+///
+/// Marks the line a worker answers an invocation with. Everything before it is regular output.
+///
+pub const FRAME_PREFIX: &str = "__MF_FRAME__";
+/// The most a worker's answer may take, longer lines are read in pieces of the output budget or this size
+const MAX_FRAME_BYTES: u64 = 1024 * 1024;
+/// The lines read ahead of the stream, the worker blocks once they weren't passed on
+const READ_AHEAD_LINES: usize = 16;
+const JS_WORKER: &str = include_str!("warm_worker.js");
+/// Marks that a module's own code started running, so errors before that are reported as compile errors
+const JS_STARTED_MARKER: &str = "globalThis.__MF_STARTED__ = true; ";
+
+///
+/// Writes the worker script for JavaScript runtimes (Deno and Node.js) and the function's `code` as an ES module to
+/// `dir`, and returns both paths. The worker imports the module for every invocation, so it runs just like a fresh
+/// process would run it, while its imports are only loaded once.
+///
+pub fn write_js_worker(dir: &Path, code: &[u8]) -> Result<(PathBuf, PathBuf)> {
+    let script = write_code_file(dir, ".mf-worker-", JS_WORKER.as_bytes(), "mjs")?;
+    // on the first line, so line numbers in stack traces match the function's code
+    let mut module = JS_STARTED_MARKER.as_bytes().to_vec();
+    module.extend_from_slice(code);
+    let module = write_code_file(dir, ".mf-module-", &module, "mjs")?;
+    Ok((script, module))
+}
+
+///
+/// Writes `code` to a file named `<prefix><hash of the code>.<extension>` in `dir` unless it's already there. Files
+/// with the same prefix and extension are left over from earlier versions of the code and removed.
+///
+pub(crate) fn write_code_file(
+    dir: &Path,
+    prefix: &str,
+    code: &[u8],
+    extension: &str,
+) -> Result<PathBuf> {
+    let suffix = if extension.is_empty() {
+        String::new()
+    } else {
+        format!(".{}", extension)
+    };
+    let name = format!("{}{:x}{}", prefix, code_hash(code), suffix);
+    let path = dir.join(&name);
+    // the file name is derived from the code, so an existing file is up to date
+    if path.exists() {
+        return Ok(path);
+    }
+    let tmp = dir.join(format!(".mf-tmp-{}", Uuid::new_v4()));
+    std::fs::write(&tmp, code)?;
+    std::fs::rename(&tmp, &path)?;
+    for entry in std::fs::read_dir(dir)? {
+        let stale = entry?.file_name();
+        let stale = stale.to_string_lossy();
+        if stale != name && stale.starts_with(prefix) && stale.ends_with(&suffix) {
+            debug!(
+                "Removing '{}', it's from an earlier version of the code",
+                stale
+            );
+            let _ = std::fs::remove_file(dir.join(&*stale));
+        }
+    }
+    Ok(path)
+}
+
+#[derive(Serialize)]
+struct WorkerRequest<'a> {
+    inputs: &'a RawFunctionInput,
+}
+
+#[derive(Deserialize, Debug)]
+struct WorkerResponse {
+    stderr: String,
+    ok: bool,
+    #[serde(default)]
+    compile_error: bool,
+}
+
+///
+/// What identifies a worker for reuse: the code and the environment variables it was started with.
+///
+pub fn worker_key(code: &[u8], vars: &[(String, String)]) -> Vec<u8> {
+    let mut key = code.to_vec();
+    for (name, value) in vars.iter() {
+        key.push(0);
+        key.extend_from_slice(name.as_bytes());
+        key.push(b'=');
+        key.extend_from_slice(value.as_bytes());
+    }
+    key
+}
+
+pub(crate) fn code_hash(code: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    code.hash(&mut hasher);
+    hasher.finish()
+}
+
+///
+/// A resident process that runs one function's code for many invocations. Invocations are written to its stdin as
+/// JSON lines, the answer is the first stdout line that starts with `FRAME_PREFIX`. What the function prints comes
+/// before it.
+///
+pub struct WarmWorker {
+    child: Child,
+    stdin: ChildStdin,
+    stdout: Receiver<Vec<u8>>,
+    stdout_budget: Arc<AtomicU64>,
+    stderr: Arc<Mutex<Vec<u8>>>,
+    stderr_reader: Option<JoinHandle<()>>,
+    code_hash: u64,
+    calls: usize,
+    alive: bool,
+}
+
+impl WarmWorker {
+    ///
+    /// Starts `cmd` in its own process group as a worker for `code`.
+    ///
+    pub fn spawn(mut cmd: Command, code: &[u8]) -> Result<Self> {
+        own_process_group(&mut cmd);
+        let mut child = cmd
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+        let stdin = child
+            .stdin
+            .take()
+            .ok_or_else(|| anyhow::Error::msg("Failed to open stdin"))?;
+        let child_stdout = child
+            .stdout
+            .take()
+            .ok_or_else(|| anyhow::Error::msg("Failed to open stdout"))?;
+        let mut child_stderr = child
+            .stderr
+            .take()
+            .ok_or_else(|| anyhow::Error::msg("Failed to open stderr"))?;
+
+        let stdout_budget = Arc::new(AtomicU64::new(0));
+        let budget = stdout_budget.clone();
+        let (tx, rx) = sync_channel(READ_AHEAD_LINES);
+        thread::spawn(move || {
+            let mut reader = BufReader::new(child_stdout);
+            loop {
+                // an endless line is passed on in pieces, see `StdoutStream::forward_all`
+                let limit = budget
+                    .load(Ordering::SeqCst)
+                    .saturating_add(1)
+                    .max(MAX_FRAME_BYTES);
+                let mut line = vec![];
+                match (&mut reader).take(limit).read_until(b'\n', &mut line) {
+                    Ok(n) if n > 0 && tx.send(line).is_ok() => {}
+                    _ => break,
+                }
+            }
+        });
+        let stderr = Arc::new(Mutex::new(vec![]));
+        let stderr_sink = stderr.clone();
+        let stderr_reader = thread::spawn(move || {
+            let mut buf = [0u8; 4096];
+            while let Ok(n) = child_stderr.read(&mut buf) {
+                if n == 0 {
+                    break;
+                }
+                if let Ok(mut sink) = stderr_sink.lock() {
+                    sink.extend_from_slice(&buf[..n]);
+                }
+            }
+        });
+        info!("Started warm worker {}", child.id());
+        Ok(WarmWorker {
+            child,
+            stdin,
+            stdout: rx,
+            stdout_budget,
+            stderr,
+            stderr_reader: Some(stderr_reader),
+            code_hash: code_hash(code),
+            calls: 0,
+            alive: true,
+        })
+    }
+
+    ///
+    /// Whether the worker can take another invocation of `code`.
+    ///
+    pub fn is_reusable(&self, code: &[u8], max_calls: usize) -> bool {
+        self.alive && self.calls < max_calls && self.code_hash == code_hash(code)
+    }
+
+    fn take_stderr(&self) -> Vec<u8> {
+        self.stderr
+            .lock()
+            .map(|mut s| std::mem::take(&mut *s))
+            .unwrap_or_default()
+    }
+
+    ///
+    /// Runs one invocation and passes what it prints on to `stdout`. The worker is killed if it doesn't answer within
+    /// `timeout` or the stream fails.
+    ///
+    pub fn invoke(
+        &mut self,
+        input: &RawFunctionInput,
+        timeout: Duration,
+        stdout: &mut StdoutStream,
+    ) -> Result<ProcessOutput> {
+        self.calls += 1;
+        self.stdout_budget
+            .store(stdout.remaining(), Ordering::SeqCst);
+        let mut request = serde_json::to_vec(&WorkerRequest { inputs: input })?;
+        request.push(b'\n');
+        if let Err(e) = self
+            .stdin
+            .write_all(&request)
+            .and_then(|_| self.stdin.flush())
+        {
+            warn!(
+                "Warm worker {} doesn't accept input: {}",
+                self.child.id(),
+                e
+            );
+            return Ok(self.crashed(vec![]));
+        }
+
+        let deadline = Instant::now() + timeout;
+        let mut kept = vec![];
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            let line = match self.stdout.recv_timeout(remaining) {
+                Ok(line) => line,
+                Err(RecvTimeoutError::Timeout) => {
+                    warn!(
+                        "Warm worker {} exceeded its runtime of {:?}, killing it",
+                        self.child.id(),
+                        timeout
+                    );
+                    self.kill();
+                    return Err(ExecutionError::Timeout(timeout).into());
+                }
+                Err(RecvTimeoutError::Disconnected) => return Ok(self.crashed(kept)),
+            };
+            if let Some(frame) = line.strip_prefix(FRAME_PREFIX.as_bytes()) {
+                let response: WorkerResponse = serde_json::from_slice(frame)?;
+                return Ok(self.output(kept, response));
+            }
+            if let Err(e) = stdout.forward(line, &mut kept) {
+                // the worker might still be writing, so it's not reused
+                self.kill();
+                return Err(e);
+            }
+        }
+    }
+
+    fn output(&self, stdout: Vec<u8>, response: WorkerResponse) -> ProcessOutput {
+        let mut stderr = self.take_stderr();
+        if !stderr.is_empty() && !stderr.ends_with(b"\n") {
+            stderr.push(b'\n');
+        }
+        if response.compile_error {
+            // the same prefix the runtimes use for errors before the code runs
+            stderr.extend_from_slice(b"error: ");
+        }
+        stderr.extend_from_slice(response.stderr.as_bytes());
+        ProcessOutput {
+            stdout,
+            stderr,
+            exit_code: Some(if response.ok { 0 } else { 1 }),
+            signal: None,
+        }
+    }
+
+    ///
+    /// The worker's process exited without answering: report its exit status.
+    ///
+    fn crashed(&mut self, stdout: Vec<u8>) -> ProcessOutput {
+        let status = self.child.wait();
+        debug!("Warm worker {} exited with {:?}", self.child.id(), status);
+        // anything left in the process group may still hold on to stderr
+        self.kill();
+        if let Some(reader) = self.stderr_reader.take() {
+            let _ = reader.join();
+        }
+        let stderr = self.take_stderr();
+        match status {
+            Ok(status) => ProcessOutput::new(stdout, stderr, status),
+            Err(_) => ProcessOutput {
+                stdout,
+                stderr,
+                ..Default::default()
+            },
+        }
+    }
+
+    fn kill(&mut self) {
+        if self.alive {
+            self.alive = false;
+            kill_process_group(&mut self.child);
+            let _ = self.child.wait();
+        }
+    }
+}
+
+impl Drop for WarmWorker {
+    fn drop(&mut self) {
+        self.kill();
+    }
+}
+
+///
+/// Idle warm workers per function (by environment id). Workers are checked out for an invocation, so concurrent
+/// invocations of a function each get their own worker. Workers are replaced after `max_calls` invocations,
+/// when they crashed, or when the function's code or environment variables changed.
+///
+#[derive(Clone)]
+pub struct WarmWorkers {
+    max_calls: usize,
+    idle: Arc<Mutex<HashMap<Uuid, Vec<WarmWorker>>>>,
+}
+
+impl WarmWorkers {
+    pub fn new(max_calls: usize) -> Self {
+        WarmWorkers {
+            max_calls: max_calls.max(1),
+            idle: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    fn checkout(&self, env_id: &Uuid, code: &[u8]) -> Option<WarmWorker> {
+        let mut idle = self.idle.lock().ok()?;
+        let workers = idle.get_mut(env_id)?;
+        // outdated workers are dropped (and killed) on the way
+        while let Some(worker) = workers.pop() {
+            if worker.is_reusable(code, self.max_calls) {
+                return Some(worker);
+            }
+        }
+        None
+    }
+
+    fn checkin(&self, env_id: Uuid, worker: WarmWorker, code: &[u8]) {
+        if worker.is_reusable(code, self.max_calls) {
+            if let Ok(mut idle) = self.idle.lock() {
+                idle.entry(env_id).or_default().push(worker);
+            }
+        }
+    }
+
+    ///
+    /// Runs `code` on an idle worker of the environment, `spawn` starts a new worker if there is none.
+    ///
+    pub fn execute<F>(
+        &self,
+        env_id: Uuid,
+        code: &[u8],
+        input: &RawFunctionInput,
+        timeout: Duration,
+        mut stdout: StdoutStream,
+        spawn: F,
+    ) -> Result<ProcessOutput>
+    where
+        F: FnOnce() -> Result<WarmWorker>,
+    {
+        let mut worker = match self.checkout(&env_id, code) {
+            Some(worker) => worker,
+            None => spawn()?,
+        };
+        let output = worker.invoke(input, timeout, &mut stdout)?;
+        self.checkin(env_id, worker, code);
+        Ok(output)
+    }
+
+    ///
+    /// Stops all workers of an environment.
+    ///
+    pub fn retire(&self, env_id: &Uuid) {
+        if let Ok(mut idle) = self.idle.lock() {
+            idle.remove(env_id);
+        }
+    }
+}
+
+impl fmt::Debug for WarmWorkers {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WarmWorkers")
+            .field("max_calls", &self.max_calls)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use minifaas_common::triggers::http::HttpTrigger;
+
+    // answers every request with its call count and exits on "crash"
+    const FAKE_WORKER: &str = r#"
+n=0
+while read -r line; do
+  n=$((n+1))
+  case "$line" in *crash*) echo "crashing" >&2; exit 7;; esac
+  case "$line" in *sleep*) sleep 5;; esac
+  case "$line" in *long*) head -c 5000000 /dev/zero | tr '\0' a; sleep 5;; esac
+  echo "stray output"
+  echo "__MF__body:$n"
+  echo "__MF_FRAME__{\"stderr\":\"\",\"ok\":true}"
+done
+"#;
+
+    fn fake_worker() -> Result<WarmWorker> {
+        let mut cmd = Command::new("bash");
+        cmd.args(&["-c", FAKE_WORKER]);
+        WarmWorker::spawn(cmd, b"code")
+    }
+
+    fn input(body: &str) -> RawFunctionInput {
+        HttpTrigger {
+            body: body.as_bytes().to_vec(),
+            ..Default::default()
+        }
+        .into()
+    }
+
+    fn stdout(output: &ProcessOutput) -> String {
+        String::from_utf8(output.stdout.clone()).unwrap()
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn warm_workers_reuse_workers() {
+        let workers = WarmWorkers::new(10);
+        let env_id = Uuid::new_v4();
+        for expected in 1..=3 {
+            let output = workers
+                .execute(
+                    env_id,
+                    b"code",
+                    &input("hi"),
+                    Duration::from_secs(5),
+                    StdoutStream::default(),
+                    fake_worker,
+                )
+                .unwrap();
+            assert!(output.success());
+            assert_eq!(
+                stdout(&output),
+                format!("stray output\n__MF__body:{}\n", expected)
+            );
+        }
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn warm_workers_recycle_workers() {
+        let workers = WarmWorkers::new(2);
+        let env_id = Uuid::new_v4();
+        let bodies: Vec<String> = (0..3)
+            .map(|_| {
+                let output = workers
+                    .execute(
+                        env_id,
+                        b"code",
+                        &input("hi"),
+                        Duration::from_secs(5),
+                        StdoutStream::default(),
+                        fake_worker,
+                    )
+                    .unwrap();
+                stdout(&output)
+            })
+            .collect();
+        // the third call runs on a fresh worker
+        assert!(bodies[1].ends_with(":2\n"));
+        assert!(bodies[2].ends_with(":1\n"));
+
+        // as does a call with new code
+        let output = workers
+            .execute(
+                env_id,
+                b"new code",
+                &input("hi"),
+                Duration::from_secs(5),
+                StdoutStream::default(),
+                || {
+                    let mut cmd = Command::new("bash");
+                    cmd.args(&["-c", FAKE_WORKER]);
+                    WarmWorker::spawn(cmd, b"new code")
+                },
+            )
+            .unwrap();
+        assert!(stdout(&output).ends_with(":1\n"));
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn warm_workers_report_crashes() {
+        let workers = WarmWorkers::new(10);
+        let env_id = Uuid::new_v4();
+        let output = workers
+            .execute(
+                env_id,
+                b"code",
+                &input("crash"),
+                Duration::from_secs(5),
+                StdoutStream::default(),
+                fake_worker,
+            )
+            .unwrap();
+        assert_eq!(output.exit_code, Some(7));
+        assert_eq!(output.stderr_lines(), vec!["crashing".to_owned()]);
+
+        // the next call gets a new worker
+        let output = workers
+            .execute(
+                env_id,
+                b"code",
+                &input("hi"),
+                Duration::from_secs(5),
+                StdoutStream::default(),
+                fake_worker,
+            )
+            .unwrap();
+        assert!(stdout(&output).ends_with(":1\n"));
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn warm_workers_stream_output() {
+        let workers = WarmWorkers::new(10);
+        let env_id = Uuid::new_v4();
+        let (stream, lines) = StdoutStream::new(100);
+        let output = workers
+            .execute(
+                env_id,
+                b"code",
+                &input("hi"),
+                Duration::from_secs(5),
+                stream,
+                fake_worker,
+            )
+            .unwrap();
+        assert!(output.success());
+        assert!(output.stdout.is_empty());
+        let lines: Vec<Vec<u8>> = futures::executor::block_on(futures::StreamExt::collect(lines));
+        assert_eq!(
+            lines,
+            vec![b"stray output\n".to_vec(), b"__MF__body:1\n".to_vec()]
+        );
+
+        // too much output kills the worker
+        let result = workers.execute(
+            env_id,
+            b"code",
+            &input("hi"),
+            Duration::from_secs(5),
+            StdoutStream::buffered(20),
+            fake_worker,
+        );
+        match result.unwrap_err().downcast::<ExecutionError>() {
+            Ok(ExecutionError::OutputTooLarge(max)) => assert_eq!(max, 20),
+            other => panic!("Unexpected result: {:?}", other),
+        }
+        let output = workers
+            .execute(
+                env_id,
+                b"code",
+                &input("hi"),
+                Duration::from_secs(5),
+                StdoutStream::default(),
+                fake_worker,
+            )
+            .unwrap();
+        assert!(stdout(&output).ends_with(":1\n"));
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn warm_workers_bound_long_lines() {
+        let workers = WarmWorkers::new(10);
+        let started = Instant::now();
+        let result = workers.execute(
+            Uuid::new_v4(),
+            b"code",
+            &input("long"),
+            Duration::from_secs(10),
+            StdoutStream::buffered(20),
+            fake_worker,
+        );
+        // there is no line break, but the worker is stopped once it wrote too much
+        assert!(started.elapsed() < Duration::from_secs(5));
+        match result.unwrap_err().downcast::<ExecutionError>() {
+            Ok(ExecutionError::OutputTooLarge(max)) => assert_eq!(max, 20),
+            other => panic!("Unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn warm_workers_kill_slow_workers() {
+        let workers = WarmWorkers::new(10);
+        let started = Instant::now();
+        let result = workers.execute(
+            Uuid::new_v4(),
+            b"code",
+            &input("sleep"),
+            Duration::from_secs(1),
+            StdoutStream::default(),
+            fake_worker,
+        );
+        assert!(started.elapsed() < Duration::from_secs(4));
+        match result.unwrap_err().downcast::<ExecutionError>() {
+            Ok(ExecutionError::Timeout(after)) => assert_eq!(after, Duration::from_secs(1)),
+            other => panic!("Unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn write_js_worker_replaces_earlier_versions() {
+        let dir = minifaas_test::get_empty_tmp_dir();
+        let (script, module) = write_js_worker(&dir, b"console.log(\"hi\");\n").unwrap();
+        assert_eq!(std::fs::read_to_string(&script).unwrap(), JS_WORKER);
+        assert_eq!(
+            std::fs::read_to_string(&module).unwrap(),
+            format!("{}console.log(\"hi\");\n", JS_STARTED_MARKER)
+        );
+        std::fs::write(dir.join("data.json"), "{}").unwrap();
+
+        let (new_script, new_module) = write_js_worker(&dir, b"console.log(\"bye\");\n").unwrap();
+        assert_eq!(new_script, script);
+        assert_ne!(new_module, module);
+        let mut files: Vec<String> = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        files.sort();
+        let file_name = |p: &PathBuf| p.file_name().unwrap().to_string_lossy().into_owned();
+        assert_eq!(
+            files,
+            vec![
+                file_name(&new_module),
+                file_name(&script),
+                "data.json".to_owned()
+            ]
+        );
+    }
+}
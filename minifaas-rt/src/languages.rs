@@ -1,47 +1,197 @@
-use crate::ext::toolchain::ToolchainSetup;
-use crate::ActiveToolchain;
+use crate::ext::toolchain::Toolchain;
+use anyhow::Result;
 use async_std::sync::Arc;
+use minifaas_common::errors::PreparationError;
 use minifaas_common::ProgrammingLanguage;
 use std::collections::HashMap;
 
 ///
-/// A mapping of a toolchains to a languages in two separate stores.
-/// Keeps build toolchains and exec toolchains separate.
+/// The toolchains of a runtime, keyed by the language they run. Toolchains for pinned versions are derived from
+/// the language's toolchain when they're first needed.
 ///
 #[derive(Debug, Default, Clone)]
-pub struct ToolchainMap<T: ToolchainSetup + Default + Clone> {
-    builders: HashMap<ProgrammingLanguage, T>,
-    execs: HashMap<ProgrammingLanguage, Arc<ActiveToolchain>>,
+pub struct ToolchainRegistry {
+    toolchains: HashMap<ProgrammingLanguage, Arc<dyn Toolchain>>,
+    versions: HashMap<(ProgrammingLanguage, String), Arc<dyn Toolchain>>,
 }
 
-impl<T: ToolchainSetup + Default + Clone> ToolchainMap<T> {
-    pub fn new(
-        toolchains: Vec<(ProgrammingLanguage, T)>,
-        executors: Vec<(ProgrammingLanguage, Arc<ActiveToolchain>)>,
-    ) -> Self {
-        ToolchainMap {
-            builders: toolchains.into_iter().collect(),
-            execs: executors.into_iter().collect(),
+impl ToolchainRegistry {
+    pub fn new() -> Self {
+        ToolchainRegistry::default()
+    }
+
+    ///
+    /// Adds a toolchain for `lang` and returns the toolchain it replaced (if any).
+    ///
+    pub fn register(
+        &mut self,
+        lang: ProgrammingLanguage,
+        toolchain: Box<dyn Toolchain>,
+    ) -> Option<Arc<dyn Toolchain>> {
+        self.toolchains.insert(lang, Arc::from(toolchain))
+    }
+
+    pub fn select(&self, lang: &ProgrammingLanguage) -> Option<&Arc<dyn Toolchain>> {
+        self.toolchains.get(lang)
+    }
+
+    ///
+    /// The toolchain for `version` of `lang`, or the language's default toolchain without a version.
+    ///
+    pub fn resolve(
+        &mut self,
+        lang: &ProgrammingLanguage,
+        version: Option<&str>,
+    ) -> Result<Arc<dyn Toolchain>> {
+        let toolchain = self
+            .toolchains
+            .get(lang)
+            .ok_or_else(|| anyhow::Error::msg(format!("No toolchain found for '{}'", lang)))?;
+        let version = match version {
+            Some(version) => version,
+            None => return Ok(toolchain.clone()),
+        };
+        let key = (lang.clone(), version.to_owned());
+        if let Some(versioned) = self.versions.get(&key) {
+            return Ok(versioned.clone());
+        }
+        let unsupported = || PreparationError::UnsupportedVersion {
+            language: lang.to_string(),
+            version: version.to_owned(),
+        };
+        // versions end up in paths, so they have to be plain names
+        if !is_valid_version(version) {
+            return Err(unsupported().into());
         }
+        let versioned: Arc<dyn Toolchain> =
+            Arc::from(toolchain.with_version(version).ok_or_else(unsupported)?);
+        self.versions.insert(key, versioned.clone());
+        Ok(versioned)
+    }
+
+    ///
+    /// The languages with a toolchain: built-in languages first, then everything else by name. `Unknown` is left out.
+    ///
+    pub fn languages(&self) -> Vec<ProgrammingLanguage> {
+        let builtin = ProgrammingLanguage::available();
+        let mut others: Vec<ProgrammingLanguage> = self
+            .toolchains
+            .keys()
+            .filter(|l| !builtin.contains(l) && **l != ProgrammingLanguage::Unknown)
+            .cloned()
+            .collect();
+        others.sort_by_key(|l| l.to_string());
+        builtin
+            .into_iter()
+            .filter(|l| self.toolchains.contains_key(l))
+            .chain(others)
+            .collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.toolchains.len()
     }
 
-    pub fn select_for(&self, lang: &ProgrammingLanguage) -> Option<&T> {
-        self.builders.get(lang)
+    pub fn is_empty(&self) -> bool {
+        self.toolchains.is_empty()
     }
+}
+
+fn is_valid_version(version: &str) -> bool {
+    !version.is_empty()
+        && !version.starts_with('.')
+        && version
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '.' || c == '-' || c == '_')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ext::bash::{Bash, BashSetup};
+    use crate::ext::toolchain::LifecycleToolchain;
 
-    pub fn select_for_mut(&mut self, lang: &ProgrammingLanguage) -> Option<&mut T> {
-        self.builders.get_mut(lang)
+    fn bash() -> Box<dyn Toolchain> {
+        Box::new(LifecycleToolchain::new(
+            BashSetup::default(),
+            Bash::default(),
+        ))
     }
 
-    pub fn select_executor(&self, lang: &ProgrammingLanguage) -> Option<&Arc<ActiveToolchain>> {
-        self.execs.get(lang)
+    #[test]
+    fn toolchain_registry_register_replaces() {
+        let mut registry = ToolchainRegistry::new();
+        assert!(registry
+            .register(ProgrammingLanguage::Bash, bash())
+            .is_none());
+        assert!(registry
+            .register(ProgrammingLanguage::Bash, bash())
+            .is_some());
+        assert_eq!(registry.len(), 1);
+        assert!(registry.select(&ProgrammingLanguage::Bash).is_some());
+        assert!(registry.select(&ProgrammingLanguage::Python).is_none());
     }
 
-    pub fn len_toolchain_setups(&self) -> usize {
-        self.builders.len()
+    #[test]
+    fn toolchain_registry_resolve_versions() {
+        use crate::ext::deno::{Deno, DenoSetup};
+
+        let mut registry = ToolchainRegistry::new();
+        registry.register(ProgrammingLanguage::Bash, bash());
+        registry.register(
+            ProgrammingLanguage::JavaScript,
+            Box::new(LifecycleToolchain::new(
+                DenoSetup::default(),
+                Deno::default(),
+            )),
+        );
+
+        let js = ProgrammingLanguage::JavaScript;
+        let default = registry.resolve(&js, None).unwrap();
+        assert!(Arc::ptr_eq(&default, registry.select(&js).unwrap()));
+        let pinned = registry.resolve(&js, Some("1.9.0")).unwrap();
+        assert!(!Arc::ptr_eq(&default, &pinned));
+        assert!(Arc::ptr_eq(
+            &pinned,
+            &registry.resolve(&js, Some("1.9.0")).unwrap()
+        ));
+
+        for (lang, version) in vec![
+            (ProgrammingLanguage::Bash, "5.0"),
+            (js.clone(), "../1.9.0"),
+            (js, ""),
+        ] {
+            let err = registry.resolve(&lang, Some(version)).unwrap_err();
+            assert!(matches!(
+                err.downcast_ref::<PreparationError>(),
+                Some(PreparationError::UnsupportedVersion { .. })
+            ));
+        }
+        assert!(registry
+            .resolve(&ProgrammingLanguage::Python, None)
+            .is_err());
     }
 
-    pub fn len_toolchain_executors(&self) -> usize {
-        self.execs.len()
+    #[test]
+    fn toolchain_registry_languages() {
+        let mut registry = ToolchainRegistry::new();
+        for lang in vec![
+            ProgrammingLanguage::Command("Ruby".to_owned()),
+            ProgrammingLanguage::Unknown,
+            ProgrammingLanguage::Bash,
+            ProgrammingLanguage::Command("Lua".to_owned()),
+            ProgrammingLanguage::JavaScript,
+        ] {
+            registry.register(lang, bash());
+        }
+        assert_eq!(
+            registry.languages(),
+            vec![
+                ProgrammingLanguage::JavaScript,
+                ProgrammingLanguage::Bash,
+                ProgrammingLanguage::Command("Lua".to_owned()),
+                ProgrammingLanguage::Command("Ruby".to_owned()),
+            ]
+        );
     }
 }
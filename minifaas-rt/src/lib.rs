@@ -5,13 +5,26 @@ mod output_parser;
 
 use crate::ext::bash::Bash;
 use crate::ext::bash::BashSetup;
+pub use crate::ext::command::{load_command_toolchains, CodeInput, CommandToolchainConfig};
+use crate::ext::command::{CommandSetup, CommandToolchain};
 use crate::ext::deno::Deno;
-use crate::ext::deno::DenoSetup;
-use crate::ext::toolchain::ActiveToolchain;
-use crate::ext::toolchain::BuildToolchain;
-use crate::languages::ToolchainMap;
+use crate::ext::deno::{DenoSetup, DEFAULT_SOURCE};
+pub use crate::ext::install::{
+    load_checksums, ToolchainCache, ToolchainInstaller, ToolchainSource,
+};
+use crate::ext::nodejs::{NodeJs, NodeJsSetup, NodeJsSource};
+pub use crate::ext::process::{
+    run_streaming, run_with_timeout, ProcessOutput, StdoutStream, DEFAULT_MAX_OUTPUT_BYTES,
+};
+use crate::ext::python::{Python, PythonSetup};
+pub use crate::ext::sandbox::Sandbox;
+pub use crate::ext::toolchain::{
+    LifecycleToolchain, Toolchain, ToolchainLifecycle, ToolchainSetup,
+};
+pub use crate::languages::ToolchainRegistry;
 use crate::logs::collectors::FileLogCollector;
 use log::{debug, error, info, trace, warn};
+use minifaas_common::errors::ExecutionError;
 use minifaas_common::*;
 use std::sync::Arc;
 use xactor::*;
@@ -19,6 +32,8 @@ mod actors;
 use actors::*;
 use chrono::Duration;
 use futures::future::join_all;
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
 /// Move this crate to actors:
 /// Management actor that manages access to the function db?
 /// Runtime actors: per each language one actor?
@@ -31,18 +46,186 @@ use futures::future::join_all;
 pub struct RuntimeConfiguration {
     num_threads: usize,
     timer_resolution_ms: i64,
+    max_runtime_secs: u64,
+    max_queue_length: usize,
+    warm_worker_calls: Option<usize>,
+    nodejs_tarball: Option<PathBuf>,
+    python_virtualenv: bool,
+    commands: Vec<CommandToolchainConfig>,
+    deno_source: Option<ToolchainSource>,
+    toolchain_cache: Option<PathBuf>,
+    toolchain_checksums: HashMap<String, String>,
+    require_checksums: bool,
+    secrets: Option<Arc<SecretStore>>,
+    resource_limits: ResourceLimits,
+    sandbox: bool,
+    trusted_functions: HashSet<String>,
+    max_output_bytes: u64,
 }
 
 impl RuntimeConfiguration {
     ///
-    /// New runtime config
+    /// New runtime config. `num_threads` limits the concurrent executors per function, `max_queue_length` the calls waiting for them.
     ///
-    pub fn new(num_threads: usize, timer_resolution_ms: i64) -> Self {
+    pub fn new(
+        num_threads: usize,
+        timer_resolution_ms: i64,
+        max_runtime_secs: u64,
+        max_queue_length: usize,
+    ) -> Self {
         RuntimeConfiguration {
             num_threads,
             timer_resolution_ms,
+            max_runtime_secs,
+            max_queue_length,
+            warm_worker_calls: None,
+            nodejs_tarball: None,
+            python_virtualenv: false,
+            commands: vec![],
+            deno_source: None,
+            toolchain_cache: None,
+            toolchain_checksums: HashMap::new(),
+            require_checksums: true,
+            secrets: None,
+            resource_limits: ResourceLimits::default(),
+            sandbox: false,
+            trusted_functions: HashSet::new(),
+            max_output_bytes: DEFAULT_MAX_OUTPUT_BYTES,
         }
     }
+
+    ///
+    /// Runs JavaScript functions that are declared as `warm` on resident workers that are replaced after `max_calls`
+    /// invocations.
+    ///
+    pub fn with_warm_workers(mut self, max_calls: usize) -> Self {
+        self.warm_worker_calls = Some(max_calls);
+        self
+    }
+
+    ///
+    /// Installs Node.js from a local release archive (`.tar.gz`) instead of using the `node` binary on the `PATH`.
+    ///
+    pub fn with_nodejs_tarball<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.nodejs_tarball = Some(path.into());
+        self
+    }
+
+    ///
+    /// Runs Python functions in a virtualenv per environment instead of the system's site packages.
+    ///
+    pub fn with_python_virtualenv(mut self) -> Self {
+        self.python_virtualenv = true;
+        self
+    }
+
+    ///
+    /// Adds interpreters as `ProgrammingLanguage::Command` (see `load_command_toolchains`).
+    ///
+    pub fn with_command_toolchains(mut self, commands: Vec<CommandToolchainConfig>) -> Self {
+        self.commands.extend(commands);
+        self
+    }
+
+    ///
+    /// Installs Deno from `source` (e.g. a local mirror of the GitHub releases) instead of GitHub.
+    ///
+    pub fn with_deno_source(mut self, source: ToolchainSource) -> Self {
+        self.deno_source = Some(source);
+        self
+    }
+
+    ///
+    /// Keeps installed toolchains in `path` (as `<toolchain>/<version>/`), shared by all environments. Versions this
+    /// runtime (identified by its environments' directory) installed that none of its environments link to are
+    /// removed at startup and whenever a function is deleted.
+    ///
+    pub fn with_toolchain_cache<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.toolchain_cache = Some(path.into());
+        self
+    }
+
+    ///
+    /// Verifies toolchain archives with these SHA-256 checksums (by file name) in addition to the pinned ones. With
+    /// `required` (the default), archives without a checksum are rejected.
+    ///
+    pub fn with_toolchain_checksums(
+        mut self,
+        checksums: HashMap<String, String>,
+        required: bool,
+    ) -> Self {
+        self.toolchain_checksums = checksums;
+        self.require_checksums = required;
+        self
+    }
+
+    ///
+    /// Runs functions with the secrets from `secrets` that their environment variables refer to. Without a store,
+    /// only functions without secrets can run.
+    ///
+    pub fn with_secrets(mut self, secrets: Arc<SecretStore>) -> Self {
+        self.secrets = Some(secrets);
+        self
+    }
+
+    ///
+    /// Limits the resources of every function's process to at most `limits` (where supported by the OS). Functions
+    /// can declare lower limits for themselves.
+    ///
+    pub fn with_resource_limits(mut self, limits: ResourceLimits) -> Self {
+        self.resource_limits = limits;
+        self
+    }
+
+    ///
+    /// Runs functions in a `Sandbox` (Linux only) unless they are trusted. Sandboxed functions only see their
+    /// environment and the system's libraries, and have no network access.
+    ///
+    pub fn with_sandbox(mut self) -> Self {
+        self.sandbox = true;
+        self
+    }
+
+    ///
+    /// The functions (by name) that may be trusted to run outside of the sandbox. Other functions are sandboxed even
+    /// if they are declared as trusted.
+    ///
+    pub fn with_trusted_functions<I: IntoIterator<Item = String>>(mut self, names: I) -> Self {
+        self.trusted_functions.extend(names);
+        self
+    }
+
+    ///
+    /// Kills functions that write more than `max_output_bytes` to stdout. Stdout goes to the logs while the function
+    /// runs, so only outputs are kept in memory.
+    ///
+    pub fn with_max_output_bytes(mut self, max_output_bytes: u64) -> Self {
+        self.max_output_bytes = max_output_bytes;
+        self
+    }
+
+    ///
+    /// The shared directory for installed toolchains, by default a private one of the runtime's user in the system's
+    /// temp directory.
+    ///
+    fn toolchain_cache(&self) -> ToolchainCache {
+        ToolchainCache::new(
+            self.toolchain_cache
+                .clone()
+                .unwrap_or_else(ToolchainCache::default_root),
+        )
+    }
+
+    fn toolchain_installer(
+        &self,
+        source: ToolchainSource,
+        cache: &ToolchainCache,
+    ) -> ToolchainInstaller {
+        ToolchainInstaller::new(source, cache.root())
+            .with_shared_cache(cache.clone())
+            .with_checksums(self.toolchain_checksums.clone())
+            .require_checksums(self.require_checksums)
+    }
 }
 
 #[derive(Clone)]
@@ -50,9 +233,41 @@ pub struct RuntimeConnection {
     controller_addr: Addr<RuntimeController>,
     http_addr: Addr<HttpTriggered>,
     timer_addr: Addr<TimerTriggered>,
+    languages: Arc<Vec<ProgrammingLanguage>>,
+    toolchain_cache: ToolchainCache,
+    secrets: Arc<SecretStore>,
+    trusted_functions: Arc<HashSet<String>>,
 }
 
 impl RuntimeConnection {
+    ///
+    /// The languages this runtime has toolchains for.
+    ///
+    pub fn languages(&self) -> Vec<ProgrammingLanguage> {
+        self.languages.as_ref().clone()
+    }
+
+    ///
+    /// The toolchain versions installed for functions as `(toolchain, version)`, e.g. `("deno", "1.7.4")`.
+    ///
+    pub fn installed_versions(&self) -> Result<Vec<(String, String)>> {
+        self.toolchain_cache.versions()
+    }
+
+    ///
+    /// The secrets functions can refer to. Changes apply to the next call of a function.
+    ///
+    pub fn secrets(&self) -> Arc<SecretStore> {
+        self.secrets.clone()
+    }
+
+    ///
+    /// Whether the function `name` may run outside of the sandbox, i.e. the operator allows it to be trusted.
+    ///
+    pub fn may_be_trusted(&self, name: &str) -> bool {
+        self.trusted_functions.contains(name)
+    }
+
     ///
     ///
     ///
@@ -77,10 +292,37 @@ impl RuntimeConnection {
                 })
                 .await?
                 .map(|s| RuntimeResponse::LogResponse(s)),
-            RuntimeRequest::FunctionCall(_, inputs) => match inputs {
-                FunctionInputs::Http(inp) => {
-                    self.http_addr.call(inp).await?.map(RuntimeResponse::from)
-                }
+            RuntimeRequest::ExportEnvironment {
+                env_id,
+                logs,
+                files,
+            } => self
+                .controller_addr
+                .call(ExportEnvMsg {
+                    env_id,
+                    logs,
+                    files,
+                })
+                .await?
+                .map(RuntimeResponse::EnvironmentContents),
+            RuntimeRequest::RestoreEnvironment { env_id, contents } => self
+                .controller_addr
+                .call(RestoreEnvMsg { env_id, contents })
+                .await?
+                .map(|_| RuntimeResponse::Ok),
+            RuntimeRequest::FunctionCall(inputs) => match inputs {
+                // only the lookup goes through the trigger, the call itself runs concurrently
+                FunctionInputs::Http(inp) => match self
+                    .http_addr
+                    .call(ResolveHttpMsg(inp))
+                    .await?
+                    .dispatch()
+                    .await
+                {
+                    Ok(outputs) => Ok(RuntimeResponse::from(outputs)),
+                    // errors from running the code are regular responses
+                    Err(e) => e.downcast::<ExecutionError>().map(RuntimeResponse::from),
+                },
                 FunctionInputs::Timer(_) => Err(Error::msg("Cannot call timers explicitly")),
             },
             RuntimeRequest::NewFunction(code) => {
@@ -89,7 +331,8 @@ impl RuntimeConnection {
                     .controller_addr
                     .call(SetupMsg {
                         env_id: code.environment_id,
-                        toolchain: *code.language(),
+                        toolchain: code.language().clone(),
+                        version: code.version().map(str::to_owned),
                     })
                     .await?;
                 debug!("Setup completed for {:?}", code);
@@ -101,19 +344,16 @@ impl RuntimeConnection {
                         RuntimeResponse::Ok
                     })
             }
-            RuntimeRequest::DeleteFunction(code) => {
-                let _ = self
-                    .controller_addr
-                    .call(SetupMsg {
-                        env_id: code.environment_id,
-                        toolchain: *code.language(),
-                    })
-                    .await?;
-
+            RuntimeRequest::DeleteFunction { code, keep_logs } => {
+                let env_id = code.environment_id;
                 self.controller_addr
-                    .call(StartExecutorMsg { code })
-                    .await
-                    .map(|_| RuntimeResponse::Ok)
+                    .call(StopExecutorMsg { code })
+                    .await??;
+                debug!("Stopped executor for '{}'", env_id);
+                self.controller_addr
+                    .call(DestroyMsg { env_id, keep_logs })
+                    .await??;
+                Ok(RuntimeResponse::Ok)
             }
             RuntimeRequest::Disable(code) => self
                 .controller_addr
@@ -125,104 +365,232 @@ impl RuntimeConnection {
 }
 
 ///
-/// Creates a runtime based on the configuration and returns a command channel to invoke things with.
+/// The built-in toolchains plus the configured commands.
 ///
-pub async fn create_runtime(
-    config: RuntimeConfiguration,
-    predefined_envs: Environments,
-    deployments: Arc<FaaSDataStore>,
-) -> Result<RuntimeConnection> {
-    let setup_map = ToolchainMap::new(
-        vec![
-            (
-                ProgrammingLanguage::JavaScript,
-                BuildToolchain::Deno(DenoSetup::default()),
-            ),
-            (
-                ProgrammingLanguage::Bash,
-                BuildToolchain::Bash(BashSetup::default()),
-            ),
-            (
-                ProgrammingLanguage::Unknown,
-                BuildToolchain::Deno(DenoSetup::default()),
-            ),
-        ],
-        vec![
-            (
-                ProgrammingLanguage::JavaScript,
-                Arc::new(ActiveToolchain::Deno(Deno::default())),
-            ),
-            (
-                ProgrammingLanguage::Bash,
-                Arc::new(ActiveToolchain::Bash(Bash::default())),
-            ),
-            (
-                ProgrammingLanguage::Unknown,
-                Arc::new(ActiveToolchain::Deno(Deno::default())),
+fn builtin_toolchains(config: &RuntimeConfiguration, cache: &ToolchainCache) -> ToolchainRegistry {
+    let (deno, nodejs) = match config.warm_worker_calls {
+        Some(max_calls) => (
+            Deno::default().with_warm_workers(max_calls),
+            NodeJs::default().with_warm_workers(max_calls),
+        ),
+        None => (Deno::default(), NodeJs::default()),
+    };
+    let nodejs_source = match &config.nodejs_tarball {
+        Some(path) => NodeJsSource::Tarball(path.clone()),
+        None => NodeJsSource::SystemPath,
+    };
+    let python_setup = if config.python_virtualenv {
+        PythonSetup::with_virtualenv()
+    } else {
+        PythonSetup::default()
+    };
+
+    let deno_source = config
+        .deno_source
+        .clone()
+        .unwrap_or_else(|| ToolchainSource::Remote(DEFAULT_SOURCE.to_owned()));
+    let deno_setup =
+        DenoSetup::default().with_installer(config.toolchain_installer(deno_source, cache));
+
+    let mut toolchains = ToolchainRegistry::new();
+    toolchains.register(
+        ProgrammingLanguage::JavaScript,
+        Box::new(LifecycleToolchain::new(deno_setup.clone(), deno.clone())),
+    );
+    toolchains.register(
+        ProgrammingLanguage::NodeJs,
+        Box::new(LifecycleToolchain::new(
+            NodeJsSetup::with_source(nodejs_source).with_installer(
+                config.toolchain_installer(ToolchainSource::Mirror(PathBuf::new()), cache),
             ),
-        ],
+            nodejs,
+        )),
+    );
+    toolchains.register(
+        ProgrammingLanguage::Python,
+        Box::new(LifecycleToolchain::new(python_setup, Python::default())),
+    );
+    toolchains.register(
+        ProgrammingLanguage::Bash,
+        Box::new(LifecycleToolchain::new(
+            BashSetup::default(),
+            Bash::default(),
+        )),
     );
+    toolchains.register(
+        ProgrammingLanguage::Unknown,
+        Box::new(LifecycleToolchain::new(deno_setup, deno)),
+    );
+    for command in config.commands.iter() {
+        toolchains.register(
+            command.language(),
+            Box::new(LifecycleToolchain::new(
+                CommandSetup::new(command.clone()),
+                CommandToolchain::new(command.clone()),
+            )),
+        );
+    }
+    toolchains
+}
+
+///
+/// Assembles a runtime from the built-in toolchains and the ones registered by the embedding application.
+///
+/// ```rust,ignore
+/// let connection = RuntimeBuilder::new(config)
+///     .with_toolchain(ProgrammingLanguage::Command("Cobol".to_owned()), Box::new(Cobol::default()))
+///     .start(environments, deployments)
+///     .await?;
+/// ```
+///
+pub struct RuntimeBuilder {
+    config: RuntimeConfiguration,
+    toolchains: ToolchainRegistry,
+    toolchain_cache: ToolchainCache,
+}
 
-    let timer_resolution = Duration::milliseconds(config.timer_resolution_ms).to_std()?;
+impl RuntimeBuilder {
+    pub fn new(config: RuntimeConfiguration) -> Self {
+        // the installers and the controller share the cache to know which versions were installed by this runtime
+        let toolchain_cache = config.toolchain_cache();
+        let toolchains = builtin_toolchains(&config, &toolchain_cache);
+        RuntimeBuilder {
+            config,
+            toolchains,
+            toolchain_cache,
+        }
+    }
 
-    info!(
-        "Found {} executors & {} setups",
-        setup_map.len_toolchain_executors(),
-        setup_map.len_toolchain_setups()
-    );
+    ///
+    /// Adds a toolchain for `lang`. Replaces the built-in toolchain if there is one for the language.
+    ///
+    pub fn with_toolchain(
+        mut self,
+        lang: ProgrammingLanguage,
+        toolchain: Box<dyn Toolchain>,
+    ) -> Self {
+        if self.toolchains.register(lang.clone(), toolchain).is_some() {
+            info!("Replaced the toolchain for '{}'", lang);
+        }
+        self
+    }
 
-    let _http = Supervisor::start(HttpTriggered::new).await?;
-    let _http2 = _http.clone();
-    let _timer = Supervisor::start(move || TimerTriggered::new(timer_resolution)).await?;
-    let _timer2 = _timer.clone();
-    let log_collector = Arc::new(FileLogCollector::new("logs"));
-
-    let _env_setup = Supervisor::start(move || {
-        RuntimeController::new(
-            predefined_envs.clone(),
-            setup_map.clone(),
-            _http2.clone(),
-            _timer2.clone(),
-            log_collector.clone(),
-        )
-    })
-    .await?;
-
-    info!("Runtime controller successfully started");
-    let setup: Vec<Result<_>> = join_all(deployments.values().await.iter().map(|v| {
-        _env_setup.call(SetupMsg {
-            env_id: v.environment_id,
-            toolchain: *v.language(),
+    ///
+    /// Starts the runtime, deploys all functions in `deployments`, and returns a command channel to invoke things with.
+    ///
+    pub async fn start(
+        self,
+        predefined_envs: Environments,
+        deployments: Arc<FaaSDataStore>,
+    ) -> Result<RuntimeConnection> {
+        let (config, toolchains, toolchain_cache) =
+            (self.config, self.toolchains, self.toolchain_cache);
+        // the environments identify the runtime, so versions it installed before a restart are still its own
+        let envs_root = predefined_envs.root();
+        let owner = std::fs::canonicalize(envs_root).unwrap_or_else(|_| envs_root.to_path_buf());
+        toolchain_cache.set_owner(&owner.to_string_lossy());
+        let timer_resolution = Duration::milliseconds(config.timer_resolution_ms).to_std()?;
+        let default_timeout = std::time::Duration::from_secs(config.max_runtime_secs);
+        let default_limits = PoolLimits::new(1, config.num_threads, config.max_queue_length);
+
+        info!("Found {} toolchains", toolchains.len());
+        let languages = toolchains.languages();
+
+        let _http = Supervisor::start(HttpTriggered::new).await?;
+        let _http2 = _http.clone();
+        let _timer = Supervisor::start(move || TimerTriggered::new(timer_resolution)).await?;
+        let _timer2 = _timer.clone();
+        let log_collector = Arc::new(FileLogCollector::new("logs"));
+        let controller_cache = toolchain_cache.clone();
+        let secrets = config.secrets.clone().unwrap_or_default();
+        let controller_secrets = secrets.clone();
+        let resource_limits = config.resource_limits;
+        let sandbox = config.sandbox;
+        let trusted_functions = Arc::new(config.trusted_functions);
+        let controller_trusted = trusted_functions.clone();
+        let max_output_bytes = config.max_output_bytes;
+        if sandbox && !Sandbox::is_supported() {
+            return Err(anyhow::Error::msg("Sandboxes are only supported on Linux"));
+        }
+
+        let _env_setup = Supervisor::start(move || {
+            RuntimeController::new(
+                predefined_envs.clone(),
+                toolchains.clone(),
+                _http2.clone(),
+                _timer2.clone(),
+                log_collector.clone(),
+                default_timeout,
+                default_limits,
+            )
+            .with_toolchain_cache(controller_cache.clone())
+            .with_secrets(controller_secrets.clone())
+            .with_resource_limits(resource_limits)
+            .with_sandbox(sandbox)
+            .with_trusted_functions(controller_trusted.clone())
+            .with_max_output_bytes(max_output_bytes)
         })
-    }))
-    .await;
-    let setup_ok: Vec<_> = setup.iter().filter(|f| f.is_ok()).collect();
-
-    info!(
-        "Success setting up {}/{} environments",
-        setup_ok.len(),
-        setup.len()
-    );
+        .await?;
 
-    let started: Vec<Result<_>> = join_all(
-        deployments
-            .values()
-            .await
-            .iter()
-            .map(|v| _env_setup.call(StartExecutorMsg { code: v.clone() })),
-    )
-    .await;
-
-    let started_ok: Vec<_> = started.iter().filter(|f| f.is_ok()).collect();
-    info!(
-        "Success starting {}/{} executors",
-        started_ok.len(),
-        started.len()
-    );
+        info!("Runtime controller successfully started");
+        let setup: Vec<Result<_>> = join_all(deployments.values().await.iter().map(|v| {
+            _env_setup.call(SetupMsg {
+                env_id: v.environment_id,
+                toolchain: v.language().clone(),
+                version: v.version().map(str::to_owned),
+            })
+        }))
+        .await;
+        let setup_ok: Vec<_> = setup.iter().filter(|f| f.is_ok()).collect();
 
-    Ok(RuntimeConnection {
-        controller_addr: _env_setup,
-        http_addr: _http.clone(),
-        timer_addr: _timer.clone(),
-    })
+        info!(
+            "Success setting up {}/{} environments",
+            setup_ok.len(),
+            setup.len()
+        );
+
+        match _env_setup.call(CollectToolchainsMsg).await? {
+            Ok(removed) => info!("Removed {} unused toolchain versions", removed.len()),
+            Err(e) => warn!("Couldn't remove unused toolchains: {:?}", e),
+        }
+
+        let started: Vec<Result<_>> = join_all(
+            deployments
+                .values()
+                .await
+                .iter()
+                .map(|v| _env_setup.call(StartExecutorMsg { code: v.clone() })),
+        )
+        .await;
+
+        let started_ok: Vec<_> = started.iter().filter(|f| f.is_ok()).collect();
+        info!(
+            "Success starting {}/{} executors",
+            started_ok.len(),
+            started.len()
+        );
+
+        Ok(RuntimeConnection {
+            controller_addr: _env_setup,
+            http_addr: _http.clone(),
+            timer_addr: _timer.clone(),
+            languages: Arc::new(languages),
+            toolchain_cache,
+            secrets,
+            trusted_functions,
+        })
+    }
+}
+
+///
+/// Creates a runtime with the built-in toolchains and returns a command channel to invoke things with.
+///
+pub async fn create_runtime(
+    config: RuntimeConfiguration,
+    predefined_envs: Environments,
+    deployments: Arc<FaaSDataStore>,
+) -> Result<RuntimeConnection> {
+    RuntimeBuilder::new(config)
+        .start(predefined_envs, deployments)
+        .await
 }
@@ -46,36 +46,34 @@ pub trait ReaderInput {
     ///
     fn parse_to_map(&self, reader: impl BufRead) -> Result<HashMap<String, Vec<u8>>> {
         let hm = reader
-            .lines()
+            .split(b'\n')
             .take_while(|r| r.is_ok())
-            .map(|r| r.unwrap())
-            .filter_map(|r| {
-                if r.starts_with(self.val_prefix()) {
-                    let mut extractors = self.extractors();
-                    let sep = r.find(':')?;
-                    let (k, val) = r.split_at(sep);
-                    let val = val.strip_prefix(':')?;
-                    let extracted: Vec<u8> = {
-                        let val = val.trim();
-                        let mut ext = None;
-                        while let Some(e) = extractors.next() {
-                            ext = e(val);
-                            if ext.is_some() {
-                                break;
-                            }
-                        }
-                        ext
-                    }?;
-
-                    let result = (k.replace(self.val_prefix(), ""), extracted);
-                    Some(result)
-                } else {
-                    None
-                }
-            })
+            .filter_map(|r| self.parse_line(&r.unwrap()))
             .collect();
         Ok(hm)
     }
+
+    ///
+    /// Extracts the key and value from a single line (with or without its line break), `None` if the line doesn't
+    /// start with the prefix. Values that aren't valid UTF-8 are kept as they are.
+    ///
+    fn parse_line(&self, line: &[u8]) -> Option<(String, Vec<u8>)> {
+        let line = line.strip_prefix(self.val_prefix().as_bytes())?;
+        let sep = line.iter().position(|b| *b == b':')?;
+        let k = std::str::from_utf8(&line[..sep]).ok()?;
+        let val = &line[sep + 1..];
+        let extracted = match std::str::from_utf8(val) {
+            Ok(val) => {
+                let val = val.trim();
+                self.extractors().find_map(|e| e(val))?
+            }
+            Err(_) => {
+                let val = val.strip_suffix(b"\n").unwrap_or(val);
+                val.strip_suffix(b"\r").unwrap_or(val).to_vec()
+            }
+        };
+        Some((k.to_owned(), extracted))
+    }
 }
 
 impl ReaderInput for Parser {
@@ -127,4 +125,23 @@ mod tests {
         let cursor = Cursor::new(s);
         assert_eq!(p.parse_to_map(cursor).unwrap(), hm);
     }
+
+    #[test]
+    fn test_parser_parse_line_binary() {
+        let p = Parser::new("__MF__".to_string(), default_extractors());
+        assert_eq!(p.parse_line(b"lorem-ipsum\n"), None);
+        assert_eq!(
+            p.parse_line(b"__MF__body: blabla \n"),
+            Some(("body".to_string(), b"blabla".to_vec()))
+        );
+        assert_eq!(
+            p.parse_line(b"__MF__body:\xff\x00\xfe\r\n"),
+            Some(("body".to_string(), b"\xff\x00\xfe".to_vec()))
+        );
+
+        let s = b"\xff\xfe\n__MF__body:\xff\n".to_vec();
+        let mut hm = HashMap::new();
+        hm.insert("body".to_string(), b"\xff".to_vec());
+        assert_eq!(p.parse_to_map(Cursor::new(s)).unwrap(), hm);
+    }
 }
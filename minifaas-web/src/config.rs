@@ -1,6 +1,8 @@
 
 use anyhow::Result;
 use envconfig::Envconfig;
+use minifaas_common::{DataStoreConfig, ResourceLimits, StorageBackend};
+use std::path::{Path, PathBuf};
 
 #[derive(Envconfig)]
 pub struct Settings {
@@ -14,6 +16,9 @@ pub struct Settings {
     #[envconfig(from = "MF_DB_PATH", default = "functions.db")]
     pub functions_db_path: String,
 
+    #[envconfig(from = "MF_DB_BACKEND", default = "json")]
+    db_backend_raw: String,
+
     #[envconfig(from = "MF_ENV_ROOT_PATH", default = "/tmp")]
     pub env_root: String,
 
@@ -25,6 +30,75 @@ pub struct Settings {
 
     #[envconfig(from = "MF_MAX_FUNCTION_RUNTIME_SECS", default = "300")]
     max_runtime_secs_raw: String,
+
+    #[envconfig(from = "MF_MAX_QUEUE_LENGTH", default = "100")]
+    max_queue_length_raw: String,
+
+    #[envconfig(from = "MF_WARM_WORKER_CALLS", default = "0")]
+    warm_worker_calls_raw: String,
+
+    #[envconfig(from = "MF_NODEJS_TARBALL", default = "")]
+    nodejs_tarball_raw: String,
+
+    #[envconfig(from = "MF_PYTHON_VIRTUALENV", default = "false")]
+    pub python_virtualenv: bool,
+
+    #[envconfig(from = "MF_TOOLCHAINS_CONFIG", default = "")]
+    toolchains_config_raw: String,
+
+    #[envconfig(from = "MF_DENO_SOURCE", default = "")]
+    deno_source_raw: String,
+
+    #[envconfig(from = "MF_TOOLCHAIN_CACHE", default = "")]
+    toolchain_cache_raw: String,
+
+    #[envconfig(from = "MF_TOOLCHAIN_CHECKSUMS", default = "")]
+    toolchain_checksums_raw: String,
+
+    #[envconfig(from = "MF_TOOLCHAIN_REQUIRE_CHECKSUMS", default = "true")]
+    pub toolchain_require_checksums: bool,
+
+    #[envconfig(from = "MF_SECRETS_DB_PATH", default = "secrets.db")]
+    pub secrets_db_path: String,
+
+    #[envconfig(from = "MF_SECRETS_KEY", default = "")]
+    secrets_key_raw: String,
+
+    #[envconfig(from = "MF_MAX_MEMORY_BYTES", default = "")]
+    max_memory_bytes_raw: String,
+
+    #[envconfig(from = "MF_MAX_CPU_SECS", default = "")]
+    max_cpu_secs_raw: String,
+
+    #[envconfig(from = "MF_MAX_FILE_SIZE_BYTES", default = "")]
+    max_file_size_bytes_raw: String,
+
+    #[envconfig(from = "MF_MAX_OPEN_FILES", default = "")]
+    max_open_files_raw: String,
+
+    #[envconfig(from = "MF_MAX_PROCESSES", default = "")]
+    max_processes_raw: String,
+
+    #[envconfig(from = "MF_SANDBOX", default = "false")]
+    pub sandbox: bool,
+
+    #[envconfig(from = "MF_TRUSTED_FUNCTIONS", default = "")]
+    trusted_functions_raw: String,
+
+    #[envconfig(from = "MF_MAX_OUTPUT_BYTES", default = "10485760")]
+    pub max_output_bytes: u64,
+
+    #[envconfig(from = "MF_GIT_REPO_PATH", default = "")]
+    git_repo_path_raw: String,
+
+    #[envconfig(from = "MF_GIT_REMOTE", default = "")]
+    git_remote_raw: String,
+
+    #[envconfig(from = "MF_MANIFESTS_PATH", default = "")]
+    manifests_path_raw: String,
+
+    #[envconfig(from = "MF_RECONCILE_ON_STARTUP", default = "false")]
+    pub reconcile_on_startup: bool,
 }
 
 impl Settings {
@@ -34,4 +108,134 @@ impl Settings {
     pub fn max_runtime_secs(&self) -> Result<u64> { self.max_runtime_secs_raw.parse().map_err(anyhow::Error::from) }
 
     pub fn no_threads(&self) -> Result<usize> { self.no_threads_raw.parse().map_err(anyhow::Error::from) }
-}
\ No newline at end of file
+
+    pub fn max_queue_length(&self) -> Result<usize> { self.max_queue_length_raw.parse().map_err(anyhow::Error::from) }
+
+    ///
+    /// Invocations per warm worker process for functions declared as `warm`, `None` (0) runs every call in a new process
+    ///
+    pub fn warm_worker_calls(&self) -> Result<Option<usize>> {
+        let calls: usize = self.warm_worker_calls_raw.parse()?;
+        Ok(if calls > 0 { Some(calls) } else { None })
+    }
+
+    ///
+    /// A local Node.js release archive to install from, `None` (empty) uses `node` from the `PATH`
+    ///
+    pub fn nodejs_tarball(&self) -> Option<&str> {
+        Some(self.nodejs_tarball_raw.as_str()).filter(|p| !p.is_empty())
+    }
+
+    ///
+    /// A JSON file with additional interpreters (see `minifaas_rt::CommandToolchainConfig`), `None` (empty) for none
+    ///
+    pub fn toolchains_config(&self) -> Option<&str> {
+        Some(self.toolchains_config_raw.as_str()).filter(|p| !p.is_empty())
+    }
+
+    ///
+    /// Where to get Deno releases: a URL, or a local mirror (path or `file://` URL). `None` (empty) uses GitHub
+    ///
+    pub fn deno_source(&self) -> Option<&str> {
+        Some(self.deno_source_raw.as_str()).filter(|p| !p.is_empty())
+    }
+
+    ///
+    /// The directory for toolchains shared by all environments, only accessible to the runtime's user. Empty means
+    /// `toolchains` next to `MF_DB_PATH`
+    ///
+    pub fn toolchain_cache(&self) -> PathBuf {
+        if self.toolchain_cache_raw.is_empty() {
+            Path::new(&self.functions_db_path)
+                .parent()
+                .unwrap_or_else(|| Path::new(""))
+                .join("toolchains")
+        } else {
+            PathBuf::from(&self.toolchain_cache_raw)
+        }
+    }
+
+    ///
+    /// A `sha256sum` style file with checksums of toolchain archives, `None` (empty) for none
+    ///
+    pub fn toolchain_checksums(&self) -> Option<&str> {
+        Some(self.toolchain_checksums_raw.as_str()).filter(|p| !p.is_empty())
+    }
+
+    ///
+    /// The master key (any passphrase) secrets are encrypted with, `None` (empty) disables secrets
+    ///
+    pub fn secrets_key(&self) -> Option<&str> {
+        Some(self.secrets_key_raw.as_str()).filter(|k| !k.is_empty())
+    }
+
+    ///
+    /// The functions (comma-separated names) that may opt out of the sandbox with `"trusted": true`, empty for none
+    ///
+    pub fn trusted_functions(&self) -> Vec<String> {
+        self.trusted_functions_raw
+            .split(',')
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+            .map(str::to_owned)
+            .collect()
+    }
+
+    ///
+    /// A Git repository to store functions in instead of `MF_DB_PATH`, `None` (empty) uses the JSON file
+    ///
+    pub fn git_repo_path(&self) -> Option<&str> {
+        Some(self.git_repo_path_raw.as_str()).filter(|p| !p.is_empty())
+    }
+
+    ///
+    /// A repository (path or URL) to pull functions from, `None` (empty) for none
+    ///
+    pub fn git_remote(&self) -> Option<&str> {
+        Some(self.git_remote_raw.as_str()).filter(|r| !r.is_empty())
+    }
+
+    ///
+    /// A directory of function manifests (`*.toml`) to reconcile the stored functions with, `None` (empty) for none
+    ///
+    pub fn manifests_path(&self) -> Option<PathBuf> {
+        Some(self.manifests_path_raw.as_str())
+            .filter(|p| !p.is_empty())
+            .map(PathBuf::from)
+    }
+
+    ///
+    /// Where functions are stored: a Git repository at `MF_GIT_REPO_PATH` if set, otherwise `MF_DB_PATH` with the
+    /// `MF_DB_BACKEND` backend (`json` or `embedded`)
+    ///
+    pub fn storage_config(&self) -> Result<DataStoreConfig> {
+        Ok(match self.git_repo_path() {
+            Some(path) => {
+                DataStoreConfig::new(path, true).with_git(self.git_remote().map(str::to_owned))
+            }
+            None => DataStoreConfig::new(&self.functions_db_path, true)
+                .with_backend(self.db_backend_raw.parse::<StorageBackend>()?),
+        })
+    }
+
+    ///
+    /// The most resources a function's process can use, empty settings leave that resource unlimited
+    ///
+    pub fn resource_limits(&self) -> Result<ResourceLimits> {
+        Ok(ResourceLimits {
+            memory_bytes: parse_limit(&self.max_memory_bytes_raw)?,
+            cpu_secs: parse_limit(&self.max_cpu_secs_raw)?,
+            file_size_bytes: parse_limit(&self.max_file_size_bytes_raw)?,
+            open_files: parse_limit(&self.max_open_files_raw)?,
+            processes: parse_limit(&self.max_processes_raw)?,
+        })
+    }
+}
+
+fn parse_limit(raw: &str) -> Result<Option<u64>> {
+    if raw.is_empty() {
+        Ok(None)
+    } else {
+        Ok(Some(raw.parse()?))
+    }
+}
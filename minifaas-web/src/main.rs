@@ -6,13 +6,15 @@ mod routes;
 use routes::*;
 
 use anyhow::Result;
-use clap::{App as ClApp, Arg};
+use clap::{App as ClApp, Arg, SubCommand};
 use config::Settings;
 use minifaas_rt::RuntimeConnection;
 use envconfig::Envconfig;
-use log::{debug, info};
+use log::{debug, info, warn};
 use minifaas_common::*;
-use minifaas_rt::{create_runtime, RuntimeConfiguration};
+use minifaas_rt::{
+    create_runtime, load_checksums, load_command_toolchains, RuntimeConfiguration, ToolchainSource,
+};
 use std::sync::Arc;
 
 use tide;
@@ -22,12 +24,51 @@ const VERSION: &str = "0.1.0";
 
 async fn start_runtime(settings: &Settings) -> Result<(Arc<FaaSDataStore>, RuntimeConnection)> {
     // set up connections to aux projects
-    let _storage = Arc::new(
-        create_or_load_storage(DataStoreConfig::new(&settings.functions_db_path, true)).await?,
-    );
+    let _storage = create_or_load_storage(settings.storage_config()?).await?;
     let predefined_envs = sync_environments(&settings.env_root, _storage.clone()).await?;
+    let secrets = Arc::new(
+        SecretStore::from_path(&settings.secrets_db_path, settings.secrets_key()).await?,
+    );
+    if !secrets.is_enabled() {
+        warn!("No master key (MF_SECRETS_KEY) set, functions can't use secrets");
+    }
+    let mut runtime_config = RuntimeConfiguration::new(
+        settings.no_threads()?,
+        settings.timer_tick_ms()?,
+        settings.max_runtime_secs()?,
+        settings.max_queue_length()?,
+    );
+    if let Some(max_calls) = settings.warm_worker_calls()? {
+        runtime_config = runtime_config.with_warm_workers(max_calls);
+    }
+    if let Some(tarball) = settings.nodejs_tarball() {
+        runtime_config = runtime_config.with_nodejs_tarball(tarball);
+    }
+    if settings.python_virtualenv {
+        runtime_config = runtime_config.with_python_virtualenv();
+    }
+    if settings.sandbox {
+        runtime_config = runtime_config.with_sandbox();
+    }
+    runtime_config = runtime_config.with_trusted_functions(settings.trusted_functions());
+    if let Some(path) = settings.toolchains_config() {
+        runtime_config = runtime_config.with_command_toolchains(load_command_toolchains(path)?);
+    }
+    if let Some(source) = settings.deno_source() {
+        runtime_config = runtime_config.with_deno_source(ToolchainSource::parse(source)?);
+    }
+    runtime_config = runtime_config.with_toolchain_cache(settings.toolchain_cache());
+    let checksums = match settings.toolchain_checksums() {
+        Some(path) => load_checksums(path)?,
+        None => Default::default(),
+    };
+    runtime_config = runtime_config
+        .with_toolchain_checksums(checksums, settings.toolchain_require_checksums)
+        .with_secrets(secrets)
+        .with_resource_limits(settings.resource_limits()?)
+        .with_max_output_bytes(settings.max_output_bytes);
     let runtime_connection = create_runtime(
-        RuntimeConfiguration::new(settings.no_threads()?, settings.timer_tick_ms()?),
+        runtime_config,
         predefined_envs,
         _storage.clone(),
     )
@@ -55,13 +96,45 @@ pub async fn start_web_server(
             .delete(remove_function);
         f.at(&format!("{}/{}", API_VERSION, FUNC_CALL_PATH))
             .get(list_all_functions);
+        f.at(&format!("{}/{}/:name/revisions", API_VERSION, FUNC_CALL_PATH))
+            .get(list_revisions);
+        f.at(&format!(
+            "{}/{}/:name/revisions/:number",
+            API_VERSION, FUNC_CALL_PATH
+        ))
+        .get(get_revision);
+        f.at(&format!(
+            "{}/{}/:name/revisions/:number/rollback",
+            API_VERSION, FUNC_CALL_PATH
+        ))
+        .post(rollback_function);
+        f.at(&format!(
+            "{}/{}/:name/revisions/:from/diff/:to",
+            API_VERSION, FUNC_CALL_PATH
+        ))
+        .get(diff_revisions);
+        f.at(&format!("{}/pull", API_VERSION)).post(pull_functions);
+        f.at(&format!("{}/export", API_VERSION)).get(export_functions);
+        f.at(&format!("{}/import", API_VERSION)).post(import_functions);
+        let manifests = settings.manifests_path();
+        f.at(&format!("{}/reconcile", API_VERSION)).post(
+            move |req: tide::Request<(Arc<FaaSDataStore>, RuntimeConnection)>| {
+                reconcile_manifests(req, manifests.clone())
+            },
+        );
         f.at(&format!("{}/logs/:name/:from/:lines", API_VERSION))
             .get(get_logs);
+        f.at(&format!("{}/toolchains", API_VERSION))
+            .get(list_toolchain_versions);
+        f.at(&format!("{}/secrets", API_VERSION)).get(list_secrets);
+        f.at(&format!("{}/secrets/:name", API_VERSION))
+            .put(save_secret)
+            .delete(remove_secret);
         f
     });
     app.at("/f/").nest({
         let mut f = tide::with_state((storage.clone(), runtime_channel.clone()));
-        f.at("/call/:name").all(call_function);
+        f.at("/call/*route").all(call_function);
         f
     });
     app.listen(settings.endpoint.to_owned()).await?;
@@ -82,6 +155,26 @@ async fn main() -> Result<()> {
                 .value_name("config.toml")
                 .takes_value(true),
         )
+        .subcommand(
+            SubCommand::with_name("migrate")
+                .about("Copies all functions and their revisions to another datastore backend")
+                .arg(
+                    Arg::with_name("backend")
+                        .long("to")
+                        .help("The backend to migrate to")
+                        .possible_values(&["json", "embedded", "git"])
+                        .required(true)
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("path")
+                        .long("path")
+                        .help("Where the new store is created")
+                        .value_name("PATH")
+                        .required(true)
+                        .takes_value(true),
+                ),
+        )
         .get_matches();
 
     env_logger::init();
@@ -92,7 +185,36 @@ async fn main() -> Result<()> {
         serde_json::to_string_pretty(&UserFunctionDeclaration::default()).unwrap()
     );
     let settings= Settings::init_from_env()?;
+    if let Some(migrate) = matches.subcommand_matches("migrate") {
+        // both arguments are required
+        let backend = migrate.value_of("backend").unwrap_or_default().parse()?;
+        let target = DataStoreConfig::new(migrate.value_of("path").unwrap_or_default(), true)
+            .with_backend(backend);
+        let count = migrate_storage(settings.storage_config()?, target).await?;
+        info!("Migrated {} functions", count);
+        return Ok(());
+    }
     let (storage, runtime_channel) = start_runtime(&settings).await?;
+    let disabled = disable_invalid_functions(&storage, &runtime_channel).await;
+    if !disabled.is_empty() {
+        warn!(
+            "Disabled {} invalid functions: {:?}",
+            disabled.len(),
+            disabled
+        );
+    }
+    if settings.reconcile_on_startup {
+        let dir = settings.manifests_path().ok_or_else(|| {
+            anyhow::Error::msg("MF_RECONCILE_ON_STARTUP requires MF_MANIFESTS_PATH")
+        })?;
+        let changes = reconcile(&storage, &runtime_channel, dir, false, false)
+            .await
+            .map_err(|e| e.into_inner())?;
+        info!(
+            "Reconciled {} functions with their manifests",
+            changes.len()
+        );
+    }
 
     start_web_server(&settings, storage, runtime_channel).await
 }
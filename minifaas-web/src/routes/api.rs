@@ -3,18 +3,29 @@ use askama::Template;
 use minifaas_rt::RuntimeConnection;
 
 use log::{debug, error};
+use minifaas_common::archive::{
+    free_name, ArchivedFunction, ConflictStrategy, EnvironmentContents, FunctionArchive,
+    ARCHIVE_VERSION,
+};
+use minifaas_common::manifests::{load_manifests, plan_reconcile, ManifestChange};
+use minifaas_common::secrets::{validate_env, SecretInfo, SecretsError};
+use minifaas_common::triggers::http::HttpMethod;
+use minifaas_common::triggers::route::RoutePattern;
 use minifaas_common::*;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 
+use std::collections::{BTreeMap, HashSet};
+use std::path::PathBuf;
 use std::str::FromStr;
 use std::sync::Arc;
+use uuid::Uuid;
 
 use tide;
 use tide::{Body, Request, Response, StatusCode};
 
 type AppState = (Arc<FaaSDataStore>, RuntimeConnection);
 
-use super::views::LogViewModel;
+use super::views::{InstalledVersion, LogViewModel};
 
 #[derive(Deserialize)]
 struct ReturnTypeOptions {
@@ -27,15 +38,95 @@ impl ReturnTypeOptions {
     }
 }
 
+#[derive(Deserialize)]
+struct SecretValue {
+    value: String,
+}
+
+#[derive(Deserialize, Default)]
+struct DeleteOptions {
+    #[serde(default)]
+    keep_logs: bool,
+}
+
+#[derive(Deserialize, Default)]
+struct SaveOptions {
+    #[serde(default)]
+    message: Option<String>,
+}
+
+#[derive(Deserialize, Default)]
+struct ReconcileOptions {
+    #[serde(default)]
+    dry_run: bool,
+    #[serde(default)]
+    force: bool,
+}
+
+#[derive(Deserialize, Default)]
+struct ExportOptions {
+    #[serde(default)]
+    logs: bool,
+    #[serde(default)]
+    files: bool,
+}
+
+#[derive(Deserialize, Default)]
+struct ImportOptions {
+    #[serde(default)]
+    conflict: ConflictStrategy,
+}
+
+#[derive(Serialize, Default)]
+struct ImportResult {
+    /// The stored names of the imported functions by their archived names
+    imported: BTreeMap<String, String>,
+    skipped: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct PullResult {
+    saved: Vec<String>,
+    deleted: Vec<String>,
+    /// Pulled functions that weren't deployed, with the reason
+    invalid: BTreeMap<String, String>,
+}
+
 ///
-/// API call to save a function using a JSON object.
+/// API call to save a function using a JSON object. Pass `?message=...` to describe the new revision.
 ///
 pub async fn save_function(mut req: Request<AppState>) -> tide::Result {
     let item: UserFunctionDeclaration = req.body_json().await?;
-    let name = &item.name;
+    let options: SaveOptions = req.query().unwrap_or_default();
+    let (storage, connection) = req.state();
+    deploy_function(storage, connection, item, options.message).await
+}
 
+///
+/// Checks that a function can be deployed: its trigger, route, permissions, and environment variables have to be
+/// valid, the secrets it references have to exist, and none of the `others` (functions with another name) may have
+/// its route and method.
+///
+async fn validate_function(
+    connection: &RuntimeConnection,
+    function: &UserFunctionDeclaration,
+    others: &[UserFunctionDeclaration],
+) -> tide::Result<()> {
+    // Check if there is a toolchain for the language, any name that isn't built in is taken for a configured command.
+    let languages = connection.languages();
+    if !languages.contains(&function.code.language) {
+        let available: Vec<String> = languages.iter().map(|l| l.to_string()).collect();
+        return Err(tide::Error::from_str(
+            StatusCode::BadRequest,
+            format!(
+                "There is no toolchain for '{}', available are: {}",
+                function.code.language,
+                available.join(", ")
+            ),
+        ));
+    }
     // Check if the cron string is valid.
-    match &item.trigger {
+    match &function.trigger {
         Trigger::Interval(cron_str) => {
             let e = cron_str.parse::<cron::Schedule>();
             if e.is_err() {
@@ -45,13 +136,106 @@ pub async fn save_function(mut req: Request<AppState>) -> tide::Result {
         }
         _ => (),
     }
+
+    // Check if the route is a valid pattern.
+    if let Some(route) = &function.route {
+        RoutePattern::parse(route).map_err(|e| {
+            error!("Couldn't parse route ({}): {:?}", route, e);
+            tide::Error::from_str(StatusCode::BadRequest, e.to_string())
+        })?;
+    }
+    // Check if another function is called on the same route and method.
+    if let Some((route, method)) = http_route(function) {
+        let taken_by = others
+            .iter()
+            .filter(|other| other.name != function.name)
+            .find(|other| http_route(other) == Some((route.clone(), method)));
+        if let Some(other) = taken_by {
+            return Err(tide::Error::from_str(
+                StatusCode::Conflict,
+                format!(
+                    "{:?} {} is already taken by function '{}'",
+                    method, route, other.name
+                ),
+            ));
+        }
+    }
+    // Check if the operator allows the function to run outside of the sandbox.
+    if function.trusted && !connection.may_be_trusted(&function.name) {
+        return Err(tide::Error::from_str(
+            StatusCode::BadRequest,
+            format!(
+                "Function '{}' can't be trusted, it isn't in MF_TRUSTED_FUNCTIONS",
+                function.name
+            ),
+        ));
+    }
+    // Check if the permissions can be passed on to the runtime.
+    function.permissions.validate().map_err(|e| {
+        error!("Invalid permissions ({:?}): {:?}", function.permissions, e);
+        tide::Error::from_str(StatusCode::BadRequest, e.to_string())
+    })?;
+    // Check if the environment variables can be set and their secrets exist.
+    validate_env(&function.env).map_err(|e| {
+        error!("Invalid environment variables: {:?}", e);
+        tide::Error::from_str(StatusCode::BadRequest, e.to_string())
+    })?;
+    let secrets = connection.secrets();
+    for value in function.env.values() {
+        if let EnvValue::Secret { secret } = value {
+            if !secrets.contains(secret).await {
+                return Err(tide::Error::from_str(
+                    StatusCode::BadRequest,
+                    SecretsError::NotFound(secret.clone()).to_string(),
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
+///
+/// The (normalized) route and method a function is called on, if it has an HTTP trigger.
+///
+fn http_route(function: &UserFunctionDeclaration) -> Option<(String, HttpMethod)> {
+    match &function.trigger {
+        Trigger::Http(method) => {
+            let route = function.route.as_ref().unwrap_or(&function.name);
+            let pattern = RoutePattern::parse(route).ok()?;
+            Some((pattern.as_str().to_owned(), *method))
+        }
+        _ => None,
+    }
+}
+
+///
+/// The declarations of all stored functions.
+///
+async fn stored_functions(storage: &FaaSDataStore) -> Vec<UserFunctionDeclaration> {
+    storage
+        .values()
+        .await
+        .iter()
+        .map(|f| f.declaration().clone())
+        .collect()
+}
+
+///
+/// Validates and stores a function as a new revision, then (re-)deploys it with `NewFunction`.
+///
+async fn deploy_function(
+    storage: &FaaSDataStore,
+    connection: &RuntimeConnection,
+    item: UserFunctionDeclaration,
+    message: Option<String>,
+) -> tide::Result {
+    let name = &item.name;
+    validate_function(connection, &item, &stored_functions(storage).await).await?;
     debug!(
         "Saving function with Name: {}, Trigger: {:?}, Code: {}",
         name, item.trigger, item.code
     );
     if !name.trim().is_empty() {
-        let (storage, connection) = req.state();
-
         let new_record = match storage.get(&name).await {
             Some(f) => {
                 // if a function is already saved it needs to be updated
@@ -75,15 +259,18 @@ pub async fn save_function(mut req: Request<AppState>) -> tide::Result {
             None => UserFunctionRecord::from(item.clone()),
         };
 
-        // replace the exisiting function
+        // replace the exisiting function and keep the new version
         storage.set(name.clone(), new_record).await;
+        let revision = storage.revisions().add(&item, message).await;
         let code = storage
             .get(&name)
             .await
             .ok_or_else(|| AnyError::msg(format!("Function couldn't be found: {}", name)))?;
 
         connection.send(RuntimeRequest::NewFunction(code)).await?;
-        Ok(Response::new(StatusCode::Ok))
+        let mut resp = Response::new(StatusCode::Ok);
+        resp.set_body(Body::from_json(&RevisionInfo::from(&revision))?);
+        Ok(resp)
     } else {
         error!("ERROR :(");
 
@@ -94,12 +281,24 @@ pub async fn save_function(mut req: Request<AppState>) -> tide::Result {
     }
 }
 
+///
+/// API call to delete a function, its executor, and its environment. Pass `?keep_logs=true` to archive the logs.
+///
 pub async fn remove_function(req: Request<AppState>) -> tide::Result {
-    let (storage, _) = req.state();
+    let (storage, connection) = req.state();
     let name = req.param("name")?;
+    let options: DeleteOptions = req.query().unwrap_or_default();
     if !name.trim().is_empty() {
-        storage.delete(name).await;
-        Ok(Response::new(StatusCode::Ok))
+        match storage.get(name).await {
+            Some(code) => {
+                delete_function(storage, connection, code, options.keep_logs).await?;
+                Ok(Response::new(StatusCode::Ok))
+            }
+            None => Err(tide::Error::from_str(
+                StatusCode::NotFound,
+                format!("Function '{}' not found", name),
+            )),
+        }
     } else {
         Err(tide::Error::from_str(
             StatusCode::BadRequest,
@@ -108,6 +307,27 @@ pub async fn remove_function(req: Request<AppState>) -> tide::Result {
     }
 }
 
+///
+/// Stops a function's executor, destroys its environment, and removes it from the store.
+///
+async fn delete_function(
+    storage: &FaaSDataStore,
+    connection: &RuntimeConnection,
+    code: UserFunctionType,
+    keep_logs: bool,
+) -> tide::Result<()> {
+    let name = code.name().clone();
+    connection
+        .send(RuntimeRequest::DeleteFunction { code, keep_logs })
+        .await
+        .map_err(|e| {
+            error!("Couldn't delete the runtime for '{}': {:?}", name, e);
+            e
+        })?;
+    storage.delete(&name).await;
+    Ok(())
+}
+
 pub async fn get_logs(req: Request<AppState>) -> tide::Result {
     let (storage, connection) = req.state();
     let name = req.param("name")?;
@@ -158,9 +378,460 @@ pub async fn get_logs(req: Request<AppState>) -> tide::Result {
     }
 }
 
+///
+/// API call to list a function's revisions, oldest first.
+///
+pub async fn list_revisions(req: Request<AppState>) -> tide::Result {
+    let (storage, _) = req.state();
+    let name = req.param("name")?;
+    let revisions = storage.revisions().list(name).await;
+    if revisions.is_empty() {
+        return Err(revision_not_found(name, None));
+    }
+    let mut resp = Response::new(StatusCode::Ok);
+    resp.set_body(Body::from_json(&revisions)?);
+    Ok(resp)
+}
+
+///
+/// API call to fetch a revision of a function, including its code.
+///
+pub async fn get_revision(req: Request<AppState>) -> tide::Result {
+    let (storage, _) = req.state();
+    let name = req.param("name")?;
+    let number = req.param("number")?.parse::<u64>()?;
+    let revision = storage
+        .revisions()
+        .get(name, number)
+        .await
+        .ok_or_else(|| revision_not_found(name, Some(number)))?;
+    let mut resp = Response::new(StatusCode::Ok);
+    resp.set_body(Body::from_json(&revision)?);
+    Ok(resp)
+}
+
+///
+/// API call to compare two revisions of a function.
+///
+pub async fn diff_revisions(req: Request<AppState>) -> tide::Result {
+    let (storage, _) = req.state();
+    let name = req.param("name")?;
+    let from = req.param("from")?.parse::<u64>()?;
+    let to = req.param("to")?.parse::<u64>()?;
+    let diff = storage
+        .revisions()
+        .diff(name, from, to)
+        .await?
+        .ok_or_else(|| {
+            tide::Error::from_str(
+                StatusCode::NotFound,
+                format!("Function '{}' has no revisions {} and {}", name, from, to),
+            )
+        })?;
+    let mut resp = Response::new(StatusCode::Ok);
+    resp.set_body(Body::from_json(&diff)?);
+    Ok(resp)
+}
+
+///
+/// API call to deploy a previous revision of a function again, which creates a new revision.
+///
+pub async fn rollback_function(req: Request<AppState>) -> tide::Result {
+    let (storage, connection) = req.state();
+    let name = req.param("name")?;
+    let number = req.param("number")?.parse::<u64>()?;
+    let revision = storage
+        .revisions()
+        .get(name, number)
+        .await
+        .ok_or_else(|| revision_not_found(name, Some(number)))?;
+    let message = Some(format!("Rollback to revision {}", number));
+    deploy_function(storage, connection, revision.function, message).await
+}
+
+fn revision_not_found(name: &str, number: Option<u64>) -> tide::Error {
+    let message = match number {
+        Some(number) => format!("Function '{}' has no revision {}", name, number),
+        None => format!("Function '{}' has no revisions", name),
+    };
+    tide::Error::from_str(StatusCode::NotFound, message)
+}
+
+///
+/// API call to pull functions from the Git remote and redeploy the ones that changed. Fails (without changes) if
+/// functions aren't stored in Git, there's no remote, or the merge has conflicts.
+///
+pub async fn pull_functions(req: Request<AppState>) -> tide::Result {
+    let (storage, connection) = req.state();
+    let changes = storage.pull().await.map_err(|e| {
+        error!("Couldn't pull functions: {:?}", e);
+        tide::Error::from_str(StatusCode::Conflict, e.to_string())
+    })?;
+    let mut result = PullResult {
+        saved: vec![],
+        deleted: vec![],
+        invalid: BTreeMap::new(),
+    };
+    let stored = stored_functions(storage).await;
+    for (old, new) in changes.saved {
+        let name = new.name().clone();
+        // the previous version (if any) keeps running
+        if let Err(e) = validate_function(connection, new.declaration(), &stored).await {
+            error!("Not deploying the pulled function '{}': {}", name, e);
+            result.invalid.insert(name, e.to_string());
+            continue;
+        }
+        if let Some(old) = old {
+            if let Err(e) = connection.send(RuntimeRequest::Disable(old)).await {
+                error!("Couldn't disable the runtime for '{}': {:?}", name, e);
+            }
+        }
+        storage
+            .revisions()
+            .add(new.declaration(), Some("Pulled from Git".to_owned()))
+            .await;
+        if let Err(e) = connection.send(RuntimeRequest::NewFunction(new)).await {
+            error!("Couldn't deploy '{}': {:?}", name, e);
+        }
+        result.saved.push(name);
+    }
+    for code in changes.deleted {
+        let name = code.name().clone();
+        let request = RuntimeRequest::DeleteFunction {
+            code,
+            keep_logs: false,
+        };
+        if let Err(e) = connection.send(request).await {
+            error!("Couldn't delete the runtime for '{}': {:?}", name, e);
+        }
+        result.deleted.push(name);
+    }
+    let mut resp = Response::new(StatusCode::Ok);
+    resp.set_body(Body::from_json(&result)?);
+    Ok(resp)
+}
+
+///
+/// Stops the stored functions that don't pass validation, e.g. ones that were pulled from a Git remote at startup,
+/// and returns their names.
+///
+pub async fn disable_invalid_functions(
+    storage: &FaaSDataStore,
+    connection: &RuntimeConnection,
+) -> Vec<String> {
+    let mut disabled = vec![];
+    let stored = stored_functions(storage).await;
+    for code in storage.values().await {
+        let name = code.name().clone();
+        if let Err(e) = validate_function(connection, code.declaration(), &stored).await {
+            error!("Disabling the invalid function '{}': {}", name, e);
+            if let Err(e) = connection.send(RuntimeRequest::Disable(code)).await {
+                error!("Couldn't disable the runtime for '{}': {:?}", name, e);
+            }
+            disabled.push(name);
+        }
+    }
+    disabled
+}
+
+///
+/// API call to reconcile the functions with the manifests in `MF_MANIFESTS_PATH` and return the changes. Pass
+/// `?dry_run=true` to only get the changes, and `?force=true` to delete all functions if there are no manifests.
+///
+pub async fn reconcile_manifests(req: Request<AppState>, dir: Option<PathBuf>) -> tide::Result {
+    let dir = dir.ok_or_else(|| {
+        tide::Error::from_str(
+            StatusCode::BadRequest,
+            "There's no manifest directory (MF_MANIFESTS_PATH) to reconcile with",
+        )
+    })?;
+    let options: ReconcileOptions = req.query().unwrap_or_default();
+    let (storage, connection) = req.state();
+    let changes = reconcile(storage, connection, dir, options.dry_run, options.force).await?;
+    let mut resp = Response::new(StatusCode::Ok);
+    resp.set_body(Body::from_json(&changes)?);
+    Ok(resp)
+}
+
+///
+/// Makes the stored functions match the manifests in `dir`: declared functions are created or updated, all others
+/// are deleted (their logs are archived). Nothing changes unless all manifests are valid, and nothing at all with
+/// `dry_run`. If there are no manifests, the functions are only deleted with `force`.
+///
+pub async fn reconcile(
+    storage: &FaaSDataStore,
+    connection: &RuntimeConnection,
+    dir: PathBuf,
+    dry_run: bool,
+    force: bool,
+) -> tide::Result<Vec<ManifestChange>> {
+    let declared = async_std::task::spawn_blocking(move || load_manifests(&dir))
+        .await
+        .map_err(|e| {
+            error!("Couldn't read the manifests: {:?}", e);
+            tide::Error::from_str(StatusCode::BadRequest, format!("{:#}", e))
+        })?;
+    // the declared functions replace all others
+    for function in &declared {
+        validate_function(connection, function, &declared).await?;
+    }
+    let changes = plan_reconcile(storage, declared, force)
+        .await
+        .map_err(|e| tide::Error::from_str(StatusCode::BadRequest, format!("{:#}", e)))?;
+    if dry_run {
+        return Ok(changes);
+    }
+    let message = Some("Reconciled with manifests".to_owned());
+    for change in &changes {
+        match change {
+            ManifestChange::Create { function } | ManifestChange::Update { function, .. } => {
+                deploy_function(storage, connection, function.clone(), message.clone()).await?;
+            }
+            ManifestChange::Delete { name } => {
+                if let Some(code) = storage.get(name).await {
+                    delete_function(storage, connection, code, true).await?;
+                }
+            }
+        }
+    }
+    Ok(changes)
+}
+
+///
+/// API call to download all functions with their revisions as a single archive. Pass `?logs=true` and/or
+/// `?files=true` to include what's in their environments.
+///
+pub async fn export_functions(req: Request<AppState>) -> tide::Result {
+    let options: ExportOptions = req.query().unwrap_or_default();
+    let (storage, connection) = req.state();
+    let mut items = storage.items().await;
+    items.sort_by(|a, b| a.0.cmp(&b.0));
+    let mut functions = vec![];
+    for (name, record) in items {
+        let contents = if options.logs || options.files {
+            let request = RuntimeRequest::ExportEnvironment {
+                env_id: record.environment_id,
+                logs: options.logs,
+                files: options.files,
+            };
+            match connection.send(request).await? {
+                RuntimeResponse::EnvironmentContents(contents) => contents,
+                _ => EnvironmentContents::default(),
+            }
+        } else {
+            EnvironmentContents::default()
+        };
+        let revisions = storage.revisions().all(&name).await;
+        functions.push(ArchivedFunction::new(&record, revisions, contents));
+    }
+    let mut resp = Response::new(StatusCode::Ok);
+    resp.insert_header(
+        "Content-Disposition",
+        "attachment; filename=\"minifaas-export.json\"",
+    );
+    resp.set_body(Body::from_json(&FunctionArchive::new(functions))?);
+    Ok(resp)
+}
+
+///
+/// API call to restore the functions in an archive from `export_functions` and deploy them. Functions with the name
+/// of a stored function are skipped unless `?conflict=overwrite` or `?conflict=rename` is passed. Overwritten
+/// functions keep their revisions, the archived ones are added after them. Nothing is imported unless all imported
+/// functions are valid under the name they get.
+///
+pub async fn import_functions(mut req: Request<AppState>) -> tide::Result {
+    let archive: FunctionArchive = req.body_json().await?;
+    let options: ImportOptions = req.query()?;
+    let (storage, connection) = req.state();
+    if archive.version > ARCHIVE_VERSION {
+        return Err(tide::Error::from_str(
+            StatusCode::BadRequest,
+            format!("Archive version {} isn't supported", archive.version),
+        ));
+    }
+    let mut result = ImportResult::default();
+    // the functions as they are after the import, by name
+    let mut planned: BTreeMap<String, UserFunctionDeclaration> = stored_functions(storage)
+        .await
+        .into_iter()
+        .map(|f| (f.name.clone(), f))
+        .collect();
+    let mut functions = vec![];
+    for archived in archive.functions {
+        let mut function = archived.function.clone();
+        if function.name.trim().is_empty() {
+            return Err(tide::Error::from_str(
+                StatusCode::BadRequest,
+                format!("Name '{}' is invalid", function.name),
+            ));
+        }
+        if planned.contains_key(&function.name) {
+            match options.conflict {
+                ConflictStrategy::Skip => {
+                    result.skipped.push(function.name);
+                    continue;
+                }
+                ConflictStrategy::Overwrite => (),
+                ConflictStrategy::Rename => {
+                    function.name = free_name(&function.name, |n| planned.contains_key(n));
+                }
+            }
+        }
+        let contents = archived
+            .contents()
+            .map_err(|e| tide::Error::from_str(StatusCode::BadRequest, format!("{:#}", e)))?;
+        planned.insert(function.name.clone(), function.clone());
+        functions.push((archived, function, contents));
+    }
+    let others: Vec<UserFunctionDeclaration> = planned.values().cloned().collect();
+    for (_, function, _) in &functions {
+        // e.g. a renamed function may only stay trusted if its new name is
+        validate_function(connection, function, &others).await?;
+    }
+
+    let mut env_ids: HashSet<Uuid> = storage
+        .values()
+        .await
+        .iter()
+        .map(|f| f.environment_id)
+        .collect();
+    for (archived, function, contents) in functions {
+        let env_id = match storage.get(&function.name).await {
+            Some(old) => {
+                // the function is replaced like it would be by saving it
+                let env_id = old.environment_id;
+                connection.send(RuntimeRequest::Disable(old)).await?;
+                env_id
+            }
+            // another function may live in the archived environment by now
+            None if env_ids.contains(&archived.environment_id) => Uuid::new_v4(),
+            None => archived.environment_id,
+        };
+        let name = function.name.clone();
+        if !contents.is_empty() {
+            let request = RuntimeRequest::RestoreEnvironment { env_id, contents };
+            connection.send(request).await?;
+        }
+        storage
+            .set(
+                name.clone(),
+                UserFunctionRecord::new(function.clone(), env_id),
+            )
+            .await;
+        storage.revisions().restore(&name, archived.revisions).await;
+        storage
+            .revisions()
+            .add(&function, Some("Imported from archive".to_owned()))
+            .await;
+        let code = storage
+            .get(&name)
+            .await
+            .ok_or_else(|| AnyError::msg(format!("Function couldn't be found: {}", name)))?;
+        connection.send(RuntimeRequest::NewFunction(code)).await?;
+        env_ids.insert(env_id);
+        result.imported.insert(archived.function.name, name);
+    }
+    let mut resp = Response::new(StatusCode::Ok);
+    resp.set_body(Body::from_json(&result)?);
+    Ok(resp)
+}
+
 pub async fn list_all_functions(req: Request<AppState>) -> tide::Result {
     let (storage, _) = req.state();
     let mut resp = Response::new(StatusCode::Ok);
     resp.set_body(Body::from_json(&storage.values().await)?);
     Ok(resp)
 }
+
+///
+/// API call to list the toolchain versions installed for functions (e.g. to pin a function to one of them).
+///
+pub async fn list_toolchain_versions(req: Request<AppState>) -> tide::Result {
+    let (_, connection) = req.state();
+    let versions: Vec<InstalledVersion> = connection
+        .installed_versions()?
+        .into_iter()
+        .map(InstalledVersion::from)
+        .collect();
+    let mut resp = Response::new(StatusCode::Ok);
+    resp.set_body(Body::from_json(&versions)?);
+    Ok(resp)
+}
+
+///
+/// Maps errors from the secret store to responses.
+///
+fn secrets_error(e: AnyError) -> tide::Error {
+    let status = match e.downcast_ref::<SecretsError>() {
+        Some(SecretsError::InvalidName(_)) => StatusCode::BadRequest,
+        Some(SecretsError::NotFound(_)) => StatusCode::NotFound,
+        Some(SecretsError::NoMasterKey) => StatusCode::ServiceUnavailable,
+        _ => StatusCode::InternalServerError,
+    };
+    tide::Error::from_str(status, e.to_string())
+}
+
+///
+/// API call to list the secrets' names. Their values are never returned.
+///
+pub async fn list_secrets(req: Request<AppState>) -> tide::Result {
+    let (_, connection) = req.state();
+    let secrets: Vec<SecretInfo> = connection.secrets().list().await;
+    let mut resp = Response::new(StatusCode::Ok);
+    resp.set_body(Body::from_json(&secrets)?);
+    Ok(resp)
+}
+
+///
+/// API call to create or replace a secret using a JSON object (`{"value": "..."}`).
+///
+pub async fn save_secret(mut req: Request<AppState>) -> tide::Result {
+    let secret: SecretValue = req.body_json().await?;
+    let name = req.param("name")?.to_owned();
+    let (_, connection) = req.state();
+    connection
+        .secrets()
+        .set(&name, &secret.value)
+        .await
+        .map_err(|e| {
+            error!("Couldn't save secret '{}': {}", name, e);
+            secrets_error(e)
+        })?;
+    Ok(Response::new(StatusCode::Ok))
+}
+
+///
+/// API call to delete a secret that no function refers to.
+///
+pub async fn remove_secret(req: Request<AppState>) -> tide::Result {
+    let (storage, connection) = req.state();
+    let name = req.param("name")?;
+    let users: Vec<String> = storage
+        .values()
+        .await
+        .iter()
+        .filter(|f| {
+            f.env()
+                .values()
+                .any(|v| matches!(v, EnvValue::Secret { secret } if secret == name))
+        })
+        .map(|f| f.name().clone())
+        .collect();
+    if !users.is_empty() {
+        return Err(tide::Error::from_str(
+            StatusCode::Conflict,
+            format!("Secret '{}' is used by: {}", name, users.join(", ")),
+        ));
+    }
+    let deleted = connection.secrets().delete(name).await.map_err(|e| {
+        error!("Couldn't delete secret '{}': {}", name, e);
+        secrets_error(e)
+    })?;
+    if deleted {
+        Ok(Response::new(StatusCode::Ok))
+    } else {
+        Err(secrets_error(
+            SecretsError::NotFound(name.to_owned()).into(),
+        ))
+    }
+}
@@ -2,7 +2,7 @@ mod models;
 use crate::{API_VERSION, FUNC_CALL_PATH};
 use askama::Template;
 use minifaas_rt::RuntimeConnection;
-pub use models::LogViewModel;
+pub use models::{InstalledVersion, LogViewModel};
 
 use log::{debug, error, info, trace, warn};
 use minifaas_common::*;
@@ -28,7 +28,7 @@ struct MainPageShowFunction {
 ///
 ///
 pub async fn index(req: Request<AppSate>) -> tide::Result {
-    let (storage, _) = req.state();
+    let (storage, runtime) = req.state();
     let which: Option<MainPageShowFunction> = req.query().ok();
     trace!("Called index function with parameters: {:?}", which);
 
@@ -41,7 +41,13 @@ pub async fn index(req: Request<AppSate>) -> tide::Result {
     IndexViewModel {
         functions,
         http_triggers: Trigger::all_http(),
-        programming_languages: ProgrammingLanguage::available(),
+        programming_languages: runtime.languages(),
+        installed_versions: runtime
+            .installed_versions()
+            .unwrap_or_default()
+            .into_iter()
+            .map(InstalledVersion::from)
+            .collect(),
         selected,
         base_url: "".to_owned(),
         fn_base_path: format!("{}/{}", API_VERSION, FUNC_CALL_PATH),
@@ -9,11 +9,27 @@ pub struct IndexViewModel {
     pub functions: Vec<UserFunctionType>,
     pub http_triggers: Vec<Trigger>,
     pub programming_languages: Vec<ProgrammingLanguage>,
+    pub installed_versions: Vec<InstalledVersion>,
     pub selected: Option<usize>,
     pub base_url: String,
     pub fn_base_path: String,
 }
 
+///
+/// A toolchain version that's installed for some function.
+///
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct InstalledVersion {
+    pub toolchain: String,
+    pub version: String,
+}
+
+impl From<(String, String)> for InstalledVersion {
+    fn from((toolchain, version): (String, String)) -> Self {
+        InstalledVersion { toolchain, version }
+    }
+}
+
 #[derive(Template, Serialize, Deserialize)]
 #[template(source = "{{ logs|linebreaks }}", ext = "html", escape = "none")]
 pub struct LogViewModel {
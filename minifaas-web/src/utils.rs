@@ -25,6 +25,18 @@ pub async fn _400<S: Into<String>>(msg: S) -> tide::Error {
     tide::Error::from_str(StatusCode::BadRequest, msg.into())
 }
 
+pub async fn _429<S: Into<String>>(msg: S) -> tide::Error {
+    tide::Error::from_str(StatusCode::TooManyRequests, msg.into())
+}
+
+pub async fn _503<S: Into<String>>(msg: S) -> tide::Error {
+    tide::Error::from_str(StatusCode::ServiceUnavailable, msg.into())
+}
+
+pub async fn _504<S: Into<String>>(msg: S) -> tide::Error {
+    tide::Error::from_str(StatusCode::GatewayTimeout, msg.into())
+}
+
 pub fn convert_http_method(other: tide::http::Method) -> HttpMethod {
     match other {
         tide::http::Method::Get => HttpMethod::GET,
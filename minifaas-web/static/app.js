@@ -47,6 +47,24 @@ async function getTrigger() {
     return trigger;
 }
 
+function splitList(value) {
+  return (value || "").split(",").map(v => v.trim()).filter(v => v.length > 0);
+}
+
+const SECRET_REFERENCE = /^\$\{secret:(.+)\}$/;
+
+function parseEnv(value) {
+  const env = {};
+  (value || "").split("\n").map(l => l.trim()).filter(l => l.length > 0).forEach(line => {
+    const at = line.indexOf("=");
+    if (at > 0) {
+      const secret = line.slice(at + 1).match(SECRET_REFERENCE);
+      env[line.slice(0, at).trim()] = secret ? { "secret": secret[1] } : line.slice(at + 1);
+    }
+  });
+  return env;
+}
+
 async function saveNewFunction() {
   let name = document.getElementById("fn-name").value;
   saveFunction(name);
@@ -58,13 +76,26 @@ async function saveFunction(name) {
 
     let code = editor.getValue();
     const trigger = await getTrigger();
-    
+    const route = $("#fn-route").val();
+    const version = $("#fn-version").val();
+    const permissions = {
+      "allow_net": splitList($("#fn-allow-net").val()),
+      "allow_read": $("#fn-allow-read").is(":checked"),
+      "allow_write": $("#fn-allow-write").is(":checked"),
+      "allow_env": splitList($("#fn-allow-env").val())
+    };
+
     let payload = {
       "id": "",
       "name": name,
+      "route": route && route.trim() ? route.trim() : null,
       "code": code,
       "trigger": trigger,
       "language": { "lang": lang },
+      "version": version && version.trim() ? version.trim() : null,
+      "permissions": permissions,
+      "trusted": $("#fn-trusted").is(":checked"),
+      "env": parseEnv($("#fn-env").val()),
       "timestamp": new Date().toISOString()
     };
     console.log(payload);